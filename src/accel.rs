@@ -0,0 +1,324 @@
+//! Maps the accelerometer's axes onto the directions the player actually means, whichever way the remote's held.
+//!
+//! A reading is reoriented for the grip first and then has the configured axes inverted, so an inverted axis is
+//! always the reoriented one, whatever the orientation. Tilt has no deadzone, so nothing else comes after.
+//!
+//! Tilt steering works out the angles the remote is held at from the same reoriented reading, and turns them into
+//! D-pad presses or a stick position, see [`TiltMode`].
+//!
+//! For readings in g rather than raw units, [`AccelCalibration`] holds what the remote measured for each axis at
+//! rest and under 1 g when it was made.
+
+use serde::{Deserialize, Serialize};
+
+/// How the remote is held. Readings are reoriented so `x` points right, `y` away from the player and `z` up.
+#[derive(Clone, Copy, Deserialize, Serialize, PartialEq)]
+#[serde(rename_all = "kebab-case")]
+pub enum AccelOrientation {
+    /// Sideways with the D-pad under the left thumb, like an NES controller.
+    Horizontal,
+    /// Pointed at the screen with the buttons facing up, which is what hid-wiimote reports natively.
+    Vertical,
+    /// Pointed at the screen but rolled onto its left side, so the buttons face left.
+    SidewaysLeft,
+    /// Pointed at the screen but rolled onto its right side, so the buttons face right.
+    SidewaysRight,
+}
+
+impl AccelOrientation {
+    /// Each row picks the raw axis (and its sign) that becomes the reoriented x, y and z respectively.
+    const fn matrix(self) -> [[i32; 3]; 3] {
+        match self {
+            AccelOrientation::Horizontal => [[0, -1, 0], [1, 0, 0], [0, 0, 1]],
+            AccelOrientation::Vertical => [[1, 0, 0], [0, 1, 0], [0, 0, 1]],
+            AccelOrientation::SidewaysLeft => [[0, 0, -1], [0, 1, 0], [1, 0, 0]],
+            AccelOrientation::SidewaysRight => [[0, 0, 1], [0, 1, 0], [-1, 0, 0]],
+        }
+    }
+}
+
+/// Reorients a raw `[x, y, z]` accelerometer reading for the given grip, then flips the sign of every axis set
+/// in `invert`, for mirrored grips.
+pub fn transform(orientation: AccelOrientation, invert: [bool; 3], raw: [i32; 3]) -> [i32; 3] {
+    let oriented = orient(orientation, raw);
+    [0, 1, 2].map(|axis| match invert[axis] {
+        true => -oriented[axis],
+        false => oriented[axis],
+    })
+}
+
+/// Reorients a raw `[x, y, z]` accelerometer reading for the given grip.
+pub fn orient(orientation: AccelOrientation, raw: [i32; 3]) -> [i32; 3] {
+    orientation
+        .matrix()
+        .map(|row| row[0] * raw[0] + row[1] * raw[1] + row[2] * raw[2])
+}
+
+/// Where the remote keeps its accelerometer calibration in EEPROM.
+pub const CALIBRATION_ADDRESS: u32 = 0x16;
+
+/// How many bytes the calibration takes, the last being its checksum.
+pub const CALIBRATION_SIZE: u8 = 10;
+
+/// hid-wiimote reports the 10-bit readings less this, so a level axis reads about 0.
+const KERNEL_OFFSET: i32 = 0x200;
+
+/// Each axis's 10-bit reading with no acceleration on it and with 1 g, as the remote stores them.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct AccelCalibration {
+    pub zero: [i32; 3],
+    pub one_g: [i32; 3],
+}
+
+impl AccelCalibration {
+    /// Reads the [`CALIBRATION_SIZE`] bytes at [`CALIBRATION_ADDRESS`], `None` when they fail their checksum or
+    /// leave an axis with no range.
+    ///
+    /// Zero and 1 g each take four bytes: the top 8 bits of x, y and z, then a byte holding the low 2 bits of
+    /// each, x's in bits 5 and 4 down to z's in bits 1 and 0.
+    pub fn parse(data: &[u8]) -> Option<AccelCalibration> {
+        let data = data.get(..CALIBRATION_SIZE as usize)?;
+        let sum = data[..9]
+            .iter()
+            .fold(0x55u8, |sum, byte| sum.wrapping_add(*byte));
+        if sum != data[9] {
+            return None;
+        }
+
+        let axes = |bytes: &[u8]| {
+            [0, 1, 2]
+                .map(|axis| (bytes[axis] as i32) << 2 | (bytes[3] as i32 >> (4 - axis * 2)) & 0x3)
+        };
+        let calibration = AccelCalibration {
+            zero: axes(&data[0..4]),
+            one_g: axes(&data[4..8]),
+        };
+
+        match (0..3).all(|axis| calibration.one_g[axis] != calibration.zero[axis]) {
+            true => Some(calibration),
+            false => None,
+        }
+    }
+
+    /// A reading as hid-wiimote reports it, in g, so a remote lying flat reads about `(0.0, 0.0, 1.0)`.
+    pub fn to_g(&self, raw: [i32; 3]) -> (f32, f32, f32) {
+        let [x, y, z] = [0, 1, 2].map(|axis| {
+            (raw[axis] + KERNEL_OFFSET - self.zero[axis]) as f32
+                / (self.one_g[axis] - self.zero[axis]) as f32
+        });
+        (x, y, z)
+    }
+}
+
+/// What tilting the remote does besides the motion axes, see [`angles`] for which way is which.
+#[derive(Clone, Copy, Debug, Default, Deserialize, Serialize, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum TiltMode {
+    /// Nothing.
+    #[default]
+    Off,
+    /// Presses the D-pad direction the remote is tilted towards, like the steering in sideways racing games.
+    Dpad,
+    /// Moves the left stick as far as the remote is tilted.
+    Stick,
+}
+
+/// How many degrees back towards level a tilted direction has to come before it's released, so holding the
+/// remote right at the threshold doesn't make the direction flicker.
+const TILT_HYSTERESIS: f32 = 5.0;
+
+/// The roll and pitch of a reoriented reading in degrees, from gravity alone so they're only right while the
+/// remote isn't being swung around.
+///
+/// Roll is positive with the right end tipped down, like steering right, and pitch is positive with the far end
+/// tipped up. Either stays between -90 and 90.
+pub fn angles([x, y, z]: [i32; 3]) -> (f32, f32) {
+    let (x, y, z) = (x as f32, y as f32, z as f32);
+    // At rest the accelerometer reads 1 g pointing up, so a tipped down side reads less than nothing
+    let roll = (-x).atan2(y.hypot(z)).to_degrees();
+    let pitch = y.atan2(x.hypot(z)).to_degrees();
+    (roll, pitch)
+}
+
+/// The `[up, down, left, right]` directions pressed at `(roll, pitch)`, given the ones `held` before.
+///
+/// A direction is pressed past `threshold` degrees and released once it's back under it by [`TILT_HYSTERESIS`].
+/// Opposite directions can't both be pressed, since the angles only go one way at a time.
+pub fn directions((roll, pitch): (f32, f32), threshold: f32, held: [bool; 4]) -> [bool; 4] {
+    let pressed = |angle: f32, held: bool| match held {
+        true => angle > (threshold - TILT_HYSTERESIS).max(0.0),
+        false => angle > threshold,
+    };
+
+    [
+        pressed(pitch, held[0]),
+        pressed(-pitch, held[1]),
+        pressed(-roll, held[2]),
+        pressed(roll, held[3]),
+    ]
+}
+
+/// The left stick position for `(roll, pitch)`, all the way over at `range` degrees and beyond, with `max` as
+/// the stick's full deflection. Up is negative, like a real stick.
+pub fn stick_position((roll, pitch): (f32, f32), range: f32, max: i32) -> (i32, i32) {
+    let scale = |angle: f32| ((angle / range).clamp(-1.0, 1.0) * max as f32).round() as i32;
+    (scale(roll), scale(-pitch))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const NONE_HELD: [bool; 4] = [false; 4];
+
+    fn assert_angles(reading: [i32; 3], (roll, pitch): (f32, f32)) {
+        let angles = angles(reading);
+        assert!(
+            (angles.0 - roll).abs() < 0.01 && (angles.1 - pitch).abs() < 0.01,
+            "{:?} gave {:?}",
+            reading,
+            angles
+        );
+    }
+
+    // Calibration as read out of a remote, zero at 513 on every axis and 1 g at 612, 612 and 616
+    const CALIBRATION: [u8; 10] = [0x80, 0x80, 0x80, 0x15, 0x99, 0x99, 0x9a, 0x00, 0x40, 0xf6];
+
+    #[test]
+    fn calibration_is_parsed() {
+        assert_eq!(
+            AccelCalibration::parse(&CALIBRATION),
+            Some(AccelCalibration {
+                zero: [513, 513, 513],
+                one_g: [612, 612, 616],
+            })
+        );
+
+        let mut corrupted = CALIBRATION;
+        corrupted[0] ^= 1;
+        assert_eq!(AccelCalibration::parse(&corrupted), None);
+        assert_eq!(AccelCalibration::parse(&CALIBRATION[..9]), None);
+    }
+
+    #[test]
+    fn resting_remote_reads_1_g() {
+        let calibration = AccelCalibration::parse(&CALIBRATION).unwrap();
+        // hid-wiimote's readings from the same remote lying flat
+        let samples = [[1, -2, 104], [0, -1, 103], [2, -1, 105], [1, 0, 104]];
+
+        let sum = samples.iter().fold((0.0, 0.0, 0.0), |sum, sample| {
+            let (x, y, z) = calibration.to_g(*sample);
+            (sum.0 + x, sum.1 + y, sum.2 + z)
+        });
+        let count = samples.len() as f32;
+        let (x, y, z) = (sum.0 / count, sum.1 / count, sum.2 / count);
+
+        assert!(x.abs() < 0.05, "x is {}", x);
+        assert!(y.abs() < 0.05, "y is {}", y);
+        assert!((z - 1.0).abs() < 0.05, "z is {}", z);
+    }
+
+    #[test]
+    fn each_preset_reorients() {
+        let raw = [1, 2, 3];
+        assert_eq!(orient(AccelOrientation::Vertical, raw), [1, 2, 3]);
+        assert_eq!(orient(AccelOrientation::Horizontal, raw), [-2, 1, 3]);
+        assert_eq!(orient(AccelOrientation::SidewaysLeft, raw), [-3, 2, 1]);
+        assert_eq!(orient(AccelOrientation::SidewaysRight, raw), [3, 2, -1]);
+    }
+
+    #[test]
+    fn gravity_points_down_in_every_grip() {
+        // What hid-wiimote reports with the remote at rest, held each way
+        let resting = [
+            (AccelOrientation::Vertical, [0, 0, 100]),
+            (AccelOrientation::Horizontal, [0, 0, 100]),
+            (AccelOrientation::SidewaysLeft, [100, 0, 0]),
+            (AccelOrientation::SidewaysRight, [-100, 0, 0]),
+        ];
+
+        for (orientation, raw) in resting {
+            assert_eq!(orient(orientation, raw), [0, 0, 100]);
+        }
+    }
+
+    #[test]
+    fn inversion_comes_after_orientation() {
+        let raw = [1, 2, 3];
+        assert_eq!(
+            transform(AccelOrientation::Vertical, [false; 3], raw),
+            [1, 2, 3]
+        );
+        assert_eq!(
+            transform(AccelOrientation::Vertical, [true, false, true], raw),
+            [-1, 2, -3]
+        );
+
+        // The reoriented x is flipped, not the raw one it came from
+        assert_eq!(
+            transform(AccelOrientation::Horizontal, [true, false, false], raw),
+            [2, 1, 3]
+        );
+        assert_eq!(
+            transform(AccelOrientation::SidewaysRight, [false, true, true], raw),
+            [3, -2, 1]
+        );
+    }
+
+    #[test]
+    fn angles_of_each_orientation() {
+        assert_angles([0, 0, 100], (0.0, 0.0));
+        // Right end down, then left end down
+        assert_angles([-100, 0, 0], (90.0, 0.0));
+        assert_angles([100, 0, 0], (-90.0, 0.0));
+        // Far end up, then down
+        assert_angles([0, 100, 0], (0.0, 90.0));
+        assert_angles([0, -100, 0], (0.0, -90.0));
+        assert_angles([-100, 0, 100], (45.0, 0.0));
+    }
+
+    #[test]
+    fn directions_of_each_orientation() {
+        let threshold = 20.0;
+        assert_eq!(directions((0.0, 0.0), threshold, NONE_HELD), NONE_HELD);
+        assert_eq!(
+            directions((0.0, 45.0), threshold, NONE_HELD),
+            [true, false, false, false]
+        );
+        assert_eq!(
+            directions((0.0, -45.0), threshold, NONE_HELD),
+            [false, true, false, false]
+        );
+        assert_eq!(
+            directions((-45.0, 0.0), threshold, NONE_HELD),
+            [false, false, true, false]
+        );
+        assert_eq!(
+            directions((45.0, 0.0), threshold, NONE_HELD),
+            [false, false, false, true]
+        );
+        assert_eq!(
+            directions((45.0, 45.0), threshold, NONE_HELD),
+            [true, false, false, true]
+        );
+    }
+
+    #[test]
+    fn directions_at_the_threshold() {
+        let right = [false, false, false, true];
+        assert_eq!(directions((20.0, 0.0), 20.0, NONE_HELD), NONE_HELD);
+        assert_eq!(directions((20.1, 0.0), 20.0, NONE_HELD), right);
+
+        // Once pressed, it takes coming back past the hysteresis to let go
+        assert_eq!(directions((15.1, 0.0), 20.0, right), right);
+        assert_eq!(directions((15.0, 0.0), 20.0, right), NONE_HELD);
+    }
+
+    #[test]
+    fn stick_position_is_capped_at_the_range() {
+        assert_eq!(stick_position((0.0, 0.0), 45.0, 32767), (0, 0));
+        assert_eq!(stick_position((45.0, 45.0), 45.0, 32767), (32767, -32767));
+        assert_eq!(stick_position((-90.0, -90.0), 45.0, 32767), (-32767, 32767));
+        assert_eq!(stick_position((22.5, 0.0), 45.0, 100), (50, 0));
+    }
+}
@@ -0,0 +1,65 @@
+//! Noticing the poll loop falling behind the remote, which shows up as input lag.
+//!
+//! A loop that keeps up drains a handful of libinput events each time it dispatches. When it's starved of CPU time
+//! the events pile up in between, and every dispatch drains a large batch of them late. One large batch happens
+//! now and then, like when a device is added, so only a run of [`STREAK`] of them in a row counts.
+
+use std::time::{Duration, Instant};
+
+/// How many events drained by one dispatch count as a backlog.
+pub const THRESHOLD: usize = 32;
+
+/// How many dispatches in a row have to drain a backlog before it's warned about.
+pub const STREAK: u32 = 20;
+
+/// The least time between two warnings, so a busy system isn't also flooded with them.
+pub const WARNING_INTERVAL: Duration = Duration::from_secs(60);
+
+pub struct Backlog {
+    streak: u32,
+    // Events drained over the current streak, for the average that's warned about
+    drained: usize,
+    warned_at: Option<Instant>,
+}
+
+impl Backlog {
+    pub fn new() -> Backlog {
+        Backlog {
+            streak: 0,
+            drained: 0,
+            warned_at: None,
+        }
+    }
+
+    /// Records that a dispatch drained `drained` events, returning the average drained over the streak when it's
+    /// time to warn about it.
+    pub fn record(&mut self, drained: usize, now: Instant) -> Option<usize> {
+        if drained < THRESHOLD {
+            self.streak = 0;
+            self.drained = 0;
+            return None;
+        }
+
+        self.streak = self.streak.saturating_add(1);
+        self.drained = self.drained.saturating_add(drained);
+        if self.streak < STREAK
+            || self
+                .warned_at
+                .is_some_and(|at| now.duration_since(at) < WARNING_INTERVAL)
+        {
+            return None;
+        }
+
+        let average = self.drained / self.streak as usize;
+        self.warned_at = Some(now);
+        self.streak = 0;
+        self.drained = 0;
+        Some(average)
+    }
+}
+
+impl Default for Backlog {
+    fn default() -> Backlog {
+        Backlog::new()
+    }
+}
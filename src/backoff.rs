@@ -0,0 +1,71 @@
+//! Spreads out the delays between connection attempts, so several remotes that lost their connection together
+//! (like after the adapter is reset) don't all hit `bluetoothctl` again at the same moment.
+
+use std::{
+    collections::hash_map::RandomState,
+    hash::{BuildHasher, Hasher},
+    time::Duration,
+};
+
+/// Moves `delay` by up to `jitter` of itself either way, `jitter` being a fraction from 0 to 1.
+pub fn jittered(delay: Duration, jitter: f64) -> Duration {
+    scale(delay, jitter, random_unit())
+}
+
+/// `delay` moved by `jitter` of itself towards the shorter end when `sample` is 0 and the longer end when it's 1,
+/// which keeps the result between `delay * (1 - jitter)` and `delay * (1 + jitter)`.
+pub fn scale(delay: Duration, jitter: f64, sample: f64) -> Duration {
+    let jitter = jitter.clamp(0.0, 1.0);
+    delay.mul_f64(1.0 - jitter + 2.0 * jitter * sample.clamp(0.0, 1.0))
+}
+
+/// A number from 0 to 1 that differs between calls. It's only there to keep retries apart, so std's randomly
+/// keyed hasher is random enough without pulling in a crate for it.
+fn random_unit() -> f64 {
+    let bits = RandomState::new().build_hasher().finish();
+    (bits >> 11) as f64 / (1u64 << 53) as f64
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const DELAY: Duration = Duration::from_secs(10);
+
+    #[test]
+    fn samples_span_the_jitter() {
+        assert_eq!(scale(DELAY, 0.2, 0.0), Duration::from_secs(8));
+        assert_eq!(scale(DELAY, 0.2, 0.5), DELAY);
+        assert_eq!(scale(DELAY, 0.2, 1.0), Duration::from_secs(12));
+    }
+
+    #[test]
+    fn grows_with_the_sample() {
+        let delays: Vec<_> = [0.0, 0.25, 0.5, 0.75, 1.0]
+            .into_iter()
+            .map(|sample| scale(DELAY, 0.2, sample))
+            .collect();
+        assert!(delays.windows(2).all(|pair| pair[0] < pair[1]));
+    }
+
+    #[test]
+    fn jitter_and_sample_are_capped() {
+        assert_eq!(scale(DELAY, 0.0, 1.0), DELAY);
+        assert_eq!(scale(DELAY, 5.0, 1.0), DELAY * 2);
+        assert_eq!(scale(DELAY, 5.0, 0.0), Duration::ZERO);
+        assert_eq!(scale(DELAY, 0.2, -1.0), Duration::from_secs(8));
+        assert_eq!(scale(DELAY, 0.2, 2.0), Duration::from_secs(12));
+    }
+
+    #[test]
+    fn jittered_stays_within_bounds() {
+        for _ in 0..1_000 {
+            let delay = jittered(DELAY, 0.2);
+            assert!(
+                (Duration::from_secs(8)..=Duration::from_secs(12)).contains(&delay),
+                "{:?}",
+                delay
+            );
+        }
+    }
+}
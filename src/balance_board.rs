@@ -0,0 +1,167 @@
+//! The Wii Balance Board (`RVL-WBC-01`), which connects like a remote but reports four load cells instead of
+//! buttons and motion.
+//!
+//! hid-wiimote reports the cells on its balance board interface in units of 10 g, already calibrated:
+//!
+//! | Axis        | Cell         |
+//! |-------------|--------------|
+//! | `ABS_HAT0X` | Top right    |
+//! | `ABS_HAT0Y` | Bottom right |
+//! | `ABS_HAT1X` | Top left     |
+//! | `ABS_HAT1Y` | Bottom left  |
+//!
+//! With `--balance-board-axes`, readings are also forwarded to a virtual device: the center of balance as
+//! `ABS_X`/`ABS_Y` from -1000 to 1000, and the total weight as `ABS_Z` in units of 10 g.
+
+use std::{fs, io, path::Path};
+
+use crate::{
+    evdev::{ABS_HAT0X, ABS_HAT1Y, ABS_X, ABS_Y, ABS_Z, EV_ABS, EV_SYN, SYN_REPORT},
+    uinput::{AbsAxis, Capabilities, VirtualDevice},
+    xwiimote::{self, Evdev},
+};
+
+pub const INTERFACE: &str = "Nintendo Wii Remote Balance Board";
+
+/// What the board calls itself over Bluetooth, remotes are `RVL-CNT-01`.
+const NAME: &str = "RVL-WBC-01";
+
+/// The most the board is specified to weigh, in units of 10 g.
+const MAX_WEIGHT: i32 = 15000;
+
+/// Whether the HID device at `udev_device_path` is a Balance Board rather than a remote.
+pub fn detect(udev_device_path: &str) -> bool {
+    fs::read_to_string(Path::new(udev_device_path).join("uevent")).is_ok_and(|uevent| {
+        uevent.lines().any(|line| {
+            line.strip_prefix("HID_NAME=")
+                .is_some_and(|name| name.contains(NAME))
+        })
+    })
+}
+
+/// One report from the load cells, each in units of 10 g.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Reading {
+    pub top_right: i32,
+    pub bottom_right: i32,
+    pub top_left: i32,
+    pub bottom_left: i32,
+}
+
+impl Reading {
+    /// Everything on the board, in kilograms.
+    pub fn total_kg(&self) -> f32 {
+        self.total() as f32 / 100.0
+    }
+
+    /// Where the weight is, from -1.0 to 1.0 on each axis with right and down being positive, or `None` when
+    /// nobody's standing on the board.
+    pub fn center_of_balance(&self) -> Option<(f32, f32)> {
+        let total = self.total();
+        if total <= 0 {
+            return None;
+        }
+
+        let right = self.top_right + self.bottom_right;
+        let left = self.top_left + self.bottom_left;
+        let bottom = self.bottom_right + self.bottom_left;
+        let top = self.top_right + self.top_left;
+        Some((
+            (right - left) as f32 / total as f32,
+            (bottom - top) as f32 / total as f32,
+        ))
+    }
+
+    fn total(&self) -> i32 {
+        self.top_right + self.bottom_right + self.top_left + self.bottom_left
+    }
+}
+
+pub struct BalanceBoard {
+    evdev: Evdev,
+    reading: Reading,
+    // Only there with `--balance-board-axes`
+    device: Option<VirtualDevice>,
+}
+
+impl BalanceBoard {
+    /// Opens the board's interface, returning `None` (the reason is logged) when it can't be.
+    pub fn open(
+        udev_device_path: &str,
+        device_name: &str,
+        forward: bool,
+    ) -> io::Result<Option<BalanceBoard>> {
+        let evdev = match xwiimote::open_interface(udev_device_path, INTERFACE) {
+            Some(evdev) => evdev,
+            None => return Ok(None),
+        };
+
+        let device = match forward {
+            true => {
+                let capabilities = Capabilities {
+                    absolute: vec![
+                        AbsAxis {
+                            code: ABS_X,
+                            min: -1000,
+                            max: 1000,
+                        },
+                        AbsAxis {
+                            code: ABS_Y,
+                            min: -1000,
+                            max: 1000,
+                        },
+                        AbsAxis {
+                            code: ABS_Z,
+                            min: 0,
+                            max: MAX_WEIGHT,
+                        },
+                    ],
+                    ..Capabilities::default()
+                };
+
+                Some(VirtualDevice::create(
+                    &format!("{} Balance Board", device_name),
+                    &capabilities,
+                )?)
+            }
+            false => None,
+        };
+
+        Ok(Some(BalanceBoard {
+            evdev,
+            reading: Reading::default(),
+            device,
+        }))
+    }
+
+    /// Reads every queued up event, returning the latest complete reading if there's a new one.
+    pub fn poll(&mut self) -> io::Result<Option<Reading>> {
+        let mut latest = None;
+        for event in self.evdev.read_events()? {
+            match (event.type_, event.code) {
+                (EV_ABS, ABS_HAT0X..=ABS_HAT1Y) => {
+                    let cell = match event.code - ABS_HAT0X {
+                        0 => &mut self.reading.top_right,
+                        1 => &mut self.reading.bottom_right,
+                        2 => &mut self.reading.top_left,
+                        _ => &mut self.reading.bottom_left,
+                    };
+
+                    *cell = event.value;
+                }
+                (EV_SYN, SYN_REPORT) => latest = Some(self.reading),
+                _ => {}
+            }
+        }
+
+        if let (Some(reading), Some(device)) = (latest, &mut self.device) {
+            let (x, y) = reading.center_of_balance().unwrap_or((0.0, 0.0));
+            device.emit(EV_ABS, ABS_X, (x * 1000.0).round() as i32)?;
+            device.emit(EV_ABS, ABS_Y, (y * 1000.0).round() as i32)?;
+            device.emit(EV_ABS, ABS_Z, reading.total().clamp(0, MAX_WEIGHT))?;
+            device.sync()?;
+        }
+
+        Ok(latest)
+    }
+}
@@ -0,0 +1,95 @@
+//! Re-reading a connected remote's battery level every so often, on one thread for everything that shows it.
+//!
+//! Each reading goes to the metrics gauges straight away and is handed to the poll loop, which keeps the battery
+//! LEDs and the low-battery notification up to date. The low-battery warning can only fire when a reading comes
+//! in, so a long interval also means a remote can sit below the threshold for that long before anyone is told.
+//!
+//! The same thread reads the LEDs back, putting the mask BlueWii last set back in place if another program
+//! changed them in the meantime.
+
+use std::{
+    path::Path,
+    sync::{
+        atomic::{AtomicU8, Ordering},
+        mpsc::{self, Receiver},
+        Arc, Mutex,
+    },
+    thread,
+    time::Duration,
+};
+
+use log::{debug, info};
+
+use crate::{metrics, wii_remote::WiiRemote};
+
+/// Starts reading the battery of the remote at `udev_device_path` every `interval`, and checking that its LEDs
+/// still show `led_mask`.
+///
+/// The thread stops once the caller drops its `led_mask` or the remote's syspath is gone, before touching the LEDs
+/// again. Levels that can't be read are skipped.
+pub fn spawn_poller(
+    wii_remote: Arc<Mutex<WiiRemote>>,
+    address: String,
+    udev_device_path: String,
+    interval: Duration,
+    led_mask: Arc<AtomicU8>,
+) -> Receiver<u8> {
+    let (levels_sender, levels) = mpsc::channel();
+    thread::spawn(move || loop {
+        thread::sleep(interval);
+
+        // The remote may be someone else's by now, and its LEDs no longer this connection's to look after. The poll
+        // loop holds the other reference to `led_mask` for as long as the connection lasts.
+        if Arc::strong_count(&led_mask) == 1 || !Path::new(&udev_device_path).exists() {
+            break;
+        }
+
+        // Skipped while the remote is being connected, it may not be the same remote afterwards
+        if let Ok(wii_remote) = wii_remote.try_lock() {
+            reconcile_leds(&wii_remote, led_mask.load(Ordering::Relaxed));
+        }
+
+        let Some(level) = WiiRemote::battery_level(&udev_device_path) else {
+            continue;
+        };
+
+        metrics::record_battery(&address, level);
+        if levels_sender.send(level).is_err() {
+            break;
+        }
+    });
+
+    levels
+}
+
+/// Sets the LEDs back to `mask` if they show anything else, unless they're being swept or blinked.
+fn reconcile_leds(wii_remote: &WiiRemote, mask: u8) {
+    if wii_remote.is_animating_leds() {
+        return;
+    }
+
+    match wii_remote.read_leds() {
+        Ok(lit) if lit != mask => {
+            info!(
+                "Wii Remote LEDs were changed to {} from outside BlueWii, setting them back to {}",
+                pattern(lit),
+                pattern(mask)
+            );
+            if let Err(err) = wii_remote.set_leds(mask) {
+                debug!("Failed to set the LEDs back: {:#}", err);
+            }
+        }
+        Ok(_) => {}
+        Err(err) => debug!("Failed to read the LEDs back: {:#}", err),
+    }
+}
+
+/// The LEDs in `mask` from left to right, like `*--*` for 1 and 4.
+pub fn pattern(mask: u8) -> String {
+    (0..4)
+        .map(|index| match mask & (1 << index) {
+            0 => '-',
+            _ => '*',
+        })
+        .collect()
+}
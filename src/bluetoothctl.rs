@@ -0,0 +1,363 @@
+//! Running `bluetoothctl` commands, optionally on an adapter other than the default one.
+//!
+//! `bluetoothctl <command>` always works on the default controller, and `select` only lasts for the session it's
+//! typed into. So once an adapter is selected with `--adapter`, every command is typed into an interactive
+//! session instead, right after `select <adapter>`, and read back with the prompts and colours stripped so it
+//! looks the same as when it's run directly. A `version` typed after the command marks where its output ends.
+//!
+//! An adapter can also be given as its kernel name like `hci0`, which is looked up through bluez's D-Bus API
+//! since `bluetoothctl` doesn't show those, see [`interface_address`].
+
+use std::{
+    io::{self, BufRead, BufReader, Read, Write},
+    process::{Child, Command, ExitStatus, Output, Stdio},
+    sync::mpsc::{self, RecvTimeoutError},
+    thread,
+    time::{Duration, Instant},
+};
+
+use crate::{utils::output_with_timeout, wii_remote::WiiRemote};
+
+/// How long a session may take to answer a command that doesn't wait on the remote.
+pub const SESSION_TIMEOUT: Duration = Duration::from_secs(10);
+
+pub struct Bluetoothctl {
+    pub path: String,
+    /// The address of the controller to use, the default one when `None`.
+    pub adapter: Option<String>,
+}
+
+/// A controller as listed by `bluetoothctl list`.
+pub struct Controller {
+    pub address: String,
+    pub name: String,
+    pub default: bool,
+}
+
+impl Bluetoothctl {
+    /// Runs a command that answers right away, like `devices` or `info <address>`.
+    ///
+    /// `None` means a session didn't answer within [`SESSION_TIMEOUT`], which only happens with an adapter
+    /// selected, since a command run directly is waited on for as long as it takes.
+    pub fn output(&self, args: &[&str]) -> io::Result<Option<Output>> {
+        match &self.adapter {
+            None => Command::new(&self.path).args(args).output().map(Some),
+            Some(adapter) => self.session(
+                Some(adapter),
+                &[&args.join(" ")],
+                is_sentinel,
+                SESSION_TIMEOUT,
+            ),
+        }
+    }
+
+    /// Runs a command that waits on a remote, like `connect <address>`, giving up after `timeout`.
+    ///
+    /// In a session the command's result arrives after the sentinel, so `done` says which line of output is the
+    /// last one to wait for.
+    pub fn output_until(
+        &self,
+        args: &[&str],
+        done: fn(&str) -> bool,
+        timeout: Duration,
+    ) -> io::Result<Option<Output>> {
+        match &self.adapter {
+            None => output_with_timeout(Command::new(&self.path).args(args), timeout),
+            Some(adapter) => self.session(Some(adapter), &[&args.join(" ")], done, timeout),
+        }
+    }
+
+    /// Types `commands` into an interactive session one after another, on the selected adapter if there is one,
+    /// for the ones that only work there, like `agent` and `pair`. `done` and `timeout` are like
+    /// [`Bluetoothctl::output_until`]'s.
+    pub fn session_output(
+        &self,
+        commands: &[&str],
+        done: fn(&str) -> bool,
+        timeout: Duration,
+    ) -> io::Result<Option<Output>> {
+        self.session(self.adapter.as_deref(), commands, done, timeout)
+    }
+
+    /// Starts `bluetoothctl -t <timeout> scan on`, returning it along with its output.
+    pub fn spawn_scan(&self, timeout: Duration) -> io::Result<(Child, Box<dyn Read + Send>)> {
+        let adapter = match &self.adapter {
+            None => {
+                let mut child = Command::new(&self.path)
+                    .args(["-t", &timeout.as_secs().to_string(), "scan", "on"])
+                    .stdout(Stdio::piped())
+                    .spawn()?;
+
+                let stdout = child.stdout.take().expect("stdout is piped");
+                return Ok((child, Box::new(stdout)));
+            }
+            Some(adapter) => adapter.clone(),
+        };
+
+        let mut child = Command::new(&self.path)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::null())
+            .spawn()?;
+
+        // The session is ended from here once the timeout is up, like `-t` would, and the stdout handed out is
+        // cleaned up on the way through so scan lines look the same either way
+        let mut stdin = child.stdin.take().expect("stdin is piped");
+        writeln!(stdin, "select {}\nscan on", adapter)?;
+        thread::spawn(move || {
+            thread::sleep(timeout);
+            let _ = writeln!(stdin, "scan off\nquit");
+        });
+
+        let raw = child.stdout.take().expect("stdout is piped");
+        let (reader, mut writer) = io::pipe()?;
+        thread::spawn(move || {
+            for line in BufReader::new(raw).lines() {
+                let Ok(line) = line else { break };
+                if writeln!(writer, "{}", clean_line(&line)).is_err() {
+                    break;
+                }
+            }
+        });
+
+        Ok((child, Box::new(reader)))
+    }
+
+    fn session(
+        &self,
+        adapter: Option<&str>,
+        commands: &[&str],
+        done: fn(&str) -> bool,
+        timeout: Duration,
+    ) -> io::Result<Option<Output>> {
+        let mut child = Command::new(&self.path)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::null())
+            .spawn()?;
+
+        let mut stdin = child.stdin.take().expect("stdin is piped");
+        if let Some(adapter) = adapter {
+            writeln!(stdin, "select {}", adapter)?;
+        }
+
+        writeln!(stdin, "{}\nversion", commands.join("\n"))?;
+
+        let stdout = child.stdout.take().expect("stdout is piped");
+        let (lines_sender, lines) = mpsc::channel();
+        thread::spawn(move || {
+            for line in BufReader::new(stdout).lines() {
+                let Ok(line) = line else { break };
+                if lines_sender.send(clean_line(&line)).is_err() {
+                    break;
+                }
+            }
+        });
+
+        let deadline = Instant::now() + timeout;
+        let mut output = String::new();
+        let finished = loop {
+            let line = match lines.recv_timeout(deadline.saturating_duration_since(Instant::now()))
+            {
+                Ok(line) => line,
+                Err(RecvTimeoutError::Timeout) => break false,
+                Err(RecvTimeoutError::Disconnected) => break true,
+            };
+
+            if !is_sentinel(&line) {
+                output.push_str(&line);
+                output.push('\n');
+            }
+
+            if done(&line) {
+                break true;
+            }
+        };
+
+        let _ = writeln!(stdin, "quit");
+        drop(stdin);
+        let status = match finished {
+            true => wait_briefly(&mut child)?,
+            false => {
+                let _ = child.kill();
+                let _ = child.wait();
+                return Ok(None);
+            }
+        };
+
+        Ok(Some(Output {
+            status,
+            stdout: output.into_bytes(),
+            stderr: Vec::new(),
+        }))
+    }
+}
+
+/// Every controller `bluetoothctl list` knows about.
+pub fn list(path: &str) -> io::Result<Vec<Controller>> {
+    let output = Command::new(path).arg("list").output()?;
+    Ok(parse_list(&String::from_utf8_lossy(&output.stdout)))
+}
+
+/// Parses lines like `Controller 00:1A:7D:DA:71:13 living-room [default]`.
+pub fn parse_list(output: &str) -> Vec<Controller> {
+    output
+        .lines()
+        .filter_map(|line| line.trim().strip_prefix("Controller "))
+        .map(|line| {
+            let (address, name) = line.split_once(' ').unwrap_or((line, ""));
+            let (name, default) = match name.strip_suffix("[default]") {
+                Some(name) => (name, true),
+                None => (name, false),
+            };
+
+            Controller {
+                address: address.to_uppercase(),
+                name: name.trim().to_owned(),
+                default,
+            }
+        })
+        .collect()
+}
+
+/// Whether `adapter` is the kernel's name for a controller, like `hci0`, rather than its address or name.
+pub fn is_interface_name(adapter: &str) -> bool {
+    adapter
+        .strip_prefix("hci")
+        .is_some_and(|index| !index.is_empty() && index.chars().all(|c| c.is_ascii_digit()))
+}
+
+/// The address of the controller the kernel calls `interface`, or `None` if bluez doesn't know it.
+///
+/// `bluetoothctl` only ever shows controllers by address, so bluez is asked over D-Bus through glib's `gdbus`
+/// instead, which prints the answer like `(<'00:1A:7D:DA:71:13'>,)`.
+pub fn interface_address(interface: &str) -> io::Result<Option<String>> {
+    let output = Command::new("gdbus")
+        .args([
+            "call",
+            "--system",
+            "--dest",
+            "org.bluez",
+            "--object-path",
+            &format!("/org/bluez/{}", interface),
+            "--method",
+            "org.freedesktop.DBus.Properties.Get",
+            "org.bluez.Adapter1",
+            "Address",
+        ])
+        .stderr(Stdio::null())
+        .output()?;
+
+    Ok(parse_gdbus_address(&String::from_utf8_lossy(
+        &output.stdout,
+    )))
+}
+
+fn parse_gdbus_address(output: &str) -> Option<String> {
+    let (_, rest) = output.split_once('\'')?;
+    let (address, _) = rest.split_once('\'')?;
+    match WiiRemote::is_valid_address(address) {
+        true => Some(address.to_uppercase()),
+        false => None,
+    }
+}
+
+/// Finds the controller `adapter` names, by address or by name, returning its address.
+pub fn resolve(controllers: &[Controller], adapter: &str) -> Option<String> {
+    controllers
+        .iter()
+        .find(|controller| {
+            controller.address.eq_ignore_ascii_case(adapter) || controller.name == adapter
+        })
+        .map(|controller| controller.address.clone())
+}
+
+/// Strips the colours, readline markers and `[bluetooth]# ` prompts an interactive session mixes into its output.
+pub fn clean_line(line: &str) -> String {
+    let mut cleaned = String::with_capacity(line.len());
+    let mut chars = line.chars();
+    while let Some(char) = chars.next() {
+        match char {
+            // An escape sequence runs until its final byte, a letter
+            '\x1b' => {
+                for char in chars.by_ref() {
+                    if char.is_ascii_alphabetic() {
+                        break;
+                    }
+                }
+            }
+            '\r' | '\x01' | '\x02' => {}
+            char => cleaned.push(char),
+        }
+    }
+
+    // Event lines like `[NEW] Device ...` keep their tag, only prompts end in `]# ` or `]> `
+    let mut cleaned = cleaned.as_str();
+    while cleaned.starts_with('[') {
+        match cleaned.find("]# ").or_else(|| cleaned.find("]> ")) {
+            Some(end) if !cleaned[..end].contains("] ") => cleaned = &cleaned[end + 3..],
+            _ => break,
+        }
+    }
+
+    cleaned.to_owned()
+}
+
+fn is_sentinel(line: &str) -> bool {
+    line.starts_with("Version ")
+}
+
+fn wait_briefly(child: &mut Child) -> io::Result<ExitStatus> {
+    let deadline = Instant::now() + Duration::from_secs(1);
+    loop {
+        if let Some(status) = child.try_wait()? {
+            return Ok(status);
+        }
+
+        if Instant::now() >= deadline {
+            let _ = child.kill();
+            return child.wait();
+        }
+
+        thread::sleep(Duration::from_millis(20));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn interface_names() {
+        assert!(is_interface_name("hci0"));
+        assert!(is_interface_name("hci12"));
+        assert!(!is_interface_name("hci"));
+        assert!(!is_interface_name("hci0x"));
+        assert!(!is_interface_name("00:1A:7D:DA:71:13"));
+    }
+
+    #[test]
+    fn gdbus_address() {
+        assert_eq!(
+            parse_gdbus_address("(<'00:1a:7d:da:71:13'>,)\n"),
+            Some("00:1A:7D:DA:71:13".to_owned())
+        );
+        assert_eq!(parse_gdbus_address(""), None);
+        assert_eq!(parse_gdbus_address("(<'hci0'>,)\n"), None);
+    }
+
+    #[test]
+    fn controllers_by_address_or_name() {
+        let controllers =
+            parse_list("Controller 00:1A:7D:DA:71:13 living-room [default]\nController 5C:F3:70:00:00:01 dongle\n");
+        assert_eq!(
+            resolve(&controllers, "00:1a:7d:da:71:13"),
+            Some("00:1A:7D:DA:71:13".to_owned())
+        );
+        assert_eq!(
+            resolve(&controllers, "dongle"),
+            Some("5C:F3:70:00:00:01".to_owned())
+        );
+        assert_eq!(resolve(&controllers, "hci1"), None);
+    }
+}
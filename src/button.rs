@@ -0,0 +1,211 @@
+//! The buttons on the Wii Remote itself.
+//!
+//! Each button converts to and from its config file name through `FromStr`/`Display`, and to and from the key
+//! code hid-wiimote reports through [`Button::code`] and [`Button::from_code`], so nothing else has to know both.
+
+use std::{fmt, str::FromStr};
+
+use serde::{de, Deserialize, Deserializer, Serialize, Serializer};
+
+use crate::{
+    error::BlueWiiError,
+    evdev::{
+        BTN_1, BTN_2, BTN_A, BTN_B, BTN_MODE, KEY_DOWN, KEY_LEFT, KEY_NEXT, KEY_PREVIOUS,
+        KEY_RIGHT, KEY_UP,
+    },
+};
+
+/// A Wii Remote button, named in config files by its lowercase variant name (`"a"`, `"home"`, `"up"`, ...).
+#[derive(Clone, Copy, Debug, Deserialize, Serialize, PartialEq, Eq, Hash)]
+#[serde(rename_all = "lowercase")]
+pub enum Button {
+    A,
+    B,
+    One,
+    Two,
+    Plus,
+    Minus,
+    Home,
+    Up,
+    Down,
+    Left,
+    Right,
+}
+
+impl Button {
+    pub const ALL: [Button; 11] = [
+        Button::A,
+        Button::B,
+        Button::One,
+        Button::Two,
+        Button::Plus,
+        Button::Minus,
+        Button::Home,
+        Button::Up,
+        Button::Down,
+        Button::Left,
+        Button::Right,
+    ];
+
+    /// The button hid-wiimote reports as `code`.
+    pub fn from_code(code: u16) -> Option<Button> {
+        Button::ALL.into_iter().find(|button| button.code() == code)
+    }
+
+    /// What the button is called in config files.
+    pub const fn name(self) -> &'static str {
+        match self {
+            Button::A => "a",
+            Button::B => "b",
+            Button::One => "one",
+            Button::Two => "two",
+            Button::Plus => "plus",
+            Button::Minus => "minus",
+            Button::Home => "home",
+            Button::Up => "up",
+            Button::Down => "down",
+            Button::Left => "left",
+            Button::Right => "right",
+        }
+    }
+
+    /// The `input-event-codes.h` name of [`code`](Button::code).
+    pub const fn code_name(self) -> &'static str {
+        match self {
+            Button::A => "BTN_A",
+            Button::B => "BTN_B",
+            Button::One => "BTN_1",
+            Button::Two => "BTN_2",
+            Button::Plus => "KEY_NEXT",
+            Button::Minus => "KEY_PREVIOUS",
+            Button::Home => "BTN_MODE",
+            Button::Up => "KEY_UP",
+            Button::Down => "KEY_DOWN",
+            Button::Left => "KEY_LEFT",
+            Button::Right => "KEY_RIGHT",
+        }
+    }
+
+    /// The button whose [`code_name`](Button::code_name) is `code_name`.
+    pub fn from_code_name(code_name: &str) -> Option<Button> {
+        Button::ALL
+            .into_iter()
+            .find(|button| button.code_name() == code_name)
+    }
+
+    /// The key code hid-wiimote reports for this button.
+    pub const fn code(self) -> u16 {
+        match self {
+            Button::A => BTN_A,
+            Button::B => BTN_B,
+            Button::One => BTN_1,
+            Button::Two => BTN_2,
+            Button::Plus => KEY_NEXT,
+            Button::Minus => KEY_PREVIOUS,
+            Button::Home => BTN_MODE,
+            Button::Up => KEY_UP,
+            Button::Down => KEY_DOWN,
+            Button::Left => KEY_LEFT,
+            Button::Right => KEY_RIGHT,
+        }
+    }
+}
+
+impl FromStr for Button {
+    type Err = BlueWiiError;
+
+    fn from_str(name: &str) -> Result<Button, BlueWiiError> {
+        Button::ALL
+            .into_iter()
+            .find(|button| button.name() == name)
+            .ok_or_else(|| BlueWiiError::ParseFailed {
+                what: format!("button `{}'", name),
+                reason: format!(
+                    "expected one of {}",
+                    Button::ALL.map(Button::name).join(", ")
+                ),
+            })
+    }
+}
+
+impl fmt::Display for Button {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+/// What a profile maps from: a single button like `"a"`, or a chord of buttons joined with `+` like `"home+plus"`.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct Binding(pub Vec<Button>);
+
+impl FromStr for Binding {
+    type Err = BlueWiiError;
+
+    fn from_str(name: &str) -> Result<Binding, BlueWiiError> {
+        let buttons = name
+            .split('+')
+            .map(|part| part.trim().parse())
+            .collect::<Result<Vec<Button>, _>>()?;
+
+        Ok(Binding(buttons))
+    }
+}
+
+impl Serialize for Binding {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let names = self
+            .0
+            .iter()
+            .map(|button| button.name())
+            .collect::<Vec<_>>();
+        serializer.serialize_str(&names.join("+"))
+    }
+}
+
+impl<'de> Deserialize<'de> for Binding {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Binding, D::Error> {
+        String::deserialize(deserializer)?
+            .parse()
+            .map_err(de::Error::custom)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn every_button_round_trips() {
+        for button in Button::ALL {
+            assert_eq!(button.to_string().parse::<Button>().unwrap(), button);
+            assert_eq!(Button::from_code(button.code()), Some(button));
+            assert_eq!(Button::from_code_name(button.code_name()), Some(button));
+        }
+    }
+
+    #[test]
+    fn names_and_codes_are_unique() {
+        for (index, button) in Button::ALL.iter().enumerate() {
+            for other in &Button::ALL[index + 1..] {
+                assert_ne!(button.name(), other.name());
+                assert_ne!(button.code(), other.code());
+            }
+        }
+    }
+
+    #[test]
+    fn unknown_names() {
+        assert!("A".parse::<Button>().is_err());
+        assert!("start".parse::<Button>().is_err());
+        assert_eq!(Button::from_code(0), None);
+    }
+
+    #[test]
+    fn chord_bindings() {
+        assert_eq!(
+            "home + plus".parse::<Binding>().unwrap(),
+            Binding(vec![Button::Home, Button::Plus])
+        );
+        assert!("home+".parse::<Binding>().is_err());
+    }
+}
@@ -0,0 +1,227 @@
+//! Chords: several buttons held together that trigger an output of their own.
+//!
+//! Once every button of a chord is held, the chord's output is pressed and its buttons stop sending their
+//! own outputs until they're released, so they don't also trigger whatever they're mapped to by themselves.
+//! When chords overlap, the one with the most buttons wins, so holding 1+2+A after 1+2 swaps the 1+2 output
+//! for the 1+2+A one. Between chords of the same size, the one listed first wins.
+
+/// What the forwarder should do in response to a key event.
+#[derive(Debug, PartialEq)]
+pub enum Action {
+    /// Forward a Wii Remote key through the normal per-button mapping.
+    Key(u16, bool),
+    /// Press or release a chord's output code.
+    Output(u16, bool),
+}
+
+pub struct ChordTracker {
+    // The Wii Remote keys of each chord and the code it outputs
+    chords: Vec<(Vec<u16>, u16)>,
+    held: Vec<u16>,
+    // Indices into `chords` whose output is currently pressed
+    active: Vec<usize>,
+    // Held keys that belong to a chord that fired, and so mustn't send their own output
+    suppressed: Vec<u16>,
+}
+
+impl ChordTracker {
+    pub fn new(chords: Vec<(Vec<u16>, u16)>) -> ChordTracker {
+        ChordTracker {
+            chords,
+            held: Vec::new(),
+            active: Vec::new(),
+            suppressed: Vec::new(),
+        }
+    }
+
+    pub fn handle(&mut self, key: u16, pressed: bool) -> Vec<Action> {
+        match pressed {
+            true => self.press(key),
+            false => self.release(key),
+        }
+    }
+
+    fn press(&mut self, key: u16) -> Vec<Action> {
+        if !self.held.contains(&key) {
+            self.held.push(key);
+        }
+
+        let completed = self
+            .chords
+            .iter()
+            .enumerate()
+            .filter(|(index, (keys, _))| {
+                !self.active.contains(index)
+                    && keys.contains(&key)
+                    && keys.iter().all(|chord_key| self.held.contains(chord_key))
+            })
+            .rev()
+            .max_by_key(|(_, (keys, _))| keys.len())
+            .map(|(index, _)| index);
+
+        let index = match completed {
+            Some(index) => index,
+            None if self.suppressed.contains(&key) => return Vec::new(),
+            None => return vec![Action::Key(key, true)],
+        };
+
+        let mut actions = Vec::new();
+        let keys = self.chords[index].0.clone();
+
+        // A bigger chord takes over from the smaller ones it contains
+        let chords = &self.chords;
+        self.active.retain(|active| {
+            let contained = chords[*active]
+                .0
+                .iter()
+                .all(|chord_key| keys.contains(chord_key));
+            if contained {
+                actions.push(Action::Output(chords[*active].1, false));
+            }
+
+            !contained
+        });
+
+        // Members that were already sending their own output let go of it
+        for chord_key in &keys {
+            if self.suppressed.contains(chord_key) {
+                continue;
+            }
+
+            if *chord_key != key {
+                actions.push(Action::Key(*chord_key, false));
+            }
+
+            self.suppressed.push(*chord_key);
+        }
+
+        actions.push(Action::Output(self.chords[index].1, true));
+        self.active.push(index);
+        actions
+    }
+
+    fn release(&mut self, key: u16) -> Vec<Action> {
+        self.held.retain(|held| *held != key);
+
+        let mut actions = Vec::new();
+        let chords = &self.chords;
+        self.active.retain(|active| {
+            let broken = chords[*active].0.contains(&key);
+            if broken {
+                actions.push(Action::Output(chords[*active].1, false));
+            }
+
+            !broken
+        });
+
+        match self
+            .suppressed
+            .iter()
+            .position(|suppressed| *suppressed == key)
+        {
+            Some(index) => {
+                self.suppressed.swap_remove(index);
+            }
+            None => actions.push(Action::Key(key, false)),
+        }
+
+        actions
+    }
+
+    /// Releases every chord output that's pressed, for switching profiles.
+    pub fn release_all(&mut self) -> Vec<Action> {
+        let actions = self
+            .active
+            .drain(..)
+            .map(|active| Action::Output(self.chords[active].1, false))
+            .collect();
+
+        self.held.clear();
+        self.suppressed.clear();
+        actions
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ONE: u16 = 1;
+    const TWO: u16 = 2;
+    const A: u16 = 3;
+
+    #[test]
+    fn bigger_chord_takes_over() {
+        let mut tracker = ChordTracker::new(vec![(vec![ONE, TWO], 100), (vec![ONE, TWO, A], 200)]);
+
+        assert_eq!(tracker.handle(ONE, true), [Action::Key(ONE, true)]);
+        assert_eq!(
+            tracker.handle(TWO, true),
+            [Action::Key(ONE, false), Action::Output(100, true)]
+        );
+        assert_eq!(
+            tracker.handle(A, true),
+            [Action::Output(100, false), Action::Output(200, true)]
+        );
+
+        // Breaking the bigger chord doesn't bring the smaller one back, and its members stay quiet
+        assert_eq!(tracker.handle(A, false), [Action::Output(200, false)]);
+        assert_eq!(tracker.handle(ONE, false), []);
+        assert_eq!(tracker.handle(TWO, false), []);
+    }
+
+    #[test]
+    fn bigger_chord_wins_when_both_complete_at_once() {
+        let mut tracker = ChordTracker::new(vec![(vec![ONE, TWO], 100), (vec![ONE, TWO, A], 200)]);
+
+        tracker.handle(ONE, true);
+        tracker.handle(A, true);
+        assert_eq!(
+            tracker.handle(TWO, true),
+            [
+                Action::Key(ONE, false),
+                Action::Key(A, false),
+                Action::Output(200, true)
+            ]
+        );
+    }
+
+    #[test]
+    fn first_listed_chord_wins_a_tie() {
+        let mut tracker = ChordTracker::new(vec![(vec![ONE, TWO], 100), (vec![TWO, A], 200)]);
+
+        tracker.handle(ONE, true);
+        tracker.handle(A, true);
+        assert_eq!(
+            tracker.handle(TWO, true),
+            [Action::Key(ONE, false), Action::Output(100, true)]
+        );
+    }
+
+    #[test]
+    fn chords_sharing_a_button() {
+        let mut tracker = ChordTracker::new(vec![(vec![ONE, TWO], 100), (vec![TWO, A], 200)]);
+
+        tracker.handle(ONE, true);
+        tracker.handle(TWO, true);
+        assert_eq!(tracker.handle(A, true), [Action::Output(200, true)]);
+
+        // Letting go of the shared button breaks both
+        assert_eq!(
+            tracker.handle(TWO, false),
+            [Action::Output(100, false), Action::Output(200, false)]
+        );
+        assert_eq!(tracker.handle(ONE, false), []);
+        assert_eq!(tracker.handle(A, false), []);
+    }
+
+    #[test]
+    fn lone_buttons_pass_through() {
+        let mut tracker = ChordTracker::new(vec![(vec![ONE, TWO], 100)]);
+
+        assert_eq!(tracker.handle(A, true), [Action::Key(A, true)]);
+        assert_eq!(tracker.handle(ONE, true), [Action::Key(ONE, true)]);
+        assert_eq!(tracker.handle(ONE, false), [Action::Key(ONE, false)]);
+        assert_eq!(tracker.handle(A, false), [Action::Key(A, false)]);
+    }
+}
@@ -0,0 +1,49 @@
+//! Where the idle timeout gets the time from, so it can be driven by something other than the system clock.
+
+use std::{
+    sync::atomic::{AtomicU64, Ordering},
+    time::{Duration, SystemTime},
+};
+
+pub trait Clock {
+    /// Seconds since the Unix epoch, or `None` if the clock is set before it.
+    fn now(&self) -> Option<u64>;
+}
+
+/// The real time, from [`SystemTime`].
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> Option<u64> {
+        SystemTime::now()
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .ok()
+            .map(|duration| duration.as_secs())
+    }
+}
+
+/// A clock that only moves when it's told to, for exercising timeouts without waiting them out.
+pub struct ManualClock(AtomicU64);
+
+impl ManualClock {
+    pub fn new(now: u64) -> ManualClock {
+        ManualClock(AtomicU64::new(now))
+    }
+
+    pub fn advance(&self, by: Duration) {
+        self.0.fetch_add(by.as_secs(), Ordering::Relaxed);
+    }
+}
+
+impl Clock for ManualClock {
+    fn now(&self) -> Option<u64> {
+        Some(self.0.load(Ordering::Relaxed))
+    }
+}
+
+/// Whether a remote last active at `last_activity` has gone `idle_timeout` seconds without anything, 0 never
+/// timing out.
+pub fn is_idle(clock: &dyn Clock, last_activity: u64, idle_timeout: u64) -> Option<bool> {
+    let now = clock.now()?;
+    Some(idle_timeout != 0 && now.saturating_sub(last_activity) >= idle_timeout)
+}
@@ -0,0 +1,98 @@
+//! Runs shell commands bound to buttons in the config file's `[commands]` table.
+//!
+//! A bound button runs its command when pressed, instead of being forwarded. Commands run through `sh -c` with
+//! the same user and environment as BlueWii, so anyone who can edit the config file can run anything as that
+//! user; keep the file writable only by its owner.
+
+use std::{
+    process::Command,
+    thread,
+    time::{Duration, Instant},
+};
+
+use log::{info, warn};
+
+use crate::config::Config;
+
+pub struct CommandRunner {
+    // Wii Remote key code -> command
+    commands: Vec<(u16, String)>,
+    cooldown: Duration,
+    // When each key's command last ran
+    last_run: Vec<(u16, Instant)>,
+    // Set when a command runs, until `take_ran` is called
+    ran: bool,
+}
+
+impl CommandRunner {
+    pub fn new(config: &Config) -> CommandRunner {
+        CommandRunner {
+            commands: config
+                .commands
+                .iter()
+                .map(|(button, command)| (button.code(), command.clone()))
+                .collect(),
+            cooldown: Duration::from_millis(config.command_cooldown),
+            last_run: Vec::new(),
+            ran: false,
+        }
+    }
+
+    /// Runs the command bound to `key` when it's pressed, returning whether `key` has one so it isn't forwarded.
+    pub fn handle(&mut self, key: u16, pressed: bool) -> bool {
+        let command = match self
+            .commands
+            .iter()
+            .find(|(bound_key, _)| *bound_key == key)
+        {
+            Some((_, command)) => command,
+            None => return false,
+        };
+
+        if !pressed {
+            return true;
+        }
+
+        // Button bounce and impatient presses shouldn't launch something twice
+        match self
+            .last_run
+            .iter_mut()
+            .find(|(bound_key, _)| *bound_key == key)
+        {
+            Some((_, last_run)) if last_run.elapsed() < self.cooldown => return true,
+            Some((_, last_run)) => *last_run = Instant::now(),
+            None => self.last_run.push((key, Instant::now())),
+        }
+
+        run(command.clone());
+        self.ran = true;
+        true
+    }
+
+    /// Whether a command ran since the last call, a press during the cooldown doesn't count.
+    pub fn take_ran(&mut self) -> bool {
+        std::mem::take(&mut self.ran)
+    }
+}
+
+/// Runs `command` on its own thread, logging how it exited.
+pub fn run(command: String) {
+    run_with_env(command, Vec::new());
+}
+
+/// Runs `command` like [`run`], with `env` added to its environment.
+pub fn run_with_env(command: String, env: Vec<(&'static str, String)>) {
+    info!("Running `{}'", command);
+    thread::spawn(move || {
+        match Command::new("sh")
+            .arg("-c")
+            .arg(&command)
+            .envs(env)
+            .status()
+        {
+            Ok(status) if status.success() => info!("`{}' finished", command),
+            Ok(status) => warn!("`{}' failed: {}", command, status),
+            Err(err) => warn!("Failed to execute `{}': {}", command, err),
+        }
+    });
+}
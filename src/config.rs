@@ -0,0 +1,989 @@
+//! The optional config file, read from `$XDG_CONFIG_HOME/bluewii/config.toml` (or `~/.config/bluewii/config.toml`).
+//!
+//! `--config <PATH>` takes precedence over both, and unlike them has to exist, so each instance running on its own
+//! adapter can have its own file.
+//!
+//! ```toml
+//! # Report the D-pad as a hat switch instead of four buttons
+//! dpad_mode = "hat"
+//!
+//! # Send keystrokes instead of gamepad buttons, can be switched at runtime through the status socket
+//! output_mode = "keyboard"
+//! # Or both at once, the D-pad as arrow keys and everything else as gamepad buttons
+//! # output_mode = "split"
+//!
+//! # Point with the IR camera, and scroll instead while B is held
+//! ir_mode = "scroll"
+//! ir_scroll_modifier = "b"
+//! ir_scroll_sensitivity = 0.05
+//! # Click with the pointer's mouse buttons instead of the gamepad's, A and B click left and right unless
+//! # this is set, and the scroll modifier never clicks in the scroll mode
+//! ir_buttons = { a = "BTN_LEFT", two = "BTN_RIGHT", home = "BTN_MIDDLE" }
+//! # For a sensor bar below the screen that moves the pointer the wrong way up and down, also --ir-invert-y
+//! ir_invert_y = true
+//! # Steady the cursor, 0 follows the camera exactly and values towards 1 lag further behind it
+//! ir_smoothing = 0.5
+//! # Make slow movements finer and fast ones go further, crossing over at 8 camera units a report
+//! ir_accel = "quadratic"
+//! ir_accel_scale = 8.0
+//!
+//! # Report tilt on the virtual gamepad's motion axes, with the remote held sideways
+//! accel_orientation = "horizontal"
+//! # Mirror left and right, for a left-handed grip
+//! accel_invert_x = true
+//! # Only once `set-accel on' is sent to the status socket, to save battery outside of motion games
+//! accel_enabled = false
+//!
+//! # Steer with the D-pad by tilting the remote, held sideways unless `accel_orientation' says otherwise,
+//! # pressing a direction once it's tilted 20 degrees. `stick' moves the left stick instead, all the way at
+//! # `tilt_range' degrees.
+//! tilt_mode = "dpad"
+//! tilt_threshold = 20.0
+//! tilt_range = 45.0
+//!
+//! # Shaking the remote presses Home, at most once every 500 milliseconds
+//! shake_action = "home"
+//! shake_threshold = 200
+//! shake_cooldown = 500
+//!
+//! # Holding Home for 800 milliseconds disconnects the remote, tapping it still presses its usual button.
+//! # An output code name is held down instead, see the `long_press' module
+//! home_long_press = "disconnect"
+//! home_long_press_time = 800
+//!
+//! # A button with a command runs it instead of being forwarded, at most once a second,
+//! # see the `commands' module before using this
+//! command_cooldown = 1000
+//!
+//! # Sweep the LEDs across and back at 80 milliseconds a step when a remote connects, with a buzz,
+//! # turned off with --no-startup-sequence. Each step is a mask of the LEDs lit, the leftmost being 0b0001
+//! startup_sweep = [0b0001, 0b0010, 0b0100, 0b1000, 0b0100, 0b0010, 0b0001]
+//! startup_sweep_step = 80
+//! startup_rumble = "connect"
+//!
+//! # Press BTN_TL2/BTN_TR2 once a Classic Controller trigger is pulled three quarters of the way
+//! trigger_threshold = 0.75
+//!
+//! # Leave a drifting Nunchuk alone even while it's plugged in. It's still logged when a remote connects
+//! # with it, and --print-events still shows it being plugged in and out, but its stick and buttons aren't
+//! # read, raw passthrough included. Extensions are looked for when a remote connects, so plugging one in
+//! # later is never forwarded either way.
+//! disabled_extensions = ["nunchuk"]
+//!
+//! # Only connect these remotes, so a neighbour's is left alone. Addresses in `deny' are never
+//! # connected, even when they're also allowed. Both add to --allow and --deny.
+//! allow = ["AA:BB:CC:DD:EE:FF", "11:22:33:44:55:66"]
+//! deny = ["66:55:44:33:22:11"]
+//!
+//! # For a bluetoothctl that doesn't print `Device <address> <name>', see the `device_line' module
+//! device_line_regex = '^(?:\[\w+\] )?Gerät (?<addr>[0-9A-Fa-f:]{17}) (?<name>.*RVL.*)$'
+//!
+//! # With --battery-aware-idle, a remote below 20% battery is disconnected after a minute of idling and one
+//! # below 10% after half a minute. Only ever shortens the idle timeout, never lengthens or turns it on.
+//! battery_idle_timeouts = [[20, 60], [10, 30]]
+//!
+//! # Press the D-pad with the Nunchuk's stick instead of moving the gamepad's, once it's pushed
+//! # halfway, for games that don't read the stick
+//! [nunchuk]
+//! stick_mode = "dpad"
+//! dpad_threshold = 0.5
+//!
+//! # Point by turning the remote instead of with the IR camera, which needs a MotionPlus but no sensor bar,
+//! # see the `gyro' module. Pressing Home takes the gyroscope's resting reading again once the pointer creeps.
+//! [gyro_mouse]
+//! enabled = true
+//! sensitivity = 0.01
+//! recenter = "home"
+//!
+//! # Report the virtual gamepad as a wired Xbox 360 controller, for games that only accept ones they know,
+//! # see `uinput::DeviceIds'. Left out, it's BlueWii's own IDs.
+//! [gamepad_ids]
+//! bustype = 0x03
+//! vendor = 0x045e
+//! product = 0x028e
+//!
+//! # Play a sound through the PC's speakers when a remote connects, see the `hooks' module before using this
+//! [hooks]
+//! on_connect = "paplay /usr/share/sounds/freedesktop/stereo/device-added.oga"
+//!
+//! # Buzz to confirm these as they happen, none do unless they're listed here.
+//! # The events are `chord', `profile', `output-mode' and `command'.
+//! [feedback]
+//! chord = "tick"
+//! profile = "connect"
+//!
+//! [commands]
+//! home = "playerctl play-pause"
+//!
+//! # Button mappings picked with --profile, a missing profile falls back to `default'.
+//! # Buttons a profile leaves out keep their usual gamepad button, and mapping to a `KEY_*'
+//! # sends it from a virtual keyboard. `media-center' is built in, see `forwarder::MEDIA_CENTER'.
+//! # Profiles can also be files of their own, see the `mappings_dir' module.
+//! [profiles.retroarch]
+//! a = "BTN_EAST"
+//! b = "BTN_SOUTH"
+//! # Chords fire once every button is held, and keep their buttons from doing anything else
+//! "home+plus" = "KEY_ESC"
+//!
+//! # Keys can repeat while they're held, after `repeat_delay' milliseconds and `repeat_rate' times a second
+//! [profiles.menus]
+//! up = { output = "KEY_UP", repeat = true }
+//! down = { output = "KEY_DOWN", repeat = true }
+//! a = "KEY_ENTER"
+//!
+//! # Never disconnect player 1's remote, but let this one time out after a minute
+//! [remote."AA:BB:CC:DD:EE:FF"]
+//! idle_timeout = 0
+//!
+//! [remote."11:22:33:44:55:66"]
+//! idle_timeout = 60
+//! ```
+//!
+//! The `[slots]` table is written by BlueWii itself to remember which player each remote was:
+//!
+//! ```toml
+//! [slots]
+//! "AA:BB:CC:DD:EE:FF" = 1
+//! ```
+//!
+//! `[calibration]` is written by `--calibrate`, see [`stick`](crate::stick).
+//!
+//! When a setting can be given in more than one place, the most specific one wins:
+//! a per-remote value beats the command line, which beats the built-in default.
+
+use std::{
+    collections::HashMap,
+    env, fs,
+    io::ErrorKind,
+    path::{Path, PathBuf},
+    sync::{Mutex, PoisonError},
+};
+
+use anyhow::Context;
+use log::{debug, warn};
+use serde::{Deserialize, Serialize, Serializer};
+use toml_edit::{DocumentMut, ImDocument, Item, TableLike};
+
+use crate::{
+    accel::{AccelOrientation, TiltMode},
+    button::{Binding, Button},
+    error::{self, BlueWiiError},
+    evdev,
+    forwarder::{self, DpadMode, OutputMode},
+    ir::{IrAccel, IrMode},
+    mappings_dir,
+    rumble::Feedback,
+    stick::{AxisCalibration, Extension, StickMode},
+    uinput::DeviceIds,
+    utils::{self, FormattedUnwrap},
+    wii_remote,
+};
+
+// Held for the whole of `Config::edit`, so workers saving their slots at once don't each write back what they read
+// before the other's change
+static EDITING: Mutex<()> = Mutex::new(());
+
+#[derive(Deserialize, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    /// `"buttons"` or `"hat"`, how the D-pad shows up on the virtual gamepad.
+    pub dpad_mode: DpadMode,
+
+    /// `"gamepad"`, `"keyboard"` or `"split"`, what the buttons are sent as to begin with.
+    pub output_mode: OutputMode,
+
+    /// `"off"`, `"pointer"` or `"scroll"`, what the IR camera is used for.
+    pub ir_mode: IrMode,
+
+    /// The button that turns pointer movement into scrolling in the `"scroll"` IR mode.
+    pub ir_scroll_modifier: Button,
+
+    /// Wii Remote button -> `BTN_LEFT`, `BTN_RIGHT` or `BTN_MIDDLE`, the mouse buttons clicked while the IR
+    /// camera is on. These buttons click instead of being forwarded to the gamepad.
+    pub ir_buttons: HashMap<Button, String>,
+
+    /// Scroll wheel steps per unit of IR camera movement, the camera sees 1024 units across.
+    #[serde(serialize_with = "serialize_f32")]
+    pub ir_scroll_sensitivity: f32,
+
+    /// Whether the pointer's horizontal movement is flipped, for a sensor bar that moves it the wrong way. The
+    /// command line's `--ir-invert-x` turns this on as well.
+    pub ir_invert_x: bool,
+
+    /// Whether the pointer's vertical movement is flipped, like `ir_invert_x`. The command line's `--ir-invert-y`
+    /// turns this on as well.
+    pub ir_invert_y: bool,
+
+    /// How much of the previous IR position carries over into the next one, from 0.0 (none) to 1.0.
+    #[serde(serialize_with = "serialize_f32")]
+    pub ir_smoothing: f32,
+
+    /// `"linear"`, `"quadratic"` or `{ gamma = 1.5 }`, how pointer movement grows with the remote's speed.
+    pub ir_accel: IrAccel,
+
+    /// The movement, in IR camera units per report, that `ir_accel` leaves unchanged: slower ones shrink and
+    /// faster ones grow. Lower it for a bigger screen or when sitting further away.
+    #[serde(serialize_with = "serialize_f32")]
+    pub ir_accel_scale: f32,
+
+    /// `"horizontal"`, `"vertical"`, `"sideways-left"` or `"sideways-right"`, how the remote is held.
+    /// Tilt isn't reported unless this is set, and the accelerometer stays off unless something needs it,
+    /// since it drains the battery faster.
+    pub accel_orientation: Option<AccelOrientation>,
+
+    /// Whether to flip the reoriented x, y or z axis, for a mirrored grip. These apply after
+    /// `accel_orientation`, so they mean the same directions whichever way the remote's held.
+    pub accel_invert_x: bool,
+    pub accel_invert_y: bool,
+    pub accel_invert_z: bool,
+
+    /// Whether the accelerometer reports to begin with, when tilt or shakes need it. It can be switched at
+    /// runtime through the status socket, and leaving it off until a motion game saves battery.
+    pub accel_enabled: bool,
+
+    /// `"off"`, `"dpad"` or `"stick"`, what tilting the remote does, see [`TiltMode`]. Uses `accel_orientation`
+    /// and `accel_invert_*`, with the remote held sideways when `accel_orientation` isn't set.
+    pub tilt_mode: TiltMode,
+
+    /// Degrees the remote has to be tilted before the `dpad` tilt mode presses a direction.
+    #[serde(serialize_with = "serialize_f32")]
+    pub tilt_threshold: f32,
+
+    /// Degrees of tilt that move the `stick` tilt mode's stick all the way over.
+    #[serde(serialize_with = "serialize_f32")]
+    pub tilt_range: f32,
+
+    /// The button a shake of the remote presses, shakes are ignored unless this is set.
+    pub shake_action: Option<Button>,
+
+    /// How far, in raw accelerometer units (about 100 per g), an axis has to swing both ways to count as a shake.
+    pub shake_threshold: i32,
+
+    /// Milliseconds after a shake before another one is recognised.
+    pub shake_cooldown: u64,
+
+    /// What holding Home does, `"disconnect"` or an output code name, on top of its usual mapping for a tap. See
+    /// [`long_press`](crate::long_press).
+    pub home_long_press: Option<String>,
+
+    /// Milliseconds Home has to be held for `home_long_press`.
+    pub home_long_press_time: u64,
+
+    /// Shell commands run when a button is pressed, see [`commands`](crate::commands).
+    pub commands: HashMap<Button, String>,
+
+    /// Milliseconds after a command runs before its button can run it again.
+    pub command_cooldown: u64,
+
+    /// The LEDs lit at each step of the sweep played when a remote connects, as masks with bit 0 being the
+    /// leftmost LED.
+    pub startup_sweep: Vec<u8>,
+
+    /// Milliseconds each step of `startup_sweep` lasts.
+    pub startup_sweep_step: u64,
+
+    /// The named rumble pattern played along with the sweep, see [`rumble::named`](crate::rumble::named).
+    pub startup_rumble: Option<String>,
+
+    /// The named rumble pattern each [`Feedback`] event plays, events left out play nothing.
+    pub feedback: HashMap<Feedback, String>,
+
+    /// Milliseconds a key mapped with `repeat = true` is held before it starts repeating.
+    pub repeat_delay: u64,
+
+    /// How many times a second a held key repeats once it's started, see [`repeat`](crate::repeat).
+    pub repeat_rate: u32,
+
+    /// Named button mappings, Wii Remote button (or `+`-joined chord of buttons) -> output code name.
+    pub profiles: HashMap<String, HashMap<Binding, Target>>,
+
+    /// How far, from 0.0 to 1.0, a Classic Controller trigger has to be pulled before it also presses its button,
+    /// see [`stick`](crate::stick).
+    #[serde(serialize_with = "serialize_f32")]
+    pub trigger_threshold: f32,
+
+    /// The bus and IDs the virtual gamepad reports, see [`DeviceIds`]. Each is checked to fit in 16 bits when the
+    /// file is read.
+    pub gamepad_ids: DeviceIds,
+
+    /// How the Nunchuk's stick is reported, see [`NunchukConfig`].
+    pub nunchuk: NunchukConfig,
+
+    /// Moving the pointer with the MotionPlus, see [`GyroMouseConfig`].
+    pub gyro_mouse: GyroMouseConfig,
+
+    /// Commands run when a remote connects or disconnects, see [`hooks`](crate::hooks).
+    pub hooks: HooksConfig,
+
+    /// Extensions that are never read from while plugged in.
+    pub disabled_extensions: Vec<Extension>,
+
+    /// Stick calibrations per extension and axis, see [`stick`](crate::stick).
+    pub calibration: HashMap<Extension, HashMap<String, AxisCalibration>>,
+
+    /// The only Bluetooth addresses that are connected, on top of `--allow`. Any address is when both are empty.
+    pub allow: Vec<String>,
+
+    /// Bluetooth addresses that are never connected, on top of `--deny`. These win over `allow`.
+    pub deny: Vec<String>,
+
+    /// A regex for reading `bluetoothctl`'s device lines, for when it words them differently, see
+    /// [`device_line`](crate::device_line).
+    pub device_line_regex: Option<String>,
+
+    /// Per-remote overrides, keyed by Bluetooth address.
+    pub remote: HashMap<String, RemoteConfig>,
+
+    /// `[percent, seconds]` pairs, the idle timeout of a remote whose battery is below `percent` with
+    /// `--battery-aware-idle`, see [`Config::battery_idle_timeout`].
+    pub battery_idle_timeouts: Vec<(u8, u64)>,
+
+    /// The player slot each remote had last time, keyed by Bluetooth address.
+    pub slots: HashMap<String, u8>,
+
+    #[serde(skip)]
+    path: Option<PathBuf>,
+}
+
+impl Default for Config {
+    fn default() -> Config {
+        Config {
+            dpad_mode: DpadMode::default(),
+            output_mode: OutputMode::default(),
+            ir_mode: IrMode::default(),
+            ir_scroll_modifier: Button::B,
+            ir_buttons: HashMap::from([
+                (Button::A, "BTN_LEFT".to_owned()),
+                (Button::B, "BTN_RIGHT".to_owned()),
+            ]),
+            ir_scroll_sensitivity: 0.05,
+            ir_invert_x: false,
+            ir_invert_y: false,
+            ir_smoothing: 0.0,
+            ir_accel: IrAccel::default(),
+            ir_accel_scale: 8.0,
+            accel_orientation: None,
+            accel_invert_x: false,
+            accel_invert_y: false,
+            accel_invert_z: false,
+            accel_enabled: true,
+            tilt_mode: TiltMode::default(),
+            tilt_threshold: 20.0,
+            tilt_range: 45.0,
+            shake_action: None,
+            shake_threshold: 200,
+            shake_cooldown: 500,
+            home_long_press: None,
+            home_long_press_time: 800,
+            commands: HashMap::new(),
+            command_cooldown: 1000,
+            startup_sweep: wii_remote::SWEEP.to_vec(),
+            startup_sweep_step: 80,
+            startup_rumble: None,
+            feedback: HashMap::new(),
+            repeat_delay: 500,
+            repeat_rate: 20,
+            profiles: HashMap::new(),
+            trigger_threshold: 0.5,
+            gamepad_ids: DeviceIds::default(),
+            nunchuk: NunchukConfig::default(),
+            gyro_mouse: GyroMouseConfig::default(),
+            hooks: HooksConfig::default(),
+            disabled_extensions: Vec::new(),
+            calibration: HashMap::new(),
+            allow: Vec::new(),
+            device_line_regex: None,
+            deny: Vec::new(),
+            remote: HashMap::new(),
+            battery_idle_timeouts: vec![(20, 60)],
+            slots: HashMap::new(),
+            path: None,
+        }
+    }
+}
+
+/// Writes an `f32` the way it would be typed, `0.05` rather than the `0.05000000074505806` it widens to.
+pub fn serialize_f32<S: Serializer>(value: &f32, serializer: S) -> Result<S::Ok, S::Error> {
+    serializer.serialize_f64(value.to_string().parse().unwrap_or(*value as f64))
+}
+
+/// What a button maps to in a profile, either just the output's code name or a table that also says whether
+/// it repeats while held. Only keyboard keys mapped from a single button repeat.
+#[derive(Clone, Deserialize, Serialize)]
+#[serde(untagged)]
+pub enum Target {
+    Output(String),
+    Table {
+        output: String,
+        #[serde(default)]
+        repeat: bool,
+    },
+}
+
+impl Target {
+    pub fn output(&self) -> &str {
+        match self {
+            Target::Output(output) | Target::Table { output, .. } => output,
+        }
+    }
+
+    pub fn repeats(&self) -> bool {
+        match self {
+            Target::Output(_) => false,
+            Target::Table { repeat, .. } => *repeat,
+        }
+    }
+}
+
+/// The `[nunchuk]` table.
+#[derive(Deserialize, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct NunchukConfig {
+    /// `"analog"` or `"dpad"`, see [`StickMode`].
+    pub stick_mode: StickMode,
+
+    /// How far, from 0.0 to 1.0, the stick has to be pushed before the `dpad` stick mode presses a direction.
+    #[serde(serialize_with = "serialize_f32")]
+    pub dpad_threshold: f32,
+}
+
+impl Default for NunchukConfig {
+    fn default() -> NunchukConfig {
+        NunchukConfig {
+            stick_mode: StickMode::default(),
+            dpad_threshold: 0.5,
+        }
+    }
+}
+
+/// The `[gyro_mouse]` table, see [`gyro`](crate::gyro).
+#[derive(Deserialize, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct GyroMouseConfig {
+    pub enabled: bool,
+
+    /// Pointer pixels moved per gyroscope unit each report.
+    #[serde(serialize_with = "serialize_f32")]
+    pub sensitivity: f32,
+
+    /// The button that takes the gyroscope's resting reading again, instead of being forwarded.
+    pub recenter: Option<Button>,
+
+    /// Flip which way the pointer moves across or up and down.
+    pub invert_x: bool,
+    pub invert_y: bool,
+}
+
+impl Default for GyroMouseConfig {
+    fn default() -> GyroMouseConfig {
+        GyroMouseConfig {
+            enabled: false,
+            sensitivity: 0.01,
+            recenter: None,
+            invert_x: false,
+            invert_y: false,
+        }
+    }
+}
+
+/// The `[hooks]` table.
+#[derive(Deserialize, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct HooksConfig {
+    pub on_connect: Option<String>,
+    pub on_disconnect: Option<String>,
+
+    /// The least seconds between two runs of the same hook.
+    pub cooldown: u64,
+}
+
+impl Default for HooksConfig {
+    fn default() -> HooksConfig {
+        HooksConfig {
+            on_connect: None,
+            on_disconnect: None,
+            cooldown: 5,
+        }
+    }
+}
+
+#[derive(Default, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct RemoteConfig {
+    /// Seconds of inactivity before the remote is disconnected, `0` to never disconnect.
+    pub idle_timeout: Option<u64>,
+}
+
+impl Config {
+    pub fn load() -> Config {
+        match Config::default_path() {
+            Some(path) => Config::read(path, false),
+            None => Config::default(),
+        }
+    }
+
+    /// Loads the config file at `path`, exiting if it doesn't exist since it was asked for by name.
+    pub fn load_from(path: &Path) -> Config {
+        Config::read(path.to_owned(), true)
+    }
+
+    fn read(path: PathBuf, required: bool) -> Config {
+        let contents = match fs::read_to_string(&path) {
+            Ok(contents) => contents,
+            Err(err) if err.kind() == ErrorKind::NotFound && !required => {
+                debug!("No config file found at {}", path.display());
+                return Config {
+                    path: Some(path),
+                    ..Config::default()
+                };
+            }
+            Err(err) => Err(err)
+                .with_context(|| format!("Failed to read config file `{}'", path.display()))
+                .unwrap_or_fmt(),
+        };
+
+        debug!("Loading config file from {}", path.display());
+        let (contents, invalid) = without_invalid_mappings(&contents);
+        for entry in &invalid {
+            warn!("{}, {}, using the default for it", path.display(), entry);
+        }
+
+        let config: Config = toml::from_str(&contents)
+            .with_context(|| format!("Failed to parse config file `{}'", path.display()))
+            .unwrap_or_fmt();
+
+        Config {
+            path: Some(path),
+            ..config
+        }
+    }
+
+    /// Records `slot` for `address` in the `[slots]` table, leaving the rest of the file untouched.
+    pub fn save_slot(&self, address: &str, slot: u8) -> error::Result<()> {
+        self.edit(|document| {
+            let slots = document
+                .entry("slots")
+                .or_insert(toml_edit::table())
+                .as_table_mut()
+                .ok_or_else(|| not_a_table("slots"))?;
+            slots[address] = toml_edit::value(slot as i64);
+            Ok(())
+        })
+    }
+
+    /// Records the calibration of each of `extension`'s axes in the `[calibration.<extension>]` table.
+    pub fn save_calibration(
+        &self,
+        extension: Extension,
+        axes: &[(&str, AxisCalibration)],
+    ) -> error::Result<()> {
+        self.edit(|document| {
+            let calibration = document
+                .entry("calibration")
+                .or_insert(toml_edit::table())
+                .as_table_mut()
+                .ok_or_else(|| not_a_table("calibration"))?;
+
+            // Keep it from being written out as an empty `[calibration]` header
+            calibration.set_implicit(true);
+            let table = calibration
+                .entry(extension.name())
+                .or_insert(toml_edit::table())
+                .as_table_mut()
+                .ok_or_else(|| not_a_table(&format!("calibration.{}", extension.name())))?;
+
+            for (name, axis) in axes {
+                let mut inline = toml_edit::InlineTable::new();
+                inline.insert("center", (axis.center as i64).into());
+                inline.insert("min", (axis.min as i64).into());
+                inline.insert("max", (axis.max as i64).into());
+                inline.insert("deadzone", (axis.deadzone as i64).into());
+                table[name] = toml_edit::value(inline);
+            }
+
+            Ok(())
+        })
+    }
+
+    /// Applies `change` to the config file, leaving everything it doesn't touch as it was. The file is replaced
+    /// in one go, so a crash partway through doesn't leave it cut short.
+    fn edit(
+        &self,
+        change: impl FnOnce(&mut DocumentMut) -> error::Result<()>,
+    ) -> error::Result<()> {
+        let path = self.path.as_ref().ok_or(BlueWiiError::NoConfigPath)?;
+        let _editing = EDITING.lock().unwrap_or_else(PoisonError::into_inner);
+
+        let contents = match fs::read_to_string(path) {
+            Ok(contents) => contents,
+            Err(err) if err.kind() == ErrorKind::NotFound => String::new(),
+            Err(err) => {
+                return Err(BlueWiiError::io(
+                    "Failed to read the config file",
+                    path,
+                    err,
+                ))
+            }
+        };
+
+        let mut document =
+            contents
+                .parse::<DocumentMut>()
+                .map_err(|err| BlueWiiError::ParseFailed {
+                    what: "the config file".to_owned(),
+                    reason: err.to_string(),
+                })?;
+
+        change(&mut document)?;
+
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent).map_err(|err| {
+                BlueWiiError::io("Failed to create the config directory", parent, err)
+            })?;
+        }
+
+        utils::write_atomically(path, &document.to_string())
+            .map_err(|err| BlueWiiError::io("Failed to write the config file", path, err))
+    }
+
+    pub fn default_path() -> Option<PathBuf> {
+        let config_dir = match env::var_os("XDG_CONFIG_HOME") {
+            Some(dir) if !dir.is_empty() => PathBuf::from(dir),
+            _ => PathBuf::from(env::var_os("HOME")?).join(".config"),
+        };
+
+        Some(config_dir.join("bluewii").join("config.toml"))
+    }
+
+    /// Returns `name` if there's a profile called that, otherwise `"default"`.
+    /// The default profile doesn't have to exist, without it every button keeps its usual mapping.
+    pub fn resolve_profile<'a>(&self, name: &'a str) -> &'a str {
+        match self.profiles.contains_key(name)
+            || mappings_dir::profile(name).is_some()
+            || forwarder::is_builtin_profile(name)
+            || name == "default"
+        {
+            true => name,
+            false => {
+                warn!("No profile called `{}', using `default' instead", name);
+                "default"
+            }
+        }
+    }
+
+    /// The calibration of `extension`'s axis called `axis`, if it has been calibrated.
+    pub fn calibration(&self, extension: Extension, axis: &str) -> Option<AxisCalibration> {
+        self.calibration.get(&extension)?.get(axis).copied()
+    }
+
+    /// Returns the idle timeout for `address`, falling back to `default` when the remote has no override.
+    pub fn idle_timeout(&self, address: &str, default: u64) -> u64 {
+        self.remote
+            .iter()
+            .find(|(remote_address, _)| remote_address.eq_ignore_ascii_case(address))
+            .and_then(|(_, remote)| remote.idle_timeout)
+            .unwrap_or(default)
+    }
+
+    /// `idle_timeout` shortened for a remote at `battery_level` percent, going by the lowest threshold in
+    /// `battery_idle_timeouts` the level is below. A timeout of 0 stays 0, and so does any timeout while the level
+    /// isn't known.
+    pub fn battery_idle_timeout(&self, idle_timeout: u64, battery_level: Option<u8>) -> u64 {
+        let Some(battery_level) = battery_level else {
+            return idle_timeout;
+        };
+
+        match self
+            .battery_idle_timeouts
+            .iter()
+            .filter(|(percent, _)| battery_level < *percent)
+            .min_by_key(|(percent, _)| *percent)
+        {
+            Some((_, seconds)) if idle_timeout > 0 => idle_timeout.min(*seconds),
+            _ => idle_timeout,
+        }
+    }
+}
+
+/// Takes out every profile mapping, IR button and command whose button or output code doesn't exist, so a typo leaves just
+/// that entry at its default instead of the whole file failing to load. Returns what's left, along with a
+/// message for each entry taken out saying which line it's on.
+///
+/// Anything else that's wrong, including a file that isn't TOML at all, is left for the full parse to report.
+pub fn without_invalid_mappings(contents: &str) -> (String, Vec<String>) {
+    let document = match ImDocument::parse(contents) {
+        Ok(document) => document,
+        Err(_) => return (contents.to_owned(), Vec::new()),
+    };
+
+    let mut invalid = Vec::new();
+    if let Some(profiles) = document.get("profiles").and_then(Item::as_table_like) {
+        for (name, profile) in profiles.iter() {
+            let Some(profile) = profile.as_table_like() else {
+                continue;
+            };
+
+            invalid.extend(invalid_entries(
+                contents,
+                &["profiles", name],
+                profile,
+                |key, target| {
+                    if let Err(err) = key.parse::<Binding>() {
+                        return Some(err.to_string());
+                    }
+
+                    // A target of the wrong shape is the full parse's to report
+                    let output = target
+                        .as_str()
+                        .or_else(|| target.get("output").and_then(Item::as_str))?;
+                    match evdev::code_from_name(output) {
+                        Some(_) => None,
+                        None => Some(format!("`{}' maps to unknown code `{}'", key, output)),
+                    }
+                },
+            ));
+        }
+    }
+
+    if let Some(commands) = document.get("commands").and_then(Item::as_table_like) {
+        invalid.extend(invalid_entries(
+            contents,
+            &["commands"],
+            commands,
+            |key, _| key.parse::<Button>().err().map(|err| err.to_string()),
+        ));
+    }
+
+    if let Some(ir_buttons) = document.get("ir_buttons").and_then(Item::as_table_like) {
+        invalid.extend(invalid_entries(
+            contents,
+            &["ir_buttons"],
+            ir_buttons,
+            |key, output| {
+                if let Err(err) = key.parse::<Button>() {
+                    return Some(err.to_string());
+                }
+
+                let output = output.as_str()?;
+                match evdev::code_from_name(output) {
+                    Some(_) => None,
+                    None => Some(format!("`{}' maps to unknown code `{}'", key, output)),
+                }
+            },
+        ));
+    }
+
+    if invalid.is_empty() {
+        return (contents.to_owned(), Vec::new());
+    }
+
+    let mut document = document.into_mut();
+    let messages = invalid
+        .into_iter()
+        .map(|entry| {
+            let mut table = document.as_table_mut() as &mut dyn TableLike;
+            for part in &entry.table {
+                table = table
+                    .get_mut(part)
+                    .and_then(Item::as_table_like_mut)
+                    .expect("the table was there when the entry was found");
+            }
+            table.remove(&entry.key);
+
+            match entry.line {
+                Some(line) => format!(
+                    "line {} in `{}': {}",
+                    line,
+                    entry.table.join("."),
+                    entry.reason
+                ),
+                None => format!("`{}': {}", entry.table.join("."), entry.reason),
+            }
+        })
+        .collect();
+
+    (document.to_string(), messages)
+}
+
+/// An entry [`without_invalid_mappings`] takes out.
+struct InvalidEntry {
+    table: Vec<String>,
+    key: String,
+    line: Option<usize>,
+    reason: String,
+}
+
+/// The entries of `entries`, the table at `table`, that `reason` finds something wrong with.
+fn invalid_entries(
+    contents: &str,
+    table: &[&str],
+    entries: &dyn TableLike,
+    reason: impl Fn(&str, &Item) -> Option<String>,
+) -> Vec<InvalidEntry> {
+    entries
+        .iter()
+        .filter_map(|(key, item)| {
+            let reason = reason(key, item)?;
+            let line = entries
+                .get_key_value(key)
+                .and_then(|(key, _)| key.span())
+                .map(|span| contents[..span.start].matches('\n').count() + 1);
+
+            Some(InvalidEntry {
+                table: table.iter().map(|part| part.to_string()).collect(),
+                key: key.to_owned(),
+                line,
+                reason,
+            })
+        })
+        .collect()
+}
+
+fn not_a_table(key: &str) -> BlueWiiError {
+    BlueWiiError::ParseFailed {
+        what: "the config file".to_owned(),
+        reason: format!("`{}' is not a table", key),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn with_battery_idle_timeouts(battery_idle_timeouts: Vec<(u8, u64)>) -> Config {
+        Config {
+            battery_idle_timeouts,
+            ..Config::default()
+        }
+    }
+
+    #[test]
+    fn slots_saved_at_once_are_all_kept() {
+        let path = env::temp_dir().join(format!("bluewii-{}-slots.toml", std::process::id()));
+        fs::write(&path, "# Hand-written\nrepeat_delay = 300\n").unwrap();
+        let config = Config {
+            path: Some(path.clone()),
+            ..Config::default()
+        };
+
+        std::thread::scope(|scope| {
+            for slot in 1..=4 {
+                let config = &config;
+                scope.spawn(move || {
+                    let address = format!("00:19:1D:00:00:0{}", slot);
+                    config.save_slot(&address, slot).unwrap();
+                });
+            }
+        });
+
+        let contents = fs::read_to_string(&path).unwrap();
+        assert!(contents.starts_with("# Hand-written\nrepeat_delay = 300\n"));
+        let saved: Config = toml::from_str(&contents).unwrap();
+        for slot in 1..=4 {
+            assert_eq!(saved.slots[&format!("00:19:1D:00:00:0{}", slot)], slot);
+        }
+
+        let _ = fs::remove_file(path);
+    }
+
+    #[test]
+    fn battery_idle_timeout_unknown_level() {
+        let config = with_battery_idle_timeouts(vec![(20, 60)]);
+        assert_eq!(config.battery_idle_timeout(300, None), 300);
+    }
+
+    #[test]
+    fn battery_idle_timeout_below_and_at_the_threshold() {
+        let config = with_battery_idle_timeouts(vec![(20, 60)]);
+        assert_eq!(config.battery_idle_timeout(300, Some(19)), 60);
+        assert_eq!(config.battery_idle_timeout(300, Some(20)), 300);
+        assert_eq!(config.battery_idle_timeout(300, Some(100)), 300);
+    }
+
+    #[test]
+    fn battery_idle_timeout_picks_the_lowest_matching_threshold() {
+        // Listed in either order
+        for thresholds in [vec![(20, 60), (10, 30)], vec![(10, 30), (20, 60)]] {
+            let config = with_battery_idle_timeouts(thresholds);
+            assert_eq!(config.battery_idle_timeout(300, Some(15)), 60);
+            assert_eq!(config.battery_idle_timeout(300, Some(5)), 30);
+        }
+    }
+
+    #[test]
+    fn battery_idle_timeout_of_zero_stays_zero() {
+        let config = with_battery_idle_timeouts(vec![(20, 60)]);
+        assert_eq!(config.battery_idle_timeout(0, Some(5)), 0);
+    }
+
+    #[test]
+    fn battery_idle_timeout_never_lengthens() {
+        let config = with_battery_idle_timeouts(vec![(20, 600)]);
+        assert_eq!(config.battery_idle_timeout(300, Some(5)), 300);
+    }
+
+    #[test]
+    fn startup_sweep_from_binary_masks() {
+        let config: Config = toml::from_str("startup_sweep = [0b0011, 0b1100]\n").unwrap();
+        assert_eq!(config.startup_sweep, [0b0011, 0b1100]);
+        assert_eq!(Config::default().startup_sweep, wii_remote::SWEEP);
+    }
+
+    #[test]
+    fn invalid_mappings_are_taken_out() {
+        let contents = r#"idle_timeout = 60
+
+[profiles.default]
+a = "BTN_SOUTH"
+bb = "BTN_EAST"
+one = "KEY_NOPE"
+"home+plus" = "KEY_ESC"
+
+[commands]
+minus = "true"
+menu = "true"
+
+[ir_buttons]
+b = "BTN_LEFT"
+a = "BTN_TYPO"
+"#;
+        let (kept, invalid) = without_invalid_mappings(contents);
+        assert_eq!(invalid.len(), 4, "{:?}", invalid);
+        assert!(invalid[0].starts_with("line 5 in `profiles.default'"));
+        assert!(invalid[1].starts_with("line 6 in `profiles.default'"));
+        assert!(invalid[1].contains("KEY_NOPE"));
+        assert!(invalid[2].starts_with("line 11 in `commands'"));
+        assert!(invalid[3].starts_with("line 15 in `ir_buttons'"));
+
+        let document: toml::Table = toml::from_str(&kept).unwrap();
+        let profile = document["profiles"]["default"].as_table().unwrap();
+        assert_eq!(
+            profile.keys().collect::<Vec<_>>(),
+            ["a", "home+plus"],
+            "{}",
+            kept
+        );
+        assert!(document["commands"].get("minus").is_some());
+        assert!(document["commands"].get("menu").is_none());
+        assert!(document["ir_buttons"].get("b").is_some());
+        assert!(document["ir_buttons"].get("a").is_none());
+        assert_eq!(document["idle_timeout"].as_integer(), Some(60));
+    }
+
+    #[test]
+    fn valid_mappings_are_left_alone() {
+        let contents = "[profiles.menus]\nup = { output = \"KEY_UP\", repeat = true }\n";
+        assert_eq!(
+            without_invalid_mappings(contents),
+            (contents.to_owned(), Vec::new())
+        );
+    }
+
+    #[test]
+    fn not_toml_is_left_for_the_full_parse() {
+        assert_eq!(
+            without_invalid_mappings("this isn't = = toml"),
+            ("this isn't = = toml".to_owned(), Vec::new())
+        );
+    }
+}
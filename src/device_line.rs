@@ -0,0 +1,68 @@
+//! Picking a Wii Remote's address and name out of the lines `bluetoothctl devices` and `bluetoothctl scan on`
+//! print, like `[NEW] Device AA:BB:CC:DD:EE:FF Nintendo RVL-CNT-01`.
+//!
+//! By default a line is a Wii Remote's when it mentions `RVL`, with the first token that looks like an address
+//! and everything after it as the name. A `bluetoothctl` that words its lines differently, such as a localized
+//! one, can be matched with `device_line_regex` in the config instead, or `BLUEWII_DEVICE_LINE_REGEX` in the
+//! environment, which wins over the config. Its `addr` group is the address and its `name` group, if it has one,
+//! is the name. Lines it doesn't match aren't Wii Remotes:
+//!
+//! ```toml
+//! device_line_regex = '^(?:\[\w+\] )?Gerät (?<addr>[0-9A-Fa-f:]{17}) (?<name>.*RVL.*)$'
+//! ```
+
+use regex::Regex;
+
+use crate::wii_remote::WiiRemote;
+
+/// Reads a line with the built-in rules or with a regex, see the [module documentation](self).
+#[derive(Clone, Debug, Default)]
+pub struct DeviceLineParser {
+    regex: Option<Regex>,
+}
+
+impl DeviceLineParser {
+    /// Matches lines with `pattern`, which needs an `addr` group.
+    pub fn new(pattern: &str) -> Result<DeviceLineParser, String> {
+        let regex = Regex::new(pattern).map_err(|err| err.to_string())?;
+        if !regex.capture_names().flatten().any(|name| name == "addr") {
+            return Err("it has no `addr' group".to_owned());
+        }
+
+        Ok(DeviceLineParser { regex: Some(regex) })
+    }
+
+    /// Whether `line` is about a Wii Remote, even when it has no address a remote could be connected by.
+    pub fn matches(&self, line: &str) -> bool {
+        match &self.regex {
+            Some(regex) => regex.is_match(line),
+            None => line.contains("RVL"),
+        }
+    }
+
+    /// The address, uppercased so addresses always compare equal, and the name of the Wii Remote `line` is
+    /// about, `None` if it's about something else or has no valid address.
+    pub fn parse(&self, line: &str) -> Option<(String, String)> {
+        let (address, name) = match &self.regex {
+            Some(regex) => {
+                let captures = regex.captures(line)?;
+                (
+                    captures.name("addr")?.as_str().trim(),
+                    captures
+                        .name("name")
+                        .map_or(String::new(), |name| name.as_str().trim().to_owned()),
+                )
+            }
+            None if line.contains("RVL") => {
+                let mut tokens = line
+                    .split_whitespace()
+                    .skip_while(|token| !WiiRemote::is_valid_address(token));
+                let address = tokens.next()?;
+                (address, tokens.collect::<Vec<_>>().join(" "))
+            }
+            None => return None,
+        };
+
+        WiiRemote::is_valid_address(address).then(|| (address.to_uppercase(), name))
+    }
+}
@@ -0,0 +1,108 @@
+//! What can go wrong in the library, as kinds a caller can match on instead of strings.
+//!
+//! The binary turns these into messages through `anyhow` like everything else, but something embedding
+//! BlueWii can tell, say, a remote that isn't there from one it isn't allowed to open and retry accordingly.
+//!
+//! Methods that don't return a [`Result`], like [`WiiRemote::scan`](crate::wii_remote::WiiRemote::scan), still
+//! exit the process when `bluetoothctl` or `xwiishow` can't be started, since there's nothing else they could do.
+
+use std::{error::Error, fmt, io, path::PathBuf, time::Duration};
+
+use crate::permissions;
+
+pub type Result<T> = std::result::Result<T, BlueWiiError>;
+
+#[derive(Debug)]
+pub enum BlueWiiError {
+    /// An external program (`bluetoothctl`, `xwiishow`, ...) couldn't be started.
+    SpawnFailed { program: String, source: io::Error },
+    /// Something BlueWii reads, like a config file or a tool's output, wasn't in the expected shape.
+    ParseFailed { what: String, reason: String },
+    /// The remote, or the part of it that was asked for, isn't there.
+    DeviceNotFound(String),
+    /// A device or file exists, but BlueWii isn't allowed to open it, see [`permissions::HELP`].
+    PermissionDenied { path: PathBuf, source: io::Error },
+    /// `bluetoothctl connect` finished but the remote didn't connect, `reason` is the last thing it printed.
+    ConnectFailed { address: String, reason: String },
+    /// A command like `bluetoothctl power on` ran but said it failed, `reason` is the last thing it printed.
+    CommandFailed { command: String, reason: String },
+    /// There's no config file to change, since neither `$XDG_CONFIG_HOME` nor `$HOME` is set.
+    NoConfigPath,
+    /// Something didn't finish in time and was given up on.
+    Timeout { what: String, after: Duration },
+    /// Any other I/O failure, with what was being done at the time.
+    Io { what: String, source: io::Error },
+}
+
+impl BlueWiiError {
+    /// Wraps an I/O error from working on `path`, telling a lack of permissions apart from everything else.
+    pub fn io(
+        what: impl Into<String>,
+        path: impl Into<PathBuf>,
+        source: io::Error,
+    ) -> BlueWiiError {
+        match permissions::is_permission_error(&source) {
+            true => BlueWiiError::PermissionDenied {
+                path: path.into(),
+                source,
+            },
+            false => BlueWiiError::Io {
+                what: what.into(),
+                source,
+            },
+        }
+    }
+
+    /// Wraps an I/O error from running `program`, like `bluetoothctl remove`.
+    pub fn spawn_failed(program: impl Into<String>, source: io::Error) -> BlueWiiError {
+        BlueWiiError::SpawnFailed {
+            program: program.into(),
+            source,
+        }
+    }
+
+    pub fn not_connected() -> BlueWiiError {
+        BlueWiiError::DeviceNotFound("Wii Remote is not connected".to_owned())
+    }
+}
+
+impl fmt::Display for BlueWiiError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            BlueWiiError::SpawnFailed { program, source } => {
+                write!(f, "Failed to execute `{}': {}", program, source)
+            }
+            BlueWiiError::ParseFailed { what, reason } => {
+                write!(f, "Failed to parse {}: {}", what, reason)
+            }
+            BlueWiiError::DeviceNotFound(what) => write!(f, "{}", what),
+            BlueWiiError::PermissionDenied { path, .. } => {
+                write!(f, "Permission denied opening {}", path.display())
+            }
+            BlueWiiError::ConnectFailed { address, reason } => {
+                write!(f, "Failed to connect to {}: {}", address, reason)
+            }
+            BlueWiiError::CommandFailed { command, reason } => {
+                write!(f, "`{}' failed: {}", command, reason)
+            }
+            BlueWiiError::NoConfigPath => {
+                write!(f, "Could not determine the config file location")
+            }
+            BlueWiiError::Timeout { what, after } => {
+                write!(f, "{} timed out after {} seconds", what, after.as_secs())
+            }
+            BlueWiiError::Io { what, source } => write!(f, "{}: {}", what, source),
+        }
+    }
+}
+
+impl Error for BlueWiiError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            BlueWiiError::SpawnFailed { source, .. }
+            | BlueWiiError::PermissionDenied { source, .. }
+            | BlueWiiError::Io { source, .. } => Some(source),
+            _ => None,
+        }
+    }
+}
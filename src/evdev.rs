@@ -0,0 +1,132 @@
+//! The subset of `linux/input-event-codes.h` that BlueWii reads from the Wii Remote or writes to uinput.
+
+pub const EV_SYN: u16 = 0x00;
+pub const EV_KEY: u16 = 0x01;
+pub const EV_REL: u16 = 0x02;
+pub const EV_ABS: u16 = 0x03;
+pub const EV_FF: u16 = 0x15;
+
+pub const SYN_REPORT: u16 = 0x00;
+
+// Keyboard keys used by the built-in profiles
+pub const KEY_ESC: u16 = 1;
+pub const KEY_1: u16 = 2;
+pub const KEY_2: u16 = 3;
+pub const KEY_ENTER: u16 = 28;
+pub const KEY_HOME: u16 = 102;
+pub const KEY_VOLUMEDOWN: u16 = 114;
+pub const KEY_VOLUMEUP: u16 = 115;
+
+// Keys reported by hid-wiimote for the Wii Remote itself
+pub const KEY_UP: u16 = 103;
+pub const KEY_LEFT: u16 = 105;
+pub const KEY_RIGHT: u16 = 106;
+pub const KEY_DOWN: u16 = 108;
+pub const KEY_NEXT: u16 = 407;
+pub const KEY_PREVIOUS: u16 = 412;
+pub const BTN_1: u16 = 0x101;
+pub const BTN_2: u16 = 0x102;
+pub const BTN_A: u16 = 0x130;
+pub const BTN_B: u16 = 0x131;
+pub const BTN_MODE: u16 = 0x13c;
+
+// Gamepad buttons used by the virtual gamepad
+pub const BTN_SOUTH: u16 = 0x130;
+pub const BTN_EAST: u16 = 0x131;
+pub const BTN_NORTH: u16 = 0x133;
+pub const BTN_WEST: u16 = 0x134;
+pub const BTN_SELECT: u16 = 0x13a;
+pub const BTN_START: u16 = 0x13b;
+pub const BTN_DPAD_UP: u16 = 0x220;
+pub const BTN_DPAD_DOWN: u16 = 0x221;
+pub const BTN_DPAD_LEFT: u16 = 0x222;
+pub const BTN_DPAD_RIGHT: u16 = 0x223;
+pub const BTN_TL2: u16 = 0x138;
+pub const BTN_TR2: u16 = 0x139;
+pub const BTN_TRIGGER_HAPPY1: u16 = 0x2c0;
+
+// Mouse buttons, clicked by the IR pointer
+pub const BTN_LEFT: u16 = 0x110;
+pub const BTN_RIGHT: u16 = 0x111;
+pub const BTN_MIDDLE: u16 = 0x112;
+
+pub const REL_X: u16 = 0x00;
+pub const REL_Y: u16 = 0x01;
+pub const REL_WHEEL: u16 = 0x08;
+
+pub const ABS_X: u16 = 0x00;
+pub const ABS_Y: u16 = 0x01;
+pub const ABS_Z: u16 = 0x02;
+
+// Reported by hid-wiimote's accelerometer interface, and by the virtual gamepad's motion axes
+pub const ABS_RX: u16 = 0x03;
+pub const ABS_RY: u16 = 0x04;
+pub const ABS_RZ: u16 = 0x05;
+
+// Reported by hid-wiimote's IR and extension interfaces
+pub const ABS_HAT0X: u16 = 0x10;
+pub const ABS_HAT0Y: u16 = 0x11;
+pub const ABS_HAT1X: u16 = 0x12;
+pub const ABS_HAT1Y: u16 = 0x13;
+pub const ABS_HAT2X: u16 = 0x14;
+pub const ABS_HAT2Y: u16 = 0x15;
+pub const ABS_HAT3X: u16 = 0x16;
+pub const ABS_HAT3Y: u16 = 0x17;
+
+// The Classic Controller's shoulder buttons, the clicks at the end of its triggers' travel
+pub const BTN_TL: u16 = 0x136;
+pub const BTN_TR: u16 = 0x137;
+
+pub const FF_RUMBLE: u16 = 0x50;
+
+pub const BUS_VIRTUAL: u16 = 0x06;
+
+/// Names accepted for output codes in config files, matched case-insensitively.
+const NAMES: [(&str, u16); 25] = [
+    ("KEY_ESC", KEY_ESC),
+    ("KEY_1", KEY_1),
+    ("KEY_2", KEY_2),
+    ("KEY_ENTER", KEY_ENTER),
+    ("KEY_HOME", KEY_HOME),
+    ("KEY_VOLUMEDOWN", KEY_VOLUMEDOWN),
+    ("KEY_VOLUMEUP", KEY_VOLUMEUP),
+    ("KEY_UP", KEY_UP),
+    ("KEY_LEFT", KEY_LEFT),
+    ("KEY_RIGHT", KEY_RIGHT),
+    ("KEY_DOWN", KEY_DOWN),
+    ("BTN_SOUTH", BTN_SOUTH),
+    ("BTN_EAST", BTN_EAST),
+    ("BTN_NORTH", BTN_NORTH),
+    ("BTN_WEST", BTN_WEST),
+    ("BTN_SELECT", BTN_SELECT),
+    ("BTN_START", BTN_START),
+    ("BTN_MODE", BTN_MODE),
+    ("BTN_DPAD_UP", BTN_DPAD_UP),
+    ("BTN_DPAD_DOWN", BTN_DPAD_DOWN),
+    ("BTN_DPAD_LEFT", BTN_DPAD_LEFT),
+    ("BTN_DPAD_RIGHT", BTN_DPAD_RIGHT),
+    ("BTN_LEFT", BTN_LEFT),
+    ("BTN_RIGHT", BTN_RIGHT),
+    ("BTN_MIDDLE", BTN_MIDDLE),
+];
+
+/// Looks up an output code by its `input-event-codes.h` name, like `"BTN_SOUTH"`.
+pub fn code_from_name(name: &str) -> Option<u16> {
+    NAMES
+        .iter()
+        .find(|(code_name, _)| code_name.eq_ignore_ascii_case(name))
+        .map(|(_, code)| *code)
+}
+
+/// The `input-event-codes.h` name of an output code, the first one listed when codes share a value.
+pub fn name_from_code(code: u16) -> Option<&'static str> {
+    NAMES
+        .iter()
+        .find(|(_, named_code)| *named_code == code)
+        .map(|(name, _)| *name)
+}
+
+/// Whether `code` is a keyboard key rather than a button, `BTN_MISC` being where the buttons start.
+pub fn is_keyboard_key(code: u16) -> bool {
+    code < 0x100
+}
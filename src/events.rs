@@ -0,0 +1,649 @@
+//! A typed view of a remote's input, for building on BlueWii as a library without going through evdev or libinput.
+//!
+//! See [`WiiRemote::poll_events`](crate::wii_remote::WiiRemote::poll_events), or
+//! [`WiiRemote::read_state`](crate::wii_remote::WiiRemote::read_state) for a [`WiiState`] with all of it at once.
+//!
+//! `--print-events` writes them to stdout with [`print_lines`], after a `# bluewii-events <version>` header:
+//!
+//! ```text
+//! # bluewii-events 1
+//! BTN_A down
+//! BTN_A up
+//! ACCEL 12 -3 98
+//! IR 0 512 384
+//! ```
+//!
+//! Buttons go by their `input-event-codes.h` name, accelerometer readings are raw `x y z`, and every visible IR
+//! dot gets its own `IR <index> <x> <y>` line. An extension being plugged in mid-session is `EXTENSION nunchuk`
+//! or `EXTENSION classic`, and pulling it out again `EXTENSION none`. [`PRINT_FORMAT_VERSION`] goes up whenever an existing line changes
+//! shape, new kinds of lines can appear without it.
+//!
+//! With `--event-mode snapshot`, the `down` and `up` lines give way to a `HELD` line every `--snapshot-interval`,
+//! listing every button held at the time, like `HELD BTN_A BTN_B`, or just `HELD` for none. [`HeldButtons`] keeps
+//! track of them for library users who'd rather poll as well.
+//!
+//! `--record <FILE>` writes the same lines through a [`Recorder`], with a `TIME <milliseconds>` line before each
+//! batch saying how long after recording started it arrived. `--replay <FILE>` reads either back with
+//! [`parse_capture`] and plays it at the recorded pace, all at once when there are no `TIME` lines.
+
+use std::{
+    fs::File,
+    io::{self, BufWriter, Write},
+    path::Path,
+    time::{Duration, Instant},
+};
+
+use libc::input_event;
+use log::warn;
+
+use crate::{
+    button::Button,
+    error::{self, BlueWiiError},
+    evdev::{ABS_HAT0X, ABS_HAT3Y, ABS_RX, ABS_RZ, EV_ABS, EV_KEY, EV_SYN, SYN_REPORT},
+    ir::{IrPoint, INVALID_DOT},
+    stick::{self, Extension},
+    xwiimote::{self, Evdev, InterfaceMonitor},
+};
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum WiiEvent {
+    ButtonPress(Button),
+    ButtonRelease(Button),
+    /// Raw accelerometer reading, about 100 units per g on each axis.
+    Accel(i32, i32, i32),
+    /// All four points the IR camera tracks, in the order of its slots, whether it can see them or not. For
+    /// working out a pointer of its own, [`dot_centroid`](crate::ir::dot_centroid) is the one BlueWii's follows.
+    Ir(Vec<IrPoint>),
+    /// An extension was plugged in after the remote connected.
+    ExtensionAttached(Extension),
+    /// The extension was pulled out.
+    ExtensionDetached,
+}
+
+pub const PRINT_FORMAT_VERSION: u32 = 1;
+
+/// How `--print-events` reports buttons.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum EventMode {
+    /// A line each time a button is pressed or released.
+    #[default]
+    Edge,
+    /// Every held button at once, over and over.
+    Snapshot,
+}
+
+/// The buttons held on a remote, kept up to date from its events.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct HeldButtons {
+    held: Vec<Button>,
+}
+
+impl HeldButtons {
+    pub fn new() -> HeldButtons {
+        HeldButtons::default()
+    }
+
+    pub fn update(&mut self, event: &WiiEvent) {
+        match event {
+            WiiEvent::ButtonPress(button) if !self.held.contains(button) => {
+                self.held.push(*button);
+                self.held.sort_by_key(|button| button.code());
+            }
+            WiiEvent::ButtonRelease(button) => self.held.retain(|held| held != button),
+            _ => {}
+        }
+    }
+
+    /// Every held button, in order of their codes.
+    pub fn held(&self) -> &[Button] {
+        &self.held
+    }
+
+    /// The `HELD` line for `--event-mode snapshot`.
+    pub fn print_line(&self) -> String {
+        std::iter::once("HELD")
+            .chain(self.held.iter().map(|button| button.code_name()))
+            .collect::<Vec<_>>()
+            .join(" ")
+    }
+}
+
+/// Everything about a remote at one moment, see [`WiiRemote::read_state`](crate::wii_remote::WiiRemote::read_state).
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct WiiState {
+    pub buttons: HeldButtons,
+    /// The latest raw accelerometer reading, `None` until the first one arrives.
+    pub accel: Option<(i32, i32, i32)>,
+    /// The IR camera's four points from its latest frame, empty until the first one arrives.
+    pub ir: Vec<IrPoint>,
+    /// The battery percentage hid-wiimote last reported.
+    pub battery: Option<u8>,
+    pub extension: Option<Extension>,
+}
+
+impl WiiState {
+    /// Folds `event` into the state, everything but the battery being kept up to date this way.
+    pub fn update(&mut self, event: &WiiEvent) {
+        match event {
+            WiiEvent::ButtonPress(_) | WiiEvent::ButtonRelease(_) => self.buttons.update(event),
+            WiiEvent::Accel(x, y, z) => self.accel = Some((*x, *y, *z)),
+            WiiEvent::Ir(points) => self.ir = points.clone(),
+            WiiEvent::ExtensionAttached(extension) => self.extension = Some(*extension),
+            WiiEvent::ExtensionDetached => self.extension = None,
+        }
+    }
+}
+
+/// The `--print-events` lines for `event`, none for an IR frame without a single visible dot.
+pub fn print_lines(event: &WiiEvent) -> Vec<String> {
+    match event {
+        WiiEvent::ButtonPress(button) => vec![format!("{} down", button.code_name())],
+        WiiEvent::ButtonRelease(button) => vec![format!("{} up", button.code_name())],
+        WiiEvent::Accel(x, y, z) => vec![format!("ACCEL {} {} {}", x, y, z)],
+        WiiEvent::Ir(points) => points
+            .iter()
+            .enumerate()
+            .filter_map(|(index, point)| {
+                point
+                    .position()
+                    .map(|(x, y)| format!("IR {} {} {}", index, x, y))
+            })
+            .collect(),
+        WiiEvent::ExtensionAttached(extension) => vec![format!("EXTENSION {}", extension.name())],
+        WiiEvent::ExtensionDetached => vec!["EXTENSION none".to_owned()],
+    }
+}
+
+/// Reads a capture written by `--print-events` or `--record` back into its events, each with how long after the
+/// start it happened. Lines of a kind this version doesn't know are skipped.
+pub fn parse_capture(capture: &str) -> error::Result<Vec<(Duration, WiiEvent)>> {
+    let malformed = |number: usize, reason: &str| BlueWiiError::ParseFailed {
+        what: format!("line {} of the capture", number + 1),
+        reason: reason.to_owned(),
+    };
+
+    let mut lines = capture
+        .lines()
+        .enumerate()
+        .filter(|(_, line)| !line.trim().is_empty());
+    match lines.next().map(|(_, line)| line.trim()) {
+        Some(header) if header == format!("# bluewii-events {}", PRINT_FORMAT_VERSION) => {}
+        Some(header) if header.starts_with("# bluewii-events ") => {
+            return Err(malformed(
+                0,
+                &format!("expected version {}", PRINT_FORMAT_VERSION),
+            ))
+        }
+        _ => return Err(malformed(0, "expected a `# bluewii-events' header")),
+    }
+
+    let mut events = Vec::new();
+    let mut offset = Duration::ZERO;
+    // IR lines of the same frame come one after another, in order of their dot's index
+    let mut frame: Option<Vec<IrPoint>> = None;
+    let mut last_index = 0;
+    for (number, line) in lines {
+        let tokens = line.split_whitespace().collect::<Vec<_>>();
+        let numbers = tokens[1..]
+            .iter()
+            .map(|token| token.parse::<i64>())
+            .collect::<Result<Vec<_>, _>>();
+
+        if let Some(points) = &frame {
+            let continues = match (tokens[0], &numbers) {
+                ("IR", Ok(numbers)) => numbers
+                    .first()
+                    .is_some_and(|index| *index > last_index as i64),
+                _ => false,
+            };
+
+            if !continues {
+                events.push((offset, WiiEvent::Ir(points.clone())));
+                frame = None;
+            }
+        }
+
+        match (tokens.as_slice(), numbers) {
+            (["#", ..], _) => {}
+            (["TIME", _], Ok(numbers)) => offset = Duration::from_millis(numbers[0].max(0) as u64),
+            (["ACCEL", _, _, _], Ok(numbers)) => events.push((
+                offset,
+                WiiEvent::Accel(numbers[0] as i32, numbers[1] as i32, numbers[2] as i32),
+            )),
+            (["IR", _, _, _], Ok(numbers)) => {
+                let index = match usize::try_from(numbers[0]) {
+                    Ok(index) if index < 4 => index,
+                    _ => return Err(malformed(number, "IR dot index out of range")),
+                };
+
+                frame.get_or_insert_with(|| vec![IrPoint::invalid(); 4])[index] =
+                    IrPoint::new(numbers[1] as i32, numbers[2] as i32);
+                last_index = index;
+            }
+            (["EXTENSION", "none"], _) => events.push((offset, WiiEvent::ExtensionDetached)),
+            (["EXTENSION", name], _) => {
+                let extension = Extension::ALL
+                    .into_iter()
+                    .find(|extension| extension.name() == *name)
+                    .ok_or_else(|| malformed(number, "unknown extension"))?;
+                events.push((offset, WiiEvent::ExtensionAttached(extension)));
+            }
+            (["TIME" | "ACCEL" | "IR" | "EXTENSION", ..], _) => {
+                return Err(malformed(number, "wrong number of values"))
+            }
+            ([code_name, state @ ("down" | "up")], _) => {
+                let button = Button::from_code_name(code_name)
+                    .ok_or_else(|| malformed(number, "unknown button"))?;
+                events.push((
+                    offset,
+                    match *state {
+                        "down" => WiiEvent::ButtonPress(button),
+                        _ => WiiEvent::ButtonRelease(button),
+                    },
+                ));
+            }
+            _ => {}
+        }
+    }
+
+    if let Some(points) = frame {
+        events.push((offset, WiiEvent::Ir(points)));
+    }
+
+    Ok(events)
+}
+
+/// The raw accelerometer or IR interface events that decode to `event`, none for buttons and extensions.
+pub fn raw_events(event: &WiiEvent) -> Vec<input_event> {
+    let raw = |type_, code, value| input_event {
+        time: libc::timeval {
+            tv_sec: 0,
+            tv_usec: 0,
+        },
+        type_,
+        code,
+        value,
+    };
+
+    let mut events = match event {
+        WiiEvent::ButtonPress(_)
+        | WiiEvent::ButtonRelease(_)
+        | WiiEvent::ExtensionAttached(_)
+        | WiiEvent::ExtensionDetached => return Vec::new(),
+        WiiEvent::Accel(x, y, z) => [*x, *y, *z]
+            .into_iter()
+            .zip(ABS_RX..=ABS_RZ)
+            .map(|(value, code)| raw(EV_ABS, code, value))
+            .collect::<Vec<_>>(),
+        WiiEvent::Ir(points) => points
+            .iter()
+            .flat_map(|point| [point.x, point.y])
+            .zip(ABS_HAT0X..=ABS_HAT3Y)
+            .map(|(value, code)| raw(EV_ABS, code, value))
+            .collect(),
+    };
+
+    events.push(raw(EV_SYN, SYN_REPORT, 0));
+    events
+}
+
+/// Writes events to a capture file as they arrive, for `--record`.
+pub struct Recorder {
+    file: BufWriter<File>,
+    started: Instant,
+    // The last `TIME` written, so events arriving in the same millisecond share one
+    last_offset: Option<u128>,
+}
+
+impl Recorder {
+    pub fn create(path: &Path) -> io::Result<Recorder> {
+        let mut file = BufWriter::new(File::create(path)?);
+        writeln!(file, "# bluewii-events {}", PRINT_FORMAT_VERSION)?;
+        file.flush()?;
+        Ok(Recorder {
+            file,
+            started: Instant::now(),
+            last_offset: None,
+        })
+    }
+
+    /// Writes `events` as having arrived just now.
+    pub fn write(&mut self, events: &[WiiEvent]) -> io::Result<()> {
+        if events.is_empty() {
+            return Ok(());
+        }
+
+        let offset = self.started.elapsed().as_millis();
+        if self.last_offset != Some(offset) {
+            writeln!(self.file, "TIME {}", offset)?;
+            self.last_offset = Some(offset);
+        }
+
+        for line in events.iter().flat_map(print_lines) {
+            writeln!(self.file, "{}", line)?;
+        }
+
+        // Flushed every time, so a capture is complete even when BlueWii is killed to stop recording
+        self.file.flush()
+    }
+}
+
+/// Turns raw events into [`WiiEvent`]s, holding on to axis values until the report they belong to is complete.
+pub struct Decoder {
+    accel: [i32; 3],
+    dots: [(i32, i32); 4],
+}
+
+impl Decoder {
+    pub fn new() -> Decoder {
+        Decoder {
+            accel: [0; 3],
+            dots: [(INVALID_DOT, INVALID_DOT); 4],
+        }
+    }
+
+    /// Decodes an event from the core interface, key repeats included in neither presses nor releases.
+    pub fn core(&mut self, event: &input_event) -> Option<WiiEvent> {
+        match (event.type_, event.value) {
+            (EV_KEY, 1) => Button::from_code(event.code).map(WiiEvent::ButtonPress),
+            (EV_KEY, 0) => Button::from_code(event.code).map(WiiEvent::ButtonRelease),
+            _ => None,
+        }
+    }
+
+    /// Decodes an event from the accelerometer interface, a reading being complete at each `SYN_REPORT`.
+    pub fn accel(&mut self, event: &input_event) -> Option<WiiEvent> {
+        match (event.type_, event.code) {
+            (EV_ABS, ABS_RX..=ABS_RZ) => {
+                self.accel[(event.code - ABS_RX) as usize] = event.value;
+                None
+            }
+            (EV_SYN, SYN_REPORT) => {
+                let [x, y, z] = self.accel;
+                Some(WiiEvent::Accel(x, y, z))
+            }
+            _ => None,
+        }
+    }
+
+    /// Decodes an event from the IR interface, a frame being complete at each `SYN_REPORT`.
+    pub fn ir(&mut self, event: &input_event) -> Option<WiiEvent> {
+        match (event.type_, event.code) {
+            (EV_ABS, ABS_HAT0X..=ABS_HAT3Y) => {
+                let index = (event.code - ABS_HAT0X) as usize;
+                match index % 2 {
+                    0 => self.dots[index / 2].0 = event.value,
+                    _ => self.dots[index / 2].1 = event.value,
+                }
+
+                None
+            }
+            (EV_SYN, SYN_REPORT) => Some(WiiEvent::Ir(
+                self.dots
+                    .iter()
+                    .map(|(x, y)| IrPoint::new(*x, *y))
+                    .collect(),
+            )),
+            _ => None,
+        }
+    }
+}
+
+impl Default for Decoder {
+    fn default() -> Decoder {
+        Decoder::new()
+    }
+}
+
+type Decode = fn(&mut Decoder, &input_event) -> Option<WiiEvent>;
+
+/// How many events `Interfaces` holds on to for the next `read`, the oldest going first once there are more.
+///
+/// Only a caller of `read_state` that never calls `poll_events` gets this far behind, and the state already has
+/// everything the dropped events said.
+const MAX_PENDING: usize = 1024;
+
+/// The interfaces `poll_events` reads from, opened the first time it's called for a remote.
+pub(crate) struct Interfaces {
+    pub(crate) udev_device_path: String,
+    core: Option<Evdev>,
+    accel: Option<Evdev>,
+    ir: Option<Evdev>,
+    // Whether `accel` and `ir` are meant to be open, they're only reopened when this changes
+    wants_accel: bool,
+    wants_ir: bool,
+    // Not there when udev couldn't be set up, extensions coming and going just isn't reported then
+    monitor: Option<InterfaceMonitor>,
+    decoder: Decoder,
+    // Read for `state` but not yet returned by `read`
+    pending: Vec<WiiEvent>,
+    pub(crate) state: WiiState,
+}
+
+impl Interfaces {
+    /// Opens the buttons, and the accelerometer and IR camera if `accel` and `ir` say so.
+    pub(crate) fn open(udev_device_path: &str, accel: bool, ir: bool) -> Interfaces {
+        Interfaces {
+            udev_device_path: udev_device_path.to_owned(),
+            core: xwiimote::open_interface(udev_device_path, xwiimote::CORE),
+            accel: match accel {
+                true => xwiimote::open_interface(udev_device_path, xwiimote::ACCELEROMETER),
+                false => None,
+            },
+            ir: match ir {
+                true => xwiimote::open_interface(udev_device_path, xwiimote::IR),
+                false => None,
+            },
+            wants_accel: accel,
+            wants_ir: ir,
+            monitor: match InterfaceMonitor::open(udev_device_path) {
+                Ok(monitor) => Some(monitor),
+                Err(err) => {
+                    warn!("Failed to watch for extensions being plugged in: {}", err);
+                    None
+                }
+            },
+            decoder: Decoder::new(),
+            pending: Vec::new(),
+            state: WiiState {
+                extension: stick::plugged_in(udev_device_path),
+                ..WiiState::default()
+            },
+        }
+    }
+
+    /// Opens or closes the accelerometer and IR camera to match `accel` and `ir`, which turns them on or off in
+    /// hid-wiimote unless something else has them open too.
+    pub(crate) fn set_open(&mut self, accel: bool, ir: bool) {
+        if accel != self.wants_accel {
+            self.accel = match accel {
+                true => xwiimote::open_interface(&self.udev_device_path, xwiimote::ACCELEROMETER),
+                false => None,
+            };
+            self.wants_accel = accel;
+        }
+
+        if ir != self.wants_ir {
+            self.ir = match ir {
+                true => xwiimote::open_interface(&self.udev_device_path, xwiimote::IR),
+                false => None,
+            };
+            self.wants_ir = ir;
+        }
+    }
+
+    /// Brings `state` up to date, keeping the last [`MAX_PENDING`] events it read for the next `read`.
+    pub(crate) fn refresh(&mut self) {
+        let events = self.read_new();
+        self.pending.extend(events);
+
+        let excess = self.pending.len().saturating_sub(MAX_PENDING);
+        self.pending.drain(..excess);
+    }
+
+    /// The accelerometer's latest `[x, y, z]`, read from the kernel's copy so nothing queued up is taken.
+    pub(crate) fn accel_reading(&self) -> Option<[i32; 3]> {
+        let accel = self.accel.as_ref()?;
+        let mut reading = [0; 3];
+        for (axis, value) in reading.iter_mut().enumerate() {
+            *value = accel.abs_info(ABS_RX + axis as u16).ok()?.value;
+        }
+
+        Some(reading)
+    }
+
+    /// Everything queued up on every interface, an interface that fails to read is closed and left out from then on.
+    pub(crate) fn read(&mut self) -> Vec<WiiEvent> {
+        let mut events = std::mem::take(&mut self.pending);
+        events.extend(self.read_new());
+        events
+    }
+
+    fn read_new(&mut self) -> Vec<WiiEvent> {
+        let mut decoded = Vec::new();
+        let decoder = &mut self.decoder;
+        let interfaces: [(&mut Option<Evdev>, Decode); 3] = [
+            (&mut self.core, Decoder::core),
+            (&mut self.accel, Decoder::accel),
+            (&mut self.ir, Decoder::ir),
+        ];
+
+        for (interface, decode) in interfaces {
+            let events = match interface.as_mut().map(Evdev::read_events) {
+                Some(Ok(events)) => events,
+                Some(Err(_)) => {
+                    *interface = None;
+                    continue;
+                }
+                None => continue,
+            };
+
+            decoded.extend(events.iter().filter_map(|event| decode(decoder, event)));
+        }
+
+        let changes = self.monitor.as_mut().map(InterfaceMonitor::changes);
+        for (name, added) in changes.into_iter().flatten() {
+            let Some(extension) = Extension::ALL
+                .into_iter()
+                .find(|extension| extension.interface() == name)
+            else {
+                continue;
+            };
+
+            decoded.push(match added {
+                true => WiiEvent::ExtensionAttached(extension),
+                false => WiiEvent::ExtensionDetached,
+            });
+        }
+
+        for event in &decoded {
+            self.state.update(event);
+        }
+
+        decoded
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::evdev::KEY_ENTER;
+
+    fn key(button: Button, value: i32) -> input_event {
+        input_event {
+            time: libc::timeval {
+                tv_sec: 0,
+                tv_usec: 0,
+            },
+            type_: EV_KEY,
+            code: button.code(),
+            value,
+        }
+    }
+
+    #[test]
+    fn state_folds_decoded_events() {
+        let mut decoder = Decoder::new();
+        let ir = vec![
+            IrPoint::new(512, 384),
+            IrPoint::new(600, 400),
+            IrPoint::new(INVALID_DOT, INVALID_DOT),
+            IrPoint::new(INVALID_DOT, INVALID_DOT),
+        ];
+
+        let mut events: Vec<WiiEvent> = [key(Button::A, 1), key(Button::B, 1), key(Button::A, 0)]
+            .iter()
+            .filter_map(|event| decoder.core(event))
+            .collect();
+        events.extend(
+            raw_events(&WiiEvent::Accel(12, -3, 98))
+                .iter()
+                .filter_map(|event| decoder.accel(event)),
+        );
+        events.extend(
+            raw_events(&WiiEvent::Ir(ir.clone()))
+                .iter()
+                .filter_map(|event| decoder.ir(event)),
+        );
+        events.push(WiiEvent::ExtensionAttached(Extension::Nunchuk));
+
+        let mut state = WiiState::default();
+        for event in &events {
+            state.update(event);
+        }
+
+        let mut buttons = HeldButtons::new();
+        buttons.update(&WiiEvent::ButtonPress(Button::B));
+        assert_eq!(
+            state,
+            WiiState {
+                buttons,
+                accel: Some((12, -3, 98)),
+                ir,
+                battery: None,
+                extension: Some(Extension::Nunchuk),
+            }
+        );
+
+        state.update(&WiiEvent::ExtensionDetached);
+        state.update(&WiiEvent::ButtonRelease(Button::B));
+        assert_eq!(state.extension, None);
+        assert!(state.buttons.held().is_empty());
+    }
+
+    #[test]
+    fn key_repeats_are_not_presses() {
+        assert_eq!(Decoder::new().core(&key(Button::Home, 2)), None);
+    }
+
+    #[test]
+    fn button_events_are_decoded() {
+        let mut decoder = Decoder::new();
+        for button in Button::ALL {
+            assert_eq!(
+                decoder.core(&key(button, 1)),
+                Some(WiiEvent::ButtonPress(button))
+            );
+            assert_eq!(
+                decoder.core(&key(button, 0)),
+                Some(WiiEvent::ButtonRelease(button))
+            );
+        }
+    }
+
+    #[test]
+    fn other_core_events_are_skipped() {
+        let mut decoder = Decoder::new();
+        let unknown_key = input_event {
+            code: KEY_ENTER,
+            ..key(Button::A, 1)
+        };
+        let sync = input_event {
+            type_: EV_SYN,
+            code: SYN_REPORT,
+            ..key(Button::A, 0)
+        };
+        assert_eq!(decoder.core(&unknown_key), None);
+        assert_eq!(decoder.core(&sync), None);
+    }
+}
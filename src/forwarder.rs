@@ -0,0 +1,1217 @@
+//! Forwards Wii Remote input to a virtual gamepad, and to a virtual keyboard for profiles that map to keys or
+//! when the output mode is `keyboard` or `split`.
+
+use std::{
+    io,
+    time::{Duration, Instant},
+};
+
+use libc::input_event;
+use log::{debug, info, warn};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    accel::{self, AccelOrientation, TiltMode},
+    button::{Binding, Button},
+    chord::{Action, ChordTracker},
+    config::Config,
+    evdev::{
+        self, ABS_HAT0X, ABS_HAT0Y, ABS_RX, ABS_RY, ABS_RZ, ABS_X, ABS_Y, ABS_Z, BTN_1, BTN_2,
+        BTN_A, BTN_B, BTN_DPAD_DOWN, BTN_DPAD_LEFT, BTN_DPAD_RIGHT, BTN_DPAD_UP, BTN_EAST,
+        BTN_MODE, BTN_NORTH, BTN_SELECT, BTN_SOUTH, BTN_START, BTN_WEST, EV_ABS, EV_KEY, EV_SYN,
+        KEY_1, KEY_2, KEY_DOWN, KEY_ENTER, KEY_ESC, KEY_HOME, KEY_LEFT, KEY_NEXT, KEY_PREVIOUS,
+        KEY_RIGHT, KEY_UP, KEY_VOLUMEDOWN, KEY_VOLUMEUP, SYN_REPORT,
+    },
+    gesture::ShakeDetector,
+    gyro::GyroMouse,
+    ir::{IrMode, IrPointer},
+    long_press::{LongPress, LongPressAction, Release},
+    mappings_dir,
+    repeat::Repeater,
+    stick::{self, AxisCalibration, Extension, ExtensionCalibration, StickMode, TriggerState},
+    throttle::Throttle,
+    uinput::{AbsAxis, Capabilities, DeviceIds, VirtualDevice},
+    xwiimote::Evdev,
+};
+
+/// Wii Remote key code -> gamepad button, assuming the remote is held upright.
+const GAMEPAD_BUTTONS: [(u16, u16); 11] = [
+    (BTN_A, BTN_SOUTH),
+    (BTN_B, BTN_EAST),
+    (BTN_1, BTN_WEST),
+    (BTN_2, BTN_NORTH),
+    (KEY_NEXT, BTN_START),
+    (KEY_PREVIOUS, BTN_SELECT),
+    (BTN_MODE, BTN_MODE),
+    (KEY_UP, BTN_DPAD_UP),
+    (KEY_DOWN, BTN_DPAD_DOWN),
+    (KEY_LEFT, BTN_DPAD_LEFT),
+    (KEY_RIGHT, BTN_DPAD_RIGHT),
+];
+
+/// Wii Remote key code -> keyboard key, for the `keyboard` output mode.
+const KEYBOARD_KEYS: [(u16, u16); 11] = [
+    (BTN_A, KEY_ENTER),
+    (BTN_B, KEY_ESC),
+    (BTN_1, KEY_1),
+    (BTN_2, KEY_2),
+    (KEY_NEXT, KEY_VOLUMEUP),
+    (KEY_PREVIOUS, KEY_VOLUMEDOWN),
+    (BTN_MODE, KEY_HOME),
+    (KEY_UP, KEY_UP),
+    (KEY_DOWN, KEY_DOWN),
+    (KEY_LEFT, KEY_LEFT),
+    (KEY_RIGHT, KEY_RIGHT),
+];
+
+/// Wii Remote key code -> output, for the `split` output mode: the D-pad as arrow keys, the rest as a gamepad.
+const SPLIT_OUTPUTS: [(u16, u16); 11] = [
+    (BTN_A, BTN_SOUTH),
+    (BTN_B, BTN_EAST),
+    (BTN_1, BTN_WEST),
+    (BTN_2, BTN_NORTH),
+    (KEY_NEXT, BTN_START),
+    (KEY_PREVIOUS, BTN_SELECT),
+    (BTN_MODE, BTN_MODE),
+    (KEY_UP, KEY_UP),
+    (KEY_DOWN, KEY_DOWN),
+    (KEY_LEFT, KEY_LEFT),
+    (KEY_RIGHT, KEY_RIGHT),
+];
+
+/// The Wii Remote's D-pad keys in the order [`accel::directions`] reports them, tilting presses them like the
+/// real D-pad would.
+const TILT_KEYS: [u16; 4] = [KEY_UP, KEY_DOWN, KEY_LEFT, KEY_RIGHT];
+
+/// Gamepad D-pad buttons in the order they're tracked for the hat switch.
+const DPAD_BUTTONS: [u16; 4] = [BTN_DPAD_UP, BTN_DPAD_DOWN, BTN_DPAD_LEFT, BTN_DPAD_RIGHT];
+
+/// The built-in `media-center` profile, for navigating Kodi and the like from the couch:
+///
+/// | Wii Remote | Key                                    |
+/// |------------|----------------------------------------|
+/// | D-pad      | `KEY_UP`/`KEY_DOWN`/`KEY_LEFT`/`KEY_RIGHT` |
+/// | A          | `KEY_ENTER`                            |
+/// | B          | `KEY_ESC`                              |
+/// | Home       | `KEY_HOME`                             |
+/// | +          | `KEY_VOLUMEUP`                         |
+/// | -          | `KEY_VOLUMEDOWN`                       |
+///
+/// 1 and 2 keep their usual gamepad buttons.
+const MEDIA_CENTER: [(u16, u16); 9] = [
+    (KEY_UP, KEY_UP),
+    (KEY_DOWN, KEY_DOWN),
+    (KEY_LEFT, KEY_LEFT),
+    (KEY_RIGHT, KEY_RIGHT),
+    (BTN_A, KEY_ENTER),
+    (BTN_B, KEY_ESC),
+    (BTN_MODE, KEY_HOME),
+    (KEY_NEXT, KEY_VOLUMEUP),
+    (KEY_PREVIOUS, KEY_VOLUMEDOWN),
+];
+
+/// Profiles that exist without being in the config file, a config file profile of the same name wins.
+const BUILTIN_PROFILES: [(&str, &[(u16, u16)]); 1] = [("media-center", &MEDIA_CENTER)];
+
+/// How the D-pad is presented on the virtual gamepad.
+#[derive(Clone, Copy, Default, Deserialize, Serialize, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum DpadMode {
+    /// Four separate `BTN_DPAD_*` buttons.
+    #[default]
+    Buttons,
+    /// An `ABS_HAT0X`/`ABS_HAT0Y` hat switch, which is what most games expect.
+    Hat,
+}
+
+/// What the Wii Remote's buttons are sent as.
+#[derive(Clone, Copy, Debug, Default, Deserialize, Serialize, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum OutputMode {
+    /// A virtual gamepad, plus a virtual keyboard for profiles that map to keys.
+    #[default]
+    Gamepad,
+    /// Only a virtual keyboard, buttons a profile leaves out send the keys in `KEYBOARD_KEYS`.
+    Keyboard,
+    /// Both devices from the start, buttons a profile leaves out send what `SPLIT_OUTPUTS` says.
+    Split,
+}
+
+impl OutputMode {
+    pub fn from_name(name: &str) -> Option<OutputMode> {
+        match name {
+            "gamepad" => Some(OutputMode::Gamepad),
+            "keyboard" => Some(OutputMode::Keyboard),
+            "split" => Some(OutputMode::Split),
+            _ => None,
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            OutputMode::Gamepad => "gamepad",
+            OutputMode::Keyboard => "keyboard",
+            OutputMode::Split => "split",
+        }
+    }
+
+    fn has_gamepad(self) -> bool {
+        self != OutputMode::Keyboard
+    }
+}
+
+pub struct Forwarder {
+    player_slot: u8,
+    // What the gamepad is called, and what the other devices' names start with
+    device_name: String,
+    // What the gamepad reports as its bus and IDs
+    gamepad_ids: DeviceIds,
+    output_mode: OutputMode,
+    // Whether virtual devices are created at all, see `Forwarder::without_devices`
+    forwarding: bool,
+    // Not there in the `keyboard` output mode
+    gamepad: Option<VirtualDevice>,
+    // Every gamepad button any profile maps to, so the gamepad can be recreated when switching output modes
+    gamepad_keys: Vec<u16>,
+    // Only created once a profile or the output mode needs a keyboard key
+    keyboard: Option<VirtualDevice>,
+    // Every keyboard key any profile maps to, so the keyboard survives profile switches
+    keyboard_keys: Vec<u16>,
+    // Wii Remote key code -> gamepad button or keyboard key for the active profile
+    mapping: Vec<(u16, u16)>,
+    // The active profile's chords
+    chords: ChordTracker,
+    // Set when a chord's output is pressed, until `take_chord_fired` is called
+    chord_fired: bool,
+    // Wii Remote keys whose keyboard key repeats while held, in the active profile
+    repeating: Vec<u16>,
+    repeater: Repeater,
+    dpad_mode: DpadMode,
+    // Held state of each button in `DPAD_BUTTONS`, the hat is derived from all of them so diagonals work
+    dpad: [bool; 4],
+    // Only there when the config turns the IR camera on
+    ir: Option<IrPointer>,
+    // Only there when the config turns the gyro mouse on
+    gyro: Option<GyroMouse>,
+    // The latest raw `[x, y, z]` reading, when something in the config needs the accelerometer
+    accel: Option<[i32; 3]>,
+    // How the remote is held, when tilt is reported on the gamepad's motion axes
+    accel_orientation: Option<AccelOrientation>,
+    // Which reoriented axes are flipped
+    accel_invert: [bool; 3],
+    // How often tilt is reported, shakes are still looked for in every reading
+    accel_throttle: Throttle,
+    // The button a shake presses, and what recognises one
+    shake: Option<(Button, ShakeDetector)>,
+    // What holding Home does instead of its usual mapping, and how long it's been held
+    home_long_press: Option<(LongPress, LongPressAction)>,
+    // Set when a long press asks for the remote to be disconnected, until `take_disconnect_requested` is called
+    disconnect_requested: bool,
+    tilt_mode: TiltMode,
+    // How the remote is held for tilt steering, sideways unless the config says otherwise
+    tilt_orientation: AccelOrientation,
+    tilt_threshold: f32,
+    tilt_range: f32,
+    // The `TILT_KEYS` tilting is holding down
+    tilt_held: [bool; 4],
+    // Source axis -> gamepad axis of the plugged in extension's sticks
+    sticks: Vec<(u16, u16, AxisCalibration)>,
+    // The threshold the Nunchuk's stick presses the D-pad at, while it's plugged in and the config asks for that
+    stick_dpad: Option<f32>,
+    // The latest calibrated `(x, y)` of the stick pressing the D-pad
+    stick_position: (i32, i32),
+    // The `TILT_KEYS` the stick is holding down
+    stick_held: [bool; 4],
+    // The plugged in extension's analog triggers, see `stick::Trigger`
+    triggers: Vec<(&'static stick::Trigger, TriggerState)>,
+}
+
+impl Forwarder {
+    /// `device_name` is what the gamepad is called, the keyboard and pointer get it with what they are appended.
+    pub fn new(
+        player_slot: u8,
+        device_name: &str,
+        config: &Config,
+        profile: &str,
+        output_mode: OutputMode,
+    ) -> io::Result<Forwarder> {
+        Forwarder::create(player_slot, device_name, config, profile, output_mode, true)
+    }
+
+    /// A forwarder that creates no virtual devices and so forwards nothing, for when `/dev/uinput` can't be used.
+    /// Everything else it keeps track of, like held buttons and long presses, still works.
+    pub fn without_devices(
+        player_slot: u8,
+        device_name: &str,
+        config: &Config,
+        profile: &str,
+        output_mode: OutputMode,
+    ) -> Forwarder {
+        Forwarder::create(
+            player_slot,
+            device_name,
+            config,
+            profile,
+            output_mode,
+            false,
+        )
+        .expect("a forwarder without devices has nothing to fail creating")
+    }
+
+    fn create(
+        player_slot: u8,
+        device_name: &str,
+        config: &Config,
+        profile: &str,
+        output_mode: OutputMode,
+        forwarding: bool,
+    ) -> io::Result<Forwarder> {
+        let dpad_mode = config.dpad_mode;
+        let home_long_press = config.home_long_press.as_deref().and_then(|name| {
+            let action = LongPressAction::from_name(name);
+            if action.is_none() {
+                warn!("home_long_press is set to unknown code `{}'", name);
+            }
+
+            action
+        });
+
+        // Register the outputs of every profile up front, so switching profiles doesn't need a new device
+        let profiles = config
+            .profiles
+            .keys()
+            .cloned()
+            .chain(mappings_dir::names())
+            .chain(BUILTIN_PROFILES.iter().map(|(name, _)| (*name).to_owned()));
+
+        let mut keys = Vec::new();
+        let mut keyboard_keys = Vec::new();
+        for output in profiles
+            .flat_map(|name| {
+                mapping(config, &name, OutputMode::Gamepad)
+                    .outputs()
+                    .collect::<Vec<_>>()
+            })
+            .chain(GAMEPAD_BUTTONS.map(|(_, button)| button))
+            .chain(stick::CLASSIC_TRIGGERS.map(|trigger| trigger.button))
+            .chain(home_long_press.and_then(|action| match action {
+                LongPressAction::Output(output) => Some(output),
+                LongPressAction::Disconnect => None,
+            }))
+            .chain(KEYBOARD_KEYS.map(|(_, key)| key))
+        {
+            let outputs = match evdev::is_keyboard_key(output) {
+                true => &mut keyboard_keys,
+                false if dpad_mode == DpadMode::Hat && DPAD_BUTTONS.contains(&output) => continue,
+                false => &mut keys,
+            };
+
+            if !outputs.contains(&output) {
+                outputs.push(output);
+            }
+        }
+
+        let mut forwarder = Forwarder {
+            player_slot,
+            device_name: device_name.to_owned(),
+            gamepad_ids: config.gamepad_ids,
+            output_mode,
+            forwarding,
+            gamepad: None,
+            gamepad_keys: keys,
+            keyboard: None,
+            keyboard_keys,
+            mapping: Vec::new(),
+            chords: ChordTracker::new(Vec::new()),
+            chord_fired: false,
+            repeating: Vec::new(),
+            repeater: Repeater::new(
+                Duration::from_millis(config.repeat_delay),
+                config.repeat_rate,
+            ),
+            dpad_mode,
+            dpad: [false; 4],
+            ir: match config.ir_mode {
+                _ if !forwarding => None,
+                IrMode::Off => None,
+                _ => Some(IrPointer::new(device_name, config)?),
+            },
+            gyro: match config.gyro_mouse.enabled && forwarding {
+                true => Some(GyroMouse::new(device_name, config)?),
+                false => None,
+            },
+            accel: match config.accel_orientation.is_some()
+                || config.shake_action.is_some()
+                || config.tilt_mode != TiltMode::Off
+            {
+                true => Some([0; 3]),
+                false => None,
+            },
+            accel_orientation: config.accel_orientation,
+            accel_invert: [
+                config.accel_invert_x,
+                config.accel_invert_y,
+                config.accel_invert_z,
+            ],
+            accel_throttle: Throttle::new(0),
+            shake: config.shake_action.map(|button| {
+                (
+                    button,
+                    ShakeDetector::new(
+                        config.shake_threshold,
+                        Duration::from_millis(config.shake_cooldown),
+                    ),
+                )
+            }),
+            home_long_press: home_long_press.map(|action| {
+                (
+                    LongPress::new(Duration::from_millis(config.home_long_press_time)),
+                    action,
+                )
+            }),
+            disconnect_requested: false,
+            tilt_mode: config.tilt_mode,
+            tilt_orientation: config
+                .accel_orientation
+                .unwrap_or(AccelOrientation::Horizontal),
+            tilt_threshold: config.tilt_threshold,
+            tilt_range: config.tilt_range,
+            tilt_held: [false; 4],
+            sticks: Vec::new(),
+            stick_dpad: None,
+            stick_position: (0, 0),
+            stick_held: [false; 4],
+            triggers: Vec::new(),
+        };
+
+        if output_mode.has_gamepad() && forwarding {
+            forwarder.gamepad = Some(forwarder.create_gamepad()?);
+        }
+
+        forwarder.use_mapping(mapping(config, profile, output_mode))?;
+        Ok(forwarder)
+    }
+
+    /// Handles a key event from the Wii Remote, returning whether it was forwarded.
+    pub fn forward_key(&mut self, key: u16, pressed: bool) -> io::Result<bool> {
+        if let (true, Some((long_press, action))) =
+            (key == Button::Home.code(), &mut self.home_long_press)
+        {
+            let (now, action) = (Instant::now(), *action);
+            if pressed {
+                long_press.press(now);
+                return Ok(true);
+            }
+
+            return match long_press.release(now) {
+                // Home was held back until it was clear this is a tap
+                Release::Short => {
+                    self.forward_now(key, true)?;
+                    self.forward_now(key, false)
+                }
+                Release::Long => {
+                    self.fire_long_press(action, true)?;
+                    self.fire_long_press(action, false)
+                }
+                Release::LongEnded => self.fire_long_press(action, false),
+                Release::NotPressed => Ok(false),
+            };
+        }
+
+        self.forward_now(key, pressed)
+    }
+
+    /// Fires `home_long_press` once Home has been held long enough, called each time round the poll loop.
+    pub fn check_long_press(&mut self) -> io::Result<()> {
+        let action = match &mut self.home_long_press {
+            Some((long_press, action)) => match long_press.poll(Instant::now()) {
+                true => *action,
+                false => return Ok(()),
+            },
+            None => return Ok(()),
+        };
+
+        self.fire_long_press(action, true).map(drop)
+    }
+
+    /// Whether a long press asked for the remote to be disconnected since the last call.
+    pub fn take_disconnect_requested(&mut self) -> bool {
+        std::mem::take(&mut self.disconnect_requested)
+    }
+
+    fn fire_long_press(&mut self, action: LongPressAction, pressed: bool) -> io::Result<bool> {
+        match action {
+            LongPressAction::Disconnect => {
+                self.disconnect_requested |= pressed;
+                Ok(true)
+            }
+            LongPressAction::Output(output) => self.emit_output(output, pressed),
+        }
+    }
+
+    fn forward_now(&mut self, key: u16, pressed: bool) -> io::Result<bool> {
+        if let Some(ir) = &mut self.ir {
+            if ir.handle_key(key, pressed)? {
+                return Ok(true);
+            }
+        }
+
+        if let Some(gyro) = &mut self.gyro {
+            if gyro.handle_key(key, pressed) {
+                return Ok(true);
+            }
+        }
+
+        let mut forwarded = false;
+        for action in self.chords.handle(key, pressed) {
+            self.chord_fired |= matches!(action, Action::Output(_, true));
+            forwarded |= match action {
+                Action::Key(key, pressed) => self.forward_mapped(key, pressed)?,
+                Action::Output(output, pressed) => self.emit_output(output, pressed)?,
+            };
+        }
+
+        Ok(forwarded)
+    }
+
+    /// Whether a chord fired since the last call, for rumble feedback.
+    pub fn take_chord_fired(&mut self) -> bool {
+        std::mem::take(&mut self.chord_fired)
+    }
+
+    fn forward_mapped(&mut self, key: u16, pressed: bool) -> io::Result<bool> {
+        let output = match self.mapping.iter().find(|(wii_key, _)| *wii_key == key) {
+            Some((_, output)) => *output,
+            None => return Ok(false),
+        };
+
+        let dpad_index = DPAD_BUTTONS.iter().position(|button| *button == output);
+        if let (DpadMode::Hat, Some(gamepad), Some(index)) =
+            (self.dpad_mode, &mut self.gamepad, dpad_index)
+        {
+            self.dpad[index] = pressed;
+            let (x, y) = hat_values(self.dpad);
+            gamepad.emit(EV_ABS, ABS_HAT0X, x)?;
+            gamepad.emit(EV_ABS, ABS_HAT0Y, y)?;
+            gamepad.sync()?;
+            return Ok(true);
+        }
+
+        if self.repeating.contains(&key) && evdev::is_keyboard_key(output) {
+            match pressed {
+                true => self.repeater.press(output, Instant::now()),
+                false => self.repeater.release(output),
+            }
+        }
+
+        self.emit_output(output, pressed)
+    }
+
+    /// Sends a repeat for every held key that's due one, called each time round the poll loop.
+    pub fn repeat_held(&mut self) -> io::Result<()> {
+        let due = self.repeater.due(Instant::now());
+        let keyboard = match (due.is_empty(), &mut self.keyboard) {
+            (false, Some(keyboard)) => keyboard,
+            _ => return Ok(()),
+        };
+
+        for output in due {
+            keyboard.emit(EV_KEY, output, 2)?;
+        }
+
+        keyboard.sync()
+    }
+
+    /// Sends `output` from whichever device has it, returning false when neither does in this output mode.
+    fn emit_output(&mut self, output: u16, pressed: bool) -> io::Result<bool> {
+        match self.device_for(output) {
+            Some(device) => {
+                device.emit(EV_KEY, output, pressed as i32)?;
+                device.sync()?;
+                Ok(true)
+            }
+            None => Ok(false),
+        }
+    }
+
+    /// Switches to the mappings of `profile`, releasing anything that's held so no button gets stuck.
+    pub fn set_profile(&mut self, config: &Config, profile: &str) -> io::Result<()> {
+        self.release_held()?;
+        self.use_mapping(mapping(config, profile, self.output_mode))
+    }
+
+    /// Switches between sending gamepad buttons and keyboard keys, re-applying the mappings of `profile`.
+    ///
+    /// The old devices are only torn down once everything held on them is released, and nothing is sent while
+    /// the new ones are created since the poll loop is the only caller.
+    pub fn set_output_mode(
+        &mut self,
+        config: &Config,
+        profile: &str,
+        output_mode: OutputMode,
+    ) -> io::Result<()> {
+        if output_mode == self.output_mode {
+            return Ok(());
+        }
+
+        info!(
+            "Switching player {} from {} to {} output",
+            self.player_slot,
+            self.output_mode.name(),
+            output_mode.name()
+        );
+
+        self.release_held()?;
+        match output_mode {
+            OutputMode::Keyboard => self.gamepad = None,
+            _ if self.gamepad.is_none() && self.forwarding => {
+                self.gamepad = Some(self.create_gamepad()?)
+            }
+            _ => {}
+        }
+
+        // `use_mapping` brings the keyboard back if the profile still maps to keys
+        if output_mode == OutputMode::Gamepad {
+            self.keyboard = None;
+        }
+
+        self.output_mode = output_mode;
+        self.use_mapping(mapping(config, profile, output_mode))
+    }
+
+    fn release_held(&mut self) -> io::Result<()> {
+        let outputs = self
+            .mapping
+            .iter()
+            .map(|(_, output)| *output)
+            .collect::<Vec<_>>();
+
+        let chord_outputs =
+            self.chords
+                .release_all()
+                .into_iter()
+                .filter_map(|action| match action {
+                    Action::Output(output, _) => Some(output),
+                    Action::Key(..) => None,
+                });
+
+        // A long press holding its output down is let go of too
+        let long_press_output = self
+            .home_long_press
+            .as_mut()
+            .and_then(|(long_press, action)| {
+                long_press.reset();
+                match action {
+                    LongPressAction::Output(output) => Some(*output),
+                    LongPressAction::Disconnect => None,
+                }
+            });
+
+        for output in outputs
+            .into_iter()
+            .chain(chord_outputs)
+            .chain(long_press_output)
+        {
+            if let Some(device) = self.device_for(output) {
+                device.emit(EV_KEY, output, 0)?;
+            }
+        }
+
+        if let Some(gamepad) = &mut self.gamepad {
+            if self.dpad_mode == DpadMode::Hat {
+                gamepad.emit(EV_ABS, ABS_HAT0X, 0)?;
+                gamepad.emit(EV_ABS, ABS_HAT0Y, 0)?;
+            }
+
+            gamepad.sync()?;
+        }
+
+        if let Some(keyboard) = &mut self.keyboard {
+            keyboard.sync()?;
+        }
+
+        if let Some(ir) = &mut self.ir {
+            ir.release_held()?;
+        }
+
+        self.dpad = [false; 4];
+        self.tilt_held = [false; 4];
+        self.stick_held = [false; 4];
+        self.repeater.clear();
+        Ok(())
+    }
+
+    fn use_mapping(&mut self, mapping: Mapping) -> io::Result<()> {
+        let needs_keyboard = self.output_mode != OutputMode::Gamepad
+            || mapping.outputs().any(evdev::is_keyboard_key);
+        self.mapping = mapping.keys;
+        self.chords = ChordTracker::new(mapping.chords);
+        self.repeating = mapping.repeating;
+
+        match needs_keyboard {
+            true => self.create_keyboard(),
+            false => Ok(()),
+        }
+    }
+
+    fn create_gamepad(&self) -> io::Result<VirtualDevice> {
+        let mut capabilities = Capabilities {
+            keys: self.gamepad_keys.clone(),
+            ..Capabilities::default()
+        };
+
+        if self.dpad_mode == DpadMode::Hat {
+            for code in [ABS_HAT0X, ABS_HAT0Y] {
+                capabilities.absolute.push(AbsAxis {
+                    code,
+                    min: -1,
+                    max: 1,
+                });
+            }
+        }
+
+        // Extension sticks, the right one only when tilt isn't using the motion axes
+        let sticks = match self.accel_orientation.is_some() {
+            true => &[ABS_X, ABS_Y][..],
+            false => &[ABS_X, ABS_Y, ABS_RX, ABS_RY][..],
+        };
+
+        for code in sticks {
+            capabilities.absolute.push(AbsAxis {
+                code: *code,
+                min: -stick::OUTPUT_RANGE,
+                max: stick::OUTPUT_RANGE,
+            });
+        }
+
+        // Classic Controller triggers, the right one only when tilt isn't using `ABS_RZ`
+        let triggers = match self.accel_orientation.is_some() {
+            true => &[ABS_Z][..],
+            false => &[ABS_Z, ABS_RZ][..],
+        };
+
+        for code in triggers {
+            capabilities.absolute.push(AbsAxis {
+                code: *code,
+                min: 0,
+                max: stick::TRIGGER_RANGE,
+            });
+        }
+
+        if self.accel_orientation.is_some() {
+            for code in [ABS_RX, ABS_RY, ABS_RZ] {
+                capabilities.absolute.push(AbsAxis {
+                    code,
+                    min: -500,
+                    max: 500,
+                });
+            }
+        }
+
+        VirtualDevice::create_with_ids(&self.device_name, &capabilities, self.gamepad_ids)
+    }
+
+    fn create_keyboard(&mut self) -> io::Result<()> {
+        if self.keyboard.is_none() && self.forwarding {
+            let capabilities = Capabilities {
+                keys: self.keyboard_keys.clone(),
+                ..Capabilities::default()
+            };
+
+            self.keyboard = Some(VirtualDevice::create(
+                &format!("{} Keyboard", self.device_name),
+                &capabilities,
+            )?);
+        }
+
+        Ok(())
+    }
+
+    fn device_for(&mut self, output: u16) -> Option<&mut VirtualDevice> {
+        match evdev::is_keyboard_key(output) {
+            true => self.keyboard.as_mut(),
+            false => self.gamepad.as_mut(),
+        }
+    }
+
+    pub fn wants_ir(&self) -> bool {
+        self.ir.is_some()
+    }
+
+    pub fn wants_motion_plus(&self) -> bool {
+        self.gyro.is_some()
+    }
+
+    pub fn wants_accel(&self) -> bool {
+        self.accel.is_some()
+    }
+
+    /// Centres the motion axes and forgets the last reading, for when the accelerometer is turned off.
+    pub fn reset_accel(&mut self) -> io::Result<()> {
+        let raw = match &mut self.accel {
+            Some(raw) => raw,
+            None => return Ok(()),
+        };
+
+        *raw = [0; 3];
+        if let (Some(_), Some(gamepad)) = (self.accel_orientation, &mut self.gamepad) {
+            for code in [ABS_RX, ABS_RY, ABS_RZ] {
+                gamepad.emit(EV_ABS, code, 0)?;
+            }
+
+            gamepad.sync()?;
+        }
+
+        self.steer_dpad([false; 4])?;
+        if let (TiltMode::Stick, Some(gamepad)) = (self.tilt_mode, &mut self.gamepad) {
+            gamepad.emit(EV_ABS, ABS_X, 0)?;
+            gamepad.emit(EV_ABS, ABS_Y, 0)?;
+            gamepad.sync()?;
+        }
+
+        Ok(())
+    }
+
+    /// Handles an event from the Wii Remote's accelerometer interface, reporting the reoriented reading and
+    /// looking for shakes once a full report has arrived.
+    pub fn forward_accel(&mut self, event: &input_event) -> io::Result<()> {
+        let raw = match &mut self.accel {
+            Some(raw) => raw,
+            None => return Ok(()),
+        };
+
+        match (event.type_, event.code) {
+            (EV_ABS, ABS_RX..=ABS_RZ) => raw[(event.code - ABS_RX) as usize] = event.value,
+            (EV_SYN, SYN_REPORT) => {
+                let raw = *raw;
+                if self.accel_throttle.ready(Instant::now()) {
+                    self.report_tilt(raw)?;
+                }
+
+                // Steering with the D-pad presses buttons, so it isn't held back like the axes
+                if self.tilt_mode == TiltMode::Dpad {
+                    let angles = accel::angles(accel::transform(
+                        self.tilt_orientation,
+                        self.accel_invert,
+                        raw,
+                    ));
+                    self.steer_dpad(accel::directions(
+                        angles,
+                        self.tilt_threshold,
+                        self.tilt_held,
+                    ))?;
+                }
+
+                let shaken = match &mut self.shake {
+                    Some((button, detector)) => detector.push(raw).then_some(*button),
+                    None => None,
+                };
+
+                if let Some(button) = shaken {
+                    debug!("Shake detected, pressing {:?}", button);
+                    self.forward_key(button.code(), true)?;
+                    self.forward_key(button.code(), false)?;
+                }
+            }
+            _ => {}
+        }
+
+        Ok(())
+    }
+
+    /// Reports tilt and moves the IR pointer at most `rate` times a second each, dropping the readings in
+    /// between. 0 reports every reading, which is what a new forwarder does.
+    pub fn set_motion_report_rate(&mut self, rate: u32) {
+        self.accel_throttle = Throttle::new(rate);
+        if let Some(ir) = &mut self.ir {
+            ir.set_report_rate(rate);
+        }
+    }
+
+    /// Reports the latest tilt and IR movement held back by [`Forwarder::set_motion_report_rate`] once they're
+    /// due, for when the remote stops reporting before a newer reading comes in. Called each time round the poll
+    /// loop.
+    pub fn flush_motion(&mut self) -> io::Result<()> {
+        if let Some(raw) = self.accel {
+            if self.accel_throttle.take_due(Instant::now()) {
+                self.report_tilt(raw)?;
+            }
+        }
+
+        match &mut self.ir {
+            Some(ir) => ir.flush(),
+            None => Ok(()),
+        }
+    }
+
+    fn report_tilt(&mut self, raw: [i32; 3]) -> io::Result<()> {
+        let Some(gamepad) = &mut self.gamepad else {
+            return Ok(());
+        };
+
+        if let Some(orientation) = self.accel_orientation {
+            let [x, y, z] = accel::transform(orientation, self.accel_invert, raw);
+            gamepad.emit(EV_ABS, ABS_RX, x)?;
+            gamepad.emit(EV_ABS, ABS_RY, y)?;
+            gamepad.emit(EV_ABS, ABS_RZ, z)?;
+            gamepad.sync()?;
+        }
+
+        if self.tilt_mode == TiltMode::Stick {
+            let angles = accel::angles(accel::transform(
+                self.tilt_orientation,
+                self.accel_invert,
+                raw,
+            ));
+            let (x, y) = accel::stick_position(angles, self.tilt_range, stick::OUTPUT_RANGE);
+            gamepad.emit(EV_ABS, ABS_X, x)?;
+            gamepad.emit(EV_ABS, ABS_Y, y)?;
+            gamepad.sync()?;
+        }
+
+        Ok(())
+    }
+
+    /// Presses and releases `TILT_KEYS` until `pressed` are the ones held.
+    fn steer_dpad(&mut self, pressed: [bool; 4]) -> io::Result<()> {
+        for (index, key) in TILT_KEYS.into_iter().enumerate() {
+            if pressed[index] != self.tilt_held[index] {
+                self.tilt_held[index] = pressed[index];
+                self.forward_key(key, pressed[index])?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Lets go of the D-pad directions the Nunchuk's stick is holding, for when it's unplugged.
+    pub fn release_stick_dpad(&mut self) -> io::Result<()> {
+        self.stick_position = (0, 0);
+        self.press_stick_dpad([false; 4])
+    }
+
+    /// Presses and releases `TILT_KEYS` until `pressed` are the ones the stick holds.
+    fn press_stick_dpad(&mut self, pressed: [bool; 4]) -> io::Result<()> {
+        for (index, key) in TILT_KEYS.into_iter().enumerate() {
+            if pressed[index] != self.stick_held[index] {
+                self.stick_held[index] = pressed[index];
+                self.forward_key(key, pressed[index])?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Starts reporting the sticks of `extension`, whose interface is `evdev`. Axes the config file doesn't
+    /// calibrate use `stored`, the extension's own calibration, with the kernel's deadzone.
+    pub fn set_extension(
+        &mut self,
+        config: &Config,
+        extension: Extension,
+        evdev: &Evdev,
+        stored: Option<&ExtensionCalibration>,
+    ) {
+        self.sticks.clear();
+        self.stick_position = (0, 0);
+        self.stick_dpad = match (extension, config.nunchuk.stick_mode) {
+            (Extension::Nunchuk, StickMode::Dpad) => Some(config.nunchuk.dpad_threshold),
+            _ => None,
+        };
+
+        for axis in extension.axes() {
+            if self.accel_orientation.is_some() && matches!(axis.output, ABS_RX | ABS_RY) {
+                debug!(
+                    "Not reporting {} {}, tilt is using the motion axes",
+                    extension.name(),
+                    axis.name
+                );
+                continue;
+            }
+
+            if let Some(calibration) = config.calibration(extension, axis.name) {
+                self.sticks.push((axis.source, axis.output, calibration));
+                continue;
+            }
+
+            let uncalibrated = match AxisCalibration::uncalibrated(evdev, axis.source) {
+                Ok(calibration) => calibration,
+                Err(err) => {
+                    warn!(
+                        "Failed to read the range of {} {}: {}",
+                        extension.name(),
+                        axis.name,
+                        err
+                    );
+                    continue;
+                }
+            };
+
+            let calibration = match stored.and_then(|stored| stored.axis(axis.name)) {
+                Some(calibration) => AxisCalibration {
+                    deadzone: uncalibrated.deadzone,
+                    ..calibration
+                },
+                None => uncalibrated,
+            };
+
+            self.sticks.push((axis.source, axis.output, calibration));
+        }
+
+        self.triggers.clear();
+        for trigger in extension.triggers() {
+            if self.accel_orientation.is_some() && trigger.output == ABS_RZ {
+                debug!(
+                    "Not reporting {} {}, tilt is using the motion axes",
+                    extension.name(),
+                    trigger.name
+                );
+                continue;
+            }
+
+            match evdev.abs_info(trigger.source) {
+                Ok(info) => self.triggers.push((
+                    trigger,
+                    TriggerState::new(info.maximum, config.trigger_threshold),
+                )),
+                Err(err) => warn!(
+                    "Failed to read the range of {} {}: {}",
+                    extension.name(),
+                    trigger.name,
+                    err
+                ),
+            }
+        }
+    }
+
+    /// Handles an event from the plugged in extension's interface.
+    pub fn forward_stick(&mut self, event: &input_event) -> io::Result<()> {
+        // The Nunchuk's two axes are x and y in that order
+        if let Some(threshold) = self.stick_dpad {
+            match (event.type_, event.code) {
+                (EV_ABS, code) => {
+                    let axis = self
+                        .sticks
+                        .iter()
+                        .position(|(source, _, _)| *source == code);
+                    match axis.map(|axis| (axis, self.sticks[axis].2.apply(event.value))) {
+                        Some((0, value)) => self.stick_position.0 = value,
+                        Some((_, value)) => self.stick_position.1 = value,
+                        None => {}
+                    }
+
+                    return Ok(());
+                }
+                (EV_SYN, SYN_REPORT) => self.press_stick_dpad(stick::directions(
+                    self.stick_position,
+                    threshold,
+                    self.stick_held,
+                ))?,
+                _ => {}
+            }
+        }
+
+        let gamepad = match &mut self.gamepad {
+            Some(gamepad) => gamepad,
+            None => return Ok(()),
+        };
+
+        match (event.type_, event.code) {
+            (EV_ABS, code) => {
+                if let Some((_, output, calibration)) =
+                    self.sticks.iter().find(|(source, _, _)| *source == code)
+                {
+                    gamepad.emit(EV_ABS, *output, calibration.apply(event.value))?;
+                }
+            }
+            (EV_KEY, _) => {}
+            (EV_SYN, SYN_REPORT) => return gamepad.sync(),
+            _ => return Ok(()),
+        }
+
+        let Some((trigger, state)) =
+            self.triggers
+                .iter_mut()
+                .find(|(trigger, _)| match event.type_ {
+                    EV_ABS => trigger.source == event.code,
+                    _ => trigger.click == event.code,
+                })
+        else {
+            return Ok(());
+        };
+
+        let (axis, pressed) = (state.axis(), state.pressed());
+        match event.type_ {
+            EV_ABS => state.set_pressure(event.value),
+            _ => state.set_clicked(event.value != 0),
+        }
+
+        if state.axis() != axis {
+            gamepad.emit(EV_ABS, trigger.output, state.axis())?;
+        }
+
+        if state.pressed() != pressed {
+            gamepad.emit(EV_KEY, trigger.button, state.pressed() as i32)?;
+        }
+
+        Ok(())
+    }
+
+    /// Handles an event from the Wii Remote's MotionPlus interface.
+    pub fn forward_motion_plus(&mut self, event: &input_event) -> io::Result<()> {
+        match &mut self.gyro {
+            Some(gyro) => gyro.handle_event(event),
+            None => Ok(()),
+        }
+    }
+
+    /// Handles an event from the Wii Remote's IR interface.
+    pub fn forward_ir(&mut self, event: &input_event) -> io::Result<()> {
+        match &mut self.ir {
+            Some(ir) => ir.handle_event(event),
+            None => Ok(()),
+        }
+    }
+}
+
+impl Drop for Forwarder {
+    // Let go of anything still held, so nothing is left pressed when the devices disappear
+    fn drop(&mut self) {
+        if let Err(err) = self.release_held() {
+            debug!(
+                "Failed to release held keys on player {}: {}",
+                self.player_slot, err
+            );
+        }
+    }
+}
+
+pub fn is_builtin_profile(name: &str) -> bool {
+    BUILTIN_PROFILES
+        .iter()
+        .any(|(builtin_name, _)| *builtin_name == name)
+}
+
+/// What a profile maps each Wii Remote key and chord to.
+pub struct Mapping {
+    /// Wii Remote key code -> output code.
+    pub keys: Vec<(u16, u16)>,
+    /// Wii Remote key codes held together -> output code.
+    pub chords: Vec<(Vec<u16>, u16)>,
+    /// Wii Remote key codes whose output repeats while held.
+    pub repeating: Vec<u16>,
+}
+
+impl Mapping {
+    /// Every output code the mapping can send.
+    pub fn outputs(&self) -> impl Iterator<Item = u16> + '_ {
+        self.keys
+            .iter()
+            .map(|(_, output)| *output)
+            .chain(self.chords.iter().map(|(_, output)| *output))
+    }
+}
+
+/// The mappings of `profile`, with every button it doesn't mention keeping its usual gamepad button, its usual
+/// key in the `keyboard` output mode, or its `SPLIT_OUTPUTS` entry in the `split` one.
+pub fn mapping(config: &Config, profile: &str, output_mode: OutputMode) -> Mapping {
+    let mut mapping = Mapping {
+        keys: match output_mode {
+            OutputMode::Gamepad => GAMEPAD_BUTTONS.to_vec(),
+            OutputMode::Keyboard => KEYBOARD_KEYS.to_vec(),
+            OutputMode::Split => SPLIT_OUTPUTS.to_vec(),
+        },
+        chords: Vec::new(),
+        repeating: Vec::new(),
+    };
+
+    let from_dir = mappings_dir::profile(profile);
+    let overrides = match from_dir.as_ref().or_else(|| config.profiles.get(profile)) {
+        Some(overrides) => overrides,
+        None => {
+            if let Some((_, builtin)) = BUILTIN_PROFILES.iter().find(|(name, _)| *name == profile) {
+                apply(&mut mapping.keys, builtin);
+            }
+
+            return mapping;
+        }
+    };
+
+    for (Binding(buttons), target) in overrides {
+        let name = target.output();
+        let code = match evdev::code_from_name(name) {
+            Some(code) => code,
+            None => {
+                warn!(
+                    "Profile `{}' maps {:?} to unknown code `{}'",
+                    profile, buttons, name
+                );
+                continue;
+            }
+        };
+
+        match buttons.as_slice() {
+            [button] => {
+                apply(&mut mapping.keys, &[(button.code(), code)]);
+                if target.repeats() {
+                    mapping.repeating.push(button.code());
+                }
+            }
+            _ => mapping
+                .chords
+                .push((buttons.iter().map(|button| button.code()).collect(), code)),
+        }
+    }
+
+    // The profile comes in no particular order, so the chords are given one: the biggest first, then by their
+    // buttons, since `ChordTracker` goes with the first listed when several complete at once
+    mapping
+        .chords
+        .sort_by(|(a, _), (b, _)| b.len().cmp(&a.len()).then_with(|| a.cmp(b)));
+
+    mapping
+}
+
+fn apply(mapping: &mut [(u16, u16)], overrides: &[(u16, u16)]) {
+    for (wii_key, output) in overrides {
+        if let Some(entry) = mapping.iter_mut().find(|(key, _)| key == wii_key) {
+            entry.1 = *output;
+        }
+    }
+}
+
+/// Encodes held `[up, down, left, right]` keys as hat values, -1 being up/left and opposing keys cancelling out.
+pub fn hat_values([up, down, left, right]: [bool; 4]) -> (i32, i32) {
+    (right as i32 - left as i32, down as i32 - up as i32)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use super::*;
+    use crate::config::Target;
+
+    #[test]
+    fn chords_are_ordered() {
+        let profile = [
+            ("two+a", "KEY_2"),
+            ("one+two", "KEY_1"),
+            ("one+two+a", "KEY_ENTER"),
+        ]
+        .into_iter()
+        .map(|(binding, output)| (binding.parse().unwrap(), Target::Output(output.to_owned())))
+        .collect::<HashMap<Binding, Target>>();
+
+        let mut config = Config::default();
+        config.profiles.insert("chords".to_owned(), profile);
+
+        assert_eq!(
+            mapping(&config, "chords", OutputMode::Gamepad).chords,
+            [
+                (vec![BTN_1, BTN_2, BTN_A], KEY_ENTER),
+                (vec![BTN_1, BTN_2], KEY_1),
+                (vec![BTN_2, BTN_A], KEY_2),
+            ]
+        );
+    }
+}
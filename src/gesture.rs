@@ -0,0 +1,124 @@
+//! Motion gestures recognised from the accelerometer stream.
+
+use std::{
+    collections::VecDeque,
+    time::{Duration, Instant},
+};
+
+/// How many accelerometer reports a shake has to happen within, about a tenth of a second at 100 Hz.
+pub const SHAKE_WINDOW: usize = 10;
+
+/// Whether `samples` contain a shake: some axis swinging from beyond `+threshold` to beyond `-threshold`
+/// (or the other way around) within the window.
+pub fn detect_shake(samples: &[[i32; 3]], threshold: i32) -> bool {
+    (0..3).any(|axis| {
+        samples.iter().any(|sample| sample[axis] > threshold)
+            && samples.iter().any(|sample| sample[axis] < -threshold)
+    })
+}
+
+pub struct ShakeDetector {
+    window: VecDeque<[i32; 3]>,
+    threshold: i32,
+    cooldown: Duration,
+    last_shake: Option<Instant>,
+}
+
+impl ShakeDetector {
+    pub fn new(threshold: i32, cooldown: Duration) -> ShakeDetector {
+        ShakeDetector {
+            window: VecDeque::with_capacity(SHAKE_WINDOW),
+            threshold,
+            cooldown,
+            last_shake: None,
+        }
+    }
+
+    /// Adds a raw `[x, y, z]` reading, returning whether it completed a shake.
+    pub fn push(&mut self, sample: [i32; 3]) -> bool {
+        if self.window.len() == SHAKE_WINDOW {
+            self.window.pop_front();
+        }
+
+        self.window.push_back(sample);
+        if !detect_shake(self.window.make_contiguous(), self.threshold) {
+            return false;
+        }
+
+        // One shake is several swings, only fire once per cooldown
+        let now = Instant::now();
+        if let Some(last_shake) = self.last_shake {
+            if now.duration_since(last_shake) < self.cooldown {
+                return false;
+            }
+        }
+
+        self.last_shake = Some(now);
+        self.window.clear();
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // hid-wiimote's readings of a remote lying still, and of one flicked left and right
+    const RESTING: [[i32; 3]; 10] = [
+        [1, -2, 104],
+        [0, -1, 103],
+        [2, -1, 105],
+        [1, 0, 104],
+        [1, -1, 104],
+        [0, -2, 103],
+        [1, -1, 104],
+        [2, 0, 105],
+        [1, -1, 104],
+        [0, -1, 103],
+    ];
+    const SHAKE: [[i32; 3]; 10] = [
+        [12, 3, 101],
+        [96, 8, 94],
+        [231, 15, 88],
+        [204, 11, 97],
+        [37, -4, 110],
+        [-148, -12, 118],
+        [-262, -19, 106],
+        [-121, -6, 99],
+        [18, 2, 102],
+        [64, 5, 100],
+    ];
+
+    #[test]
+    fn shake_in_recorded_samples() {
+        assert!(detect_shake(&SHAKE, 150));
+        assert!(!detect_shake(&RESTING, 150));
+        // Gravity alone pulls z past the threshold, but only ever one way
+        assert!(!detect_shake(&RESTING, 50));
+        // A flick that doesn't swing far enough back
+        assert!(!detect_shake(&SHAKE, 250));
+    }
+
+    #[test]
+    fn detector_fires_once_per_cooldown() {
+        let mut detector = ShakeDetector::new(150, Duration::from_secs(60));
+        let fired = SHAKE
+            .iter()
+            .chain(&RESTING)
+            .chain(&SHAKE)
+            .filter(|sample| detector.push(**sample))
+            .count();
+        assert_eq!(fired, 1);
+    }
+
+    #[test]
+    fn detector_fires_again_after_the_cooldown() {
+        let mut detector = ShakeDetector::new(150, Duration::ZERO);
+        let fired = SHAKE
+            .iter()
+            .chain(&SHAKE)
+            .filter(|sample| detector.push(**sample))
+            .count();
+        assert_eq!(fired, 2);
+    }
+}
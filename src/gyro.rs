@@ -0,0 +1,173 @@
+//! Moving the mouse pointer by turning the remote, read from the MotionPlus gyroscope, for pointing without a sensor
+//! bar.
+//!
+//! The gyroscope reports how fast the remote turns rather than where it points, so the pointer moves by the turn
+//! rate: yaw across and pitch up and down. A gyroscope at rest never reads exactly zero, and what it reads instead
+//! drifts as it warms up, which would creep the pointer along. The first [`CALIBRATION_REPORTS`] reports after
+//! connecting are taken as the zero offset, with the pointer held still meanwhile, and the offset then keeps
+//! following the readings slowly whenever the remote is close to still. Pressing the `recenter` button takes the
+//! offset again, for when the pointer starts creeping anyway; the remote should be put down while it does.
+
+use std::io;
+
+use libc::input_event;
+
+use crate::{
+    button::Button,
+    config::Config,
+    evdev::{ABS_RX, ABS_RZ, EV_ABS, EV_REL, EV_SYN, REL_X, REL_Y, SYN_REPORT},
+    ir,
+    uinput::{Capabilities, VirtualDevice},
+};
+
+/// How many reports the zero offset is averaged over, about a second's worth.
+pub const CALIBRATION_REPORTS: u32 = 100;
+
+/// How far from the zero offset, in gyroscope units, still counts as resting, so drift is followed.
+pub const REST_THRESHOLD: f32 = 40.0;
+
+/// How much of the way to a resting reading the zero offset moves each report.
+pub const DRIFT_RATE: f32 = 0.01;
+
+/// Learns a gyroscope's zero offset, and follows it as it drifts.
+pub struct DriftCompensator {
+    zero: [f32; 2],
+    // Summed readings while calibrating, and how many of them there are
+    sum: [f32; 2],
+    reports: u32,
+}
+
+impl DriftCompensator {
+    pub fn new() -> DriftCompensator {
+        DriftCompensator {
+            zero: [0.0; 2],
+            sum: [0.0; 2],
+            reports: 0,
+        }
+    }
+
+    /// Forgets the zero offset, taking it again over the next [`CALIBRATION_REPORTS`] reports.
+    pub fn recalibrate(&mut self) {
+        self.sum = [0.0; 2];
+        self.reports = 0;
+    }
+
+    /// Whether the zero offset is still being taken.
+    pub fn is_calibrating(&self) -> bool {
+        self.reports < CALIBRATION_REPORTS
+    }
+
+    /// The rates with the zero offset taken out, `None` while it's still being taken.
+    pub fn correct(&mut self, rates: [f32; 2]) -> Option<[f32; 2]> {
+        if self.is_calibrating() {
+            self.sum = [self.sum[0] + rates[0], self.sum[1] + rates[1]];
+            self.reports += 1;
+            if !self.is_calibrating() {
+                self.zero = self.sum.map(|sum| sum / CALIBRATION_REPORTS as f32);
+            }
+
+            return None;
+        }
+
+        let corrected = [rates[0] - self.zero[0], rates[1] - self.zero[1]];
+        if corrected.iter().all(|rate| rate.abs() < REST_THRESHOLD) {
+            self.zero = [
+                self.zero[0] + corrected[0] * DRIFT_RATE,
+                self.zero[1] + corrected[1] * DRIFT_RATE,
+            ];
+        }
+
+        Some(corrected)
+    }
+}
+
+impl Default for DriftCompensator {
+    fn default() -> DriftCompensator {
+        DriftCompensator::new()
+    }
+}
+
+pub struct GyroMouse {
+    device: VirtualDevice,
+    sensitivity: f32,
+    // Which of x and y are flipped
+    invert: (bool, bool),
+    recenter: Option<Button>,
+    // The latest `[yaw, roll, pitch]` rates, hid-wiimote's ABS_RX to ABS_RZ
+    rates: [i32; 3],
+    drift: DriftCompensator,
+    // Fractional pixels that haven't been moved yet
+    remainder: (f32, f32),
+}
+
+impl GyroMouse {
+    /// Creates the pointer device for the `[gyro_mouse]` settings in `config`.
+    pub fn new(device_name: &str, config: &Config) -> io::Result<GyroMouse> {
+        let capabilities = Capabilities {
+            relative: vec![REL_X, REL_Y],
+            ..Capabilities::default()
+        };
+
+        let gyro_mouse = &config.gyro_mouse;
+        Ok(GyroMouse {
+            device: VirtualDevice::create(&format!("{} Gyro Pointer", device_name), &capabilities)?,
+            sensitivity: gyro_mouse.sensitivity,
+            invert: (gyro_mouse.invert_x, gyro_mouse.invert_y),
+            recenter: gyro_mouse.recenter,
+            rates: [0; 3],
+            drift: DriftCompensator::new(),
+            remainder: (0.0, 0.0),
+        })
+    }
+
+    /// Handles a key event from the Wii Remote, returning whether it was the recenter button, in which case it
+    /// shouldn't go anywhere else.
+    pub fn handle_key(&mut self, key: u16, pressed: bool) -> bool {
+        if self.recenter.map(Button::code) != Some(key) {
+            return false;
+        }
+
+        if pressed {
+            self.drift.recalibrate();
+            self.remainder = (0.0, 0.0);
+        }
+
+        true
+    }
+
+    /// Feeds one event from the MotionPlus interface, moving the pointer once a full report has arrived.
+    pub fn handle_event(&mut self, event: &input_event) -> io::Result<()> {
+        match (event.type_, event.code) {
+            (EV_ABS, ABS_RX..=ABS_RZ) => {
+                self.rates[(event.code - ABS_RX) as usize] = event.value;
+                Ok(())
+            }
+            (EV_SYN, SYN_REPORT) => self.frame(),
+            _ => Ok(()),
+        }
+    }
+
+    fn frame(&mut self) -> io::Result<()> {
+        let [yaw, _, pitch] = self.rates;
+        let Some([yaw, pitch]) = self.drift.correct([yaw as f32, pitch as f32]) else {
+            return Ok(());
+        };
+
+        // Turning right and up reads as negative, the opposite of the pointer's axes
+        let (dx, dy) = ir::invert((-yaw, -pitch), self.invert);
+        let (x, y) = (
+            self.remainder.0 + dx * self.sensitivity,
+            self.remainder.1 + dy * self.sensitivity,
+        );
+        self.remainder = (x.fract(), y.fract());
+
+        let (x, y) = (x.trunc() as i32, y.trunc() as i32);
+        if x != 0 || y != 0 {
+            self.device.emit(EV_REL, REL_X, x)?;
+            self.device.emit(EV_REL, REL_Y, y)?;
+            self.device.sync()?;
+        }
+
+        Ok(())
+    }
+}
@@ -0,0 +1,105 @@
+//! The last few connects and disconnects, for the status socket's `history` command to show how a remote has been
+//! behaving without going through the logs.
+//!
+//! Only the newest [`capacity`](set_capacity) events are kept, older ones are dropped as new ones come in.
+
+use std::{
+    collections::VecDeque,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Mutex,
+    },
+    time::SystemTime,
+};
+
+/// How many events are kept unless [`set_capacity`] says otherwise.
+pub const DEFAULT_CAPACITY: usize = 50;
+
+/// The most events [`set_capacity`] allows, each one is only a few dozen bytes.
+pub const MAX_CAPACITY: usize = 1000;
+
+static CAPACITY: AtomicUsize = AtomicUsize::new(DEFAULT_CAPACITY);
+static EVENTS: Mutex<VecDeque<Entry>> = Mutex::new(VecDeque::new());
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ConnectionEvent {
+    Connected,
+    /// Came back within the reconnect grace period, keeping its player slot.
+    Reconnected,
+    Disconnected(DisconnectReason),
+}
+
+/// Why a remote disconnected, as far as BlueWii can tell.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum DisconnectReason {
+    /// Disconnected by BlueWii for not being used within the idle timeout.
+    Idle,
+    /// Turned off with its power button.
+    PowerOff,
+    /// Went out of range or ran flat, anything that stops it reporting before the link drops.
+    OutOfRange,
+    /// Disconnected by BlueWii after the system resumed from suspend.
+    Resume,
+    /// Disconnected by `--simulate-disconnect-after`.
+    Simulated,
+    /// Disconnected by holding Home, see `home_long_press`.
+    LongPress,
+}
+
+impl DisconnectReason {
+    pub fn name(self) -> &'static str {
+        match self {
+            DisconnectReason::Idle => "idle",
+            DisconnectReason::PowerOff => "power-off",
+            DisconnectReason::OutOfRange => "out-of-range",
+            DisconnectReason::Resume => "resume",
+            DisconnectReason::Simulated => "simulated",
+            DisconnectReason::LongPress => "long-press",
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct Entry {
+    pub at: SystemTime,
+    pub address: String,
+    pub event: ConnectionEvent,
+}
+
+/// Keeps the newest `capacity` events from now on, capped at [`MAX_CAPACITY`], 0 keeps none.
+pub fn set_capacity(capacity: usize) {
+    let capacity = capacity.min(MAX_CAPACITY);
+    CAPACITY.store(capacity, Ordering::Relaxed);
+
+    let mut events = EVENTS.lock().unwrap();
+    let excess = events.len().saturating_sub(capacity);
+    events.drain(..excess);
+}
+
+pub fn record(address: &str, event: ConnectionEvent) {
+    let capacity = CAPACITY.load(Ordering::Relaxed);
+    if capacity == 0 {
+        return;
+    }
+
+    let mut events = EVENTS.lock().unwrap();
+    while events.len() >= capacity {
+        events.pop_front();
+    }
+
+    events.push_back(Entry {
+        at: SystemTime::now(),
+        address: address.to_owned(),
+        event,
+    });
+}
+
+/// The newest `count` events, oldest first.
+pub fn recent(count: usize) -> Vec<Entry> {
+    let events = EVENTS.lock().unwrap();
+    events
+        .iter()
+        .skip(events.len().saturating_sub(count))
+        .cloned()
+        .collect()
+}
@@ -0,0 +1,67 @@
+//! Shell commands run when a remote connects or disconnects, from the config file's `[hooks]` table, like playing a
+//! sound through the PC's speakers since the remote's own is hard to hear:
+//!
+//! ```toml
+//! [hooks]
+//! on_connect = "paplay /usr/share/sounds/freedesktop/stereo/device-added.oga"
+//! on_disconnect = "paplay /usr/share/sounds/freedesktop/stereo/device-removed.oga"
+//! cooldown = 5
+//! ```
+//!
+//! They run like the [`commands`](crate::commands) bound to buttons do, through `sh -c` without being waited for,
+//! with `BLUEWII_ADDRESS` and `BLUEWII_PLAYER` set to the remote's. The same goes for who can run what: anyone who can
+//! edit the config file can run anything as the user BlueWii runs as, so keep the file writable only by its owner.
+//!
+//! Each hook runs at most once every `cooldown` seconds for the same remote, so one dropping in and out of range
+//! doesn't start a pile of them, while another remote connecting in the meantime still gets its own.
+
+use std::{
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+use log::debug;
+
+use crate::{commands, config::HooksConfig};
+
+/// When each hook last ran for each remote, by hook (connect first) and address.
+static LAST_RUN: Mutex<Vec<(usize, String, Instant)>> = Mutex::new(Vec::new());
+
+/// Runs `on_connect` for the remote at `address`, now player `player_slot`.
+pub fn connected(hooks: &HooksConfig, address: &str, player_slot: u8) {
+    if let Some(command) = &hooks.on_connect {
+        run(0, command, hooks.cooldown, address, player_slot);
+    }
+}
+
+/// Runs `on_disconnect` for the remote at `address`, which was player `player_slot`.
+pub fn disconnected(hooks: &HooksConfig, address: &str, player_slot: u8) {
+    if let Some(command) = &hooks.on_disconnect {
+        run(1, command, hooks.cooldown, address, player_slot);
+    }
+}
+
+fn run(hook: usize, command: &str, cooldown: u64, address: &str, player_slot: u8) {
+    {
+        let mut last_run = LAST_RUN.lock().unwrap();
+        let cooldown = Duration::from_secs(cooldown);
+        last_run.retain(|(_, _, at)| at.elapsed() < cooldown);
+        if last_run
+            .iter()
+            .any(|(ran, ran_for, _)| *ran == hook && ran_for == address)
+        {
+            debug!("Not running `{}' again so soon for {}", command, address);
+            return;
+        }
+
+        last_run.push((hook, address.to_owned(), Instant::now()));
+    }
+
+    commands::run_with_env(
+        command.to_owned(),
+        vec![
+            ("BLUEWII_ADDRESS", address.to_owned()),
+            ("BLUEWII_PLAYER", player_slot.to_string()),
+        ],
+    );
+}
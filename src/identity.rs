@@ -0,0 +1,112 @@
+//! Which HID device each remote is, by Bluetooth address.
+//!
+//! The kernel numbers HID devices in the order they appear, so a remote that reconnects comes back under a new
+//! syspath, `0005:057E:0306.0006` becoming `0005:057E:0306.0007`. Everything that has to outlive a reconnect, like
+//! player slots and the virtual devices held through the grace period, goes by the address, and this is where the
+//! address is looked up from a syspath or the other way around.
+
+use std::{collections::HashMap, sync::Mutex};
+
+static IDENTITIES: Mutex<Option<RemoteIdentities>> = Mutex::new(None);
+
+/// Bluetooth addresses and the syspaths of their HID devices, each address having at most one.
+#[derive(Clone, Debug, Default)]
+pub struct RemoteIdentities {
+    syspaths: HashMap<String, String>,
+}
+
+impl RemoteIdentities {
+    pub fn new() -> RemoteIdentities {
+        RemoteIdentities::default()
+    }
+
+    /// Records that `address` is at `syspath` now, returning the syspath it had before when that's a different one.
+    /// Another address last seen at `syspath` loses it, the kernel having handed it on.
+    pub fn update(&mut self, address: &str, syspath: &str) -> Option<String> {
+        let address = address.to_uppercase();
+        self.syspaths
+            .retain(|other, other_syspath| *other == address || other_syspath != syspath);
+
+        match self.syspaths.insert(address, syspath.to_owned()) {
+            Some(previous) if previous != syspath => Some(previous),
+            _ => None,
+        }
+    }
+
+    /// Where `address` was last seen.
+    pub fn syspath(&self, address: &str) -> Option<&str> {
+        self.syspaths
+            .get(&address.to_uppercase())
+            .map(String::as_str)
+    }
+
+    /// The address last seen at `syspath`.
+    pub fn address(&self, syspath: &str) -> Option<&str> {
+        self.syspaths
+            .iter()
+            .find(|(_, known)| *known == syspath)
+            .map(|(address, _)| address.as_str())
+    }
+}
+
+/// Records that `address` is at `syspath` now, see [`RemoteIdentities::update`].
+pub fn update(address: &str, syspath: &str) -> Option<String> {
+    IDENTITIES
+        .lock()
+        .unwrap()
+        .get_or_insert_with(RemoteIdentities::new)
+        .update(address, syspath)
+}
+
+/// Where `address` was last seen.
+pub fn syspath(address: &str) -> Option<String> {
+    IDENTITIES
+        .lock()
+        .unwrap()
+        .as_ref()?
+        .syspath(address)
+        .map(str::to_owned)
+}
+
+/// The address last seen at `syspath`.
+pub fn address(syspath: &str) -> Option<String> {
+    IDENTITIES
+        .lock()
+        .unwrap()
+        .as_ref()?
+        .address(syspath)
+        .map(str::to_owned)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const FIRST: &str = "/sys/devices/virtual/misc/uhid/0005:057E:0306.0006";
+    const SECOND: &str = "/sys/devices/virtual/misc/uhid/0005:057E:0306.0007";
+
+    #[test]
+    fn address_keeps_its_identity_across_syspaths() {
+        let mut identities = RemoteIdentities::new();
+        assert_eq!(identities.update("00:19:1d:12:34:56", FIRST), None);
+        assert_eq!(identities.update("00:19:1D:12:34:56", FIRST), None);
+        assert_eq!(
+            identities.update("00:19:1D:12:34:56", SECOND),
+            Some(FIRST.to_owned())
+        );
+
+        assert_eq!(identities.syspath("00:19:1d:12:34:56"), Some(SECOND));
+        assert_eq!(identities.address(SECOND), Some("00:19:1D:12:34:56"));
+        assert_eq!(identities.address(FIRST), None);
+    }
+
+    #[test]
+    fn syspath_handed_on_belongs_to_the_new_address() {
+        let mut identities = RemoteIdentities::new();
+        identities.update("00:19:1D:12:34:56", FIRST);
+        identities.update("00:19:1D:AB:CD:EF", FIRST);
+
+        assert_eq!(identities.address(FIRST), Some("00:19:1D:AB:CD:EF"));
+        assert_eq!(identities.syspath("00:19:1D:12:34:56"), None);
+    }
+}
@@ -0,0 +1,61 @@
+//! Keeps two daemons from fighting over the same remote, which otherwise shows up as endless connect/disconnect
+//! cycles.
+//!
+//! The lock is an `flock` on `$XDG_RUNTIME_DIR/bluewii.lock` (or `/tmp/bluewii.lock`), and the file holds the
+//! owner's pid so a refusal can say who has it. With `--adapter` each controller gets a lock file of its own, so
+//! one daemon per adapter can run side by side. The kernel drops the lock along with the last descriptor, so a
+//! daemon that crashed never leaves a stale lock behind, whatever the file still says.
+
+use std::{
+    env,
+    fs::{self, File, OpenOptions},
+    io::{self, Write},
+    os::fd::AsRawFd,
+    path::{Path, PathBuf},
+};
+
+/// Held for as long as the daemon runs, dropping it releases the lock.
+pub struct InstanceLock {
+    _file: File,
+}
+
+/// Where the lock for `adapter` lives, the default controller sharing one with every other daemon using it.
+pub fn path(adapter: Option<&str>) -> PathBuf {
+    let name = match adapter {
+        Some(adapter) => format!("bluewii-{}.lock", adapter.replace(':', "")),
+        None => "bluewii.lock".to_owned(),
+    };
+
+    match env::var_os("XDG_RUNTIME_DIR") {
+        Some(dir) if !dir.is_empty() => PathBuf::from(dir).join(name),
+        _ => PathBuf::from("/tmp").join(name),
+    }
+}
+
+/// Takes the lock at `path` without waiting, `None` if another process already holds it.
+pub fn acquire(path: &Path) -> io::Result<Option<InstanceLock>> {
+    // Not truncated until the lock is ours, the pid in it belongs to whoever holds it
+    let mut file = OpenOptions::new()
+        .read(true)
+        .write(true)
+        .create(true)
+        .truncate(false)
+        .open(path)?;
+
+    if unsafe { libc::flock(file.as_raw_fd(), libc::LOCK_EX | libc::LOCK_NB) } < 0 {
+        let err = io::Error::last_os_error();
+        return match err.kind() {
+            io::ErrorKind::WouldBlock => Ok(None),
+            _ => Err(err),
+        };
+    }
+
+    file.set_len(0)?;
+    writeln!(file, "{}", std::process::id())?;
+    Ok(Some(InstanceLock { _file: file }))
+}
+
+/// The pid written by whoever holds the lock at `path`.
+pub fn holder(path: &Path) -> Option<u32> {
+    fs::read_to_string(path).ok()?.trim().parse().ok()
+}
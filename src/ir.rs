@@ -0,0 +1,507 @@
+//! Turns the IR camera's view of the sensor bar into pointer movement or scrolling, and the buttons in
+//! `ir_buttons` into mouse clicks.
+//!
+//! The camera's sensitivity can be changed from the level hid-wiimote sets up, see [`set_sensitivity`].
+
+use std::{fs::OpenOptions, io, path::Path, time::Instant};
+
+use libc::input_event;
+use log::warn;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    button::Button,
+    config::{self, Config},
+    evdev::{
+        self, ABS_HAT0X, ABS_HAT3Y, EV_ABS, EV_KEY, EV_REL, EV_SYN, REL_WHEEL, REL_X, REL_Y,
+        SYN_REPORT,
+    },
+    speaker,
+    throttle::Throttle,
+    uinput::{Capabilities, VirtualDevice},
+};
+
+/// hid-wiimote reports this coordinate for a dot the camera can't see.
+pub(crate) const INVALID_DOT: i32 = 1023;
+
+/// The sensitivity levels the Wii itself offers, from 1 (least sensitive) to 5, as the two blocks of camera
+/// registers each one is written to, see https://wiibrew.org/wiki/Wiimote#Sensitivity_Settings.
+///
+/// hid-wiimote always sets up level 3. A higher level picks up the sensor bar from further away and in bright
+/// rooms, at the cost of mistaking more reflections and light bulbs for dots, which a lower level filters out.
+const SENSITIVITY_LEVELS: [([u8; 9], [u8; 2]); 5] = [
+    (
+        [0x02, 0x00, 0x00, 0x71, 0x01, 0x00, 0x64, 0x00, 0xfe],
+        [0xfd, 0x05],
+    ),
+    (
+        [0x02, 0x00, 0x00, 0x71, 0x01, 0x00, 0x96, 0x00, 0xb4],
+        [0xb3, 0x04],
+    ),
+    (
+        [0x02, 0x00, 0x00, 0x71, 0x01, 0x00, 0xaa, 0x00, 0x64],
+        [0x63, 0x03],
+    ),
+    (
+        [0x02, 0x00, 0x00, 0x71, 0x01, 0x00, 0xc8, 0x00, 0x36],
+        [0x35, 0x03],
+    ),
+    (
+        [0x07, 0x00, 0x00, 0x71, 0x01, 0x00, 0x72, 0x00, 0x20],
+        [0x1f, 0x03],
+    ),
+];
+
+// Camera registers, the enable register has to be 0x01 while the others are written and 0x08 to use them
+const REGISTER_ENABLE: u32 = 0xb00030;
+const REGISTER_SENSITIVITY_1: u32 = 0xb00000;
+const REGISTER_SENSITIVITY_2: u32 = 0xb0001a;
+
+/// One of the four dots the IR camera tracks, in its 1024x768 view.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct IrPoint {
+    pub x: i32,
+    pub y: i32,
+    /// Whether the camera can see it, `x` and `y` mean nothing when it can't.
+    pub valid: bool,
+}
+
+impl IrPoint {
+    /// A point as hid-wiimote reports it, invalid when either coordinate is [`INVALID_DOT`].
+    pub fn new(x: i32, y: i32) -> IrPoint {
+        IrPoint {
+            x,
+            y,
+            valid: x != INVALID_DOT && y != INVALID_DOT,
+        }
+    }
+
+    /// A point the camera can't see.
+    pub fn invalid() -> IrPoint {
+        IrPoint::new(INVALID_DOT, INVALID_DOT)
+    }
+
+    /// Where it is, if the camera can see it.
+    pub fn position(&self) -> Option<(i32, i32)> {
+        self.valid.then_some((self.x, self.y))
+    }
+}
+
+/// Pointer pixels moved per unit of IR camera movement.
+const POINTER_SPEED: f32 = 2.0;
+
+#[derive(Clone, Copy, Default, Deserialize, Serialize, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum IrMode {
+    /// The IR camera isn't used.
+    #[default]
+    Off,
+    /// Moving the remote moves the mouse pointer.
+    Pointer,
+    /// Like `Pointer`, except that vertical movement scrolls while the scroll modifier is held.
+    Scroll,
+}
+
+/// How pointer movement grows with the speed the remote is moved at.
+#[derive(Clone, Copy, Debug, Default, Deserialize, Serialize, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum IrAccel {
+    /// The pointer moves as far as the remote does, however fast.
+    #[default]
+    Linear,
+    /// Slow movements shrink and fast ones grow, with the speed squared.
+    Quadratic,
+    /// Like `Quadratic` with any exponent, above 1.0 accelerates and below 1.0 decelerates.
+    #[serde(serialize_with = "config::serialize_f32")]
+    Gamma(f32),
+}
+
+impl IrAccel {
+    pub fn gamma(self) -> f32 {
+        match self {
+            IrAccel::Linear => 1.0,
+            IrAccel::Quadratic => 2.0,
+            IrAccel::Gamma(gamma) => gamma,
+        }
+    }
+}
+
+pub struct IrPointer {
+    device: VirtualDevice,
+    mode: IrMode,
+    // Wii Remote key code -> mouse button
+    buttons: Vec<(u16, u16)>,
+    // Mouse buttons currently pressed, released again if the pointer goes away
+    held: Vec<u16>,
+    scroll_modifier: Button,
+    scroll_sensitivity: f32,
+    modifier_held: bool,
+    dots: [(i32, i32); 4],
+    // Which of x and y are flipped, for a sensor bar the wrong way up
+    invert: (bool, bool),
+    smoothing: Smoother,
+    accel: IrAccel,
+    accel_scale: f32,
+    last_position: Option<(f32, f32)>,
+    // Fractional wheel steps that haven't been emitted yet
+    scroll_remainder: f32,
+    // Skipped frames aren't lost, the next one moves the pointer by both
+    throttle: Throttle,
+}
+
+impl IrPointer {
+    /// Creates the pointer device for the `ir_*` settings in `config`. In the scroll mode the scroll modifier never
+    /// clicks, even when it's in `ir_buttons`.
+    pub fn new(device_name: &str, config: &Config) -> io::Result<IrPointer> {
+        let (mode, scroll_modifier) = (config.ir_mode, config.ir_scroll_modifier);
+        let mut clicks = Vec::new();
+        for (button, name) in &config.ir_buttons {
+            if mode == IrMode::Scroll && *button == scroll_modifier {
+                continue;
+            }
+
+            match evdev::code_from_name(name) {
+                Some(output) => clicks.push((button.code(), output)),
+                None => warn!("ir_buttons maps {} to unknown code `{}'", button, name),
+            }
+        }
+
+        let capabilities = Capabilities {
+            keys: clicks.iter().map(|(_, output)| *output).collect(),
+            relative: vec![REL_X, REL_Y, REL_WHEEL],
+            ..Capabilities::default()
+        };
+
+        Ok(IrPointer {
+            device: VirtualDevice::create(&format!("{} Pointer", device_name), &capabilities)?,
+            mode,
+            buttons: clicks,
+            held: Vec::new(),
+            scroll_modifier,
+            scroll_sensitivity: config.ir_scroll_sensitivity,
+            modifier_held: false,
+            dots: [(INVALID_DOT, INVALID_DOT); 4],
+            invert: (config.ir_invert_x, config.ir_invert_y),
+            smoothing: Smoother::new(config.ir_smoothing),
+            accel: config.ir_accel,
+            accel_scale: config.ir_accel_scale,
+            last_position: None,
+            scroll_remainder: 0.0,
+            throttle: Throttle::new(0),
+        })
+    }
+
+    /// Handles a key event from the Wii Remote, returning whether it clicked a mouse button, in which case it
+    /// shouldn't go anywhere else.
+    pub fn handle_key(&mut self, key: u16, pressed: bool) -> io::Result<bool> {
+        if key == self.scroll_modifier.code() {
+            self.modifier_held = pressed;
+            self.scroll_remainder = 0.0;
+        }
+
+        let output = match self.buttons.iter().find(|(wii_key, _)| *wii_key == key) {
+            Some((_, output)) => *output,
+            None => return Ok(false),
+        };
+
+        self.held.retain(|held| *held != output);
+        if pressed {
+            self.held.push(output);
+        }
+
+        self.device.emit(EV_KEY, output, pressed as i32)?;
+        self.device.sync()?;
+        Ok(true)
+    }
+
+    /// Lets go of every mouse button that's held, so none gets stuck.
+    pub fn release_held(&mut self) -> io::Result<()> {
+        if self.held.is_empty() {
+            return Ok(());
+        }
+
+        for output in std::mem::take(&mut self.held) {
+            self.device.emit(EV_KEY, output, 0)?;
+        }
+
+        self.device.sync()
+    }
+
+    /// Feeds one event from the IR interface, moving the pointer once a full report has arrived.
+    pub fn handle_event(&mut self, event: &input_event) -> io::Result<()> {
+        match (event.type_, event.code) {
+            (EV_ABS, ABS_HAT0X..=ABS_HAT3Y) => {
+                // The axes go HAT0X, HAT0Y, HAT1X, ... so each pair is one dot
+                let index = (event.code - ABS_HAT0X) as usize;
+                match index % 2 {
+                    0 => self.dots[index / 2].0 = event.value,
+                    _ => self.dots[index / 2].1 = event.value,
+                }
+
+                Ok(())
+            }
+            // Losing sight of the sensor bar is never skipped, or the pointer would jump once it's back
+            (EV_SYN, SYN_REPORT)
+                if dot_centroid(&self.points()).is_none()
+                    || self.throttle.ready(Instant::now()) =>
+            {
+                self.frame()
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// Moves the pointer at most `rate` times a second, 0 moves it on every report.
+    pub fn set_report_rate(&mut self, rate: u32) {
+        self.throttle = Throttle::new(rate);
+    }
+
+    /// Moves the pointer for a report that was held back, if it's due and no newer one replaced it.
+    pub fn flush(&mut self) -> io::Result<()> {
+        match self.throttle.take_due(Instant::now()) {
+            true => self.frame(),
+            false => Ok(()),
+        }
+    }
+
+    fn points(&self) -> [IrPoint; 4] {
+        self.dots.map(|(x, y)| IrPoint::new(x, y))
+    }
+
+    fn frame(&mut self) -> io::Result<()> {
+        let position = match dot_centroid(&self.points()) {
+            Some(position) => Some(self.smoothing.push(invert(position, self.invert))),
+            None => {
+                self.smoothing.reset();
+                None
+            }
+        };
+
+        let (dx, dy) = match (self.last_position, position) {
+            (Some((last_x, last_y)), Some((x, y))) => (x - last_x, y - last_y),
+            _ => {
+                // Lost sight of the sensor bar, start over once it's back instead of jumping
+                self.last_position = position;
+                return Ok(());
+            }
+        };
+
+        self.last_position = position;
+
+        // The camera sees the sensor bar move the opposite way the remote does
+        let (dx, dy) = (-dx, -dy);
+        if self.mode == IrMode::Scroll && self.modifier_held {
+            self.scroll_remainder += -dy * self.scroll_sensitivity;
+            let steps = self.scroll_remainder.trunc();
+            if steps != 0.0 {
+                self.scroll_remainder -= steps;
+                self.device.emit(EV_REL, REL_WHEEL, steps as i32)?;
+                self.device.sync()?;
+            }
+
+            return Ok(());
+        }
+
+        let (dx, dy) = accelerate((dx, dy), self.accel.gamma(), self.accel_scale);
+        let (x, y) = (
+            (dx * POINTER_SPEED).round() as i32,
+            (dy * POINTER_SPEED).round() as i32,
+        );
+
+        if x != 0 || y != 0 {
+            self.device.emit(EV_REL, REL_X, x)?;
+            self.device.emit(EV_REL, REL_Y, y)?;
+            self.device.sync()?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Flips the sign of `x` and `y` as asked, so the pointer moves the other way along them. Only ever used on
+/// positions that are then compared with each other, so where they end up doesn't matter.
+pub fn invert((x, y): (f32, f32), (invert_x, invert_y): (bool, bool)) -> (f32, f32) {
+    let flip = |value: f32, invert: bool| match invert {
+        true => -value,
+        false => value,
+    };
+
+    (flip(x, invert_x), flip(y, invert_y))
+}
+
+/// Sets the camera to sensitivity `level` through the remote's `hidraw` node, clamped to 1 to 5. hid-wiimote sets
+/// the camera up again whenever its IR interface is opened, so this only lasts until then.
+pub fn set_sensitivity(hidraw_path: &Path, level: u8) -> io::Result<()> {
+    let (block_1, block_2) = SENSITIVITY_LEVELS[level.clamp(1, 5) as usize - 1];
+    let mut hidraw = OpenOptions::new().write(true).open(hidraw_path)?;
+    speaker::write_register(&mut hidraw, REGISTER_ENABLE, &[0x01])?;
+    speaker::write_register(&mut hidraw, REGISTER_SENSITIVITY_1, &block_1)?;
+    speaker::write_register(&mut hidraw, REGISTER_SENSITIVITY_2, &block_2)?;
+    speaker::write_register(&mut hidraw, REGISTER_ENABLE, &[0x08])
+}
+
+/// The midpoint of every point the camera can currently see, the single cursor position the pointer follows.
+pub fn dot_centroid(points: &[IrPoint]) -> Option<(f32, f32)> {
+    let visible = points
+        .iter()
+        .filter_map(IrPoint::position)
+        .collect::<Vec<_>>();
+
+    if visible.is_empty() {
+        return None;
+    }
+
+    let count = visible.len() as f32;
+    Some((
+        visible.iter().map(|(x, _)| *x as f32).sum::<f32>() / count,
+        visible.iter().map(|(_, y)| *y as f32).sum::<f32>() / count,
+    ))
+}
+
+/// Scales a movement of `distance` camera units along a curve of exponent `gamma` (clamped to be positive),
+/// which crosses the linear one at `scale` units: 0 stays 0, `scale` stays `scale`, and a longer movement never
+/// turns into a shorter one.
+pub fn curve(distance: f32, gamma: f32, scale: f32) -> f32 {
+    if distance <= 0.0 || scale <= 0.0 {
+        return distance.max(0.0);
+    }
+
+    scale * (distance / scale).powf(gamma.max(f32::EPSILON))
+}
+
+/// Applies [`curve`] to the length of a `(dx, dy)` movement, keeping its direction.
+pub fn accelerate((dx, dy): (f32, f32), gamma: f32, scale: f32) -> (f32, f32) {
+    let distance = dx.hypot(dy);
+    if distance == 0.0 {
+        return (0.0, 0.0);
+    }
+
+    let factor = curve(distance, gamma, scale) / distance;
+    (dx * factor, dy * factor)
+}
+
+/// An exponential moving average over IR positions, which steadies a jittery cursor.
+pub struct Smoother {
+    factor: f32,
+    value: Option<(f32, f32)>,
+}
+
+impl Smoother {
+    /// `factor` is how much of the previous value carries over, 0.0 passes positions straight through and
+    /// 1.0 never moves off the first one.
+    pub fn new(factor: f32) -> Smoother {
+        Smoother {
+            factor: factor.clamp(0.0, 1.0),
+            value: None,
+        }
+    }
+
+    /// Adds a position, returning the smoothed one.
+    pub fn push(&mut self, (x, y): (f32, f32)) -> (f32, f32) {
+        let value = match self.value {
+            Some((last_x, last_y)) => (
+                last_x * self.factor + x * (1.0 - self.factor),
+                last_y * self.factor + y * (1.0 - self.factor),
+            ),
+            None => (x, y),
+        };
+
+        self.value = Some(value);
+        value
+    }
+
+    /// Forgets the previous position, so the next one is taken as is.
+    pub fn reset(&mut self) {
+        self.value = None;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn invert_flips_only_the_asked_axes() {
+        let position = (300.0, -200.0);
+        assert_eq!(invert(position, (false, false)), (300.0, -200.0));
+        assert_eq!(invert(position, (true, false)), (-300.0, -200.0));
+        assert_eq!(invert(position, (false, true)), (300.0, 200.0));
+        assert_eq!(invert(position, (true, true)), (-300.0, 200.0));
+    }
+
+    #[test]
+    fn invert_reverses_movement() {
+        // Movement is the difference of two positions, so flipping both flips the movement between them
+        let (from, to) = ((100.0, 100.0), (140.0, 90.0));
+        let movement = |(x1, y1): (f32, f32), (x2, y2): (f32, f32)| (x2 - x1, y2 - y1);
+        assert_eq!(movement(from, to), (40.0, -10.0));
+        assert_eq!(
+            movement(invert(from, (true, true)), invert(to, (true, true))),
+            (-40.0, 10.0)
+        );
+    }
+
+    #[test]
+    fn smoothing_converges_on_a_step() {
+        let mut smoother = Smoother::new(0.5);
+        assert_eq!(smoother.push((0.0, 0.0)), (0.0, 0.0));
+
+        let mut last = 0.0;
+        for _ in 0..20 {
+            let (x, y) = smoother.push((100.0, -100.0));
+            assert!(x > last && x <= 100.0);
+            assert_eq!(y, -x);
+            last = x;
+        }
+        assert!(100.0 - last < 0.001);
+    }
+
+    #[test]
+    fn smoothing_extremes() {
+        let mut passthrough = Smoother::new(0.0);
+        passthrough.push((0.0, 0.0));
+        assert_eq!(passthrough.push((100.0, 50.0)), (100.0, 50.0));
+
+        let mut frozen = Smoother::new(1.0);
+        frozen.push((10.0, 20.0));
+        for _ in 0..10 {
+            assert_eq!(frozen.push((500.0, 500.0)), (10.0, 20.0));
+        }
+
+        // Losing the sensor bar lets the next position through as is
+        frozen.reset();
+        assert_eq!(frozen.push((500.0, 500.0)), (500.0, 500.0));
+    }
+
+    #[test]
+    fn curve_endpoints() {
+        let scale = 40.0;
+        for accel in [IrAccel::Linear, IrAccel::Quadratic, IrAccel::Gamma(0.5)] {
+            assert_eq!(curve(0.0, accel.gamma(), scale), 0.0);
+            assert!((curve(scale, accel.gamma(), scale) - scale).abs() < 0.001);
+        }
+
+        assert_eq!(curve(25.0, IrAccel::Linear.gamma(), scale), 25.0);
+        assert_eq!(curve(80.0, IrAccel::Quadratic.gamma(), scale), 160.0);
+        assert_eq!(curve(-5.0, IrAccel::Quadratic.gamma(), scale), 0.0);
+    }
+
+    #[test]
+    fn curve_is_monotonic() {
+        for gamma in [0.5, 1.0, 1.5, 2.0, 3.0] {
+            let mut last = 0.0;
+            for step in 1..=400 {
+                let distance = curve(step as f32 * 0.5, gamma, 40.0);
+                assert!(distance > last, "gamma {} at {}", gamma, step);
+                last = distance;
+            }
+        }
+    }
+
+    #[test]
+    fn acceleration_keeps_direction() {
+        let (dx, dy) = accelerate((30.0, -40.0), 2.0, 25.0);
+        assert!((dx - 60.0).abs() < 0.001 && (dy + 80.0).abs() < 0.001);
+        assert_eq!(accelerate((0.0, 0.0), 2.0, 25.0), (0.0, 0.0));
+    }
+}
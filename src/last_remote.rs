@@ -0,0 +1,36 @@
+//! Remembers the last remote that connected, so the next run can call it back directly instead of scanning.
+//!
+//! The address is kept on its own in `$XDG_STATE_HOME/bluewii/last.txt` (or `~/.local/state/bluewii/last.txt`),
+//! away from the config file since it changes on its own.
+
+use std::{env, fs, io, path::PathBuf};
+
+use crate::wii_remote::WiiRemote;
+
+pub fn path() -> Option<PathBuf> {
+    let state_dir = match env::var_os("XDG_STATE_HOME") {
+        Some(dir) if !dir.is_empty() => PathBuf::from(dir),
+        _ => PathBuf::from(env::var_os("HOME")?).join(".local/state"),
+    };
+
+    Some(state_dir.join("bluewii").join("last.txt"))
+}
+
+/// The address saved last time, unless the file is missing or doesn't hold a valid address.
+pub fn load() -> Option<String> {
+    let address = fs::read_to_string(path()?).ok()?;
+    let address = address.trim().to_uppercase();
+    match WiiRemote::is_valid_address(&address) {
+        true => Some(address),
+        false => None,
+    }
+}
+
+pub fn save(address: &str) -> io::Result<()> {
+    let path = path().ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "$HOME is not set"))?;
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir)?;
+    }
+
+    fs::write(path, format!("{}\n", address.to_uppercase()))
+}
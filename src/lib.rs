@@ -0,0 +1,48 @@
+//! Manages Wii Remotes through bluez and hid-wiimote, and forwards their input to virtual devices.
+//!
+//! The `BlueWii` binary is built on top of this crate, [`wii_remote::WiiRemote`] is the place to start.
+
+pub mod accel;
+pub mod backlog;
+pub mod backoff;
+pub mod balance_board;
+pub mod battery;
+pub mod bluetoothctl;
+pub mod button;
+pub mod chord;
+pub mod clock;
+pub mod commands;
+pub mod config;
+pub mod device_line;
+pub mod error;
+pub mod evdev;
+pub mod events;
+pub mod forwarder;
+pub mod gesture;
+pub mod gyro;
+pub mod history;
+pub mod hooks;
+pub mod identity;
+pub mod instance_lock;
+pub mod ir;
+pub mod last_remote;
+pub mod lib_input;
+pub mod long_press;
+pub mod mappings_dir;
+pub mod metrics;
+pub mod notify;
+pub mod permissions;
+pub mod raw_extension;
+pub mod repeat;
+pub mod resume;
+pub mod rumble;
+pub mod slots;
+pub mod speaker;
+pub mod status;
+pub mod stick;
+pub mod throttle;
+pub mod uinput;
+pub mod utils;
+pub mod versions;
+pub mod wii_remote;
+pub mod xwiimote;
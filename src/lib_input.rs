@@ -1,15 +1,97 @@
 use std::{
-    ffi::{c_int, CStr, OsStr},
+    ffi::{c_char, c_int, CStr, OsStr},
     fs::{File, OpenOptions},
+    io,
     os::{
         fd::{FromRawFd, IntoRawFd},
         raw::c_void,
         unix::{ffi::OsStrExt, fs::OpenOptionsExt},
     },
     path::Path,
+    sync::Mutex,
 };
 
-use input_sys::libinput_interface;
+use input_sys::{
+    libinput, libinput_device_capability, libinput_device_capability_LIBINPUT_DEVICE_CAP_GESTURE,
+    libinput_device_capability_LIBINPUT_DEVICE_CAP_KEYBOARD,
+    libinput_device_capability_LIBINPUT_DEVICE_CAP_POINTER,
+    libinput_device_capability_LIBINPUT_DEVICE_CAP_SWITCH,
+    libinput_device_capability_LIBINPUT_DEVICE_CAP_TABLET_PAD,
+    libinput_device_capability_LIBINPUT_DEVICE_CAP_TABLET_TOOL,
+    libinput_device_capability_LIBINPUT_DEVICE_CAP_TOUCH, libinput_device_get_udev_device,
+    libinput_device_has_capability, libinput_dispatch, libinput_event_destroy,
+    libinput_event_get_device, libinput_event_get_type, libinput_event_type,
+    libinput_event_type_LIBINPUT_EVENT_DEVICE_ADDED as EVENT_DEVICE_ADDED,
+    libinput_event_type_LIBINPUT_EVENT_DEVICE_REMOVED as EVENT_DEVICE_REMOVED,
+    libinput_event_type_LIBINPUT_EVENT_GESTURE_SWIPE_BEGIN as EVENT_GESTURE_SWIPE_BEGIN,
+    libinput_event_type_LIBINPUT_EVENT_KEYBOARD_KEY as EVENT_KEYBOARD_KEY,
+    libinput_event_type_LIBINPUT_EVENT_POINTER_MOTION as EVENT_POINTER_MOTION,
+    libinput_event_type_LIBINPUT_EVENT_SWITCH_TOGGLE as EVENT_SWITCH_TOGGLE,
+    libinput_event_type_LIBINPUT_EVENT_TABLET_TOOL_AXIS as EVENT_TABLET_TOOL_AXIS,
+    libinput_event_type_LIBINPUT_EVENT_TOUCH_DOWN as EVENT_TOUCH_DOWN, libinput_get_event,
+    libinput_interface, libinput_log_priority, libinput_log_priority_LIBINPUT_LOG_PRIORITY_DEBUG,
+    libinput_log_priority_LIBINPUT_LOG_PRIORITY_ERROR,
+    libinput_log_priority_LIBINPUT_LOG_PRIORITY_INFO, libinput_log_set_priority,
+    libinput_udev_assign_seat, libinput_udev_create_context, libinput_unref,
+};
+use libudev_sys::{udev_device_get_syspath, udev_device_unref};
+use log::{debug, error, info, warn, LevelFilter};
+
+use crate::{
+    error::{self, BlueWiiError},
+    permissions,
+};
+
+// Every descriptor handed to libinput that it hasn't closed yet, so a repeated close is caught instead of
+// closing whatever unrelated file has since been given the same number
+static OPEN_FDS: Mutex<Vec<i32>> = Mutex::new(Vec::new());
+
+// `va_list` is passed on to `vsnprintf` untouched, so an opaque pointer is all that's needed for it. input-sys
+// leaves this function out for that reason.
+type LogHandler =
+    unsafe extern "C" fn(*mut libinput, libinput_log_priority, *const c_char, *mut c_void);
+
+extern "C" {
+    fn libinput_log_set_handler(libinput: *mut libinput, handler: Option<LogHandler>);
+    fn vsnprintf(
+        buffer: *mut c_char,
+        size: usize,
+        format: *const c_char,
+        args: *mut c_void,
+    ) -> c_int;
+}
+
+/// Every capability a libinput device can have, with the name it's logged under.
+const CAPABILITIES: [(libinput_device_capability, &str); 7] = [
+    (
+        libinput_device_capability_LIBINPUT_DEVICE_CAP_KEYBOARD,
+        "keyboard",
+    ),
+    (
+        libinput_device_capability_LIBINPUT_DEVICE_CAP_POINTER,
+        "pointer",
+    ),
+    (
+        libinput_device_capability_LIBINPUT_DEVICE_CAP_TOUCH,
+        "touch",
+    ),
+    (
+        libinput_device_capability_LIBINPUT_DEVICE_CAP_TABLET_TOOL,
+        "tablet tool",
+    ),
+    (
+        libinput_device_capability_LIBINPUT_DEVICE_CAP_TABLET_PAD,
+        "tablet pad",
+    ),
+    (
+        libinput_device_capability_LIBINPUT_DEVICE_CAP_GESTURE,
+        "gesture",
+    ),
+    (
+        libinput_device_capability_LIBINPUT_DEVICE_CAP_SWITCH,
+        "switch",
+    ),
+];
 
 pub static INTERFACE: libinput_interface = libinput_interface {
     open_restricted: Some(open_restricted_func),
@@ -27,14 +109,342 @@ extern "C" fn open_restricted_func(path: *const i8, flags: i32, _user_data: *mut
         .custom_flags(flags)
         .open(path)
     {
-        Ok(file) => file.into_raw_fd(), // Return the file descriptor on success
-        Err(err) => -err.raw_os_error().unwrap_or(-1), // Return a negative errno on failure
+        Ok(file) => {
+            // Ownership moves to libinput, which hands the descriptor back through `close_restricted_func`
+            let fd = file.into_raw_fd();
+            let mut open_fds = OPEN_FDS.lock().unwrap();
+            open_fds.push(fd);
+            debug!(
+                "Opened {} as fd {} ({} open)",
+                path.display(),
+                fd,
+                open_fds.len()
+            );
+            fd
+        }
+        Err(err) => {
+            if permissions::is_permission_error(&err) {
+                permissions::warn_denied(path);
+            }
+
+            -err.raw_os_error().unwrap_or(-1) // Return a negative errno on failure
+        }
     }
 }
 
 extern "C" fn close_restricted_func(fd: i32, _user_data: *mut c_void) {
+    if fd < 0 {
+        debug!("libinput asked to close invalid fd {}, ignoring", fd);
+        return;
+    }
+
+    let mut open_fds = OPEN_FDS.lock().unwrap();
+    let index = match open_fds.iter().position(|open_fd| *open_fd == fd) {
+        Some(index) => index,
+        None => {
+            warn!(
+                "libinput asked to close fd {} which isn't open, ignoring",
+                fd
+            );
+            return;
+        }
+    };
+
     // Convert the raw file descriptor to a `File` and drop it to close it
-    if fd >= 0 {
-        drop(unsafe { File::from_raw_fd(fd) });
+    open_fds.swap_remove(index);
+    drop(unsafe { File::from_raw_fd(fd) });
+    debug!("Closed fd {} ({} open)", fd, open_fds.len());
+}
+
+/// Creates a libinput context for `seat0`, with its messages going to our log.
+///
+/// # Safety
+/// The context is never freed once it's been handed out.
+pub unsafe fn open_seat() -> error::Result<*mut libinput> {
+    // Both only fail when out of memory or file descriptors, which errno says
+    let failed = |what: &str| BlueWiiError::Io {
+        what: what.to_owned(),
+        source: io::Error::last_os_error(),
+    };
+
+    let udev = libudev_sys::udev_new();
+    if udev.is_null() {
+        return Err(failed("Failed to create a udev context"));
+    }
+
+    let libinput = libinput_udev_create_context(&INTERFACE, std::ptr::null_mut(), udev as *mut _);
+    if libinput.is_null() {
+        let err = failed("Failed to create a libinput context");
+        libudev_sys::udev_unref(udev);
+        return Err(err);
+    }
+
+    // Before assigning the seat, so problems opening the devices already plugged in are logged too
+    forward_logs(libinput);
+    if libinput_udev_assign_seat(libinput, c"seat0".as_ptr()) != 0 {
+        let err = failed("Failed to assign libinput to seat0");
+        libinput_unref(libinput);
+        libudev_sys::udev_unref(udev);
+        return Err(err);
+    }
+
+    Ok(libinput)
+}
+
+/// The udev syspath of the device behind a libinput event, which is the one the poll loop filters on. Empty
+/// when libinput can't say, which doesn't belong to any remote.
+///
+/// # Safety
+/// `event` must be a valid, not yet destroyed event.
+pub unsafe fn event_syspath(event: *mut input_sys::libinput_event) -> String {
+    let device = libinput_event_get_device(event);
+    let udev_device = libinput_device_get_udev_device(device);
+    if udev_device.is_null() {
+        return String::new();
+    }
+
+    let syspath = CStr::from_ptr(udev_device_get_syspath(udev_device as *mut _))
+        .to_string_lossy()
+        .into_owned();
+    udev_device_unref(udev_device as *mut _);
+    syspath
+}
+
+/// The names of the capabilities the device behind a libinput event has, like `keyboard` or `pointer`.
+///
+/// # Safety
+/// `event` must be a valid, not yet destroyed event.
+pub unsafe fn event_capabilities(event: *mut input_sys::libinput_event) -> Vec<&'static str> {
+    let device = libinput_event_get_device(event);
+    CAPABILITIES
+        .iter()
+        .filter(|(capability, _)| libinput_device_has_capability(device, *capability) != 0)
+        .map(|(_, name)| *name)
+        .collect()
+}
+
+/// The syspath of every device libinput has added so far, consuming whatever events are queued.
+///
+/// # Safety
+/// `libinput` must be a valid context.
+pub unsafe fn device_syspaths(libinput: *mut libinput) -> Vec<String> {
+    let mut syspaths = Vec::new();
+    if libinput_dispatch(libinput) != 0 {
+        return syspaths;
+    }
+
+    loop {
+        let event = libinput_get_event(libinput);
+        if event.is_null() {
+            break;
+        }
+
+        if libinput_event_get_type(event) == EVENT_DEVICE_ADDED {
+            syspaths.push(event_syspath(event));
+        }
+
+        libinput_event_destroy(event);
+    }
+
+    syspaths
+}
+
+/// Whether an event node's syspath belongs to the remote at `remote_syspath`. libinput reports the event node,
+/// which lives underneath the HID device xwiishow gives us.
+pub fn belongs_to(event_syspath: &str, remote_syspath: &str) -> bool {
+    Path::new(event_syspath).starts_with(remote_syspath)
+}
+
+/// Sends libinput's own messages (like failing to open a device) to our log, as verbose as ours is.
+///
+/// # Safety
+/// `libinput` must be a valid context.
+pub unsafe fn forward_logs(libinput: *mut libinput) {
+    let priority = match log::max_level() {
+        LevelFilter::Debug | LevelFilter::Trace => {
+            libinput_log_priority_LIBINPUT_LOG_PRIORITY_DEBUG
+        }
+        LevelFilter::Info => libinput_log_priority_LIBINPUT_LOG_PRIORITY_INFO,
+        _ => libinput_log_priority_LIBINPUT_LOG_PRIORITY_ERROR,
+    };
+
+    libinput_log_set_handler(libinput, Some(log_handler_func));
+    libinput_log_set_priority(libinput, priority);
+}
+
+unsafe extern "C" fn log_handler_func(
+    _libinput: *mut libinput,
+    priority: libinput_log_priority,
+    format: *const c_char,
+    args: *mut c_void,
+) {
+    let mut buffer = [0 as c_char; 1024];
+    if vsnprintf(buffer.as_mut_ptr(), buffer.len(), format, args) < 0 {
+        return;
+    }
+
+    // Messages come with their own newline, and anything too long is cut off at the end of the buffer
+    let message = CStr::from_ptr(buffer.as_ptr()).to_string_lossy();
+    let message = message.trim_end();
+    match priority {
+        libinput_log_priority_LIBINPUT_LOG_PRIORITY_ERROR.. => error!("libinput: {}", message),
+        libinput_log_priority_LIBINPUT_LOG_PRIORITY_INFO.. => info!("libinput: {}", message),
+        _ => debug!("libinput: {}", message),
+    }
+}
+
+/// The kinds of libinput events `--input-filter` picks from. Devices being added and removed isn't one, those
+/// events always go through since the poll loop can't tell when a remote is gone without them.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum EventCategory {
+    /// Button presses and releases, the only events that are forwarded.
+    Buttons,
+    Pointer,
+    Touch,
+    Tablet,
+    Gesture,
+    Switch,
+}
+
+impl EventCategory {
+    pub const ALL: [EventCategory; 6] = [
+        EventCategory::Buttons,
+        EventCategory::Pointer,
+        EventCategory::Touch,
+        EventCategory::Tablet,
+        EventCategory::Gesture,
+        EventCategory::Switch,
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            EventCategory::Buttons => "buttons",
+            EventCategory::Pointer => "pointer",
+            EventCategory::Touch => "touch",
+            EventCategory::Tablet => "tablet",
+            EventCategory::Gesture => "gesture",
+            EventCategory::Switch => "switch",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<EventCategory> {
+        EventCategory::ALL
+            .into_iter()
+            .find(|category| category.name() == name)
+    }
+
+    /// Which category an event of `event_type` is in, `None` for devices being added and removed.
+    pub fn of(event_type: libinput_event_type) -> Option<EventCategory> {
+        // Each kind's event types are numbered from the first one, leaving room for ones newer libinputs add
+        match event_type {
+            EVENT_KEYBOARD_KEY => Some(EventCategory::Buttons),
+            EVENT_POINTER_MOTION..EVENT_TOUCH_DOWN => Some(EventCategory::Pointer),
+            EVENT_TOUCH_DOWN..EVENT_TABLET_TOOL_AXIS => Some(EventCategory::Touch),
+            EVENT_TABLET_TOOL_AXIS..EVENT_GESTURE_SWIPE_BEGIN => Some(EventCategory::Tablet),
+            EVENT_GESTURE_SWIPE_BEGIN..EVENT_SWITCH_TOGGLE => Some(EventCategory::Gesture),
+            EVENT_SWITCH_TOGGLE => Some(EventCategory::Switch),
+            _ => None,
+        }
+    }
+}
+
+/// Whether an event of `event_type` gets past `filter`, which lets everything through when it's empty.
+pub fn passes_filter(filter: &[EventCategory], event_type: libinput_event_type) -> bool {
+    match EventCategory::of(event_type) {
+        Some(category) => filter.is_empty() || filter.contains(&category),
+        None => true,
+    }
+}
+
+/// A readable name for a `libinput_event_type`, grouping the event types BlueWii never expects to see.
+pub fn event_type_name(event_type: libinput_event_type) -> &'static str {
+    match event_type {
+        EVENT_DEVICE_ADDED => "DEVICE_ADDED",
+        EVENT_DEVICE_REMOVED => "DEVICE_REMOVED",
+        _ => match EventCategory::of(event_type) {
+            Some(EventCategory::Buttons) => "KEYBOARD_KEY",
+            Some(EventCategory::Pointer) => "POINTER",
+            Some(EventCategory::Touch) => "TOUCH",
+            Some(EventCategory::Tablet) => "TABLET",
+            Some(EventCategory::Gesture) => "GESTURE",
+            Some(EventCategory::Switch) => "SWITCH_TOGGLE",
+            None => "UNKNOWN",
+        },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{ffi::CString, io::Write, os::fd::AsRawFd, ptr};
+
+    use super::*;
+
+    fn open(path: &str) -> c_int {
+        let path = CString::new(path).unwrap();
+        open_restricted_func(path.as_ptr(), libc::O_CLOEXEC, ptr::null_mut())
+    }
+
+    fn is_open(fd: i32) -> bool {
+        OPEN_FDS.lock().unwrap().contains(&fd)
+    }
+
+    #[test]
+    fn every_open_is_closed() {
+        // A burst of connects and disconnects, each one handing its descriptor back
+        for _ in 0..64 {
+            let fd = open("/dev/null");
+            assert!(fd >= 0);
+            assert!(is_open(fd));
+
+            close_restricted_func(fd, ptr::null_mut());
+            assert!(!is_open(fd));
+        }
+    }
+
+    #[test]
+    fn repeated_close_leaves_other_files_alone() {
+        let fd = open("/dev/null");
+        close_restricted_func(fd, ptr::null_mut());
+
+        // Whatever file gets the number next isn't ours to close
+        let mut file = File::create("/dev/null").unwrap();
+        close_restricted_func(file.as_raw_fd(), ptr::null_mut());
+        close_restricted_func(-1, ptr::null_mut());
+        assert!(file.write_all(b"still open").is_ok());
+    }
+
+    #[test]
+    fn failed_open_returns_negative_errno() {
+        assert_eq!(open("/nonexistent/bluewii/event0"), -libc::ENOENT);
+    }
+
+    #[test]
+    fn event_categories() {
+        assert_eq!(
+            EventCategory::of(EVENT_KEYBOARD_KEY),
+            Some(EventCategory::Buttons)
+        );
+        assert_eq!(
+            EventCategory::of(EVENT_TOUCH_DOWN - 1),
+            Some(EventCategory::Pointer)
+        );
+        assert_eq!(
+            EventCategory::of(EVENT_GESTURE_SWIPE_BEGIN - 1),
+            Some(EventCategory::Tablet)
+        );
+        assert_eq!(
+            EventCategory::of(EVENT_SWITCH_TOGGLE),
+            Some(EventCategory::Switch)
+        );
+        assert_eq!(EventCategory::of(EVENT_DEVICE_ADDED), None);
+        assert_eq!(EventCategory::of(EVENT_DEVICE_REMOVED), None);
+    }
+
+    #[test]
+    fn event_type_names() {
+        assert_eq!(event_type_name(EVENT_DEVICE_REMOVED), "DEVICE_REMOVED");
+        assert_eq!(event_type_name(EVENT_KEYBOARD_KEY), "KEYBOARD_KEY");
+        assert_eq!(event_type_name(EVENT_TABLET_TOOL_AXIS + 100), "TABLET");
+        assert_eq!(event_type_name(EVENT_SWITCH_TOGGLE + 1), "UNKNOWN");
     }
 }
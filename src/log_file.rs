@@ -0,0 +1,80 @@
+//! A log file that's rotated once it grows past a size limit, for `--log-file`.
+//!
+//! Rotation renames `bluewii.log` to `bluewii.log.1`, `bluewii.log.1` to `bluewii.log.2` and so on, dropping
+//! whatever falls past the number of files to keep.
+
+use std::{
+    fs::{self, File, OpenOptions},
+    io::{self, Write},
+    path::{Path, PathBuf},
+};
+
+pub struct RotatingFile {
+    path: PathBuf,
+    max_size: u64,
+    keep: u32,
+    file: File,
+    size: u64,
+}
+
+impl RotatingFile {
+    pub fn open(path: &Path, max_size: u64, keep: u32) -> io::Result<RotatingFile> {
+        let file = OpenOptions::new().create(true).append(true).open(path)?;
+        let size = file.metadata()?.len();
+
+        Ok(RotatingFile {
+            path: path.to_owned(),
+            max_size,
+            keep,
+            file,
+            size,
+        })
+    }
+
+    fn rotated_path(&self, index: u32) -> PathBuf {
+        let mut path = self.path.clone().into_os_string();
+        path.push(format!(".{}", index));
+        PathBuf::from(path)
+    }
+
+    fn rotate(&mut self) -> io::Result<()> {
+        self.file.flush()?;
+
+        match self.keep {
+            0 => fs::remove_file(&self.path)?,
+            keep => {
+                // Missing files just mean there hasn't been that many rotations yet
+                let _ = fs::remove_file(self.rotated_path(keep));
+                for index in (1..keep).rev() {
+                    let _ = fs::rename(self.rotated_path(index), self.rotated_path(index + 1));
+                }
+
+                fs::rename(&self.path, self.rotated_path(1))?;
+            }
+        }
+
+        self.file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.path)?;
+        self.size = 0;
+        Ok(())
+    }
+}
+
+impl Write for RotatingFile {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        // Never rotate an empty file, or a single oversized record would rotate forever
+        if self.size > 0 && self.size + buf.len() as u64 > self.max_size {
+            self.rotate()?;
+        }
+
+        let written = self.file.write(buf)?;
+        self.size += written as u64;
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.file.flush()
+    }
+}
@@ -0,0 +1,151 @@
+//! Telling a tap of a button from holding it down, for `home_long_press`.
+//!
+//! A tap only counts once the button is let go of before [`LongPress::threshold`], so the button's usual mapping
+//! is sent late, pressed and released at once. Holding it fires the long press the moment the threshold is
+//! crossed, without waiting for it to be let go of.
+
+use std::time::{Duration, Instant};
+
+use crate::evdev;
+
+/// How a press ended, see [`LongPress::release`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Release {
+    /// Let go of before the threshold.
+    Short,
+    /// Held past the threshold, but let go of before [`LongPress::poll`] noticed, so the long press is only
+    /// firing now.
+    Long,
+    /// Held past the threshold, and the long press already fired.
+    LongEnded,
+    /// It wasn't pressed to begin with.
+    NotPressed,
+}
+
+#[derive(Clone, Debug)]
+pub struct LongPress {
+    pub threshold: Duration,
+    pressed_at: Option<Instant>,
+    fired: bool,
+}
+
+impl LongPress {
+    pub fn new(threshold: Duration) -> LongPress {
+        LongPress {
+            threshold,
+            pressed_at: None,
+            fired: false,
+        }
+    }
+
+    pub fn press(&mut self, now: Instant) {
+        self.pressed_at = Some(now);
+        self.fired = false;
+    }
+
+    /// Whether the long press fires as of `now`, true only once per press.
+    pub fn poll(&mut self, now: Instant) -> bool {
+        match self.pressed_at {
+            Some(at) if !self.fired && now.saturating_duration_since(at) >= self.threshold => {
+                self.fired = true;
+                true
+            }
+            _ => false,
+        }
+    }
+
+    pub fn release(&mut self, now: Instant) -> Release {
+        let Some(at) = self.pressed_at.take() else {
+            return Release::NotPressed;
+        };
+
+        match (
+            self.fired,
+            now.saturating_duration_since(at) >= self.threshold,
+        ) {
+            (true, _) => Release::LongEnded,
+            (false, true) => Release::Long,
+            (false, false) => Release::Short,
+        }
+    }
+
+    /// Forgets the press without it counting as either, for when the remote is gone.
+    pub fn reset(&mut self) {
+        self.pressed_at = None;
+        self.fired = false;
+    }
+}
+
+/// What a long press does.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum LongPressAction {
+    /// Disconnects the remote.
+    Disconnect,
+    /// Holds an output code down until the button is let go of.
+    Output(u16),
+}
+
+impl LongPressAction {
+    /// `"disconnect"` or an output code name like `"KEY_ESC"`.
+    pub fn from_name(name: &str) -> Option<LongPressAction> {
+        match name {
+            "disconnect" => Some(LongPressAction::Disconnect),
+            _ => evdev::code_from_name(name).map(LongPressAction::Output),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const THRESHOLD: Duration = Duration::from_millis(1_000);
+
+    fn ms(millis: u64) -> Duration {
+        Duration::from_millis(millis)
+    }
+
+    #[test]
+    fn released_just_under_the_threshold_is_short() {
+        let start = Instant::now();
+        let mut long_press = LongPress::new(THRESHOLD);
+        long_press.press(start);
+
+        assert!(!long_press.poll(start + ms(999)));
+        assert_eq!(long_press.release(start + ms(999)), Release::Short);
+    }
+
+    #[test]
+    fn held_just_over_the_threshold_fires_once() {
+        let start = Instant::now();
+        let mut long_press = LongPress::new(THRESHOLD);
+        long_press.press(start);
+
+        assert!(long_press.poll(start + ms(1_000)));
+        assert!(!long_press.poll(start + ms(1_500)));
+        assert_eq!(long_press.release(start + ms(2_000)), Release::LongEnded);
+    }
+
+    #[test]
+    fn released_over_the_threshold_before_a_poll_is_long() {
+        let start = Instant::now();
+        let mut long_press = LongPress::new(THRESHOLD);
+        long_press.press(start);
+
+        assert!(!long_press.poll(start + ms(999)));
+        assert_eq!(long_press.release(start + ms(1_001)), Release::Long);
+    }
+
+    #[test]
+    fn nothing_without_a_press() {
+        let start = Instant::now();
+        let mut long_press = LongPress::new(THRESHOLD);
+        assert!(!long_press.poll(start + ms(5_000)));
+        assert_eq!(long_press.release(start), Release::NotPressed);
+
+        long_press.press(start);
+        long_press.reset();
+        assert!(!long_press.poll(start + ms(5_000)));
+        assert_eq!(long_press.release(start + ms(5_000)), Release::NotPressed);
+    }
+}
@@ -1,62 +1,123 @@
-mod lib_input;
-mod utils;
-mod wii_remote;
+mod log_file;
 
 use std::{
-    ffi::CStr,
+    cell::RefCell,
+    env, fs,
+    io::{self, IsTerminal},
+    panic::{self, AssertUnwindSafe},
+    path::{Path, PathBuf},
+    process,
     sync::{
-        atomic::{AtomicBool, AtomicU64, Ordering},
+        atomic::{AtomicBool, AtomicU64, AtomicU8, Ordering},
+        mpsc::{self, Receiver},
         Arc, Mutex,
     },
-    thread,
-    time::SystemTime,
+    thread::{self, Thread},
+    time::{Duration, Instant, SystemTime},
 };
 
-use chrono::Local;
+use anyhow::Context;
+use backlog::Backlog;
+use balance_board::BalanceBoard;
+use bluewii::{
+    backlog, backoff, balance_board, battery, bluetoothctl, button, clock, commands, config,
+    device_line, evdev, events, forwarder, history, hooks, identity, instance_lock, ir,
+    last_remote, lib_input, mappings_dir, metrics, notify, permissions, raw_extension, resume,
+    rumble, slots, speaker, status, stick, uinput, utils, versions, wii_remote, xwiimote,
+};
+use button::Button;
+use chrono::{
+    format::{Item, StrftimeItems},
+    Local,
+};
 use clap::{
     builder::BoolishValueParser, crate_authors, crate_description, crate_name, crate_version, Arg,
-    Command,
+    ArgAction, ArgMatches, Command,
 };
+use clock::{Clock, SystemClock};
+use commands::CommandRunner;
+use config::Config;
+use device_line::DeviceLineParser;
 use env_logger::fmt::Formatter;
-use env_logger::Builder;
+use env_logger::{Builder, Target, WriteStyle};
+use events::{EventMode, HeldButtons, Recorder, WiiEvent};
+use forwarder::{Forwarder, OutputMode};
+use history::{ConnectionEvent, DisconnectReason};
 use input_sys::{
-    libinput_device_get_udev_device, libinput_dispatch, libinput_event_get_device,
-    libinput_get_event,
+    libinput_dispatch, libinput_event_destroy, libinput_event_get_keyboard_event,
+    libinput_event_get_type, libinput_event_keyboard_get_key,
+    libinput_event_keyboard_get_key_state, libinput_event_type_LIBINPUT_EVENT_DEVICE_ADDED,
+    libinput_event_type_LIBINPUT_EVENT_DEVICE_REMOVED,
+    libinput_event_type_LIBINPUT_EVENT_KEYBOARD_KEY, libinput_get_event,
+    libinput_key_state_LIBINPUT_KEY_STATE_PRESSED,
 };
-use input_sys::{libinput_udev_assign_seat, libinput_udev_create_context};
-use lib_input::INTERFACE;
-use libudev_sys::udev_device_get_syspath;
+use ir::IrMode;
+use lib_input::EventCategory;
 use log::error;
 use log::info;
+use log::log_enabled;
+use log::trace;
 use log::warn;
+use log::Level;
 use log::LevelFilter;
 use log::Record;
+use log_file::RotatingFile;
+use raw_extension::RawExtension;
+use rumble::Feedback;
+use slots::PlayerSlots;
+use status::{Request, STATUS};
 use std::io::Error;
 use std::io::Write;
+use uinput::NameTemplate;
 
 use log::debug;
 
-use wii_remote::WiiRemote;
+use utils::{FormattedUnwrap, MissingBinaryExit, EXIT_NO_REMOTE};
+use versions::ToolVersion;
+use wii_remote::{ConnectBackend, WiiRemote};
+use xwiimote::Evdev;
 
+/// Listed by `--help`, see `utils` for where each one comes from.
+const EXIT_CODES: &str = "Exit codes:
+  0  Success, or --check found a Wii Remote connected
+  1  Something went wrong, or --check found no Wii Remote connected
+  2  --check couldn't tell, a tool is missing or a device can't be opened
+  3  bluetoothctl or xwiishow isn't installed
+  4  --fail-fast found no Wii Remote to connect to";
+
+// The newest input from any remote, each remote's own is kept in its `Activity`
 static CURRENT_TIME: AtomicU64 = AtomicU64::new(0);
 static RUNNING: AtomicBool = AtomicBool::new(true);
 
-fn main() {
-    let matches = Command::new(crate_name!())
+/// Every flag and subcommand, along with those of the features this build has.
+fn cli() -> Command {
+    let command = Command::new(crate_name!())
         .about(crate_description!())
         .author(crate_authors!(", "))
         .arg_required_else_help(false)
+        .after_help(EXIT_CODES)
         .args([
             Arg::new("bluetoothctl-path")
                 .short('b')
                 .long("bluetoothctl-path")
                 .help("The filepath to the `bluetoothctl' executable.")
+                .default_value("bluetoothctl")
                 .required(false),
             Arg::new("xwiishow-path")
                 .short('w')
                 .long("xwiishow-path")
                 .help("The filepath to the `xwiishow' executable.")
+                .default_value("xwiishow")
                 .required(false),
+            Arg::new("config")
+                .long("config")
+                .value_name("PATH")
+                .help("The config file to use instead of `$XDG_CONFIG_HOME/bluewii/config.toml' or `~/.config/bluewii/config.toml', it has to exist.")
+                .value_parser(clap::value_parser!(PathBuf)),
+            Arg::new("adapter")
+                .long("adapter")
+                .value_name("ADDRESS|NAME|hciX")
+                .help("The Bluetooth controller to use for Wii Remotes, by address or name as shown by `bluetoothctl list', or by its kernel name like `hci0'."),
             Arg::new("debug")
                 .short('d')
                 .long("debug")
@@ -67,174 +128,3689 @@ fn main() {
                 })
                 .required(false)
                 .value_parser(BoolishValueParser::new()),
+            Arg::new("trace")
+                .long("trace")
+                .help("Enables trace logging, which dumps every libinput event as it's processed.")
+                .action(ArgAction::SetTrue),
+            Arg::new("quiet")
+                .short('q')
+                .long("quiet")
+                .help("Only logs warnings and errors.")
+                .conflicts_with_all(["debug", "trace"])
+                .action(ArgAction::SetTrue),
+            Arg::new("log-file")
+                .long("log-file")
+                .value_name("PATH")
+                .help("Writes logs to this file instead of stderr.")
+                .value_parser(clap::value_parser!(PathBuf)),
+            Arg::new("log-max-size")
+                .long("log-max-size")
+                .value_name("BYTES")
+                .help("Rotates the log file once it grows past this size.")
+                .requires("log-file")
+                .default_value("10485760")
+                .value_parser(clap::value_parser!(u64)),
+            Arg::new("log-keep")
+                .long("log-keep")
+                .value_name("COUNT")
+                .help("How many rotated log files to keep.")
+                .requires("log-file")
+                .default_value("5")
+                .value_parser(clap::value_parser!(u32)),
+            Arg::new("log-timestamp")
+                .long("log-timestamp")
+                .value_name("rfc3339|epoch|none|custom:FORMAT")
+                .help("How log lines are timestamped: local time as RFC 3339, Unix seconds, not at all, or a strftime FORMAT.")
+                .default_value("rfc3339")
+                .value_parser(parse_log_timestamp),
+            Arg::new("color")
+                .long("color")
+                .value_name("WHEN")
+                .help("Colors the log level: `auto' only when logging to a terminal and NO_COLOR isn't set, `always' or `never'.")
+                .default_value("auto")
+                .value_parser(["auto", "always", "never"]),
+            Arg::new("no-color")
+                .long("no-color")
+                .help("Same as --color never.")
+                .conflicts_with("color")
+                .action(ArgAction::SetTrue),
+            Arg::new("check-permissions")
+                .long("check-permissions")
+                .help("Checks that every device BlueWii needs can be opened, reports what's missing and exits.")
+                .action(ArgAction::SetTrue),
+            Arg::new("version-check")
+                .long("version-check")
+                .help("Reports the detected bluetoothctl and xwiishow versions and exits.")
+                .action(ArgAction::SetTrue),
+            Arg::new("print-config")
+                .long("print-config")
+                .help("Prints the config in effect as TOML and exits: the defaults, overridden by the config file, then BLUEWII_DEVICE_LINE_REGEX, then the flags that also have a config key (--allow, --deny, --ir-invert-x and --ir-invert-y).")
+                .action(ArgAction::SetTrue),
+            Arg::new("list-capabilities")
+                .long("list-capabilities")
+                .help("Prints which optional features this build of BlueWii was compiled with and exits, worth including in bug reports.")
+                .action(ArgAction::SetTrue),
+            Arg::new("check")
+                .long("check")
+                .help("Exits with 0 if a Wii Remote is connected, 1 if none is, or 2 if a tool is missing or a device can't be opened.")
+                .action(ArgAction::SetTrue),
+            Arg::new("dry-run")
+                .long("dry-run")
+                .help("Reads the config file and flags, logs what would be connected, created and lit, and exits without running bluetoothctl or xwiishow or creating any virtual device.")
+                .action(ArgAction::SetTrue),
+            Arg::new("calibrate")
+                .long("calibrate")
+                .help("Calibrates the sticks of the extension plugged into the connected Wii Remote and exits.")
+                .action(ArgAction::SetTrue),
+            Arg::new("self-test")
+                .long("self-test")
+                .help("Connects to a Wii Remote, rumbles it and lights each LED in turn, asking whether each worked, and exits with 1 if any didn't.")
+                .action(ArgAction::SetTrue),
+            Arg::new("list")
+                .short('l')
+                .long("list")
+                .help("Scans for Wii Remotes, prints every one that was found and exits.")
+                .action(ArgAction::SetTrue),
+            Arg::new("dump-syspath")
+                .long("dump-syspath")
+                .help("Connects a Wii Remote, prints its device path next to every device libinput sees, marking the ones that would be read from, and exits.")
+                .action(ArgAction::SetTrue),
+            Arg::new("replay")
+                .long("replay")
+                .value_name("FILE")
+                .help("Plays the events in a --record or --print-events capture through player 1's virtual devices at the recorded pace, and exits.")
+                .conflicts_with("record")
+                .value_parser(clap::value_parser!(PathBuf)),
+            Arg::new("record")
+                .long("record")
+                .value_name("FILE")
+                .help("Writes every button, accelerometer and IR event of the first Wii Remote to a file, with timestamps, for --replay.")
+                .value_parser(clap::value_parser!(PathBuf)),
+            Arg::new("idle-timeout")
+                .short('i')
+                .long("idle-timeout")
+                .help("Seconds without input before a Wii Remote is disconnected, 0 to never disconnect.")
+                .default_value("300")
+                .value_parser(clap::value_parser!(u64)),
+            Arg::new("idle-warning")
+                .long("idle-warning")
+                .value_name("SECONDS")
+                .help("Rumbles and blinks the LEDs this many seconds before an idle Wii Remote is disconnected, pressing a button keeps it connected. 0 disconnects without warning.")
+                .default_value("0")
+                .value_parser(clap::value_parser!(u64)),
+            Arg::new("battery-aware-idle")
+                .long("battery-aware-idle")
+                .help("Shortens the idle timeout while the Wii Remote's battery is low, by the config file's battery_idle_timeouts (a minute below 20% unless it says otherwise). Follows the battery as it drains, each --status-poll-interval.")
+                .action(ArgAction::SetTrue),
+            Arg::new("exit-on-idle")
+                .long("exit-on-idle")
+                .help("Exits once the first Wii Remote is disconnected for idling, instead of waiting for it to connect again.")
+                .action(ArgAction::SetTrue),
+            Arg::new("idle-basis")
+                .long("idle-basis")
+                .value_name("BASIS")
+                .help("What keeps a Wii Remote from idling out: `input' needs button presses, `connection' only needs it to stay connected.")
+                .default_value("input")
+                .value_parser(["input", "connection"]),
+            Arg::new("led-mode")
+                .long("led-mode")
+                .value_name("MODE")
+                .help("What the LEDs show: `slot' lights the player number, `battery' lights one LED per quarter of charge left.")
+                .default_value("slot")
+                .value_parser(["slot", "battery"]),
+            Arg::new("connect")
+                .short('c')
+                .long("connect")
+                .value_name("ADDRESS")
+                .help("Connects directly to the paired Wii Remote with this Bluetooth address instead of scanning.")
+                .value_parser(parse_address),
+            Arg::new("device-name-template")
+                .long("device-name-template")
+                .value_name("TEMPLATE")
+                .help("What each Wii Remote's virtual devices are called, `{slot}' being its player number and `{mac}' its Bluetooth address.")
+                .default_value(NameTemplate::DEFAULT)
+                .value_parser(NameTemplate::parse),
+            Arg::new("connect-backend")
+                .long("connect-backend")
+                .value_name("BACKEND")
+                .help("What decides a connect worked: `bluetoothctl' takes its word for it, `xwiimote' waits for hid-wiimote to pick the Wii Remote up and connects again if it drops first.")
+                .default_value("bluetoothctl")
+                .value_parser(["bluetoothctl", "xwiimote"]),
+            Arg::new("allow")
+                .long("allow")
+                .value_name("ADDRESS")
+                .help("Only connects Wii Remotes with this Bluetooth address, can be given more than once. Adds to `allow' in the config file.")
+                .action(ArgAction::Append)
+                .value_parser(parse_address),
+            Arg::new("deny")
+                .long("deny")
+                .value_name("ADDRESS")
+                .help("Never connects the Wii Remote with this Bluetooth address, even when it's allowed, can be given more than once. Adds to `deny' in the config file.")
+                .action(ArgAction::Append)
+                .value_parser(parse_address),
+            Arg::new("no-connect-on-start")
+                .long("no-connect-on-start")
+                .help("Never scans or connects, only manages Wii Remotes that were connected some other way.")
+                .conflicts_with("connect")
+                .action(ArgAction::SetTrue),
+            Arg::new("fallback-scan")
+                .long("fallback-scan")
+                .help("Scans for any Wii Remote when connecting to the --connect address fails.")
+                .requires("connect")
+                .action(ArgAction::SetTrue),
+            Arg::new("prefer-strongest")
+                .long("prefer-strongest")
+                .help("Connects to the Wii Remote with the strongest signal when a scan finds several, instead of the one that connected most recently. Falls back to the first one found when bluetoothctl doesn't print the signal strength.")
+                .action(ArgAction::SetTrue),
+            Arg::new("reset-adapter")
+                .long("reset-adapter")
+                .help("Turns the Bluetooth controller off and on again before looking for a Wii Remote, for one that's stopped connecting anything (often after a suspend). Every other Bluetooth device on it disconnects too.")
+                .action(ArgAction::SetTrue),
+            Arg::new("prefer-paired")
+                .long("prefer-paired")
+                .help("Tries to reconnect each Wii Remote that's already paired before scanning for new ones, which is quicker when it's one you've set up before.")
+                .action(ArgAction::SetTrue),
+            Arg::new("fail-fast")
+                .long("fail-fast")
+                .help("Exits with 4 if no Wii Remote could be connected on the first attempt, instead of retrying.")
+                .action(ArgAction::SetTrue),
+            Arg::new("keep-scanning")
+                .long("keep-scanning")
+                .help("Keeps scanning while connected to pick up more Wii Remotes, each as the next player. Only player 1 follows the status socket, every remote has its own idle timeout.")
+                .action(ArgAction::SetTrue),
+            Arg::new("no-daemon-threads")
+                .long("no-daemon-threads")
+                .help("Connects, polls and checks the idle timeout one after another on the main thread, instead of on threads of their own. Uses fewer threads, but the idle timeout is only checked while a Wii Remote is connected, and exiting waits for a scan to finish. The status socket keeps its thread.")
+                .action(ArgAction::SetTrue)
+                .conflicts_with("keep-scanning"),
+            Arg::new("max-remotes")
+                .long("max-remotes")
+                .value_name("COUNT")
+                .help("How many Wii Remotes --keep-scanning connects at most.")
+                .default_value("4")
+                .value_parser(clap::value_parser!(u8).range(2..))
+                .requires("keep-scanning"),
+            Arg::new("reconnect-grace")
+                .long("reconnect-grace")
+                .value_name("SECONDS")
+                .help("How long a disconnected Wii Remote keeps its player slot and virtual gamepad.")
+                .default_value("10")
+                .value_parser(clap::value_parser!(u64)),
+            Arg::new("device-removed-grace-ms")
+                .long("device-removed-grace-ms")
+                .value_name("MILLISECONDS")
+                .help("How long a Wii Remote's input device must stay gone before it counts as disconnected, for adapters that drop it for a moment.")
+                .default_value("500")
+                .value_parser(clap::value_parser!(u64)),
+            Arg::new("max-retries")
+                .long("max-retries")
+                .value_name("COUNT")
+                .help("Failed connection attempts in a row before resting for --retry-cooldown, 0 to never rest.")
+                .default_value("10")
+                .value_parser(clap::value_parser!(u32)),
+            Arg::new("retry-cooldown")
+                .long("retry-cooldown")
+                .value_name("SECONDS")
+                .help("How long to rest after --max-retries failed connection attempts before scanning again.")
+                .default_value("60")
+                .value_parser(clap::value_parser!(u64)),
+            Arg::new("on-give-up")
+                .long("on-give-up")
+                .value_name("ACTION")
+                .help("What happens each time --max-retries attempts fail: `continue' rests for --retry-cooldown, `notify' also shows a desktop notification, `command:CMD' also runs CMD through `sh -c', `exit:CODE' exits with CODE.")
+                .default_value("continue")
+                .value_parser(parse_give_up),
+            Arg::new("retry-jitter")
+                .long("retry-jitter")
+                .value_name("PERCENT")
+                .help("How far each wait between connection attempts is randomly moved either way, so several remotes don't all retry at once.")
+                .default_value("20")
+                .value_parser(clap::value_parser!(u8).range(..=100)),
+            Arg::new("simulate-disconnect-after")
+                .long("simulate-disconnect-after")
+                .value_name("SECONDS")
+                .help("Debugging aid: disconnects each Wii Remote this long after it connects, to exercise reconnecting.")
+                .hide(true)
+                .value_parser(clap::value_parser!(u64).range(1..)),
+            Arg::new("power-off-cooldown")
+                .long("power-off-cooldown")
+                .value_name("SECONDS")
+                .help("How long to leave a Wii Remote alone after its power button turns it off, unless 1+2 is pressed.")
+                .default_value("60")
+                .value_parser(clap::value_parser!(u64)),
+            Arg::new("min-battery")
+                .long("min-battery")
+                .value_name("PERCENT")
+                .help("Warns loudly, with a rumble, when a Wii Remote connects with less battery than this.")
+                .value_parser(clap::value_parser!(u8).range(1..=100)),
+            Arg::new("refuse-low-battery")
+                .long("refuse-low-battery")
+                .help("Disconnects a Wii Remote below --min-battery instead of using it, until the power-off cooldown is over.")
+                .requires("min-battery")
+                .action(ArgAction::SetTrue),
+            Arg::new("connect-timeout")
+                .long("connect-timeout")
+                .value_name("SECONDS")
+                .help("How long `bluetoothctl connect' may take before it's given up on.")
+                .default_value("20")
+                .value_parser(clap::value_parser!(u64)),
+            Arg::new("stats-interval")
+                .long("stats-interval")
+                .value_name("SECONDS")
+                .help("Logs a one-line `stats' summary of the connected remotes, reconnects, events per second and batteries this often, 0 for never.")
+                .default_value("0")
+                .value_parser(clap::value_parser!(u64)),
+            Arg::new("history-size")
+                .long("history-size")
+                .value_name("COUNT")
+                .help("How many connects and disconnects the status socket's `history' command remembers, at most 1000.")
+                .default_value("50")
+                .value_parser(clap::value_parser!(u64).range(0..=history::MAX_CAPACITY as u64)),
+            Arg::new("status-poll-interval")
+                .long("status-poll-interval")
+                .value_name("SECONDS")
+                .help("How often the battery is re-read for the battery LEDs, the metrics and the low-battery warning, which can be late by up to this long.")
+                .default_value("30")
+                .value_parser(clap::value_parser!(u64).range(1..)),
+            Arg::new("scan-cooldown")
+                .long("scan-cooldown")
+                .value_name("SECONDS")
+                .help("How long to leave the Bluetooth adapter idle between scans. Longer rests disturb headphones and other devices less, but a Wii Remote can take that much longer to be found.")
+                .default_value("0")
+                .value_parser(clap::value_parser!(u64)),
+            Arg::new("connection-cache-ttl")
+                .long("connection-cache-ttl")
+                .value_name("SECONDS")
+                .help("How long to reuse the last `bluetoothctl devices' answer before checking the connection again.")
+                .default_value("2")
+                .value_parser(clap::value_parser!(u64)),
+            Arg::new("profile")
+                .short('p')
+                .long("profile")
+                .value_name("NAME")
+                .help("The button mapping profile from the config file or --mappings-dir to start with.")
+                .default_value("default"),
+            Arg::new("mappings-dir")
+                .long("mappings-dir")
+                .value_name("DIR")
+                .help("A directory of <profile>.toml mapping files, reloaded as they're edited, see the mappings_dir module.")
+                .value_parser(clap::value_parser!(PathBuf)),
+            Arg::new("status-socket")
+                .long("status-socket")
+                .value_name("PATH")
+                .help("Where to listen for status commands, defaults to $XDG_RUNTIME_DIR/bluewii.sock.")
+                .value_parser(clap::value_parser!(PathBuf)),
+            Arg::new("raw-extension")
+                .long("raw-extension")
+                .help("Passes the raw bytes of the connected extension through to a generic virtual device, see the raw_extension module.")
+                .action(ArgAction::SetTrue),
+            Arg::new("enable-ir")
+                .long("enable-ir")
+                .help("Turns the IR camera on as soon as a Wii Remote connects, even when nothing in the config uses it. The camera costs noticeably more battery.")
+                .action(ArgAction::SetTrue),
+            Arg::new("ir-invert-x")
+                .long("ir-invert-x")
+                .help("Flips the IR pointer's horizontal movement, like `ir_invert_x' in the config file.")
+                .action(ArgAction::SetTrue),
+            Arg::new("ir-invert-y")
+                .long("ir-invert-y")
+                .help("Flips the IR pointer's vertical movement, like `ir_invert_y' in the config file, for a sensor bar that moves it the wrong way up and down.")
+                .action(ArgAction::SetTrue),
+            Arg::new("ir-sensitivity")
+                .long("ir-sensitivity")
+                .value_name("LEVEL")
+                .help("Sets the IR camera's sensitivity from 1 to 5, like the Wii's own setting, whenever it's turned on. Higher picks up the sensor bar from further away and in bright rooms, lower ignores more stray reflections. 3 is what hid-wiimote uses.")
+                .value_parser(clap::value_parser!(u8).range(1..=5)),
+            Arg::new("enable-accel")
+                .long("enable-accel")
+                .help("Turns the accelerometer on as soon as a Wii Remote connects, even when nothing in the config uses it or `accel_enabled' is off. It costs some battery.")
+                .action(ArgAction::SetTrue),
+            Arg::new("enable-motionplus")
+                .long("enable-motionplus")
+                .help("Turns the MotionPlus gyroscope on as soon as a Wii Remote connects, when it has one. It costs the most battery of the three, and a Wii Remote Plus has it on either way.")
+                .action(ArgAction::SetTrue),
+            Arg::new("motion-report-hz")
+                .long("motion-report-hz")
+                .value_name("HZ")
+                .help("Forwards tilt and IR pointer movement at most this many times a second, dropping the readings in between. Buttons are never held back. 0 forwards every reading.")
+                .default_value("0")
+                .value_parser(clap::value_parser!(u32)),
+            Arg::new("balance-board-axes")
+                .long("balance-board-axes")
+                .help("Forwards a connected Balance Board's center of balance and total weight to a virtual device.")
+                .action(ArgAction::SetTrue),
+            Arg::new("startup-chime")
+                .long("startup-chime")
+                .help("Plays a short chime through the Wii Remote's speaker when it connects (experimental).")
+                .action(ArgAction::SetTrue),
+            Arg::new("only-device")
+                .long("only-device")
+                .value_name("SYSPATH")
+                .help("Only takes input from devices below this syspath, like a Wii Remote's HID device, or from the Wii Remote with this Bluetooth address wherever it is. Can be given more than once, input from every other device is ignored.")
+                .action(ArgAction::Append),
+            Arg::new("require-uinput")
+                .long("require-uinput")
+                .help("Exits when the virtual devices can't be created through /dev/uinput, instead of keeping Wii Remotes connected with their LEDs, battery and idle timeout but not forwarding their input.")
+                .action(ArgAction::SetTrue),
+            Arg::new("input-filter")
+                .long("input-filter")
+                .value_name("CATEGORIES")
+                .help("Only handles these kinds of libinput events, comma-separated: buttons, pointer, touch, tablet, gesture and switch. Filtered out events are dropped before anything sees them, so they don't keep a Wii Remote from idling out either. Devices coming and going are always handled.")
+                .value_parser(parse_input_filter),
+            Arg::new("write-device-path")
+                .long("write-device-path")
+                .value_name("FILE")
+                .help("Writes the first Wii Remote's device path to a file while it's connected, and removes the file once it disconnects.")
+                .value_parser(clap::value_parser!(PathBuf)),
+            Arg::new("print-events")
+                .long("print-events")
+                .help("Also writes every button, accelerometer and IR event to stdout, one line each, see the `events' module.")
+                .action(ArgAction::SetTrue),
+            Arg::new("event-mode")
+                .long("event-mode")
+                .value_name("MODE")
+                .help("How --print-events reports buttons: `edge' writes each press and release, `snapshot' writes every held button each --snapshot-interval.")
+                .value_parser(["edge", "snapshot"])
+                .default_value("edge"),
+            Arg::new("snapshot-interval")
+                .long("snapshot-interval")
+                .value_name("MILLISECONDS")
+                .help("How often --event-mode snapshot writes the held buttons.")
+                .default_value("100")
+                .value_parser(clap::value_parser!(u64).range(1..)),
+            Arg::new("no-startup-sequence")
+                .long("no-startup-sequence")
+                .help("Skips the LED sweep (and rumble, if configured) played when a Wii Remote connects.")
+                .action(ArgAction::SetTrue),
         ])
-        .version(crate_version!())
-        .get_matches();
+        .subcommand(
+            Command::new("doctor")
+                .about("Checks tool versions and device permissions, reports any problems and exits."),
+        )
+        .subcommand(
+            Command::new("pair")
+                .about("Pairs and trusts a Wii Remote whose red SYNC button was just pressed, so it connects with any button from then on, and exits.")
+                .arg(
+                    Arg::new("address")
+                        .value_name("ADDRESS")
+                        .help("The Bluetooth address of the Wii Remote to pair, see --list.")
+                        .required(true)
+                        .value_parser(parse_address),
+                ),
+        )
+        .subcommand(
+            Command::new("forget")
+                .about("Unpairs a Wii Remote through `bluetoothctl remove' and exits, so it has to be paired again.")
+                .arg(
+                    Arg::new("address")
+                        .value_name("ADDRESS")
+                        .help("The Bluetooth address of the Wii Remote to forget.")
+                        .required(true)
+                        .value_parser(parse_address),
+                )
+                .arg(
+                    Arg::new("force")
+                        .long("force")
+                        .help("Forgets the Wii Remote even while it's connected.")
+                        .action(ArgAction::SetTrue),
+                ),
+        )
+        .subcommand(
+            Command::new("disconnect")
+                .about("Disconnects Wii Remotes and exits.")
+                .arg(
+                    Arg::new("all")
+                        .long("all")
+                        .help("Disconnects every connected Wii Remote.")
+                        .required(true)
+                        .action(ArgAction::SetTrue),
+                ),
+        )
+        .version(crate_version!());
+
+    #[cfg(feature = "metrics")]
+    let command = command.arg(
+        Arg::new("metrics-addr")
+            .long("metrics-addr")
+            .value_name("HOST:PORT")
+            .help("Serves Prometheus metrics over HTTP on the given address."),
+    );
+
+    #[cfg(feature = "desktop-notify")]
+    let command = command.arg(
+        Arg::new("notify")
+            .long("notify")
+            .help("Shows a desktop notification when a Wii Remote connects, disconnects or runs low on battery.")
+            .action(ArgAction::SetTrue),
+    );
+
+    #[cfg(feature = "suspend-resume")]
+    let command = command.arg(
+        Arg::new("on-resume")
+            .long("on-resume")
+            .value_name("ACTION")
+            .help("What happens when the system wakes from suspend: `reconnect' drops and reconnects the Wii Remotes, `reset-idle' only restarts the idle timeout, `ignore' does neither.")
+            .default_value("reconnect")
+            .value_parser(["reconnect", "reset-idle", "ignore"]),
+    );
+
+    command
+}
+
+fn main() {
+    let matches = cli().get_matches();
 
     // Initialize the logger
-    Builder::new()
-        .format(process_log_buffer)
+    let mut builder = Builder::new();
+    let color = match matches.get_one::<String>("color").unwrap().as_str() {
+        _ if matches.get_flag("no-color") => false,
+        "always" => true,
+        "never" => false,
+        // A log file or pipe would end up full of escape codes
+        _ => {
+            !matches.contains_id("log-file")
+                && io::stderr().is_terminal()
+                && env::var_os("NO_COLOR").is_none_or(|value| value.is_empty())
+        }
+    };
+    if let Some(log_file) = matches.get_one::<PathBuf>("log-file") {
+        // The logger isn't up yet, so this can't go through `unwrap_or_fmt'
+        let log_file = match RotatingFile::open(
+            log_file,
+            *matches.get_one::<u64>("log-max-size").unwrap(),
+            *matches.get_one::<u32>("log-keep").unwrap(),
+        ) {
+            Ok(log_file) => log_file,
+            Err(err) => {
+                eprintln!("Failed to open log file `{}': {}", log_file.display(), err);
+                process::exit(1);
+            }
+        };
+
+        builder.target(Target::Pipe(Box::new(log_file)));
+    }
+
+    let log_timestamp = matches
+        .get_one::<LogTimestamp>("log-timestamp")
+        .unwrap()
+        .clone();
+    builder
+        .format(move |buf, record| process_log_buffer(buf, record, &log_timestamp, color))
+        // The codes are written by `process_log_buffer`, env_logger would otherwise strip them from a pipe
+        .write_style(match color {
+            true => WriteStyle::Always,
+            false => WriteStyle::Never,
+        })
         .filter(None, LevelFilter::Info)
         .filter_level(match matches.get_one::<bool>("debug") {
+            // Debug builds default `--debug' to on, which `--quiet' still overrides
+            _ if matches.get_flag("quiet") => LevelFilter::Warn,
+            _ if matches.get_flag("trace") => LevelFilter::Trace,
             Some(debug) if *debug => LevelFilter::Debug,
             _ => LevelFilter::Info,
         })
         .init();
 
-    info!("Starting Wii Remote manager...");
+    #[cfg(feature = "desktop-notify")]
+    if matches.get_flag("notify") {
+        notify::enable();
+    }
+
+    let bluetoothctl_path = matches.get_one::<String>("bluetoothctl-path").unwrap();
+    let xwiishow_path = matches.get_one::<String>("xwiishow-path").unwrap();
+
+    // `--dry-run` doesn't run bluetoothctl, so it goes by the name as given
+    let adapter =
+        matches
+            .get_one::<String>("adapter")
+            .map(|adapter| match matches.get_flag("dry-run") {
+                true => adapter.clone(),
+                false => resolve_adapter(bluetoothctl_path, adapter),
+            });
+    let device_line_regex = env::var("BLUEWII_DEVICE_LINE_REGEX").ok();
+    let load_config = || merged_config(&matches, device_line_regex.clone());
+
+    if matches.get_flag("print-config") {
+        match toml::to_string(&load_config()) {
+            Ok(config) => print!("{}", config),
+            Err(err) => {
+                error!("Failed to write out the config: {}", err);
+                process::exit(1);
+            }
+        }
+
+        return;
+    }
+
+    let remote_builder = WiiRemote::builder()
+        .bluetoothctl_path(bluetoothctl_path)
+        .xwiishow_path(xwiishow_path)
+        .adapter(adapter.clone());
+    let new_remote = || {
+        remote_builder
+            .clone()
+            .device_line(device_line_parser(&load_config()))
+            .build()
+    };
+
+    if let Some(("disconnect", _)) = matches.subcommand() {
+        disconnect_all(&mut new_remote());
+        return;
+    }
+
+    if let Some(("pair", pair_matches)) = matches.subcommand() {
+        let address = pair_matches.get_one::<String>("address").unwrap();
+        info!(
+            "Pairing {}, press the red SYNC button under its battery cover now",
+            address
+        );
+        let mut wii_remote = new_remote();
+        let paired = wii_remote
+            .pair(address)
+            .exit_if_missing(&wii_remote.bluetoothctl_path, "bluez");
+        process::exit(match paired {
+            Ok(()) => {
+                println!("Paired {}", address);
+                0
+            }
+            Err(err) => {
+                error!("Failed to pair {}: {}", address, err);
+                1
+            }
+        });
+    }
+
+    if let Some(("forget", forget_matches)) = matches.subcommand() {
+        process::exit(forget(
+            &mut new_remote(),
+            forget_matches.get_one::<String>("address").unwrap(),
+            forget_matches.get_flag("force"),
+        ));
+    }
+
+    if matches.get_flag("list-capabilities") {
+        list_capabilities();
+        return;
+    }
+
+    if let Some(("doctor", _)) = matches.subcommand() {
+        let versions_ok = versions::check(bluetoothctl_path, xwiishow_path);
+        let uinput_ok = check_uinput();
+        let permissions_ok = check_permissions();
+        process::exit(match versions_ok && uinput_ok && permissions_ok {
+            true => 0,
+            false => 1,
+        });
+    }
+
+    if matches.get_flag("version-check") {
+        process::exit(match versions::check(bluetoothctl_path, xwiishow_path) {
+            true => 0,
+            false => 1,
+        });
+    }
+
+    if matches.get_flag("check-permissions") {
+        process::exit(match check_permissions() {
+            true => 0,
+            false => 1,
+        });
+    }
+
+    if matches.get_flag("check") {
+        process::exit(health_check(&new_remote()));
+    }
+
+    if matches.get_flag("calibrate") {
+        process::exit(match calibrate(&load_config()) {
+            true => 0,
+            false => 1,
+        });
+    }
 
-    let wii_remote = Arc::new(Mutex::new(WiiRemote::new()));
+    if matches.get_flag("list") {
+        list_remotes(&new_remote());
+        return;
+    }
+
+    if matches.get_flag("self-test") {
+        process::exit(match self_test(&mut new_remote()) {
+            true => 0,
+            false => 1,
+        });
+    }
+
+    if matches.get_flag("dump-syspath") {
+        process::exit(match dump_syspath(&mut new_remote()) {
+            true => 0,
+            false => 1,
+        });
+    }
+
+    if let Some(dir) = matches.get_one::<PathBuf>("mappings-dir") {
+        mappings_dir::watch(dir.clone());
+    }
+
+    if let Some(path) = matches.get_one::<PathBuf>("replay") {
+        let config = load_config();
+        let profile = config
+            .resolve_profile(matches.get_one::<String>("profile").unwrap())
+            .to_owned();
+        process::exit(match replay(path, &config, &profile) {
+            true => 0,
+            false => 1,
+        });
+    }
+
+    let config = Arc::new(load_config());
+    let config_connect = Arc::clone(&config);
+    let idle_timeout = *matches.get_one::<u64>("idle-timeout").unwrap();
+    let idle_warning = *matches.get_one::<u64>("idle-warning").unwrap();
+    let exit_on_idle = matches.get_flag("exit-on-idle");
+    let battery_aware_idle = matches.get_flag("battery-aware-idle");
+    history::set_capacity(*matches.get_one::<u64>("history-size").unwrap() as usize);
+    let idle_basis = match matches.get_one::<String>("idle-basis").unwrap().as_str() {
+        "connection" => IdleBasis::Connection,
+        _ => IdleBasis::Input,
+    };
+
+    let remote_builder = remote_builder
+        .device_line(device_line_parser(&config))
+        .connection_cache_ttl(Duration::from_secs(
+            *matches.get_one::<u64>("connection-cache-ttl").unwrap(),
+        ))
+        .connect_timeout(Duration::from_secs(
+            *matches.get_one::<u64>("connect-timeout").unwrap(),
+        ))
+        .scan_cooldown(Duration::from_secs(
+            *matches.get_one::<u64>("scan-cooldown").unwrap(),
+        ))
+        .connect_backend(
+            match matches
+                .get_one::<String>("connect-backend")
+                .unwrap()
+                .as_str()
+            {
+                "xwiimote" => ConnectBackend::Xwiimote,
+                _ => ConnectBackend::Bluetoothctl,
+            },
+        )
+        .prefer_strongest(matches.get_flag("prefer-strongest"))
+        .prefer_paired(matches.get_flag("prefer-paired"))
+        .allow(config.allow.clone())
+        .deny(config.deny.clone());
+    let wii_remote = remote_builder.build();
+
+    // Every remote past the first gets its own `WiiRemote`, polled on its own thread
+    let keep_scanning = matches.get_flag("keep-scanning");
+    let extra_remotes = match keep_scanning {
+        true => (1..*matches.get_one::<u8>("max-remotes").unwrap())
+            .map(|_| remote_builder.build())
+            .collect::<Vec<_>>(),
+        false => Vec::new(),
+    };
+
+    #[cfg(feature = "suspend-resume")]
+    let on_resume = matches.get_one::<String>("on-resume").unwrap().as_str();
+    #[cfg(not(feature = "suspend-resume"))]
+    let on_resume = "ignore";
+
+    let wii_remote = Arc::new(Mutex::new(wii_remote));
     let wii_remote_connect = Arc::clone(&wii_remote);
     let wii_remote_timeout = Arc::clone(&wii_remote);
+    let player_slots = Arc::new(Mutex::new(PlayerSlots::new(&config.slots)));
+    let no_daemon_threads = matches.get_flag("no-daemon-threads");
+    let idle_timer = |activity: &Arc<Activity>, exit_on_idle: Option<Thread>| {
+        IdleTimer::new(
+            Arc::clone(activity),
+            idle_timeout,
+            idle_warning,
+            idle_basis,
+            battery_aware_idle,
+            exit_on_idle,
+        )
+    };
+    // One per worker, in the same order
+    let activities = (0..=extra_remotes.len())
+        .map(|_| Arc::new(Activity::default()))
+        .collect::<Vec<_>>();
+    let options = ConnectOptions {
+        keep_scanning,
+        activity: Arc::clone(&activities[0]),
+        idle_timer: no_daemon_threads.then(|| {
+            RefCell::new(idle_timer(
+                &activities[0],
+                exit_on_idle.then(thread::current),
+            ))
+        }),
+        pinned_address: matches.get_one::<String>("connect").cloned(),
+        fallback_scan: matches.get_flag("fallback-scan"),
+        fail_fast: matches.get_flag("fail-fast"),
+        no_connect_on_start: matches.get_flag("no-connect-on-start"),
+        startup_chime: matches.get_flag("startup-chime"),
+        startup_sequence: !matches.get_flag("no-startup-sequence"),
+        print_events: matches.get_flag("print-events"),
+        event_mode: match matches.get_one::<String>("event-mode").unwrap().as_str() {
+            "snapshot" => EventMode::Snapshot,
+            _ => EventMode::Edge,
+        },
+        snapshot_interval: Duration::from_millis(
+            *matches.get_one::<u64>("snapshot-interval").unwrap(),
+        ),
+        record: matches.get_one::<PathBuf>("record").cloned(),
+        write_device_path: matches.get_one::<PathBuf>("write-device-path").cloned(),
+        only_devices: matches
+            .get_many::<String>("only-device")
+            .into_iter()
+            .flatten()
+            .map(|syspath| syspath.trim_end_matches('/').to_owned())
+            .collect(),
+        require_uinput: matches.get_flag("require-uinput"),
+        input_filter: matches
+            .get_one::<Vec<EventCategory>>("input-filter")
+            .cloned()
+            .unwrap_or_default(),
+        raw_extension: matches.get_flag("raw-extension"),
+        balance_board_axes: matches.get_flag("balance-board-axes"),
+        enable_ir: matches.get_flag("enable-ir"),
+        enable_accel: matches.get_flag("enable-accel"),
+        ir_sensitivity: matches.get_one::<u8>("ir-sensitivity").copied(),
+        enable_motion_plus: matches.get_flag("enable-motionplus"),
+        motion_report_hz: *matches.get_one::<u32>("motion-report-hz").unwrap(),
+        led_mode: match matches.get_one::<String>("led-mode").unwrap().as_str() {
+            "battery" => LedMode::Battery,
+            _ => LedMode::Slot,
+        },
+        reconnect_grace: Duration::from_secs(*matches.get_one::<u64>("reconnect-grace").unwrap()),
+        device_removed_grace: Duration::from_millis(
+            *matches.get_one::<u64>("device-removed-grace-ms").unwrap(),
+        ),
+        max_retries: *matches.get_one::<u32>("max-retries").unwrap(),
+        retry_cooldown: Duration::from_secs(*matches.get_one::<u64>("retry-cooldown").unwrap()),
+        on_give_up: matches.get_one::<GiveUp>("on-give-up").unwrap().clone(),
+        retry_jitter: *matches.get_one::<u8>("retry-jitter").unwrap() as f64 / 100.0,
+        min_battery: matches.get_one::<u8>("min-battery").copied(),
+        status_poll_interval: Duration::from_secs(
+            *matches.get_one::<u64>("status-poll-interval").unwrap(),
+        ),
+        refuse_low_battery: matches.get_flag("refuse-low-battery"),
+        power_off_cooldown: Duration::from_secs(
+            *matches.get_one::<u64>("power-off-cooldown").unwrap(),
+        ),
+        simulate_disconnect_after: matches
+            .get_one::<u64>("simulate-disconnect-after")
+            .map(|seconds| Duration::from_secs(*seconds)),
+        reconnect_on_resume: on_resume == "reconnect",
+        device_name: matches
+            .get_one::<NameTemplate>("device-name-template")
+            .unwrap()
+            .clone(),
+        profile: config
+            .resolve_profile(matches.get_one::<String>("profile").unwrap())
+            .to_owned(),
+    };
 
-    let _connect_and_poll_handle = thread::spawn(move || {
-        connect_and_poll(&wii_remote_connect);
-    });
+    if matches.get_flag("dry-run") {
+        dry_run(
+            &config,
+            &options,
+            &wii_remote.lock().unwrap(),
+            idle_timeout,
+            battery_aware_idle,
+            extra_remotes.len() + 1,
+            matches.get_flag("reset-adapter"),
+        );
+        return;
+    }
 
-    let _timeout_handle = thread::spawn(move || {
-        timeout(&wii_remote_timeout);
+    // Kept until the process exits, by whatever means
+    let lock_path = instance_lock::path(adapter.as_deref());
+    let _instance_lock = match instance_lock::acquire(&lock_path) {
+        Ok(Some(lock)) => lock,
+        Ok(None) => {
+            error!(
+                "Another BlueWii{} is already running{}, two would keep taking the Wii Remote from each other",
+                match instance_lock::holder(&lock_path) {
+                    Some(pid) => format!(" (pid {})", pid),
+                    None => String::new(),
+                },
+                match &adapter {
+                    Some(adapter) => format!(" on {}", adapter),
+                    None => String::new(),
+                }
+            );
+            process::exit(1);
+        }
+        Err(err) => Err(err)
+            .with_context(|| format!("Failed to lock `{}'", lock_path.display()))
+            .unwrap_or_fmt(),
+    };
+
+    info!("Starting Wii Remote manager...");
+    versions::check(bluetoothctl_path, xwiishow_path);
+
+    // Every remote forwards through virtual devices, better to find out before one has connected
+    if let Some(problem) = permissions::uinput_problem() {
+        match options.require_uinput {
+            true => {
+                error!("Virtual devices can't be created: {}", problem);
+                process::exit(1);
+            }
+            false => warn!(
+                "Virtual devices can't be created: {}. Wii Remotes are still connected and kept track of, \
+                 but their input isn't forwarded (--require-uinput exits instead)",
+                problem
+            ),
+        }
+    }
+
+    if on_resume != "ignore" {
+        // The time spent asleep would otherwise count as idle, disconnecting the remotes the moment they wake
+        let activities = activities.clone();
+        resume::watch(move || {
+            if let Some(now) = SystemClock.now() {
+                for activity in &activities {
+                    activity.record(now);
+                }
+            }
+        });
+    }
+
+    if matches.get_flag("reset-adapter") {
+        info!("Resetting the Bluetooth controller, other Bluetooth devices will disconnect (--reset-adapter)");
+        let mut wii_remote = wii_remote.lock().unwrap();
+        let bluetoothctl_path = wii_remote.bluetoothctl_path.clone();
+        match wii_remote
+            .reset_adapter()
+            .exit_if_missing(&bluetoothctl_path, "bluez")
+        {
+            Ok(()) => info!("Bluetooth controller is back on"),
+            Err(err) => warn!("Failed to reset the Bluetooth controller: {}", err),
+        }
+    }
+
+    // Left behind when the last run was killed, no remote is connected yet
+    if let Some(path) = &options.write_device_path {
+        let _ = fs::remove_file(path);
+    }
+
+    if let Some(after) = options.simulate_disconnect_after {
+        warn!(
+            "--simulate-disconnect-after is on, every Wii Remote will be disconnected {} seconds after it connects",
+            after.as_secs()
+        );
+    }
+
+    if options.print_events {
+        println!("# bluewii-events {}", events::PRINT_FORMAT_VERSION);
+    }
+
+    let (requests_sender, requests) = mpsc::channel();
+    let config_status = Arc::clone(&config);
+    let status_socket = matches
+        .get_one::<PathBuf>("status-socket")
+        .cloned()
+        .unwrap_or_else(status::default_path);
+
+    let mut workers = Vec::new();
+    // For their idle timeouts to be checked on threads of their own
+    let mut idle_remotes = vec![wii_remote_timeout];
+    for (index, extra_remote) in extra_remotes.into_iter().enumerate() {
+        let extra_remote = Arc::new(Mutex::new(extra_remote));
+        let activity = &activities[index + 1];
+        idle_remotes.push(Arc::clone(&extra_remote));
+        let config = Arc::clone(&config);
+        let player_slots = Arc::clone(&player_slots);
+        // Someone else's remote is never pinned, and the status socket only talks to player 1
+        let options = ConnectOptions {
+            pinned_address: None,
+            fallback_scan: false,
+            fail_fast: false,
+            record: None,
+            write_device_path: None,
+            only_devices: options.only_devices.clone(),
+            input_filter: options.input_filter.clone(),
+            device_name: options.device_name.clone(),
+            on_give_up: options.on_give_up.clone(),
+            profile: options.profile.clone(),
+            activity: Arc::clone(activity),
+            idle_timer: no_daemon_threads.then(|| RefCell::new(idle_timer(activity, None))),
+            ..options
+        };
+
+        workers.push(thread::spawn(move || {
+            let (_, requests) = mpsc::channel();
+            run_worker(
+                &extra_remote,
+                &config,
+                &options,
+                &requests,
+                &player_slots,
+                index + 1,
+            );
+        }));
+    }
+
+    let first_worker = move || {
+        run_worker(
+            &wii_remote_connect,
+            &config_connect,
+            &options,
+            &requests,
+            &player_slots,
+            0,
+        );
+    };
+
+    // Run once everything else is started, taking the main thread's place waiting for the exit
+    let mut worker_on_main_thread = None;
+    match no_daemon_threads {
+        true => worker_on_main_thread = Some(first_worker),
+        false => workers.push(thread::spawn(first_worker)),
+    }
+
+    let status_remotes = remote_builder.clone();
+    let _status_handle = thread::spawn(move || {
+        status::serve(
+            &status_socket,
+            config_status,
+            requests_sender,
+            status_remotes,
+        );
     });
 
-    while RUNNING.load(Ordering::Relaxed) {
-        thread::park();
+    // With --no-daemon-threads, each worker checks its idle timeout itself in between polls. Only the first
+    // remote idling out exits with --exit-on-idle
+    if !no_daemon_threads {
+        let main_thread = thread::current();
+        for (worker, (wii_remote, activity)) in
+            idle_remotes.into_iter().zip(&activities).enumerate()
+        {
+            let config = Arc::clone(&config);
+            let idle_timer = idle_timer(
+                activity,
+                (exit_on_idle && worker == 0).then(|| main_thread.clone()),
+            );
+            let _timeout_handle = thread::spawn(move || {
+                supervise("idle timeout", || {
+                    wii_remote.clear_poison();
+                    timeout(&wii_remote, &config, idle_timer.clone(), &SystemClock);
+                });
+            });
+        }
+    }
+
+    let stats_interval = *matches.get_one::<u64>("stats-interval").unwrap();
+    if stats_interval > 0 {
+        thread::spawn(move || log_stats(Duration::from_secs(stats_interval)));
+    }
+
+    #[cfg(feature = "metrics")]
+    if let Some(metrics_addr) = matches.get_one::<String>("metrics-addr").cloned() {
+        let _metrics_handle = thread::spawn(move || {
+            metrics::serve(&metrics_addr, idle_seconds);
+        });
+    }
+
+    match worker_on_main_thread {
+        Some(worker) => worker(),
+        None => {
+            while RUNNING.load(Ordering::Relaxed) {
+                thread::park();
+            }
+        }
     }
 
     info!("Shutting down...");
+
+    // A worker that's still scanning only notices once the scan is over, its virtual devices go with the process
+    let deadline = Instant::now() + SHUTDOWN_TIMEOUT;
+    while workers.iter().any(|worker| !worker.is_finished()) && Instant::now() < deadline {
+        thread::sleep(Duration::from_millis(100));
+    }
+}
+
+/// How long the workers get to tear down their virtual devices when exiting.
+const SHUTDOWN_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// How often a long sleep checks whether BlueWii is shutting down.
+const SHUTDOWN_CHECK_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Sleeps for `duration` a slice of [`SHUTDOWN_CHECK_INTERVAL`] at a time, returning `false` as soon as BlueWii is
+/// shutting down so it doesn't have to wait out the rest.
+fn sleep_while_running(duration: Duration) -> bool {
+    let deadline = Instant::now() + duration;
+    while RUNNING.load(Ordering::Relaxed) {
+        let left = deadline.saturating_duration_since(Instant::now());
+        if left.is_zero() {
+            return true;
+        }
+
+        thread::sleep(left.min(SHUTDOWN_CHECK_INTERVAL));
+    }
+
+    false
+}
+
+/// How soon before disconnecting a remote must have last reported something to count as turned off.
+///
+/// Pressing the power button disconnects right away, while a remote that goes out of range or runs flat only
+/// drops once the Bluetooth link supervision timeout (several seconds of silence) runs out. A remote that wasn't
+/// reporting anything at all beforehand can't be told apart, so it's treated as a drop-out and reconnected.
+const POWER_OFF_WINDOW: Duration = Duration::from_secs(1);
+
+/// How long the kernel gets to create a remote's HID device once it's connected.
+const DEVICE_PATH_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// How long `--no-connect-on-start` waits for a remote before letting go of the lock for a moment.
+const EXISTING_REMOTE_WAIT: Duration = Duration::from_secs(30);
+
+/// How long a thread that panicked waits before it's started again.
+const RESTART_DELAY: Duration = Duration::from_secs(2);
+
+/// How many times a thread may panic within [`RESTART_WINDOW`] before BlueWii gives up on it and exits, so a
+/// panic that happens every time doesn't loop forever.
+const MAX_RESTARTS: usize = 5;
+const RESTART_WINDOW: Duration = Duration::from_secs(60);
+
+/// Runs `body` until it returns, starting it again after [`RESTART_DELAY`] whenever it panics, and exiting once it
+/// has panicked [`MAX_RESTARTS`] times in a row within [`RESTART_WINDOW`]. `name` is what the thread is called in
+/// the log.
+fn supervise(name: &str, mut body: impl FnMut()) {
+    let mut panicked_at: Vec<Instant> = Vec::new();
+    loop {
+        if panic::catch_unwind(AssertUnwindSafe(&mut body)).is_ok() {
+            return;
+        }
+
+        let now = Instant::now();
+        panicked_at.retain(|at| now.duration_since(*at) < RESTART_WINDOW);
+        panicked_at.push(now);
+        if panicked_at.len() > MAX_RESTARTS {
+            error!(
+                "The {} thread panicked {} times within {} seconds, exiting",
+                name,
+                panicked_at.len(),
+                RESTART_WINDOW.as_secs()
+            );
+            process::exit(1);
+        }
+
+        error!(
+            "The {} thread panicked, restarting it in {} seconds",
+            name,
+            RESTART_DELAY.as_secs()
+        );
+        thread::sleep(RESTART_DELAY);
+        if !RUNNING.load(Ordering::Relaxed) {
+            return;
+        }
+    }
+}
+
+/// Runs `connect_and_poll`, starting it over if it panics, see [`supervise`].
+///
+/// A panic unwinds through the forwarder first, taking its virtual devices with it, so the restarted thread
+/// creates them anew for whichever remote it connects next.
+fn run_worker(
+    shared_remote: &Arc<Mutex<WiiRemote>>,
+    config: &Config,
+    options: &ConnectOptions,
+    requests: &Receiver<Request>,
+    player_slots: &Mutex<PlayerSlots>,
+    worker: usize,
+) {
+    supervise("connection", || {
+        // Whatever the panic interrupted was either finished or is started over, the locks are safe to take again
+        shared_remote.clear_poison();
+        player_slots.clear_poison();
+        STATUS.clear_poison();
+        connect_and_poll(
+            shared_remote,
+            config,
+            options,
+            requests,
+            player_slots,
+            worker,
+        );
+    });
+}
+
+/// How `connect_and_poll` finds a remote and what it does once one connects.
+struct ConnectOptions {
+    /// Whether more remotes are picked up once connected, each claiming its own so they don't overlap.
+    keep_scanning: bool,
+    /// Kept up to date for this worker's [`IdleTimer`].
+    activity: Arc<Activity>,
+    /// Checked in between polls with `--no-daemon-threads`, instead of by [`timeout`] on a thread of its own.
+    idle_timer: Option<RefCell<IdleTimer>>,
+    pinned_address: Option<String>,
+    fallback_scan: bool,
+    /// Whether failing to connect before any remote ever has exits with [`EXIT_NO_REMOTE`].
+    fail_fast: bool,
+    no_connect_on_start: bool,
+    startup_chime: bool,
+    startup_sequence: bool,
+    print_events: bool,
+    /// How `print_events` reports buttons, and how often in a snapshot.
+    event_mode: EventMode,
+    snapshot_interval: Duration,
+    /// Where to write a capture of the remote's events, see [`Recorder`].
+    record: Option<PathBuf>,
+    /// Where to keep the remote's device path while it's connected, for other tools to watch.
+    write_device_path: Option<PathBuf>,
+    /// The syspaths, or remotes by Bluetooth address, input is taken from, any device's when empty.
+    only_devices: Vec<String>,
+    /// Whether failing to create the virtual devices exits, instead of carrying on without forwarding input.
+    require_uinput: bool,
+    /// The kinds of libinput events handled, every kind when empty.
+    input_filter: Vec<EventCategory>,
+    raw_extension: bool,
+    balance_board_axes: bool,
+    /// Interfaces turned on at connect whether or not anything forwards them, for other programs reading them.
+    enable_ir: bool,
+    enable_accel: bool,
+    enable_motion_plus: bool,
+    /// The IR camera sensitivity set once it's on, hid-wiimote's own when `None`.
+    ir_sensitivity: Option<u8>,
+    /// How many times a second tilt and IR movement are forwarded at most, 0 for every reading.
+    motion_report_hz: u32,
+    led_mode: LedMode,
+    reconnect_grace: Duration,
+    device_removed_grace: Duration,
+    /// Failed attempts in a row before resting, 0 for never.
+    max_retries: u32,
+    retry_cooldown: Duration,
+    on_give_up: GiveUp,
+    /// The fraction every wait between attempts is randomly moved by, see [`backoff`].
+    retry_jitter: f64,
+    power_off_cooldown: Duration,
+    min_battery: Option<u8>,
+    /// How often the battery is re-read once connected, see [`battery`].
+    status_poll_interval: Duration,
+    refuse_low_battery: bool,
+    /// Debugging aid that disconnects the remote this long after each connect.
+    simulate_disconnect_after: Option<Duration>,
+    /// Whether the remote is dropped and reconnected when the system wakes from suspend, see [`resume`].
+    reconnect_on_resume: bool,
+    /// What the virtual devices are called, see [`NameTemplate`].
+    device_name: NameTemplate,
+    profile: String,
 }
 
-fn connect_and_poll(wii_remote: &Arc<Mutex<WiiRemote>>) {
+/// Connects a remote and forwards its input until it's gone, over and over.
+///
+/// `worker` 0 is the remote everything else (the status socket, the remembered address) is about. With
+/// `--keep-scanning`, worker `n` only starts looking once `n` remotes are claimed, so the extra workers take their
+/// turns one after another instead of all scanning at once.
+fn connect_and_poll(
+    shared_remote: &Arc<Mutex<WiiRemote>>,
+    config: &Config,
+    options: &ConnectOptions,
+    requests: &Receiver<Request>,
+    player_slots: &Mutex<PlayerSlots>,
+    worker: usize,
+) {
     info!("Initializing libinput...");
 
-    let libinput;
-    unsafe {
-        let udev = libudev_sys::udev_new();
-        libinput = libinput_udev_create_context(&INTERFACE, std::ptr::null_mut(), udev as *mut _);
-        libinput_udev_assign_seat(libinput, c"seat0".as_ptr());
+    let reconnect_grace = options.reconnect_grace;
+    let mut profile = options.profile.clone();
+    let mut mappings_generation = mappings_dir::generation();
+    let mut output_mode = config.output_mode;
+    let mut accel_enabled = config.accel_enabled || options.enable_accel;
+    if worker == 0 {
+        let mut status = STATUS.lock().unwrap();
+        status.profile = profile.clone();
+        status.output_mode = output_mode;
+        status.accel = accel_enabled;
     }
 
-    const MAX_RETRIES: u32 = 10;
+    let libinput = unsafe { lib_input::open_seat() }
+        .context("Failed to start libinput")
+        .unwrap_or_fmt();
+
+    // One capture across every reconnect, so a flaky remote doesn't cut a recording short
+    let mut recorder = options.record.as_deref().map(|path| {
+        Recorder::create(path)
+            .with_context(|| format!("Failed to create `{}'", path.display()))
+            .unwrap_or_fmt()
+    });
+
     let mut retries = 0;
 
+    let mut held_slot: Option<HeldSlot> = None;
+    let mut commands = CommandRunner::new(config);
+    // When the last remote was turned off with its power button
+    let mut powered_off_at: Option<Instant> = None;
+    // The remote `--refuse-low-battery` last turned away, until a reading shows its batteries were swapped
+    let mut refused_address: Option<String> = None;
+    // The remote from the previous run, tried once before the first scan
+    let mut saved_address = match worker {
+        0 => last_remote::load(),
+        _ => None,
+    };
+    let mut last_address = saved_address.clone();
+    let mut connected_once = false;
+
     loop {
-        if retries >= MAX_RETRIES {
+        // Returning drops a held slot's forwarder, removing its virtual devices
+        if !RUNNING.load(Ordering::Relaxed) {
+            return;
+        }
+
+        if held_slot.is_none() && WiiRemote::claimed_count() < worker {
+            thread::sleep(Duration::from_secs(1));
+            continue;
+        }
+
+        // Rest instead of giving up, so a remote that's turned on hours later still gets picked up
+        if options.max_retries != 0 && retries >= options.max_retries {
+            match &options.on_give_up {
+                GiveUp::Continue => {}
+                GiveUp::Notify => notify::gave_up(options.max_retries),
+                GiveUp::Command(command) => commands::run(command.clone()),
+                GiveUp::Exit(code) => {
+                    error!(
+                        "Failed to connect to Wii Remote after {} attempts, exiting (--on-give-up)",
+                        options.max_retries
+                    );
+                    process::exit(*code);
+                }
+            }
+
+            let cooldown = backoff::jittered(options.retry_cooldown, options.retry_jitter);
             error!(
-                "Failed to connect to Wii Remote after {} attempts",
-                MAX_RETRIES
+                "Failed to connect to Wii Remote after {} attempts, trying again in {} seconds",
+                options.max_retries,
+                cooldown.as_secs()
             );
-            break;
+            if !sleep_while_running(cooldown) {
+                return;
+            }
+
+            retries = 0;
         }
 
-        let mut wii_remote = match wii_remote.try_lock() {
-            Ok(lock) => lock,
-            Err(_) => {
-                debug!("Mutex is locked, retrying...");
-                thread::sleep(std::time::Duration::from_millis(50));
-                continue;
+        // Held while connecting and setting the remote up, but not while polling it
+        let mut wii_remote = shared_remote.lock().unwrap();
+
+        // Only tear down a disconnected remote once it's had its chance to come back
+        if let Some(HeldSlot {
+            address,
+            player_slot,
+            disconnected_at,
+            ..
+        }) = &held_slot
+        {
+            if disconnected_at.elapsed() >= reconnect_grace {
+                info!(
+                    "Wii Remote {} did not reconnect within {} seconds, releasing player {}",
+                    address,
+                    reconnect_grace.as_secs(),
+                    player_slot
+                );
+                player_slots.lock().unwrap().release(address);
+                wii_remote.release_claim();
+                held_slot = None;
+            }
+        }
+
+        // A remote that was turned off is left alone, but scanning still finds it once 1+2 makes it discoverable
+        let cooling_down =
+            powered_off_at.is_some_and(|at| at.elapsed() < options.power_off_cooldown);
+
+        metrics::record_connect_attempt();
+        let connected = match options.pinned_address.as_deref() {
+            _ if options.no_connect_on_start => wii_remote.wait_for_existing(EXISTING_REMOTE_WAIT),
+            Some(address) if wii_remote.connect(address).is_ok() => true,
+            Some(address) if options.fallback_scan => {
+                warn!(
+                    "Failed to connect to {}, scanning for any Wii Remote instead...",
+                    address
+                );
+                wii_remote.try_connect()
             }
+            Some(_) => false,
+            // A pinned remote is always wanted back, any other only once it's made itself discoverable
+            None if cooling_down => wii_remote.try_connect(),
+            // A remote that dropped out is called back straight away, without waiting for it to be discoverable
+            None => match (&held_slot, last_address.take()) {
+                (Some(held), _) if wii_remote.connect(&held.address).is_ok() => true,
+                // The remote from last time is the one most likely to be turned on again
+                (None, Some(address))
+                    if wii_remote.is_allowed(&address) && wii_remote.connect(&address).is_ok() =>
+                {
+                    info!("Reconnected to the last Wii Remote, {}", address);
+                    true
+                }
+                _ => wii_remote.try_connect(),
+            },
         };
 
-        if !wii_remote.try_connect() {
+        // Only the first connection, a remote that drops out later is still waited for
+        if !connected && options.fail_fast && !connected_once {
+            error!("No Wii Remote could be connected, exiting (--fail-fast)");
+            process::exit(EXIT_NO_REMOTE);
+        }
+        connected_once |= connected;
+
+        // Waiting for someone else to connect a remote isn't a failure, so it never runs out of retries
+        if !connected && (options.no_connect_on_start || cooling_down) {
+            drop(wii_remote);
+            continue;
+        }
+
+        if !connected {
             retries += 1;
-            warn!(
-                "Failed to connect to Wii Remote, retrying... (attempt {}/{})",
-                retries, MAX_RETRIES
-            );
-            thread::sleep(std::time::Duration::from_secs(1));
+            match options.max_retries {
+                0 => warn!(
+                    "Failed to connect to Wii Remote, retrying... (attempt {})",
+                    retries
+                ),
+                max_retries => warn!(
+                    "Failed to connect to Wii Remote, retrying... (attempt {}/{})",
+                    retries, max_retries
+                ),
+            }
+            thread::sleep(backoff::jittered(
+                Duration::from_secs(1),
+                options.retry_jitter,
+            ));
             continue;
         }
 
         retries = 0;
+        powered_off_at = None;
         info!("Wii Remote connected successfully.");
 
-        let wii_remote_udev_device_path = match wii_remote.get_udev_device_path() {
+        // Connecting counts as activity, so a remote that was adopted while idle isn't disconnected right away
+        if let Ok(duration) = SystemTime::now().duration_since(SystemTime::UNIX_EPOCH) {
+            options.activity.record(duration.as_secs());
+        }
+
+        let wii_remote_udev_device_path = match wii_remote
+            .wait_for_udev_device_path(DEVICE_PATH_TIMEOUT)
+        {
             Some(path) => path,
             None => {
-                warn!("Failed to get udev device path");
+                warn!(
+                    "Failed to get udev device path, xwiishow didn't list the Wii Remote within {} seconds",
+                    DEVICE_PATH_TIMEOUT.as_secs()
+                );
                 continue;
             }
         };
 
-        unsafe {
-            loop {
-                let ret = libinput_dispatch(libinput);
-                if ret != 0 {
-                    error!("Failed to dispatch libinput events: {}", ret);
-                    break;
+        let battery_level = WiiRemote::battery_level(&wii_remote_udev_device_path);
+        if let Some(battery_level) = battery_level {
+            info!("Wii Remote battery is at {}%", battery_level);
+        }
+
+        wii_remote.udev_device_path = Some(wii_remote_udev_device_path.clone());
+        if let Some(previous) =
+            identity::update(&wii_remote.bluetooth_address, &wii_remote_udev_device_path)
+        {
+            info!(
+                "Wii Remote {} came back at {}, it was at {}",
+                wii_remote.bluetooth_address, wii_remote_udev_device_path, previous
+            );
+        }
+
+        // Addresses in --only-device stand for wherever that remote is now
+        let only_devices = options
+            .only_devices
+            .iter()
+            .map(|entry| match WiiRemote::is_valid_address(entry) {
+                true => identity::syspath(entry).unwrap_or_else(|| entry.clone()),
+                false => entry.clone(),
+            })
+            .collect::<Vec<_>>();
+
+        // A listed syspath can be the remote itself or one of its interfaces
+        if !only_devices.is_empty()
+            && !only_devices.iter().any(|syspath| {
+                lib_input::belongs_to(syspath, &wii_remote_udev_device_path)
+                    || lib_input::belongs_to(&wii_remote_udev_device_path, syspath)
+            })
+        {
+            warn!(
+                "Wii Remote {} at {} isn't covered by --only-device, none of its input will be forwarded",
+                wii_remote.bluetooth_address, wii_remote_udev_device_path
+            );
+        }
+
+        if let (Some(min_battery), Some(battery_level)) = (options.min_battery, battery_level) {
+            let refused_before =
+                refused_address.as_deref() == Some(wii_remote.bluetooth_address.as_str());
+            match battery_level < min_battery {
+                // Already warned about, rumbling at it again every time it comes back would only drain it further
+                true if refused_before && options.refuse_low_battery => {
+                    debug!(
+                        "Wii Remote {} is still at {}%, refusing it again",
+                        wii_remote.bluetooth_address, battery_level
+                    );
+                    wii_remote.disconnect();
+                    wii_remote.udev_device_path = None;
+                    powered_off_at = Some(Instant::now());
+                    continue;
                 }
+                true => {}
+                false if refused_before => {
+                    info!(
+                        "Wii Remote {} is at {}% now, no longer refusing it",
+                        wii_remote.bluetooth_address, battery_level
+                    );
+                    refused_address = None;
+                }
+                false => {}
+            }
 
-                loop {
-                    let event = libinput_get_event(libinput);
-                    if event == std::ptr::null_mut() {
-                        break;
-                    }
+            if battery_level < min_battery {
+                error!(
+                    "Wii Remote battery is at {}%, below the minimum of {}%, swap the batteries!",
+                    battery_level, min_battery
+                );
 
-                    let device = libinput_event_get_device(event);
-                    let udev_device = libinput_device_get_udev_device(device);
-                    let udev_device_path = udev_device_get_syspath(udev_device as *mut _);
-                    let udev_device_path_cstr = CStr::from_ptr(udev_device_path);
-                    if udev_device_path_cstr.to_str().unwrap()
-                        != wii_remote_udev_device_path.as_str()
-                    {
-                        debug!(
-                            "Ignoring event from unrelated device: {}",
-                            udev_device_path_cstr.to_str().unwrap()
-                        );
+                if let Err(err) = wii_remote.set_leds(WiiRemote::battery_led_mask(battery_level)) {
+                    warn!("Failed to show the battery level: {:#}", err);
+                }
 
-                        continue;
-                    }
+                if let Err(err) = wii_remote.set_rumble_pattern(rumble::LOW_BATTERY) {
+                    warn!("Failed to play the low battery rumble: {:#}", err);
+                }
 
-                    let current_time =
-                        match SystemTime::now().duration_since(SystemTime::UNIX_EPOCH) {
-                            Ok(duration) => duration.as_secs(),
-                            Err(_) => {
-                                error!("System time error: clock went backwards");
-                                continue;
-                            }
-                        };
+                // Left alone like a remote that was turned off, or it would just be connected again right away
+                if options.refuse_low_battery {
+                    if let Some((duration, _)) = rumble::schedule(rumble::LOW_BATTERY).last() {
+                        thread::sleep(*duration);
+                    }
 
-                    CURRENT_TIME.store(current_time, Ordering::Relaxed);
-                    debug!("Updated current time: {}", current_time);
+                    info!("Refusing the Wii Remote until its batteries are swapped");
+                    refused_address = Some(wii_remote.bluetooth_address.clone());
+                    wii_remote.disconnect();
+                    wii_remote.udev_device_path = None;
+                    powered_off_at = Some(Instant::now());
+                    continue;
                 }
             }
         }
-    }
-}
 
-fn timeout(wii_remote: &Arc<Mutex<WiiRemote>>) {
-    loop {
-        thread::sleep(std::time::Duration::from_secs(1));
+        if options.keep_scanning {
+            wii_remote.claim();
+        }
 
-        let mut wii_remote = match wii_remote.try_lock() {
-            Ok(lock) => lock,
-            Err(_) => {
-                debug!("Mutex is locked, skipping timeout check...");
-                continue;
+        metrics::record_connected(&wii_remote.bluetooth_address, battery_level);
+
+        let device_info = wii_remote.device_info();
+        if let Some(device_info) = device_info {
+            info!(
+                "Wii Remote is vendor {:04X}, product {:04X} ({}), MotionPlus {}",
+                device_info.vendor,
+                device_info.product,
+                match (device_info.model(), device_info.is_genuine()) {
+                    (Some(model), _) => model,
+                    (None, true) => "unknown Nintendo model",
+                    (None, false) => "not a Nintendo vendor ID, likely third-party",
+                },
+                device_info
+                    .motion_plus
+                    .map_or("none", |source| source.name())
+            );
+        }
+
+        // Only what's there is opened, an interface that's missing would otherwise be warned about on every connect
+        let capabilities = device_info
+            .and_then(|device_info| device_info.capabilities)
+            .unwrap_or_else(|| xwiimote::capabilities(&wii_remote_udev_device_path));
+        info!(
+            "Wii Remote {} has: {}",
+            wii_remote.bluetooth_address,
+            capabilities.summary()
+        );
+
+        let address = wii_remote.bluetooth_address.clone();
+        if worker == 0
+            && WiiRemote::is_valid_address(&address)
+            && saved_address.as_deref() != Some(address.as_str())
+        {
+            match last_remote::save(&address) {
+                Ok(()) => saved_address = Some(address.clone()),
+                Err(err) => warn!("Failed to remember {} for next time: {}", address, err),
+            }
+        }
+
+        let (player_slot, mut forwarder) = match held_slot.take() {
+            Some(held) if held.address == address => {
+                info!(
+                    "Wii Remote {} reconnected within the grace period, keeping player {}",
+                    address, held.player_slot
+                );
+                history::record(&address, ConnectionEvent::Reconnected);
+                (held.player_slot, held.forwarder)
+            }
+            held => {
+                // A different remote showed up, so the old one's slot is up for grabs again
+                if let Some(held) = held {
+                    info!(
+                        "Releasing player {} held by Wii Remote {}",
+                        held.player_slot, held.address
+                    );
+                    player_slots.lock().unwrap().release(&held.address);
+                }
+
+                let (previous_slot, player_slot) = {
+                    let mut player_slots = player_slots.lock().unwrap();
+                    (
+                        player_slots.remembered(&address),
+                        player_slots.assign(&address),
+                    )
+                };
+                info!("Wii Remote {} is player {}", address, player_slot);
+
+                if previous_slot != Some(player_slot) {
+                    if let Err(err) = config.save_slot(&address, player_slot) {
+                        warn!(
+                            "Failed to remember the player slot of {}: {:#}",
+                            address, err
+                        );
+                    }
+                }
+
+                let device_name = options.device_name.expand(player_slot, &address);
+                let mut forwarder = match Forwarder::new(
+                    player_slot,
+                    &device_name,
+                    config,
+                    &profile,
+                    output_mode,
+                ) {
+                    Ok(forwarder) => forwarder,
+                    Err(err) if options.require_uinput => Err(err)
+                        .context("Failed to create the virtual gamepad through /dev/uinput")
+                        .unwrap_or_fmt(),
+                    // The LEDs, battery and idle timeout don't need them
+                    Err(err) => {
+                        warn!(
+                            "Failed to create the virtual devices through /dev/uinput, not forwarding input from Wii Remote {}: {}",
+                            address, err
+                        );
+                        Forwarder::without_devices(
+                            player_slot,
+                            &device_name,
+                            config,
+                            &profile,
+                            output_mode,
+                        )
+                    }
+                };
+                forwarder.set_motion_report_rate(options.motion_report_hz);
+                history::record(&address, ConnectionEvent::Connected);
+
+                (player_slot, forwarder)
             }
         };
 
-        let current_time = match SystemTime::now().duration_since(SystemTime::UNIX_EPOCH) {
-            Ok(duration) => duration.as_secs(),
-            Err(_) => {
-                error!("System time error: clock went backwards");
-                continue;
+        let mut led_mask = match (options.led_mode, battery_level) {
+            (LedMode::Battery, Some(battery_level)) => WiiRemote::battery_led_mask(battery_level),
+            (LedMode::Battery, None) => {
+                warn!("Could not read the battery level, showing the player number instead");
+                PlayerSlots::led_mask(player_slot)
             }
+            (LedMode::Slot, _) => PlayerSlots::led_mask(player_slot),
         };
 
-        let elapsed_time = current_time - CURRENT_TIME.load(Ordering::Relaxed);
+        if let Err(err) = wii_remote.set_leds(led_mask) {
+            warn!("Failed to set the player LEDs: {:#}", err);
+        }
 
-        if elapsed_time >= (5 * 60) {
-            info!("Wii Remote has been idle for 5 minutes, disconnecting...");
-            wii_remote.disconnect();
+        {
+            let mut status = STATUS.lock().unwrap();
+            if worker == 0 {
+                status.remote = Some((address.clone(), player_slot));
+                status.capabilities = Some(capabilities);
+            }
+            // A worker that panicked while its remote was connected left it behind
+            status
+                .remotes
+                .retain(|(connected, _)| *connected != address);
+            status.remotes.push((address.clone(), player_slot));
+        }
+        *options.activity.address.lock().unwrap() = Some(address.clone());
+
+        if let Some(path) = &options.write_device_path {
+            if let Err(err) =
+                utils::write_atomically(path, &format!("{}\n", wii_remote_udev_device_path))
+            {
+                warn!(
+                    "Failed to write the device path to `{}': {}",
+                    path.display(),
+                    err
+                );
+            }
+        }
+        notify::connected(player_slot, battery_level);
+        hooks::connected(&config.hooks, &address, player_slot);
+
+        // When anything last arrived from the remote, which tells a power-off apart from a drop-out
+        let mut last_report = Instant::now();
+        // Only `--simulate-disconnect-after` cares about this, and only until it has disconnected the remote
+        let mut connected_at = Some(Instant::now());
+        // The Bluetooth link rarely survives a suspend intact, even when it still looks connected
+        let mut resumes = resume::count();
+
+        // The poller goes away with `battery_levels` once this connection ends, and keeps the LEDs on `led_mask`
+        let shown_led_mask = Arc::new(AtomicU8::new(led_mask));
+        let battery_levels = battery::spawn_poller(
+            Arc::clone(shared_remote),
+            address.clone(),
+            wii_remote_udev_device_path.clone(),
+            options.status_poll_interval,
+            Arc::clone(&shown_led_mask),
+        );
+
+        // Start out warned if the battery was already low when connecting
+        let mut low_battery_notified = false;
+        if let Some(battery_level) = battery_level {
+            if battery_level <= notify::LOW_BATTERY_THRESHOLD {
+                notify::low_battery(player_slot, battery_level);
+                low_battery_notified = true;
+            }
+        }
+
+        // Tells the user which remote just joined, without holding up the poll loop
+        if options.startup_sequence {
+            let step = Duration::from_millis(config.startup_sweep_step);
+            if let Err(err) = wii_remote.sweep_leds(&config.startup_sweep, step, led_mask) {
+                warn!("Failed to sweep the LEDs: {:#}", err);
+            }
+
+            if let Some(name) = &config.startup_rumble {
+                match rumble::named(name) {
+                    Some(pattern) => {
+                        if let Err(err) = wii_remote.set_rumble_pattern(pattern) {
+                            warn!("Failed to play the startup rumble: {:#}", err);
+                        }
+                    }
+                    None => warn!("Unknown startup rumble pattern `{}'", name),
+                }
+            }
         }
-    }
-}
 
-fn process_log_buffer(buf: &mut Formatter, record: &Record<'_>) -> Result<(), Error> {
-    writeln!(
-        buf,
-        "[{}] [{}]: {}",
-        Local::now().format("%+"),
-        record.level(),
-        record.args()
-    )
+        if options.startup_chime {
+            if let Err(err) = wii_remote.play_sound(&speaker::startup_chime()) {
+                warn!("Failed to play startup chime: {:#}", err);
+            }
+        }
+
+        // A Balance Board has none of a remote's other interfaces, only its load cells
+        let is_balance_board = balance_board::detect(&wii_remote_udev_device_path);
+        let mut balance_board = match is_balance_board {
+            true => {
+                info!("Wii Remote {} is a Balance Board", address);
+                match BalanceBoard::open(
+                    &wii_remote_udev_device_path,
+                    &options.device_name.expand(player_slot, &address),
+                    options.balance_board_axes,
+                ) {
+                    Ok(balance_board) => balance_board,
+                    Err(err) => {
+                        warn!("Failed to create the Balance Board virtual device: {}", err);
+                        None
+                    }
+                }
+            }
+            false => None,
+        };
+
+        // hid-wiimote only turns the IR camera and accelerometer on while their interfaces are open
+        let mut ir_interface =
+            match (forwarder.wants_ir() || options.enable_ir) && !is_balance_board {
+                true if !capabilities.ir => {
+                    info!("Wii Remote {} has no IR camera, not reporting it", address);
+                    None
+                }
+                true => xwiimote::open_interface(&wii_remote_udev_device_path, xwiimote::IR),
+                false => None,
+            };
+
+        // Opening the interface is what sets the camera up, so it has to come first
+        if let (Some(_), Some(level)) = (&ir_interface, options.ir_sensitivity) {
+            if let Err(err) = wii_remote.set_ir_sensitivity(level) {
+                warn!("Failed to set the IR sensitivity: {:#}", err);
+            }
+        }
+
+        let mut accel_interface = match accel_enabled
+            && (forwarder.wants_accel() || options.enable_accel)
+            && !is_balance_board
+        {
+            true if !capabilities.accel => {
+                info!(
+                    "Wii Remote {} has no accelerometer, not reporting it",
+                    address
+                );
+                None
+            }
+            true => xwiimote::open_interface(&wii_remote_udev_device_path, xwiimote::ACCELEROMETER),
+            false => None,
+        };
+
+        // Only forwarded by the gyro mouse, otherwise just read to keep its queue drained. A Wii Remote Plus always
+        // has one, so it's turned on without being asked for
+        let builtin_motion_plus =
+            device_info.is_some_and(|device_info| device_info.has_builtin_motion_plus());
+        let mut motion_plus_interface = match (options.enable_motion_plus
+            || builtin_motion_plus
+            || forwarder.wants_motion_plus())
+            && !is_balance_board
+        {
+            true if !capabilities.motion_plus => {
+                match builtin_motion_plus {
+                    true => warn!(
+                        "Wii Remote {} is a Wii Remote Plus, but hid-wiimote found no MotionPlus in it, continuing without it",
+                        address
+                    ),
+                    false => warn!(
+                        "Wii Remote {} has no MotionPlus, continuing without it",
+                        address
+                    ),
+                }
+                None
+            }
+            true => xwiimote::open_interface(&wii_remote_udev_device_path, xwiimote::MOTION_PLUS),
+            false => None,
+        };
+
+        let mut stick_interface = match is_balance_board {
+            true => None,
+            false => stick::open(&wii_remote_udev_device_path),
+        };
+        if let Some((extension, _)) = &stick_interface {
+            if config.disabled_extensions.contains(extension) {
+                info!(
+                    "The {} is plugged in but in disabled_extensions, ignoring it",
+                    extension.name()
+                );
+                stick_interface = None;
+            }
+        }
+        if let Some((extension, evdev)) = &stick_interface {
+            info!("Reporting the sticks of the {}", extension.name());
+            let stored = wii_remote.extension_calibration();
+            forwarder.set_extension(config, *extension, evdev, stored.as_ref());
+        }
+
+        let extension_disabled = stick::plugged_in(&wii_remote_udev_device_path)
+            .is_some_and(|extension| config.disabled_extensions.contains(&extension));
+        let mut raw_extension = match options.raw_extension && !extension_disabled {
+            true => match speaker::find_hidraw(&wii_remote_udev_device_path).map(|path| {
+                RawExtension::open(&path, &options.device_name.expand(player_slot, &address))
+            }) {
+                Some(Ok(raw_extension)) => Some(raw_extension),
+                Some(Err(err)) => {
+                    warn!("Failed to set up raw extension passthrough: {}", err);
+                    None
+                }
+                None => {
+                    warn!("Wii Remote has no hidraw device for raw extension passthrough");
+                    None
+                }
+            },
+            false => None,
+        };
+
+        // Let `timeout` get at the remote while input is flowing
+        drop(wii_remote);
+
+        // When libinput last saw the remote go away, it only counts once it's stayed gone for the grace period
+        let mut removed_at: Option<Instant> = None;
+        let mut backlog = Backlog::new();
+        // For --event-mode snapshot, a remote that reconnects starts out with nothing held
+        let mut held_buttons = HeldButtons::new();
+        let mut last_snapshot = Instant::now();
+        // Set when the poll loop disconnects the remote itself
+        let mut disconnect_reason: Option<DisconnectReason> = None;
+        options.activity.idled_out.store(false, Ordering::Relaxed);
+
+        if let Some(idle_timer) = &options.idle_timer {
+            idle_timer.borrow_mut().reset();
+        }
+
+        let mut idle_checked_at = Instant::now();
+
+        unsafe {
+            'poll: loop {
+                if let Some(idle_timer) = &options.idle_timer {
+                    if idle_checked_at.elapsed() >= IDLE_CHECK_INTERVAL {
+                        idle_checked_at = Instant::now();
+                        idle_timer
+                            .borrow_mut()
+                            .check(shared_remote, config, &SystemClock);
+                    }
+                }
+
+                if !RUNNING.load(Ordering::Relaxed) {
+                    break 'poll;
+                }
+
+                if removed_at.is_some_and(|at| at.elapsed() >= options.device_removed_grace) {
+                    break 'poll;
+                }
+
+                // The device going away afterwards is what ends the loop, like with a real drop-out
+                if let (Some(at), Some(after)) = (connected_at, options.simulate_disconnect_after) {
+                    if at.elapsed() >= after {
+                        warn!(
+                            "Simulating a disconnect of Wii Remote {} (--simulate-disconnect-after)",
+                            address
+                        );
+                        shared_remote.lock().unwrap().disconnect();
+                        disconnect_reason = Some(DisconnectReason::Simulated);
+                        connected_at = None;
+                    }
+                }
+
+                if options.reconnect_on_resume && resume::count() != resumes {
+                    info!(
+                        "Reconnecting Wii Remote {} after the system resumed",
+                        address
+                    );
+                    resumes = resume::count();
+                    shared_remote.lock().unwrap().disconnect();
+                    disconnect_reason = Some(DisconnectReason::Resume);
+                }
+
+                if options.print_events || recorder.is_some() {
+                    // Opening any more would switch the accelerometer or IR camera on against the user's settings
+                    let events = shared_remote
+                        .lock()
+                        .unwrap()
+                        .poll_events_from(accel_interface.is_some(), ir_interface.is_some());
+                    if options.print_events {
+                        for event in &events {
+                            held_buttons.update(event);
+                            let is_button = matches!(
+                                event,
+                                WiiEvent::ButtonPress(_) | WiiEvent::ButtonRelease(_)
+                            );
+                            if options.event_mode == EventMode::Snapshot && is_button {
+                                continue;
+                            }
+
+                            for line in events::print_lines(event) {
+                                println!("{}", line);
+                            }
+                        }
+
+                        if options.event_mode == EventMode::Snapshot
+                            && last_snapshot.elapsed() >= options.snapshot_interval
+                        {
+                            println!("{}", held_buttons.print_line());
+                            last_snapshot = Instant::now();
+                        }
+                    }
+
+                    if let Some(Err(err)) =
+                        recorder.as_mut().map(|recorder| recorder.write(&events))
+                    {
+                        warn!("Failed to write to the recording, stopping it: {}", err);
+                        recorder = None;
+                    }
+                }
+
+                if mappings_dir::generation() != mappings_generation {
+                    if mappings_dir::reloaded_since(&profile, mappings_generation) {
+                        info!("Profile `{}' changed, applying it again", profile);
+                        if let Err(err) = forwarder.set_profile(config, &profile) {
+                            warn!(
+                                "Failed to release buttons while reloading the profile: {}",
+                                err
+                            );
+                        }
+                    }
+
+                    mappings_generation = mappings_dir::generation();
+                }
+
+                while let Ok(request) = requests.try_recv() {
+                    match request {
+                        Request::SetProfile(name) => {
+                            info!("Switching to profile `{}'", name);
+                            if let Err(err) = forwarder.set_profile(config, &name) {
+                                warn!(
+                                    "Failed to release buttons while switching profiles: {}",
+                                    err
+                                );
+                            }
+
+                            profile = name;
+                            play_feedback(shared_remote, config, Feedback::Profile);
+                        }
+                        Request::SetOutputMode(mode) => {
+                            match forwarder.set_output_mode(config, &profile, mode) {
+                                Ok(()) => {
+                                    output_mode = mode;
+                                    STATUS.lock().unwrap().output_mode = mode;
+                                    play_feedback(shared_remote, config, Feedback::OutputMode);
+                                }
+                                Err(err) => {
+                                    warn!("Failed to switch to {} output: {}", mode.name(), err)
+                                }
+                            }
+                        }
+                        Request::Rumble(pattern) => {
+                            if let Err(err) =
+                                shared_remote.lock().unwrap().set_rumble_pattern(pattern)
+                            {
+                                warn!("Failed to play rumble pattern: {:#}", err);
+                            }
+                        }
+                        Request::SetAccel(enabled) if enabled == accel_enabled => {}
+                        Request::SetAccel(enabled) => {
+                            accel_enabled = enabled;
+                            info!(
+                                "Turning the accelerometer {}",
+                                match enabled {
+                                    true => "on",
+                                    false => "off",
+                                }
+                            );
+
+                            // Closing the interface is what tells hid-wiimote to stop reporting it
+                            match enabled {
+                                true if (forwarder.wants_accel() || options.enable_accel)
+                                    && !is_balance_board =>
+                                {
+                                    accel_interface = xwiimote::open_interface(
+                                        &wii_remote_udev_device_path,
+                                        xwiimote::ACCELEROMETER,
+                                    );
+                                }
+                                true => {}
+                                false => {
+                                    accel_interface = None;
+                                    if let Err(err) = forwarder.reset_accel() {
+                                        warn!("Failed to centre the motion axes: {}", err);
+                                    }
+                                }
+                            }
+                        }
+                    }
+                }
+
+                if let Err(err) = forwarder.repeat_held() {
+                    warn!("Failed to repeat held keys: {}", err);
+                }
+
+                if let Err(err) = forwarder.flush_motion() {
+                    warn!("Failed to forward held back motion: {}", err);
+                }
+
+                if let Err(err) = forwarder.check_long_press() {
+                    warn!("Failed to forward the Home long press: {}", err);
+                }
+
+                // The device going away afterwards is what ends the loop, like with a simulated disconnect
+                if forwarder.take_disconnect_requested() {
+                    info!("Home was held down, disconnecting Wii Remote {}", address);
+                    shared_remote.lock().unwrap().disconnect();
+                    disconnect_reason = Some(DisconnectReason::LongPress);
+                }
+
+                if let Some(evdev) = &mut ir_interface {
+                    match evdev.read_events() {
+                        Ok(events) => {
+                            if !events.is_empty() {
+                                last_report = Instant::now();
+                            }
+
+                            for event in &events {
+                                if let Err(err) = forwarder.forward_ir(event) {
+                                    warn!("Failed to forward IR movement: {}", err);
+                                }
+                            }
+                        }
+                        Err(err) => {
+                            warn!("Failed to read from the IR interface: {}", err);
+                            ir_interface = None;
+                        }
+                    }
+                }
+
+                if let Some(evdev) = &mut motion_plus_interface {
+                    match evdev.read_events() {
+                        Ok(events) => {
+                            for event in &events {
+                                if let Err(err) = forwarder.forward_motion_plus(event) {
+                                    warn!("Failed to move the gyro pointer: {}", err);
+                                }
+                            }
+                        }
+                        Err(err) => {
+                            warn!("Failed to read from the MotionPlus interface: {}", err);
+                            motion_plus_interface = None;
+                        }
+                    }
+                }
+
+                while let Ok(level) = battery_levels.try_recv() {
+                    if options.led_mode == LedMode::Battery {
+                        let mask = WiiRemote::battery_led_mask(level);
+                        if mask != led_mask {
+                            led_mask = mask;
+                            shown_led_mask.store(mask, Ordering::Relaxed);
+                            if let Err(err) = shared_remote.lock().unwrap().set_leds(mask) {
+                                warn!("Failed to update the battery LEDs: {:#}", err);
+                            }
+                        }
+                    }
+
+                    match level > notify::LOW_BATTERY_THRESHOLD {
+                        true => low_battery_notified = false,
+                        false if !low_battery_notified => {
+                            warn!("Wii Remote battery is low, at {}%", level);
+                            notify::low_battery(player_slot, level);
+                            low_battery_notified = true;
+                        }
+                        _ => {}
+                    }
+                }
+
+                if let Some(extension) = &mut raw_extension {
+                    if let Err(err) = extension.poll() {
+                        warn!("Failed to read raw extension reports: {}", err);
+                        raw_extension = None;
+                    }
+                }
+
+                if let Some(board) = &mut balance_board {
+                    match board.poll() {
+                        Ok(Some(reading)) => {
+                            last_report = Instant::now();
+                            trace!(
+                                "Balance Board: {:.2} kg, balance {:?}",
+                                reading.total_kg(),
+                                reading.center_of_balance()
+                            );
+                        }
+                        Ok(None) => {}
+                        Err(err) => {
+                            warn!("Failed to read from the Balance Board: {}", err);
+                            balance_board = None;
+                        }
+                    }
+                }
+
+                if let Some((_, evdev)) = &mut stick_interface {
+                    match evdev.read_events() {
+                        Ok(events) => {
+                            if !events.is_empty() {
+                                last_report = Instant::now();
+                            }
+
+                            for event in &events {
+                                if let Err(err) = forwarder.forward_stick(event) {
+                                    warn!("Failed to forward stick movement: {}", err);
+                                }
+                            }
+                        }
+                        Err(err) => {
+                            // Also what unplugging the extension looks like
+                            debug!("Stopped reading the extension interface: {}", err);
+                            stick_interface = None;
+                            if worker == 0 {
+                                STATUS.lock().unwrap().capabilities =
+                                    Some(xwiimote::capabilities(&wii_remote_udev_device_path));
+                            }
+                            if let Err(err) = forwarder.release_stick_dpad() {
+                                warn!("Failed to release the D-pad held by the stick: {}", err);
+                            }
+                        }
+                    }
+                }
+
+                if let Some(evdev) = &mut accel_interface {
+                    match evdev.read_events() {
+                        Ok(events) => {
+                            if !events.is_empty() {
+                                last_report = Instant::now();
+                            }
+
+                            for event in &events {
+                                if let Err(err) = forwarder.forward_accel(event) {
+                                    warn!("Failed to forward accelerometer readings: {}", err);
+                                }
+                            }
+                        }
+                        Err(err) => {
+                            warn!("Failed to read from the accelerometer interface: {}", err);
+                            accel_interface = None;
+                        }
+                    }
+                }
+
+                let ret = libinput_dispatch(libinput);
+                if ret != 0 {
+                    error!("Failed to dispatch libinput events: {}", ret);
+                    break;
+                }
+
+                let mut drained = 0;
+                loop {
+                    let event = libinput_get_event(libinput);
+                    if event.is_null() {
+                        break;
+                    }
+
+                    drained += 1;
+
+                    let udev_device_path = lib_input::event_syspath(event);
+
+                    let event_type = libinput_event_get_type(event);
+                    if !lib_input::passes_filter(&options.input_filter, event_type) {
+                        libinput_event_destroy(event);
+                        continue;
+                    }
+
+                    // What libinput makes of the remote's devices, for when input goes missing because the
+                    // kernel exposes them differently than expected. Asked for before the event is destroyed.
+                    let capabilities = match event_type
+                        == libinput_event_type_LIBINPUT_EVENT_DEVICE_ADDED
+                        && log_enabled!(Level::Debug)
+                    {
+                        true => Some(lib_input::event_capabilities(event)),
+                        false => None,
+                    };
+                    let key = match event_type == libinput_event_type_LIBINPUT_EVENT_KEYBOARD_KEY {
+                        true => {
+                            let keyboard_event = libinput_event_get_keyboard_event(event);
+                            Some((
+                                libinput_event_keyboard_get_key(keyboard_event),
+                                libinput_event_keyboard_get_key_state(keyboard_event)
+                                    == libinput_key_state_LIBINPUT_KEY_STATE_PRESSED,
+                            ))
+                        }
+                        false => None,
+                    };
+
+                    libinput_event_destroy(event);
+
+                    match key {
+                        Some((key, pressed)) => trace!(
+                            "libinput event {} from {}: key {} {}",
+                            lib_input::event_type_name(event_type),
+                            udev_device_path,
+                            key,
+                            match pressed {
+                                true => "pressed",
+                                false => "released",
+                            }
+                        ),
+                        None => trace!(
+                            "libinput event {} from {}",
+                            lib_input::event_type_name(event_type),
+                            udev_device_path
+                        ),
+                    }
+
+                    if !lib_input::belongs_to(&udev_device_path, &wii_remote_udev_device_path) {
+                        debug!("Ignoring event from unrelated device: {}", udev_device_path);
+                        continue;
+                    }
+
+                    if !only_devices.is_empty()
+                        && !only_devices
+                            .iter()
+                            .any(|syspath| lib_input::belongs_to(&udev_device_path, syspath))
+                    {
+                        debug!(
+                            "Ignoring event from device outside of --only-device: {}",
+                            udev_device_path
+                        );
+                        continue;
+                    }
+
+                    if event_type == libinput_event_type_LIBINPUT_EVENT_DEVICE_REMOVED {
+                        match options.device_removed_grace.is_zero() {
+                            true => break 'poll,
+                            false => removed_at = removed_at.or(Some(Instant::now())),
+                        }
+                    }
+
+                    if let Some(capabilities) = capabilities {
+                        info!(
+                            "libinput added {} with capabilities: {}",
+                            udev_device_path,
+                            match capabilities.is_empty() {
+                                true => "none".to_owned(),
+                                false => capabilities.join(", "),
+                            }
+                        );
+                    }
+
+                    if event_type == libinput_event_type_LIBINPUT_EVENT_DEVICE_ADDED
+                        && removed_at.take().is_some()
+                    {
+                        debug!("Wii Remote came back within the device removal grace period");
+                    }
+
+                    if let Some((key, pressed)) = key {
+                        last_report = Instant::now();
+                        // A button bound to a command runs it instead of being forwarded
+                        match commands.handle(key as u16, pressed) {
+                            true if commands.take_ran() => {
+                                play_feedback(shared_remote, config, Feedback::Command)
+                            }
+                            true => {}
+                            false => {
+                                if let Err(err) = forwarder.forward_key(key as u16, pressed) {
+                                    warn!(
+                                        "Failed to forward key {} to the virtual gamepad: {}",
+                                        key, err
+                                    );
+                                }
+
+                                if forwarder.take_chord_fired() {
+                                    play_feedback(shared_remote, config, Feedback::Chord);
+                                }
+                            }
+                        }
+                    }
+
+                    let current_time =
+                        match SystemTime::now().duration_since(SystemTime::UNIX_EPOCH) {
+                            Ok(duration) => duration.as_secs(),
+                            Err(_) => {
+                                error!("System time error: clock went backwards");
+                                continue;
+                            }
+                        };
+
+                    options.activity.record(current_time);
+                    debug!("Updated current time: {}", current_time);
+                }
+
+                metrics::record_input_events(drained as u64);
+                if let Some(average) = backlog.record(drained, Instant::now()) {
+                    warn!(
+                        "Input from Wii Remote {} is piling up, {} events waited for each dispatch lately. \
+                         Something else is probably hogging the CPU, expect input lag",
+                        address, average
+                    );
+                }
+            }
+        }
+
+        info!(
+            "Wii Remote {} disconnected, holding player {} for {} seconds",
+            address,
+            player_slot,
+            reconnect_grace.as_secs()
+        );
+
+        // Measured from when the device went away, so the removal grace doesn't make a power-off look like a drop-out
+        let gone_at = removed_at.unwrap_or_else(Instant::now);
+        let powered_off = gone_at.saturating_duration_since(last_report) < POWER_OFF_WINDOW;
+        let reason = match disconnect_reason {
+            Some(reason) => reason,
+            None if options.activity.idled_out.swap(false, Ordering::Relaxed) => {
+                DisconnectReason::Idle
+            }
+            None if powered_off => DisconnectReason::PowerOff,
+            None => DisconnectReason::OutOfRange,
+        };
+        history::record(&address, ConnectionEvent::Disconnected(reason));
+
+        // Only a remote the user turned off, one BlueWii disconnected itself is as welcome back as any other
+        match reason {
+            DisconnectReason::PowerOff if !options.power_off_cooldown.is_zero() => {
+                info!(
+                    "Wii Remote {} was turned off, leaving it alone for {} seconds unless 1+2 is pressed",
+                    address,
+                    options.power_off_cooldown.as_secs()
+                );
+                powered_off_at = Some(Instant::now());
+            }
+            _ => powered_off_at = None,
+        }
+
+        if let Some(path) = &options.write_device_path {
+            if let Err(err) = fs::remove_file(path) {
+                warn!("Failed to remove `{}': {}", path.display(), err);
+            }
+        }
+
+        metrics::record_disconnected(&address);
+        notify::disconnected(player_slot);
+        hooks::disconnected(&config.hooks, &address, player_slot);
+        {
+            let mut status = STATUS.lock().unwrap();
+            if worker == 0 {
+                status.remote = None;
+                status.capabilities = None;
+            }
+            status
+                .remotes
+                .retain(|(connected, _)| *connected != address);
+        }
+        *options.activity.address.lock().unwrap() = None;
+        shared_remote.lock().unwrap().udev_device_path = None;
+        held_slot = Some(HeldSlot {
+            address,
+            player_slot,
+            forwarder,
+            disconnected_at: Instant::now(),
+        });
+    }
+}
+
+/// The player slot and virtual gamepad of the current remote, kept around for a while after it disconnects.
+struct HeldSlot {
+    address: String,
+    player_slot: u8,
+    forwarder: Forwarder,
+    disconnected_at: Instant,
+}
+
+/// The Cargo features that can be left out of a build, whether this one has each and what it adds.
+const FEATURES: [(&str, bool, &str); 3] = [
+    (
+        "metrics",
+        cfg!(feature = "metrics"),
+        "--metrics-addr, Prometheus metrics over HTTP",
+    ),
+    (
+        "desktop-notify",
+        cfg!(feature = "desktop-notify"),
+        "--notify, desktop notifications",
+    ),
+    (
+        "suspend-resume",
+        cfg!(feature = "suspend-resume"),
+        "--on-resume, noticing the system waking from suspend",
+    ),
+];
+
+fn list_capabilities() {
+    println!("bluewii {}", env!("CARGO_PKG_VERSION"));
+
+    // One feature per line so the output can be piped through grep/cut
+    for (feature, enabled, adds) in FEATURES {
+        println!("{}\t{}\t{}", feature, yes_no(enabled), adds);
+    }
+}
+
+fn list_remotes(wii_remote: &WiiRemote) {
+    info!("Scanning for Wii Remotes...");
+
+    // One remote per line so the output can be piped through grep/cut
+    for remote in wii_remote.list_remotes() {
+        println!(
+            "{}\tpaired={}\ttrusted={}\tconnected={}\t{}",
+            remote.address,
+            yes_no(remote.paired),
+            yes_no(remote.trusted),
+            yes_no(remote.connected),
+            remote.name
+        );
+    }
+}
+
+/// Plays a capture through player 1's virtual devices, like the remote it was recorded from was connected again.
+/// Configured commands aren't run.
+fn replay(path: &Path, config: &Config, profile: &str) -> bool {
+    let capture = match fs::read_to_string(path) {
+        Ok(capture) => capture,
+        Err(err) => {
+            eprintln!("Failed to read `{}': {}", path.display(), err);
+            return false;
+        }
+    };
+
+    let captured = match events::parse_capture(&capture) {
+        Ok(captured) => captured,
+        Err(err) => {
+            eprintln!("`{}' can't be replayed: {}", path.display(), err);
+            return false;
+        }
+    };
+
+    if let Some(problem) = permissions::uinput_problem() {
+        eprintln!("Virtual devices can't be created: {}", problem);
+        return false;
+    }
+
+    let device_name = NameTemplate::default().expand(1, "");
+    let mut forwarder = match Forwarder::new(1, &device_name, config, profile, config.output_mode) {
+        Ok(forwarder) => forwarder,
+        Err(err) => {
+            eprintln!("Failed to create the virtual devices: {}", err);
+            return false;
+        }
+    };
+
+    // Give whatever reads the virtual devices a moment to notice them, or the first events go nowhere
+    thread::sleep(Duration::from_secs(1));
+
+    info!(
+        "Replaying {} events from `{}'",
+        captured.len(),
+        path.display()
+    );
+    let started = Instant::now();
+    for (offset, event) in captured {
+        // Held keys keep repeating while waiting on the next event, like they would live
+        while let Some(left) = offset.checked_sub(started.elapsed()) {
+            if left.is_zero() {
+                break;
+            }
+
+            if let Err(err) = forwarder.repeat_held() {
+                warn!("Failed to repeat held keys: {}", err);
+            }
+
+            thread::sleep(left.min(Duration::from_millis(10)));
+        }
+
+        let result = match &event {
+            WiiEvent::ButtonPress(button) => forwarder.forward_key(button.code(), true).map(drop),
+            WiiEvent::ButtonRelease(button) => {
+                forwarder.forward_key(button.code(), false).map(drop)
+            }
+            WiiEvent::Accel(..) => events::raw_events(&event)
+                .iter()
+                .try_for_each(|raw| forwarder.forward_accel(raw)),
+            WiiEvent::Ir(_) => events::raw_events(&event)
+                .iter()
+                .try_for_each(|raw| forwarder.forward_ir(raw)),
+            // Captures don't have the extension's sticks in them, so there's nothing for it to drive
+            WiiEvent::ExtensionAttached(_) | WiiEvent::ExtensionDetached => Ok(()),
+        };
+
+        if let Err(err) = result {
+            warn!("Failed to replay {:?}: {}", event, err);
+        }
+    }
+
+    true
+}
+
+/// Walks through the rumble and each LED of a remote, asking whether each one worked, for checking the hardware
+/// and the sysfs paths BlueWii uses after a kernel update. Returns whether they all did.
+fn self_test(wii_remote: &mut WiiRemote) -> bool {
+    println!("Press 1+2 on the Wii Remote to test...");
+    if !wii_remote.try_connect() {
+        eprintln!("Failed to connect to a Wii Remote");
+        return false;
+    }
+
+    wii_remote.udev_device_path = wii_remote.wait_for_udev_device_path(DEVICE_PATH_TIMEOUT);
+    if wii_remote.udev_device_path.is_none() {
+        eprintln!(
+            "Wii Remote {} is connected, but xwiishow doesn't list it",
+            wii_remote.bluetooth_address
+        );
+        return false;
+    }
+
+    println!("Testing Wii Remote {}.", wii_remote.bluetooth_address);
+    let mut results = Vec::new();
+
+    let rumbled = match wii_remote.set_rumble_pattern(rumble::LOW_BATTERY) {
+        Ok(()) => confirm("Did the Wii Remote buzz twice?"),
+        Err(err) => {
+            eprintln!("Failed to rumble: {:#}", err);
+            false
+        }
+    };
+    results.push(("Rumble".to_owned(), rumbled));
+
+    for index in 0..4 {
+        let lit = match wii_remote.set_leds(1 << index) {
+            Ok(()) => confirm(&format!("Is only LED {} lit?", index + 1)),
+            Err(err) => {
+                eprintln!("Failed to light LED {}: {:#}", index + 1, err);
+                false
+            }
+        };
+        results.push((format!("LED {}", index + 1), lit));
+    }
+
+    if let Err(err) = wii_remote.set_leds(0) {
+        warn!("Failed to turn the LEDs off: {:#}", err);
+    }
+
+    println!();
+    for (step, passed) in &results {
+        let result = match passed {
+            true => "pass",
+            false => "fail",
+        };
+        println!("{}\t{}", step, result);
+    }
+
+    results.iter().all(|(_, passed)| *passed)
+}
+
+/// Asks a yes or no question on stdin, anything but an answer starting with `n` counts as yes.
+fn confirm(question: &str) -> bool {
+    print!("{} [Y/n] ", question);
+    let _ = io::stdout().flush();
+
+    let mut answer = String::new();
+    let _ = io::stdin().read_line(&mut answer);
+    !answer.trim_start().to_lowercase().starts_with('n')
+}
+
+/// Prints the syspaths the poll loop compares, for when a connected remote's buttons seem to be ignored.
+fn dump_syspath(wii_remote: &mut WiiRemote) -> bool {
+    if !wii_remote.try_connect() {
+        eprintln!("Failed to connect to a Wii Remote");
+        return false;
+    }
+
+    let udev_device_path = match wii_remote.wait_for_udev_device_path(DEVICE_PATH_TIMEOUT) {
+        Some(path) => path,
+        None => {
+            eprintln!(
+                "Wii Remote {} is connected, but xwiishow doesn't list it",
+                wii_remote.bluetooth_address
+            );
+            return false;
+        }
+    };
+
+    println!("xwiishow\t{}", udev_device_path);
+    for (syspath, address) in xwiimote::bound_remotes() {
+        println!("hid-wiimote\t{}\t{}", syspath.display(), address);
+    }
+
+    // One line per device, `read` marks the ones the poll loop would take events from
+    let libinput = match unsafe { lib_input::open_seat() } {
+        Ok(libinput) => libinput,
+        Err(err) => {
+            eprintln!("Failed to start libinput: {}", err);
+            return false;
+        }
+    };
+
+    let syspaths = unsafe { lib_input::device_syspaths(libinput) };
+    for syspath in &syspaths {
+        println!(
+            "libinput\t{}\t{}",
+            syspath,
+            match lib_input::belongs_to(syspath, &udev_device_path) {
+                true => "read",
+                false => "ignored",
+            }
+        );
+    }
+
+    syspaths
+        .iter()
+        .any(|syspath| lib_input::belongs_to(syspath, &udev_device_path))
+}
+
+/// Reports whether virtual devices can be created, with what to do about it when they can't.
+fn check_uinput() -> bool {
+    match permissions::uinput_problem() {
+        Some(problem) => {
+            println!("{}", problem);
+            false
+        }
+        None => {
+            println!("Virtual devices can be created through /dev/uinput");
+            true
+        }
+    }
+}
+
+fn check_permissions() -> bool {
+    let wii_remote_nodes = permissions::wii_remote_event_nodes();
+    if wii_remote_nodes.is_empty() {
+        info!("No Wii Remote is connected, so only /dev/uinput can be checked");
+    }
+
+    let denied = permissions::check();
+    for (path, err) in &denied {
+        println!("{}\t{}", path.display(), err);
+    }
+
+    if denied.is_empty() {
+        println!("All {} device(s) can be opened", wii_remote_nodes.len() + 1);
+    }
+
+    if denied
+        .iter()
+        .any(|(_, err)| permissions::is_permission_error(err))
+    {
+        println!("{}", permissions::HELP);
+    }
+
+    denied.is_empty()
+}
+
+/// Plays the rumble pattern the config gives `event`, if it gives it one.
+fn play_feedback(shared_remote: &Mutex<WiiRemote>, config: &Config, event: Feedback) {
+    let Some(name) = config.feedback.get(&event) else {
+        return;
+    };
+
+    match rumble::named(name) {
+        Some(pattern) => {
+            if let Err(err) = shared_remote.lock().unwrap().set_rumble_pattern(pattern) {
+                warn!("Failed to play the {:?} feedback rumble: {:#}", event, err);
+            }
+        }
+        None => warn!("Unknown feedback rumble pattern `{}'", name),
+    }
+}
+
+/// The config in effect: the defaults, then the config file, then `device_line_regex` from the environment, then
+/// the flags, each winning over the last.
+fn merged_config(matches: &ArgMatches, device_line_regex: Option<String>) -> Config {
+    let mut config = match matches.get_one::<PathBuf>("config") {
+        Some(path) => Config::load_from(path),
+        None => Config::load(),
+    };
+
+    if let Some(pattern) = device_line_regex.filter(|pattern| !pattern.is_empty()) {
+        config.device_line_regex = Some(pattern);
+    }
+
+    // These can only turn the config file's settings on, not off
+    config.ir_invert_x |= matches.get_flag("ir-invert-x");
+    config.ir_invert_y |= matches.get_flag("ir-invert-y");
+    config.allow.extend(
+        matches
+            .get_many::<String>("allow")
+            .into_iter()
+            .flatten()
+            .cloned(),
+    );
+    config.deny.extend(
+        matches
+            .get_many::<String>("deny")
+            .into_iter()
+            .flatten()
+            .cloned(),
+    );
+    config
+}
+
+/// The address of the controller `adapter` names, exiting with the ones there are when it names none of them.
+fn resolve_adapter(bluetoothctl_path: &str, adapter: &str) -> String {
+    let controllers = bluetoothctl::list(bluetoothctl_path)
+        .exit_if_missing(bluetoothctl_path, "bluez")
+        .context("Failed to execute `bluetoothctl list'")
+        .unwrap_or_fmt();
+
+    // `bluetoothctl list' has no kernel names, so those are turned into the address it does show first
+    let interface_address = match bluetoothctl::is_interface_name(adapter) {
+        true => bluetoothctl::interface_address(adapter)
+            .exit_if_missing("gdbus", "glib")
+            .context("Failed to execute `gdbus call'")
+            .unwrap_or_fmt(),
+        false => None,
+    };
+
+    match bluetoothctl::resolve(
+        &controllers,
+        interface_address.as_deref().unwrap_or(adapter),
+    ) {
+        Some(address) => address,
+        None => {
+            error!("There is no Bluetooth controller `{}'", adapter);
+            for controller in &controllers {
+                error!(
+                    "    {} {}{}",
+                    controller.address,
+                    controller.name,
+                    match controller.default {
+                        true => " (default)",
+                        false => "",
+                    }
+                );
+            }
+
+            process::exit(1);
+        }
+    }
+}
+
+/// Logs what the daemon would do with `config` and `options`, for `--dry-run`. Nothing here spawns a process or
+/// opens a device, so it's safe to try flags out with.
+fn dry_run(
+    config: &Config,
+    options: &ConnectOptions,
+    wii_remote: &WiiRemote,
+    idle_timeout: u64,
+    battery_aware_idle: bool,
+    max_remotes: usize,
+    reset_adapter: bool,
+) {
+    info!("Dry run, nothing is connected or created");
+
+    if reset_adapter {
+        info!("Would turn the Bluetooth controller off and on again first");
+    }
+
+    let backend = match wii_remote.connect_backend {
+        ConnectBackend::Bluetoothctl => "bluetoothctl",
+        ConnectBackend::Xwiimote => "xwiimote",
+    };
+    match &options.pinned_address {
+        Some(address) => info!(
+            "Would connect {} with `{}' ({} backend){}",
+            address,
+            wii_remote.bluetoothctl_path,
+            backend,
+            match options.fallback_scan {
+                true => ", scanning for any other Wii Remote if it can't be",
+                false => "",
+            }
+        ),
+        None => info!(
+            "Would {}scan for up to {} Wii Remote(s) with `{}' ({} backend), picking {}",
+            match wii_remote.prefer_paired {
+                true => "try the paired Wii Remotes, then ",
+                false => "",
+            },
+            max_remotes,
+            wii_remote.bluetoothctl_path,
+            backend,
+            match wii_remote.prefer_strongest {
+                true => "the strongest signal",
+                false => "the most recently connected",
+            }
+        ),
+    }
+
+    if let Some(adapter) = &wii_remote.adapter {
+        info!("Would use the Bluetooth controller {}", adapter);
+    }
+
+    if !wii_remote.allow.is_empty() {
+        info!("Would only connect {}", wii_remote.allow.join(", "));
+    }
+
+    if !wii_remote.deny.is_empty() {
+        info!("Would never connect {}", wii_remote.deny.join(", "));
+    }
+
+    let mapping = forwarder::mapping(config, &options.profile, config.output_mode);
+    info!(
+        "Would use profile `{}' with {} output",
+        options.profile,
+        config.output_mode.name()
+    );
+    for (key, output) in &mapping.keys {
+        info!(
+            "  {} -> {}",
+            Button::from_code(*key).map_or("?", Button::name),
+            evdev::name_from_code(*output).unwrap_or("?")
+        );
+    }
+
+    for (keys, output) in &mapping.chords {
+        info!(
+            "  {} -> {}",
+            keys.iter()
+                .map(|key| Button::from_code(*key).map_or("?", Button::name))
+                .collect::<Vec<_>>()
+                .join("+"),
+            evdev::name_from_code(*output).unwrap_or("?")
+        );
+    }
+
+    let device_name = options.device_name.expand(1, "<address>");
+    let mut devices = Vec::new();
+    if config.output_mode != OutputMode::Keyboard {
+        devices.push(device_name.clone());
+    }
+
+    if config.output_mode != OutputMode::Gamepad || mapping.outputs().any(evdev::is_keyboard_key) {
+        devices.push(format!("{} Keyboard", device_name));
+    }
+
+    if config.ir_mode != IrMode::Off {
+        devices.push(format!("{} Pointer", device_name));
+    }
+
+    if options.raw_extension {
+        devices.push(format!("{} Extension", device_name));
+    }
+
+    for device in devices {
+        info!("Would create the virtual device `{}' for player 1", device);
+    }
+
+    info!(
+        "Would light the LEDs {} for player 1",
+        match options.led_mode {
+            LedMode::Slot => battery::pattern(PlayerSlots::led_mask(1)),
+            LedMode::Battery => "by battery level".to_owned(),
+        }
+    );
+
+    match config.idle_timeout("", idle_timeout) {
+        0 => info!("Would never disconnect an idle Wii Remote"),
+        timeout => info!(
+            "Would disconnect a Wii Remote after {} idle seconds",
+            timeout
+        ),
+    }
+
+    if battery_aware_idle {
+        for (percent, seconds) in &config.battery_idle_timeouts {
+            info!(
+                "Would disconnect a Wii Remote below {}% battery after {} idle seconds at most",
+                percent, seconds
+            );
+        }
+    }
+}
+
+/// The exit code of `--check`, for monitoring scripts. A remote counts once hid-wiimote drives it or bluez says it's
+/// connected, a paired remote that's turned off doesn't.
+fn health_check(wii_remote: &WiiRemote) -> i32 {
+    // Check the tool first, `is_connected` would exit with its own code if it's missing
+    if let ToolVersion::Missing = versions::detect(&wii_remote.bluetoothctl_path) {
+        error!("`{}' is not installed", wii_remote.bluetoothctl_path);
+        return 2;
+    }
+
+    let denied = permissions::check();
+    for (path, err) in &denied {
+        error!("Cannot open {}: {}", path.display(), err);
+    }
+
+    if !denied.is_empty() {
+        return 2;
+    }
+
+    match !xwiimote::bound_remotes().is_empty() || wii_remote.is_connected() {
+        true => 0,
+        false => 1,
+    }
+}
+
+/// Walks through calibrating the sticks of the extension plugged into the first connected remote, saving the
+/// result to the config file.
+fn calibrate(config: &Config) -> bool {
+    let udev_device_path = match xwiimote::bound_remotes().into_iter().next() {
+        Some((syspath, _)) => syspath.to_string_lossy().into_owned(),
+        None => {
+            eprintln!(
+                "No Wii Remote is connected, connect one with its extension plugged in first"
+            );
+            return false;
+        }
+    };
+
+    let (extension, mut evdev) = match stick::open(&udev_device_path) {
+        Some(interface) => interface,
+        None => {
+            eprintln!("The Wii Remote has no Nunchuk or Classic Controller plugged in");
+            return false;
+        }
+    };
+
+    let axes = extension.axes();
+    let sample = |evdev: &Evdev| {
+        axes.iter()
+            .map(|axis| evdev.abs_info(axis.source).map(|info| info.value).ok())
+            .collect::<Vec<_>>()
+    };
+
+    println!("Calibrating the {}.", extension.name());
+    println!("Leave the stick(s) alone, then press Enter...");
+    let mut line = String::new();
+    let _ = io::stdin().read_line(&mut line);
+
+    // Sample the resting position for a second
+    let mut resting = vec![Vec::new(); axes.len()];
+    let started = Instant::now();
+    while started.elapsed() < Duration::from_secs(1) {
+        for (samples, value) in resting.iter_mut().zip(sample(&evdev)) {
+            samples.extend(value);
+        }
+
+        thread::sleep(Duration::from_millis(10));
+    }
+
+    println!("Move the stick(s) all the way around a few times, then press Enter...");
+    let (done_sender, done) = mpsc::channel();
+    thread::spawn(move || {
+        let mut line = String::new();
+        let _ = io::stdin().read_line(&mut line);
+        let _ = done_sender.send(());
+    });
+
+    let mut moving = vec![Vec::new(); axes.len()];
+    while done.try_recv().is_err() {
+        for (samples, value) in moving.iter_mut().zip(sample(&evdev)) {
+            samples.extend(value);
+        }
+
+        // Only the latest values matter, throw away what queued up
+        let _ = evdev.read_events();
+        thread::sleep(Duration::from_millis(10));
+    }
+
+    let mut calibrations = Vec::new();
+    for ((axis, resting), moving) in axes.iter().zip(&resting).zip(&moving) {
+        match stick::calibrate(resting, moving) {
+            Some(calibration) => {
+                println!(
+                    "{}: center {}, range {} to {}, deadzone {}",
+                    axis.name,
+                    calibration.center,
+                    calibration.min,
+                    calibration.max,
+                    calibration.deadzone
+                );
+                calibrations.push((axis.name, calibration));
+            }
+            None => eprintln!("{}: no readings, leaving it uncalibrated", axis.name),
+        }
+    }
+
+    match config.save_calibration(extension, &calibrations) {
+        Ok(()) => {
+            println!("Saved to the config file.");
+            true
+        }
+        Err(err) => {
+            eprintln!("Failed to save the calibration: {:#}", err);
+            false
+        }
+    }
+}
+
+/// Unpairs `address`, refusing while it's connected unless `force` is set, since it may be in use. Returns the
+/// exit code.
+fn forget(wii_remote: &mut WiiRemote, address: &str, force: bool) -> i32 {
+    let connected = wii_remote
+        .enumerate_known()
+        .into_iter()
+        .any(|device| device.address == address && device.info.is_some_and(|info| info.connected));
+    if connected && !force {
+        error!(
+            "{} is connected and may be in use, add --force to forget it anyway",
+            address
+        );
+        return 1;
+    }
+
+    match wii_remote
+        .forget(address)
+        .exit_if_missing(&wii_remote.bluetoothctl_path, "bluez")
+    {
+        Ok(()) => {
+            println!("Forgot {}", address);
+            0
+        }
+        Err(err) => {
+            error!("Failed to forget {}: {}", address, err);
+            1
+        }
+    }
+}
+
+fn disconnect_all(wii_remote: &mut WiiRemote) {
+    let connected = wii_remote
+        .known_remotes()
+        .into_iter()
+        .filter(|remote| remote.connected)
+        .collect::<Vec<_>>();
+
+    for remote in &connected {
+        wii_remote.disconnect_address(&remote.address);
+        println!("Disconnected {}\t{}", remote.address, remote.name);
+    }
+
+    info!("Disconnected {} Wii Remote(s)", connected.len());
+}
+
+fn yes_no(value: bool) -> &'static str {
+    match value {
+        true => "yes",
+        false => "no",
+    }
+}
+
+/// What the remote's four LEDs show.
+#[derive(Clone, Copy, PartialEq)]
+enum LedMode {
+    /// The player slot, see [`PlayerSlots::led_mask`].
+    Slot,
+    /// Roughly how much charge is left, needs hid-wiimote's battery reporting.
+    Battery,
+}
+
+/// What counts as activity for the idle timeout.
+#[derive(Clone, Copy, PartialEq)]
+enum IdleBasis {
+    /// Only input from the remote, so a remote left alone during a paused game is disconnected to save its
+    /// batteries.
+    Input,
+    /// Staying connected, so a remote is only ever disconnected by the user or by going out of range.
+    Connection,
+}
+
+/// How long the LEDs spend off and then on each time they blink for `--idle-warning`.
+const IDLE_WARNING_BLINK: Duration = Duration::from_millis(250);
+
+/// How often each remote is checked for going idle.
+const IDLE_CHECK_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Checks for a worker's remote going idle every [`IDLE_CHECK_INTERVAL`], see [`IdleTimer::check`].
+fn timeout(
+    wii_remote: &Arc<Mutex<WiiRemote>>,
+    config: &Config,
+    mut idle_timer: IdleTimer,
+    clock: &dyn Clock,
+) {
+    loop {
+        thread::sleep(IDLE_CHECK_INTERVAL);
+
+        if !idle_timer.check(wii_remote, config, clock) {
+            return;
+        }
+    }
+}
+
+/// What a worker's [`IdleTimer`] goes by, updated by the worker as it goes so the timer doesn't need the remote's
+/// lock to check on it.
+#[derive(Default)]
+struct Activity {
+    /// When the remote last sent input or connected, in seconds since the epoch.
+    last_input: AtomicU64,
+    /// The address of the remote while the worker is polling it.
+    address: Mutex<Option<String>>,
+    /// Set by the idle timer when it disconnects the remote, so the poll loop can tell why the remote went away.
+    idled_out: AtomicBool,
+}
+
+impl Activity {
+    fn record(&self, now: u64) {
+        self.last_input.store(now, Ordering::Relaxed);
+        CURRENT_TIME.fetch_max(now, Ordering::Relaxed);
+    }
+}
+
+/// The idle timeout of one worker's remote, checked by [`timeout`] or, with `--no-daemon-threads`, by the worker
+/// itself in between polls.
+#[derive(Clone)]
+struct IdleTimer {
+    activity: Arc<Activity>,
+    default_idle_timeout: u64,
+    idle_warning: u64,
+    idle_basis: IdleBasis,
+    /// Whether the timeout is shortened while the battery is low, see [`Config::battery_idle_timeout`].
+    battery_aware: bool,
+    /// Woken up after the idle timeout disconnects the remote, to exit (`--exit-on-idle`).
+    exit_on_idle: Option<Thread>,
+    /// Whether the remote was warned about this stretch of idling, until input arrives or it's disconnected.
+    warned: bool,
+}
+
+impl IdleTimer {
+    fn new(
+        activity: Arc<Activity>,
+        default_idle_timeout: u64,
+        idle_warning: u64,
+        idle_basis: IdleBasis,
+        battery_aware: bool,
+        exit_on_idle: Option<Thread>,
+    ) -> IdleTimer {
+        IdleTimer {
+            activity,
+            default_idle_timeout,
+            idle_warning,
+            idle_basis,
+            battery_aware,
+            exit_on_idle,
+            warned: false,
+        }
+    }
+
+    /// Forgets the warning, for a remote that just connected.
+    fn reset(&mut self) {
+        self.warned = false;
+    }
+
+    /// Warns about or disconnects the remote if it's been idle for long enough, returning false once BlueWii is
+    /// exiting because of it.
+    ///
+    /// The check itself only reads the worker's [`Activity`], so it goes on while the worker holds the remote to
+    /// connect or poll it. The remote is only locked for the warning and the disconnect, and idleness is checked
+    /// again once it is, since a remote that reconnected while waiting for the lock has just been used.
+    fn check(
+        &mut self,
+        wii_remote: &Arc<Mutex<WiiRemote>>,
+        config: &Config,
+        clock: &dyn Clock,
+    ) -> bool {
+        let current_time = match clock.now() {
+            Some(current_time) => current_time,
+            None => {
+                error!("System time error: clock went backwards");
+                return true;
+            }
+        };
+
+        // Set by the worker for as long as it's polling a connected remote
+        let address = match self.activity.address.lock().unwrap().clone() {
+            Some(address) => address,
+            None => {
+                self.warned = false;
+                return true;
+            }
+        };
+
+        let mut idle_timeout = config.idle_timeout(&address, self.default_idle_timeout);
+        // Read again every time, so the timeout shrinks as the battery drains
+        if self.battery_aware {
+            idle_timeout = config.battery_idle_timeout(idle_timeout, metrics::battery(&address));
+        }
+
+        if idle_timeout == 0 {
+            return true;
+        }
+
+        if self.idle_basis == IdleBasis::Connection {
+            self.activity.record(current_time);
+            return true;
+        }
+
+        // A warning as long as the timeout itself would go off the moment the remote connects
+        let idle_warning = self.idle_warning;
+        if idle_warning > 0 && idle_warning < idle_timeout {
+            let last_activity = self.activity.last_input.load(Ordering::Relaxed);
+            match clock::is_idle(clock, last_activity, idle_timeout - idle_warning) {
+                Some(true) if !self.warned => {
+                    warn!(
+                        "Wii Remote {} has been idle for {} seconds, disconnecting in {} unless a button is pressed",
+                        address,
+                        idle_timeout - idle_warning,
+                        idle_warning
+                    );
+                    let wii_remote = wii_remote.lock().unwrap();
+                    if let Err(err) = wii_remote.set_rumble_pattern(rumble::IDLE_WARNING) {
+                        debug!("Failed to rumble the idle warning: {:#}", err);
+                    }
+
+                    if let Err(err) = wii_remote.blink_leds(3, IDLE_WARNING_BLINK) {
+                        debug!("Failed to blink the idle warning: {:#}", err);
+                    }
+
+                    self.warned = true;
+                }
+                Some(false) if self.warned => {
+                    info!(
+                        "Wii Remote {} is in use again, keeping it connected",
+                        address
+                    );
+                    self.warned = false;
+                }
+                _ => {}
+            }
+        }
+
+        if clock::is_idle(
+            clock,
+            self.activity.last_input.load(Ordering::Relaxed),
+            idle_timeout,
+        ) != Some(true)
+        {
+            return true;
+        }
+
+        let mut wii_remote = wii_remote.lock().unwrap();
+        if wii_remote.bluetooth_address != address
+            || wii_remote.udev_device_path.is_none()
+            || clock::is_idle(
+                clock,
+                self.activity.last_input.load(Ordering::Relaxed),
+                idle_timeout,
+            ) != Some(true)
+        {
+            debug!("Wii Remote was used or reconnected while waiting to disconnect it, keeping it connected");
+            return true;
+        }
+
+        info!(
+            "Wii Remote {} has been idle for {} seconds, disconnecting...",
+            address, idle_timeout
+        );
+        self.warned = false;
+        self.activity.idled_out.store(true, Ordering::Relaxed);
+        wii_remote.disconnect();
+        wii_remote.udev_device_path = None;
+        metrics::record_disconnected(&wii_remote.bluetooth_address);
+
+        // The main thread returns once woken up, after giving the workers a moment to clean up
+        if let Some(main_thread) = &self.exit_on_idle {
+            info!("Exiting after the idle timeout (--exit-on-idle)");
+            RUNNING.store(false, Ordering::Relaxed);
+            main_thread.unpark();
+            return false;
+        }
+
+        true
+    }
+}
+
+/// Logs a [`metrics::stats_line`] every `interval`, with the events per second averaged over it.
+fn log_stats(interval: Duration) {
+    let mut last_events = metrics::input_events();
+    loop {
+        thread::sleep(interval);
+
+        let events = metrics::input_events();
+        let events_per_second = (events - last_events) as f64 / interval.as_secs_f64();
+        last_events = events;
+        info!("{}", metrics::stats_line(events_per_second));
+    }
+}
+
+#[cfg(feature = "metrics")]
+fn idle_seconds() -> u64 {
+    match SystemTime::now().duration_since(SystemTime::UNIX_EPOCH) {
+        Ok(duration) => duration
+            .as_secs()
+            .saturating_sub(CURRENT_TIME.load(Ordering::Relaxed)),
+        Err(_) => 0,
+    }
+}
+
+/// What `process_log_buffer` puts in front of each line.
+#[derive(Clone)]
+enum LogTimestamp {
+    Rfc3339,
+    Epoch,
+    None,
+    /// A strftime format, checked when the arguments are parsed so a typo doesn't break every line.
+    Custom(String),
+}
+
+/// What `connect_and_poll` does on running out of `--max-retries`, before resting for `--retry-cooldown`.
+#[derive(Clone)]
+enum GiveUp {
+    Continue,
+    Notify,
+    /// A shell command, run without waiting for it.
+    Command(String),
+    /// Exits with this code instead of resting.
+    Exit(i32),
+}
+
+/// How `bluetoothctl`'s device lines are read, by the config's regex (which `BLUEWII_DEVICE_LINE_REGEX` overrides
+/// when it's loaded), see [`device_line`].
+fn device_line_parser(config: &Config) -> DeviceLineParser {
+    let Some(pattern) = &config.device_line_regex else {
+        return DeviceLineParser::default();
+    };
+
+    match DeviceLineParser::new(pattern) {
+        Ok(parser) => parser,
+        Err(err) => {
+            warn!(
+                "Ignoring the device line regex `{}', {}, reading bluetoothctl's lines the usual way",
+                pattern, err
+            );
+            DeviceLineParser::default()
+        }
+    }
+}
+
+fn parse_address(address: &str) -> Result<String, String> {
+    match WiiRemote::is_valid_address(address) {
+        true => Ok(address.to_uppercase()),
+        false => Err(format!(
+            "`{}' is not a Bluetooth address like AA:BB:CC:DD:EE:FF",
+            address
+        )),
+    }
+}
+
+fn parse_input_filter(value: &str) -> Result<Vec<EventCategory>, String> {
+    value
+        .split(',')
+        .map(|name| {
+            EventCategory::from_name(name.trim()).ok_or_else(|| {
+                format!(
+                    "unknown event category `{}', expected buttons, pointer, touch, tablet, gesture or switch",
+                    name.trim()
+                )
+            })
+        })
+        .collect()
+}
+
+fn parse_log_timestamp(value: &str) -> Result<LogTimestamp, String> {
+    match value {
+        "rfc3339" => Ok(LogTimestamp::Rfc3339),
+        "epoch" => Ok(LogTimestamp::Epoch),
+        "none" => Ok(LogTimestamp::None),
+        _ => match value.strip_prefix("custom:") {
+            Some(format) if StrftimeItems::new(format).any(|item| item == Item::Error) => {
+                Err(format!("`{}' is not a valid strftime format", format))
+            }
+            Some(format) => Ok(LogTimestamp::Custom(format.to_owned())),
+            None => Err("expected rfc3339, epoch, none or custom:FORMAT".to_owned()),
+        },
+    }
+}
+
+fn parse_give_up(value: &str) -> Result<GiveUp, String> {
+    match value {
+        "continue" => Ok(GiveUp::Continue),
+        "notify" if cfg!(feature = "desktop-notify") => Ok(GiveUp::Notify),
+        "notify" => Err("BlueWii was built without the `desktop-notify' feature".to_owned()),
+        _ => match (value.strip_prefix("command:"), value.strip_prefix("exit:")) {
+            (Some(""), _) => Err("expected a command after `command:'".to_owned()),
+            (Some(command), _) => Ok(GiveUp::Command(command.to_owned())),
+            (_, Some(code)) => code
+                .parse()
+                .map(GiveUp::Exit)
+                .map_err(|_| format!("`{}' is not an exit code", code)),
+            (None, None) => Err("expected continue, notify, command:CMD or exit:CODE".to_owned()),
+        },
+    }
+}
+
+fn process_log_buffer(
+    buf: &mut Formatter,
+    record: &Record<'_>,
+    timestamp: &LogTimestamp,
+    color: bool,
+) -> Result<(), Error> {
+    let now = Local::now();
+    match timestamp {
+        LogTimestamp::Rfc3339 => write!(buf, "[{}] ", now.format("%+"))?,
+        LogTimestamp::Epoch => write!(buf, "[{}] ", now.timestamp())?,
+        LogTimestamp::None => {}
+        LogTimestamp::Custom(format) => write!(buf, "[{}] ", now.format(format))?,
+    }
+
+    match color {
+        true => writeln!(
+            buf,
+            "[\x1b[{}m{}\x1b[0m]: {}",
+            level_color(record.level()),
+            record.level(),
+            record.args()
+        ),
+        false => writeln!(buf, "[{}]: {}", record.level(), record.args()),
+    }
+}
+
+/// The SGR parameters `process_log_buffer` colors `level` with.
+fn level_color(level: Level) -> &'static str {
+    match level {
+        Level::Error => "1;31",
+        Level::Warn => "33",
+        Level::Info => "32",
+        Level::Debug => "36",
+        Level::Trace => "2",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::PoisonError;
+
+    use clock::ManualClock;
+
+    use super::*;
+
+    const ADDRESS: &str = "00:19:1D:12:34:56";
+
+    // `RUNNING` is shared by everything, so the tests changing it take turns
+    static SHARED_STATE: Mutex<()> = Mutex::new(());
+
+    // A remote at `address` its worker's `IdleTimer` sees as connected and last used at `last_activity`,
+    // disconnecting it runs `true` in place of bluetoothctl
+    fn connected_remote(
+        address: &str,
+        last_activity: u64,
+    ) -> (Arc<Mutex<WiiRemote>>, Arc<Activity>) {
+        let mut wii_remote = WiiRemote::with_paths("true", "true");
+        wii_remote.bluetooth_address = address.to_owned();
+        wii_remote.udev_device_path =
+            Some("/sys/devices/virtual/misc/uhid/0005:057E:0306.0006".to_owned());
+
+        let activity = Activity::default();
+        activity.record(last_activity);
+        *activity.address.lock().unwrap() = Some(address.to_owned());
+        (Arc::new(Mutex::new(wii_remote)), Arc::new(activity))
+    }
+
+    fn idle_timer(activity: &Arc<Activity>, idle_timeout: u64) -> IdleTimer {
+        IdleTimer::new(
+            Arc::clone(activity),
+            idle_timeout,
+            0,
+            IdleBasis::Input,
+            false,
+            None,
+        )
+    }
+
+    fn is_connected(wii_remote: &Arc<Mutex<WiiRemote>>) -> bool {
+        wii_remote.lock().unwrap().udev_device_path.is_some()
+    }
+
+    // The config `merged_config` ends up with for `contents` in the config file, the environment's
+    // `device_line_regex` and `flags`
+    fn merged(
+        name: &str,
+        contents: &str,
+        device_line_regex: Option<&str>,
+        flags: &[&str],
+    ) -> Config {
+        let path = env::temp_dir().join(format!("bluewii-{}-{}.toml", process::id(), name));
+        fs::write(&path, contents).unwrap();
+
+        let path_flag = format!("--config={}", path.display());
+        let matches = cli()
+            .try_get_matches_from(["BlueWii", &path_flag].iter().chain(flags))
+            .unwrap();
+        let config = merged_config(&matches, device_line_regex.map(str::to_owned));
+
+        fs::remove_file(&path).unwrap();
+        config
+    }
+
+    #[test]
+    fn config_file_beats_defaults() {
+        let defaults = Config::default();
+        let config = merged("defaults", "", None, &[]);
+        assert_eq!(config.startup_sweep_step, defaults.startup_sweep_step);
+        assert_eq!(config.device_line_regex, None);
+
+        let config = merged("file", "startup_sweep_step = 7\n", None, &[]);
+        assert_eq!(config.startup_sweep_step, 7);
+    }
+
+    #[test]
+    fn environment_beats_config_file() {
+        let contents = "device_line_regex = 'from the file'\n";
+        let config = merged("environment", contents, Some("from the environment"), &[]);
+        assert_eq!(
+            config.device_line_regex.as_deref(),
+            Some("from the environment")
+        );
+
+        // Set but empty counts as not set
+        let config = merged("empty-environment", contents, Some(""), &[]);
+        assert_eq!(config.device_line_regex.as_deref(), Some("from the file"));
+    }
+
+    #[test]
+    fn flags_beat_config_file() {
+        let contents = "ir_invert_x = true
+ir_invert_y = false
+allow = ['00:19:1D:00:00:01']
+deny = ['00:19:1D:00:00:02']
+";
+        let config = merged(
+            "flags",
+            contents,
+            None,
+            &[
+                "--ir-invert-y",
+                "--allow",
+                "00:19:1D:00:00:03",
+                "--deny",
+                "00:19:1D:00:00:04",
+            ],
+        );
+
+        // The flags can only turn inversion on
+        assert!(config.ir_invert_x);
+        assert!(config.ir_invert_y);
+        assert_eq!(config.allow, ["00:19:1D:00:00:01", "00:19:1D:00:00:03"]);
+        assert_eq!(config.deny, ["00:19:1D:00:00:02", "00:19:1D:00:00:04"]);
+    }
+
+    #[test]
+    fn idle_timeout_disconnects_at_the_threshold() {
+        let config = Config::default();
+        let clock = ManualClock::new(1_000);
+        let (wii_remote, activity) = connected_remote(ADDRESS, 1_000);
+        let mut idle_timer = idle_timer(&activity, 300);
+
+        clock.advance(Duration::from_secs(299));
+        assert!(idle_timer.check(&wii_remote, &config, &clock));
+        assert!(is_connected(&wii_remote));
+        assert!(!activity.idled_out.load(Ordering::Relaxed));
+
+        clock.advance(Duration::from_secs(1));
+        assert!(idle_timer.check(&wii_remote, &config, &clock));
+        assert!(!is_connected(&wii_remote));
+        assert!(activity.idled_out.load(Ordering::Relaxed));
+    }
+
+    #[test]
+    fn input_restarts_the_idle_timeout() {
+        let config = Config::default();
+        let clock = ManualClock::new(1_000);
+        let (wii_remote, activity) = connected_remote(ADDRESS, 1_000);
+        let mut idle_timer = idle_timer(&activity, 300);
+
+        clock.advance(Duration::from_secs(200));
+        activity.record(1_200);
+        clock.advance(Duration::from_secs(200));
+        assert!(idle_timer.check(&wii_remote, &config, &clock));
+        assert!(is_connected(&wii_remote));
+    }
+
+    #[test]
+    fn each_remote_idles_out_on_its_own() {
+        let config: Config = toml::from_str(
+            "[remote.\"00:19:1D:00:00:01\"]\n\
+             idle_timeout = 0\n\
+             [remote.\"00:19:1D:00:00:02\"]\n\
+             idle_timeout = 60\n",
+        )
+        .unwrap();
+        let clock = ManualClock::new(1_000);
+        let (first, first_activity) = connected_remote("00:19:1D:00:00:01", 1_000);
+        let (second, second_activity) = connected_remote("00:19:1D:00:00:02", 1_000);
+        let (third, third_activity) = connected_remote("00:19:1D:00:00:03", 1_000);
+        let mut first_timer = idle_timer(&first_activity, 300);
+        let mut second_timer = idle_timer(&second_activity, 300);
+        let mut third_timer = idle_timer(&third_activity, 300);
+
+        // Input on the other remotes doesn't keep the second one connected
+        clock.advance(Duration::from_secs(60));
+        first_activity.record(1_060);
+        third_activity.record(1_060);
+        for (idle_timer, wii_remote) in [
+            (&mut first_timer, &first),
+            (&mut second_timer, &second),
+            (&mut third_timer, &third),
+        ] {
+            assert!(idle_timer.check(wii_remote, &config, &clock));
+        }
+        assert!(is_connected(&first));
+        assert!(!is_connected(&second));
+        assert!(second_activity.idled_out.load(Ordering::Relaxed));
+        assert!(is_connected(&third));
+
+        // The third one goes by the default, the first one never idles out
+        clock.advance(Duration::from_secs(86_400));
+        assert!(first_timer.check(&first, &config, &clock));
+        assert!(third_timer.check(&third, &config, &clock));
+        assert!(is_connected(&first));
+        assert!(!is_connected(&third));
+        assert!(!first_activity.idled_out.load(Ordering::Relaxed));
+    }
+
+    #[test]
+    fn idle_timeout_fires_while_the_poll_loop_is_busy() {
+        let config = Config::default();
+        let clock = ManualClock::new(1_000);
+        let (wii_remote, activity) = connected_remote(ADDRESS, 1_000);
+        let mut idle_timer = idle_timer(&activity, 300);
+
+        // Holds the remote nearly all the time, like a worker busy polling it
+        let busy = Arc::new(AtomicBool::new(true));
+        let poll_loop = {
+            let wii_remote = Arc::clone(&wii_remote);
+            let busy = Arc::clone(&busy);
+            thread::spawn(move || {
+                while busy.load(Ordering::Relaxed) {
+                    let _held = wii_remote.lock().unwrap();
+                    thread::sleep(Duration::from_millis(5));
+                }
+            })
+        };
+
+        clock.advance(Duration::from_secs(300));
+        assert!(idle_timer.check(&wii_remote, &config, &clock));
+        busy.store(false, Ordering::Relaxed);
+        poll_loop.join().unwrap();
+
+        assert!(!is_connected(&wii_remote));
+        assert!(activity.idled_out.load(Ordering::Relaxed));
+    }
+
+    #[test]
+    fn zero_never_times_out() {
+        let config = Config::default();
+        let clock = ManualClock::new(1_000);
+        let (wii_remote, activity) = connected_remote(ADDRESS, 1_000);
+        let mut idle_timer = idle_timer(&activity, 0);
+
+        clock.advance(Duration::from_secs(86_400));
+        assert!(idle_timer.check(&wii_remote, &config, &clock));
+        assert!(is_connected(&wii_remote));
+    }
+
+    #[test]
+    fn sleep_stops_early_when_shutting_down() {
+        let _shared = SHARED_STATE.lock().unwrap_or_else(PoisonError::into_inner);
+
+        let start = Instant::now();
+        assert!(sleep_while_running(Duration::from_millis(250)));
+        assert!(start.elapsed() >= Duration::from_millis(250));
+
+        let stopping = thread::spawn(|| {
+            thread::sleep(Duration::from_millis(200));
+            RUNNING.store(false, Ordering::Relaxed);
+        });
+        let start = Instant::now();
+        let finished = sleep_while_running(Duration::from_secs(60));
+        let slept = start.elapsed();
+        stopping.join().unwrap();
+        RUNNING.store(true, Ordering::Relaxed);
+
+        assert!(!finished);
+        assert!(slept < Duration::from_secs(1));
+    }
 }
@@ -0,0 +1,203 @@
+//! Profiles kept as files of their own in `--mappings-dir`, for whoever has too many to keep in the config file.
+//!
+//! Every `<name>.toml` in the directory is the profile `<name>`, holding what a `[profiles.<name>]` table would:
+//!
+//! ```toml
+//! # ~/.config/bluewii/mappings/retroarch.toml
+//! a = "BTN_EAST"
+//! b = "BTN_SOUTH"
+//! "home+plus" = "KEY_ESC"
+//! ```
+//!
+//! They're picked like any other profile, with `--profile` or the status socket's `set-profile`, and win over a
+//! profile of the same name in the config file.
+//!
+//! The directory is checked for changes every [`POLL_INTERVAL`], and a file that changed is read again and swapped
+//! in, taking effect right away if it's the profile in use. A file that no longer parses, or maps to a code that
+//! doesn't exist, is left out with a warning and its profile keeps the mappings it had. So does a profile whose
+//! file is removed, until BlueWii restarts.
+//!
+//! The virtual devices are created with the outputs every profile had at the time, so an output that no profile
+//! used before takes reconnecting the remote.
+
+use std::{
+    collections::HashMap,
+    fs,
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Mutex,
+    },
+    thread,
+    time::{Duration, SystemTime},
+};
+
+use log::{debug, info, warn};
+
+use crate::{button::Binding, config::Target, evdev};
+
+/// How often the directory is checked for changed files.
+pub const POLL_INTERVAL: Duration = Duration::from_secs(1);
+
+struct Profile {
+    modified: SystemTime,
+    /// The [`generation`] it was loaded in.
+    generation: u64,
+    /// `None` for a file that hasn't parsed yet.
+    mappings: Option<HashMap<Binding, Target>>,
+}
+
+static PROFILES: Mutex<Option<HashMap<String, Profile>>> = Mutex::new(None);
+static GENERATION: AtomicU64 = AtomicU64::new(0);
+
+/// Loads every profile in `dir`, then keeps checking it for changes on a thread of its own.
+pub fn watch(dir: PathBuf) {
+    let mut profiles = HashMap::new();
+    scan(&dir, &mut profiles, 0);
+    info!(
+        "Loaded {} profile(s) from `{}'",
+        profiles
+            .values()
+            .filter(|profile| profile.mappings.is_some())
+            .count(),
+        dir.display()
+    );
+    *PROFILES.lock().unwrap() = Some(profiles);
+
+    thread::spawn(move || loop {
+        thread::sleep(POLL_INTERVAL);
+
+        let generation = GENERATION.load(Ordering::Relaxed) + 1;
+        let mut profiles = PROFILES.lock().unwrap();
+        if scan(&dir, profiles.get_or_insert_with(HashMap::new), generation) {
+            GENERATION.store(generation, Ordering::Relaxed);
+        }
+    });
+}
+
+/// Goes up by one each time a profile is reloaded.
+pub fn generation() -> u64 {
+    GENERATION.load(Ordering::Relaxed)
+}
+
+/// Whether the profile called `name` was reloaded after `generation`.
+pub fn reloaded_since(name: &str, generation: u64) -> bool {
+    match &*PROFILES.lock().unwrap() {
+        Some(profiles) => profiles
+            .get(name)
+            .is_some_and(|profile| profile.generation > generation),
+        None => false,
+    }
+}
+
+/// The mappings of the profile called `name`, if the directory has one.
+pub fn profile(name: &str) -> Option<HashMap<Binding, Target>> {
+    let profiles = PROFILES.lock().unwrap();
+    profiles.as_ref()?.get(name)?.mappings.clone()
+}
+
+/// The names of every profile in the directory.
+pub fn names() -> Vec<String> {
+    match &*PROFILES.lock().unwrap() {
+        Some(profiles) => profiles
+            .iter()
+            .filter(|(_, profile)| profile.mappings.is_some())
+            .map(|(name, _)| name.clone())
+            .collect(),
+        None => Vec::new(),
+    }
+}
+
+/// Reads the files in `dir` that changed since they were last read into `profiles`, returning whether any were.
+fn scan(dir: &Path, profiles: &mut HashMap<String, Profile>, generation: u64) -> bool {
+    let entries = match fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(err) => {
+            debug!("Failed to read `{}': {}", dir.display(), err);
+            return false;
+        }
+    };
+
+    let mut reloaded = false;
+    for entry in entries.flatten() {
+        let path = entry.path();
+        let name = match path.file_stem() {
+            Some(name)
+                if path
+                    .extension()
+                    .is_some_and(|extension| extension == "toml") =>
+            {
+                name.to_string_lossy().into_owned()
+            }
+            _ => continue,
+        };
+
+        let Ok(modified) = entry.metadata().and_then(|metadata| metadata.modified()) else {
+            continue;
+        };
+
+        if profiles
+            .get(&name)
+            .is_some_and(|profile| profile.modified == modified)
+        {
+            continue;
+        }
+
+        match read(&path) {
+            Ok(mappings) => {
+                if generation > 0 {
+                    info!("Reloaded profile `{}' from `{}'", name, path.display());
+                }
+
+                profiles.insert(
+                    name,
+                    Profile {
+                        modified,
+                        generation,
+                        mappings: Some(mappings),
+                    },
+                );
+                reloaded = true;
+            }
+            Err(err) => {
+                warn!(
+                    "Ignoring `{}', keeping profile `{}' as it was: {}",
+                    path.display(),
+                    name,
+                    err
+                );
+
+                // Not warned about again until it changes
+                match profiles.get_mut(&name) {
+                    Some(profile) => profile.modified = modified,
+                    None => {
+                        profiles.insert(
+                            name,
+                            Profile {
+                                modified,
+                                generation,
+                                mappings: None,
+                            },
+                        );
+                    }
+                }
+            }
+        }
+    }
+
+    reloaded
+}
+
+fn read(path: &Path) -> Result<HashMap<Binding, Target>, String> {
+    let contents = fs::read_to_string(path).map_err(|err| err.to_string())?;
+    let mappings =
+        toml::from_str::<HashMap<Binding, Target>>(&contents).map_err(|err| err.to_string())?;
+
+    for target in mappings.values() {
+        if evdev::code_from_name(target.output()).is_none() {
+            return Err(format!("unknown code `{}'", target.output()));
+        }
+    }
+
+    Ok(mappings)
+}
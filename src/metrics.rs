@@ -0,0 +1,176 @@
+//! Connection statistics, optionally exposed in the Prometheus text format through `--metrics-addr`, and logged
+//! every `--stats-interval` as a [`stats_line`].
+
+use std::sync::{
+    atomic::{AtomicU64, Ordering},
+    Mutex,
+};
+
+static RECONNECT_ATTEMPTS: AtomicU64 = AtomicU64::new(0);
+static RECONNECTS: AtomicU64 = AtomicU64::new(0);
+static INPUT_EVENTS: AtomicU64 = AtomicU64::new(0);
+
+// Every address that's connected since startup, so connecting one again counts as a reconnect
+static SEEN_REMOTES: Mutex<Vec<String>> = Mutex::new(Vec::new());
+
+// Bluetooth address -> last battery reading of every connected remote, kept up to date by [`crate::battery`]
+static CONNECTED_REMOTES: Mutex<Vec<(String, Option<u8>)>> = Mutex::new(Vec::new());
+
+pub fn record_connect_attempt() {
+    RECONNECT_ATTEMPTS.fetch_add(1, Ordering::Relaxed);
+}
+
+pub fn record_connected(address: &str, battery_level: Option<u8>) {
+    let mut seen = SEEN_REMOTES.lock().unwrap();
+    match seen.iter().any(|seen_address| seen_address == address) {
+        true => {
+            RECONNECTS.fetch_add(1, Ordering::Relaxed);
+        }
+        false => seen.push(address.to_owned()),
+    }
+    drop(seen);
+
+    let mut remotes = CONNECTED_REMOTES.lock().unwrap();
+    remotes.retain(|(remote_address, _)| remote_address != address);
+    remotes.push((address.to_owned(), battery_level));
+}
+
+/// Updates the battery level of a connected remote, a remote that's since disconnected is left out.
+pub fn record_battery(address: &str, battery_level: u8) {
+    let mut remotes = CONNECTED_REMOTES.lock().unwrap();
+    if let Some((_, level)) = remotes
+        .iter_mut()
+        .find(|(remote_address, _)| remote_address == address)
+    {
+        *level = Some(battery_level);
+    }
+}
+
+/// The last battery reading of the connected remote at `address`.
+pub fn battery(address: &str) -> Option<u8> {
+    CONNECTED_REMOTES
+        .lock()
+        .unwrap()
+        .iter()
+        .find(|(remote_address, _)| remote_address == address)
+        .and_then(|(_, level)| *level)
+}
+
+pub fn record_disconnected(address: &str) {
+    let mut remotes = CONNECTED_REMOTES.lock().unwrap();
+    remotes.retain(|(remote_address, _)| remote_address != address);
+}
+
+/// Counts `count` input events having been read from the remotes.
+pub fn record_input_events(count: u64) {
+    INPUT_EVENTS.fetch_add(count, Ordering::Relaxed);
+}
+
+/// Every input event read from any remote since startup.
+pub fn input_events() -> u64 {
+    INPUT_EVENTS.load(Ordering::Relaxed)
+}
+
+/// The `--stats-interval` summary, like
+/// `stats remotes=1 reconnects=2 events_per_second=14.5 batteries=AA:BB:CC:DD:EE:FF@80%`.
+///
+/// Its fields keep their names and order so it can be grepped for, a remote without a battery reading is
+/// `@unknown`, and `batteries=none` means no remote is connected.
+pub fn stats_line(events_per_second: f64) -> String {
+    let remotes = CONNECTED_REMOTES.lock().unwrap().clone();
+    let batteries = match remotes.is_empty() {
+        true => "none".to_owned(),
+        false => remotes
+            .iter()
+            .map(|(address, level)| match level {
+                Some(level) => format!("{}@{}%", address, level),
+                None => format!("{}@unknown", address),
+            })
+            .collect::<Vec<_>>()
+            .join(","),
+    };
+
+    format!(
+        "stats remotes={} reconnects={} events_per_second={:.1} batteries={}",
+        remotes.len(),
+        RECONNECTS.load(Ordering::Relaxed),
+        events_per_second,
+        batteries
+    )
+}
+
+#[cfg(feature = "metrics")]
+pub fn render(idle_seconds: u64) -> String {
+    use std::fmt::Write;
+
+    let remotes = CONNECTED_REMOTES.lock().unwrap().clone();
+    let batteries = remotes
+        .iter()
+        .filter_map(|(address, level)| level.map(|level| (address, level)));
+
+    let mut output = String::new();
+    let _ = writeln!(
+        output,
+        "# HELP bluewii_connected_remotes Number of connected Wii Remotes."
+    );
+    let _ = writeln!(output, "# TYPE bluewii_connected_remotes gauge");
+    let _ = writeln!(output, "bluewii_connected_remotes {}", remotes.len());
+    let _ = writeln!(
+        output,
+        "# HELP bluewii_reconnect_attempts_total Connection attempts made since startup."
+    );
+    let _ = writeln!(output, "# TYPE bluewii_reconnect_attempts_total counter");
+    let _ = writeln!(
+        output,
+        "bluewii_reconnect_attempts_total {}",
+        RECONNECT_ATTEMPTS.load(Ordering::Relaxed)
+    );
+    let _ = writeln!(
+        output,
+        "# HELP bluewii_battery_percent Battery level of each connected Wii Remote."
+    );
+    let _ = writeln!(output, "# TYPE bluewii_battery_percent gauge");
+    for (address, level) in batteries {
+        let _ = writeln!(
+            output,
+            "bluewii_battery_percent{{address=\"{}\"}} {}",
+            address, level
+        );
+    }
+    let _ = writeln!(
+        output,
+        "# HELP bluewii_idle_seconds Seconds since the last input event."
+    );
+    let _ = writeln!(output, "# TYPE bluewii_idle_seconds gauge");
+    let _ = writeln!(output, "bluewii_idle_seconds {}", idle_seconds);
+    output
+}
+
+#[cfg(feature = "metrics")]
+pub fn serve(address: &str, idle_seconds: fn() -> u64) {
+    use log::{error, info, warn};
+    use tiny_http::{Header, Response, Server};
+
+    let server = match Server::http(address) {
+        Ok(server) => server,
+        Err(err) => {
+            error!("Failed to start metrics server on {}: {}", address, err);
+            return;
+        }
+    };
+
+    info!("Serving metrics on http://{}/metrics", address);
+    let content_type = Header::from_bytes("Content-Type", "text/plain; version=0.0.4").unwrap();
+    for request in server.incoming_requests() {
+        let response = match request.url() {
+            "/metrics" => {
+                Response::from_string(render(idle_seconds())).with_header(content_type.clone())
+            }
+            _ => Response::from_string("Not Found").with_status_code(404),
+        };
+
+        if let Err(err) = request.respond(response) {
+            warn!("Failed to respond to metrics request: {}", err);
+        }
+    }
+}
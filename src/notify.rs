@@ -0,0 +1,86 @@
+//! Desktop notifications for `--notify`, sent through libnotify's `notify-send` so BlueWii doesn't need a D-Bus
+//! client of its own.
+//!
+//! Without the `desktop-notify` feature nothing here shows anything, and until [`enable`] is called only
+//! [`gave_up`] does.
+
+use std::sync::atomic::{AtomicBool, Ordering};
+
+/// Battery percentage at or below which a low battery notification is shown.
+pub const LOW_BATTERY_THRESHOLD: u8 = 20;
+
+static ENABLED: AtomicBool = AtomicBool::new(false);
+
+pub fn enable() {
+    ENABLED.store(true, Ordering::Relaxed);
+}
+
+pub fn connected(player_slot: u8, battery_level: Option<u8>) {
+    let body = match battery_level {
+        Some(level) => format!("Player {}, battery at {}%", player_slot, level),
+        None => format!("Player {}", player_slot),
+    };
+
+    send("Wii Remote connected", body);
+}
+
+pub fn disconnected(player_slot: u8) {
+    send("Wii Remote disconnected", format!("Player {}", player_slot));
+}
+
+pub fn low_battery(player_slot: u8, battery_level: u8) {
+    send(
+        "Wii Remote battery low",
+        format!("Player {}, battery at {}%", player_slot, battery_level),
+    );
+}
+
+/// Shown for `--on-give-up notify` whether or not `--notify` is on, having been asked for on its own.
+pub fn gave_up(attempts: u32) {
+    show(
+        "Wii Remote not connecting",
+        format!(
+            "Gave up after {} attempts, resting before trying again",
+            attempts
+        ),
+    );
+}
+
+fn send(summary: &'static str, body: String) {
+    if ENABLED.load(Ordering::Relaxed) {
+        show(summary, body);
+    }
+}
+
+#[cfg(feature = "desktop-notify")]
+fn show(summary: &'static str, body: String) {
+    use std::{env, path::Path, process::Command, thread};
+
+    use log::{debug, warn};
+
+    // A service without a session bus has no notification daemon to talk to
+    let has_session_bus = env::var_os("DBUS_SESSION_BUS_ADDRESS").is_some()
+        || env::var_os("XDG_RUNTIME_DIR").is_some_and(|dir| Path::new(&dir).join("bus").exists());
+    if !has_session_bus {
+        debug!("No D-Bus session, not showing `{}'", summary);
+        return;
+    }
+
+    // Don't hold up input forwarding while the notification daemon answers
+    thread::spawn(move || {
+        match Command::new("notify-send")
+            .args(["--app-name=BlueWii", summary, &body])
+            .output()
+        {
+            Ok(output) if output.status.success() => {}
+            Ok(output) => warn!(
+                "`notify-send' failed: {}",
+                String::from_utf8_lossy(&output.stderr).trim()
+            ),
+            Err(err) => warn!("Failed to execute `notify-send': {}", err),
+        }
+    });
+}
+
+#[cfg(not(feature = "desktop-notify"))]
+fn show(_summary: &'static str, _body: String) {}
@@ -0,0 +1,96 @@
+//! Checks for the device permissions BlueWii needs, which is what usually goes wrong when it isn't run as root.
+
+use std::{
+    fs::{self, OpenOptions},
+    io::{self, ErrorKind},
+    path::{Path, PathBuf},
+    sync::Once,
+};
+
+use log::warn;
+
+/// What to tell someone who's missing access to the input devices.
+pub const HELP: &str =
+    "Add your user to the `input' group and log in again, or grant access with a udev rule like:
+    KERNEL==\"uinput\", GROUP=\"input\", MODE=\"0660\"
+    SUBSYSTEM==\"input\", ATTRS{name}==\"Nintendo Wii Remote*\", GROUP=\"input\", MODE=\"0660\"";
+
+const UINPUT: &str = "/dev/uinput";
+
+static PERMISSION_WARNING: Once = Once::new();
+
+/// Whether `err` means the device exists but we're not allowed to open it (`EACCES` and `EPERM` alike).
+pub fn is_permission_error(err: &io::Error) -> bool {
+    err.kind() == ErrorKind::PermissionDenied
+}
+
+/// Warns about a device that couldn't be opened for lack of permissions, only the first time it happens.
+pub fn warn_denied(path: &Path) {
+    PERMISSION_WARNING.call_once(|| {
+        warn!(
+            "Permission denied opening {}, Wii Remote input won't arrive. {}",
+            path.display(),
+            HELP
+        );
+    });
+}
+
+/// Why virtual devices can't be created through `/dev/uinput`, along with what to do about it, or `None` if they
+/// can. Checked before anything connects, since a missing uinput otherwise only shows once a remote has.
+pub fn uinput_problem() -> Option<String> {
+    let err = OpenOptions::new().write(true).open(UINPUT).err()?;
+    Some(match err.kind() {
+        ErrorKind::NotFound => format!(
+            "{} doesn't exist, load the `uinput' kernel module with `modprobe uinput' and list it in \
+             /etc/modules-load.d/ to have it loaded at boot",
+            UINPUT
+        ),
+        _ if is_permission_error(&err) => format!(
+            "{} can't be written to, add your user to the `input' group or grant access with a udev rule",
+            UINPUT
+        ),
+        _ => format!("Failed to open {}: {}", UINPUT, err),
+    })
+}
+
+/// Tries every device BlueWii would open, returning each one it can't along with why.
+/// Wii Remote input devices are only checked while a remote is connected.
+pub fn check() -> Vec<(PathBuf, io::Error)> {
+    let mut devices = vec![(PathBuf::from(UINPUT), true)];
+    devices.extend(
+        wii_remote_event_nodes()
+            .into_iter()
+            .map(|path| (path, false)),
+    );
+
+    devices
+        .into_iter()
+        .filter_map(|(path, write)| {
+            match OpenOptions::new().read(!write).write(write).open(&path) {
+                Ok(_) => None,
+                Err(err) => Some((path, err)),
+            }
+        })
+        .collect()
+}
+
+/// The `/dev/input/eventN` node of every input device hid-wiimote created.
+pub fn wii_remote_event_nodes() -> Vec<PathBuf> {
+    let entries = match fs::read_dir("/sys/class/input") {
+        Ok(entries) => entries,
+        Err(_) => return Vec::new(),
+    };
+
+    let mut nodes = entries
+        .flatten()
+        .filter(|entry| entry.file_name().to_string_lossy().starts_with("event"))
+        .filter(|entry| {
+            fs::read_to_string(entry.path().join("device/name"))
+                .is_ok_and(|name| name.starts_with("Nintendo Wii Remote"))
+        })
+        .map(|entry| Path::new("/dev/input").join(entry.file_name()))
+        .collect::<Vec<_>>();
+
+    nodes.sort();
+    nodes
+}
@@ -0,0 +1,110 @@
+//! Passes the raw bytes of extensions BlueWii has no mapping for through to a generic virtual device.
+//!
+//! The bytes are copied out of the input reports on the remote's `hidraw` node, in whatever format the
+//! extension uses, and every change is logged at trace level for reverse engineering. They're forwarded as:
+//!
+//! | Extension bytes | Output                                                        |
+//! |-----------------|---------------------------------------------------------------|
+//! | 0 to 3          | `ABS_X`, `ABS_Y`, `ABS_Z` and `ABS_RX`, 0 to 255              |
+//! | 4 and 5         | `BTN_TRIGGER_HAPPY1` to `BTN_TRIGGER_HAPPY16`, bit 0 of byte 4 first |
+//!
+//! Extensions report their buttons active low, so a cleared bit is forwarded as a press.
+
+use std::{
+    fs::{File, OpenOptions},
+    io::{self, ErrorKind, Read},
+    os::unix::fs::OpenOptionsExt,
+    path::Path,
+};
+
+use log::trace;
+
+use crate::{
+    evdev::{ABS_RX, ABS_X, ABS_Y, ABS_Z, BTN_TRIGGER_HAPPY1, EV_ABS, EV_KEY},
+    uinput::{AbsAxis, Capabilities, VirtualDevice},
+};
+
+const AXES: [u16; 4] = [ABS_X, ABS_Y, ABS_Z, ABS_RX];
+
+/// The extension bytes inside an input report, see https://wiibrew.org/wiki/Wiimote#Data_Reporting
+pub fn extension_bytes(report: &[u8]) -> Option<&[u8]> {
+    let (offset, length) = match report.first()? {
+        0x32 => (3, 8),
+        0x34 => (3, 19),
+        0x35 => (6, 16),
+        0x36 => (13, 9),
+        0x37 => (16, 6),
+        0x3d => (1, 21),
+        _ => return None,
+    };
+
+    report.get(offset..offset + length)
+}
+
+pub struct RawExtension {
+    hidraw: File,
+    device: VirtualDevice,
+    last: Vec<u8>,
+}
+
+impl RawExtension {
+    pub fn open(hidraw_path: &Path, device_name: &str) -> io::Result<RawExtension> {
+        let hidraw = OpenOptions::new()
+            .read(true)
+            .custom_flags(libc::O_NONBLOCK)
+            .open(hidraw_path)?;
+
+        let capabilities = Capabilities {
+            keys: (0..16).map(|bit| BTN_TRIGGER_HAPPY1 + bit).collect(),
+            absolute: AXES
+                .iter()
+                .map(|code| AbsAxis {
+                    code: *code,
+                    min: 0,
+                    max: 255,
+                })
+                .collect(),
+            ..Capabilities::default()
+        };
+
+        Ok(RawExtension {
+            hidraw,
+            device: VirtualDevice::create(&format!("{} Extension", device_name), &capabilities)?,
+            last: Vec::new(),
+        })
+    }
+
+    /// Forwards every report that's queued up on the `hidraw` node.
+    pub fn poll(&mut self) -> io::Result<()> {
+        let mut report = [0u8; 22];
+        loop {
+            let read = match self.hidraw.read(&mut report) {
+                Ok(read) => read,
+                Err(err) if err.kind() == ErrorKind::WouldBlock => return Ok(()),
+                Err(err) => return Err(err),
+            };
+
+            let bytes = match extension_bytes(&report[..read]) {
+                Some(bytes) if bytes != self.last.as_slice() => bytes,
+                _ => continue,
+            };
+
+            trace!("Extension bytes: {:02x?}", bytes);
+            for (code, value) in AXES.iter().zip(bytes) {
+                self.device.emit(EV_ABS, *code, *value as i32)?;
+            }
+
+            if let [.., low, high] = bytes[..bytes.len().min(6)] {
+                let buttons = u16::from_le_bytes([low, high]);
+                for bit in 0..16 {
+                    let pressed = buttons & (1 << bit) == 0;
+                    self.device
+                        .emit(EV_KEY, BTN_TRIGGER_HAPPY1 + bit, pressed as i32)?;
+                }
+            }
+
+            self.device.sync()?;
+            self.last = bytes.to_vec();
+        }
+    }
+}
@@ -0,0 +1,122 @@
+//! Software auto-repeat for held keys, since the Wii Remote only ever reports presses and releases.
+//!
+//! A key starts repeating once it's been held for the delay, then repeats at the rate, like a keyboard. This only
+//! keeps the time, the forwarder asks it which keys are due each time round the poll loop and sends those as
+//! repeat events.
+
+use std::time::{Duration, Instant};
+
+pub struct Repeater {
+    delay: Duration,
+    interval: Duration,
+    // Held key -> when it next repeats
+    held: Vec<(u16, Instant)>,
+}
+
+impl Repeater {
+    /// Repeats after `delay`, `rate` times a second, a rate of 0 counts as 1.
+    pub fn new(delay: Duration, rate: u32) -> Repeater {
+        Repeater {
+            delay,
+            interval: Duration::from_secs(1) / rate.max(1),
+            held: Vec::new(),
+        }
+    }
+
+    pub fn press(&mut self, key: u16, now: Instant) {
+        self.release(key);
+        self.held.push((key, now + self.delay));
+    }
+
+    pub fn release(&mut self, key: u16) {
+        self.held.retain(|(held_key, _)| *held_key != key);
+    }
+
+    /// Forgets every held key, for when they're all released at once.
+    pub fn clear(&mut self) {
+        self.held.clear();
+    }
+
+    /// The keys due to repeat at `now`, each at most once so a stalled loop doesn't send a burst afterwards.
+    pub fn due(&mut self, now: Instant) -> Vec<u16> {
+        let mut due = Vec::new();
+        for (key, next) in &mut self.held {
+            if *next <= now {
+                due.push(*key);
+                *next = now + self.interval;
+            }
+        }
+
+        due
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const KEY: u16 = 103;
+
+    fn ms(millis: u64) -> Duration {
+        Duration::from_millis(millis)
+    }
+
+    #[test]
+    fn repeats_after_the_delay_then_at_the_rate() {
+        let start = Instant::now();
+        let mut repeater = Repeater::new(ms(500), 10);
+        repeater.press(KEY, start);
+
+        assert!(repeater.due(start + ms(499)).is_empty());
+        assert_eq!(repeater.due(start + ms(500)), [KEY]);
+        assert!(repeater.due(start + ms(599)).is_empty());
+        assert_eq!(repeater.due(start + ms(600)), [KEY]);
+        assert_eq!(repeater.due(start + ms(700)), [KEY]);
+    }
+
+    #[test]
+    fn release_stops_repeating() {
+        let start = Instant::now();
+        let mut repeater = Repeater::new(ms(500), 10);
+        repeater.press(KEY, start);
+        repeater.release(KEY);
+        assert!(repeater.due(start + ms(1_000)).is_empty());
+
+        repeater.press(KEY, start);
+        repeater.clear();
+        assert!(repeater.due(start + ms(1_000)).is_empty());
+    }
+
+    #[test]
+    fn a_stalled_loop_gets_one_repeat() {
+        let start = Instant::now();
+        let mut repeater = Repeater::new(ms(500), 10);
+        repeater.press(KEY, start);
+
+        assert_eq!(repeater.due(start + ms(2_000)), [KEY]);
+        assert!(repeater.due(start + ms(2_099)).is_empty());
+        assert_eq!(repeater.due(start + ms(2_100)), [KEY]);
+    }
+
+    #[test]
+    fn pressing_again_restarts_the_delay() {
+        let start = Instant::now();
+        let mut repeater = Repeater::new(ms(500), 10);
+        repeater.press(KEY, start);
+        repeater.press(KEY, start + ms(400));
+
+        assert!(repeater.due(start + ms(899)).is_empty());
+        assert_eq!(repeater.due(start + ms(900)), [KEY]);
+    }
+
+    #[test]
+    fn rate_of_zero_repeats_once_a_second() {
+        let start = Instant::now();
+        let mut repeater = Repeater::new(Duration::ZERO, 0);
+        repeater.press(KEY, start);
+
+        assert_eq!(repeater.due(start), [KEY]);
+        assert!(repeater.due(start + ms(999)).is_empty());
+        assert_eq!(repeater.due(start + ms(1_000)), [KEY]);
+    }
+}
@@ -0,0 +1,69 @@
+//! Noticing the system waking up from suspend for `--on-resume`, through systemd-logind's `PrepareForSleep`
+//! signal. The signal is read from glib's `gdbus monitor` so BlueWii doesn't need a D-Bus client of its own.
+//!
+//! Without the `suspend-resume` feature, or until [`watch`] is called, the system never seems to resume.
+
+use std::sync::atomic::{AtomicU64, Ordering};
+
+static RESUMES: AtomicU64 = AtomicU64::new(0);
+
+/// How many times the system has resumed since [`watch`] was called, compared against an earlier count to tell
+/// whether it's happened since.
+pub fn count() -> u64 {
+    RESUMES.load(Ordering::Relaxed)
+}
+
+/// Starts watching for the system to resume on a thread of its own, calling `on_resume` each time it does.
+#[cfg(feature = "suspend-resume")]
+pub fn watch(on_resume: impl Fn() + Send + 'static) {
+    use std::{
+        io::{BufRead, BufReader},
+        process::{Command, Stdio},
+        thread,
+    };
+
+    use log::{debug, info, warn};
+
+    let mut child = match Command::new("gdbus")
+        .args([
+            "monitor",
+            "--system",
+            "--dest",
+            "org.freedesktop.login1",
+            "--object-path",
+            "/org/freedesktop/login1",
+        ])
+        .stdout(Stdio::piped())
+        .stderr(Stdio::null())
+        .spawn()
+    {
+        Ok(child) => child,
+        Err(err) => {
+            warn!(
+                "Failed to execute `gdbus monitor', resuming from suspend won't be noticed: {}",
+                err
+            );
+            return;
+        }
+    };
+
+    let stdout = child.stdout.take().unwrap();
+    thread::spawn(move || {
+        // Sent with `true` right before sleeping and with `false` once awake again
+        for line in BufReader::new(stdout).lines().map_while(Result::ok) {
+            if line.contains("PrepareForSleep (false") {
+                info!("System resumed from suspend");
+                RESUMES.fetch_add(1, Ordering::Relaxed);
+                on_resume();
+            } else if line.contains("PrepareForSleep (true") {
+                debug!("System is going to sleep");
+            }
+        }
+
+        let _ = child.wait();
+        warn!("`gdbus monitor' exited, resuming from suspend won't be noticed anymore");
+    });
+}
+
+#[cfg(not(feature = "suspend-resume"))]
+pub fn watch(_on_resume: impl Fn() + Send + 'static) {}
@@ -0,0 +1,221 @@
+//! Rumble patterns, played through the force feedback effect hid-wiimote registers on the remote's core interface.
+//!
+//! A pattern is a list of `(on, duration_ms)` segments played back to back, the motor is always switched
+//! off again at the end. Starting a new pattern cuts off whichever one was still playing.
+//!
+//! [`Feedback`] events only buzz when the `[feedback]` config table gives them a pattern. Since patterns cut
+//! each other off rather than queueing, quick successive chords give one buzz each instead of a backlog.
+
+use std::{
+    ffi::c_int,
+    fs::{File, OpenOptions},
+    io::{self, Write},
+    mem,
+    os::fd::AsRawFd,
+    path::Path,
+    slice,
+    sync::atomic::{AtomicU64, Ordering},
+    thread,
+    time::{Duration, Instant},
+};
+
+use libc::{ff_effect, input_event, Ioctl, _IOW};
+use log::warn;
+use serde::{Deserialize, Serialize};
+
+use crate::evdev::{EV_FF, FF_RUMBLE};
+
+const EVIOCSFF: Ioctl = _IOW::<ff_effect>(b'E' as u32, 0x80);
+
+/// A single short buzz, for when a remote connects.
+pub const CONNECT: &[(bool, u64)] = &[(true, 200)];
+
+/// Two short buzzes.
+pub const LOW_BATTERY: &[(bool, u64)] = &[(true, 100), (false, 100), (true, 100)];
+
+/// One long buzz.
+pub const ERROR: &[(bool, u64)] = &[(true, 600)];
+
+/// A buzz just long enough to feel, for confirming an action.
+pub const TICK: &[(bool, u64)] = &[(true, 60)];
+
+/// Three short buzzes, for when a remote is about to be disconnected for idling.
+pub const IDLE_WARNING: &[(bool, u64)] = &[
+    (true, 100),
+    (false, 100),
+    (true, 100),
+    (false, 100),
+    (true, 100),
+];
+
+const NAMED_PATTERNS: [(&str, &[(bool, u64)]); 5] = [
+    ("connect", CONNECT),
+    ("low-battery", LOW_BATTERY),
+    ("error", ERROR),
+    ("tick", TICK),
+    ("idle-warning", IDLE_WARNING),
+];
+
+/// Things that happen while forwarding which can be confirmed with a rumble.
+#[derive(Clone, Copy, Debug, Deserialize, Serialize, PartialEq, Eq, Hash)]
+#[serde(rename_all = "kebab-case")]
+pub enum Feedback {
+    /// A chord's buttons were all held and its output was pressed.
+    Chord,
+    /// The profile was switched through the status socket.
+    Profile,
+    /// The output mode was switched through the status socket.
+    OutputMode,
+    /// A button ran its command.
+    Command,
+}
+
+// Bumped whenever a pattern starts, so older patterns know to stop
+static GENERATION: AtomicU64 = AtomicU64::new(0);
+
+pub fn named(name: &str) -> Option<&'static [(bool, u64)]> {
+    NAMED_PATTERNS
+        .iter()
+        .find(|(pattern_name, _)| *pattern_name == name)
+        .map(|(_, pattern)| *pattern)
+}
+
+/// Turns a pattern into the offsets from its start at which the motor should be switched on or off.
+pub fn schedule(pattern: &[(bool, u64)]) -> Vec<(Duration, bool)> {
+    let mut offset = Duration::ZERO;
+    let mut schedule = Vec::with_capacity(pattern.len() + 1);
+    for (on, duration_ms) in pattern {
+        schedule.push((offset, *on));
+        offset += Duration::from_millis(*duration_ms);
+    }
+
+    schedule.push((offset, false));
+    schedule
+}
+
+/// Plays `pattern` on the core interface at `event_path` from a separate thread, returning right away.
+pub fn play(event_path: &Path, pattern: &[(bool, u64)]) -> io::Result<()> {
+    let mut motor = Motor::open(event_path)?;
+    let generation = GENERATION.fetch_add(1, Ordering::Relaxed) + 1;
+    let schedule = schedule(pattern);
+
+    thread::spawn(move || {
+        let start = Instant::now();
+        for (offset, on) in schedule {
+            thread::sleep((start + offset).saturating_duration_since(Instant::now()));
+            if GENERATION.load(Ordering::Relaxed) != generation {
+                return;
+            }
+
+            if let Err(err) = motor.set(on) {
+                warn!("Failed to drive the rumble motor: {}", err);
+                return;
+            }
+        }
+    });
+
+    Ok(())
+}
+
+struct Motor {
+    file: File,
+    effect_id: u16,
+}
+
+impl Motor {
+    fn open(event_path: &Path) -> io::Result<Motor> {
+        let file = OpenOptions::new().read(true).write(true).open(event_path)?;
+
+        // hid-wiimote's motor is either on or off, so any strength will do. A length of 0 plays until stopped.
+        let mut effect: ff_effect = unsafe { mem::zeroed() };
+        effect.type_ = FF_RUMBLE;
+        effect.id = -1;
+        effect.u[0] = 0xffff;
+
+        if unsafe { libc::ioctl(file.as_raw_fd(), EVIOCSFF, &mut effect) } < 0 {
+            return Err(io::Error::last_os_error());
+        }
+
+        Ok(Motor {
+            file,
+            effect_id: effect.id as u16,
+        })
+    }
+
+    fn set(&mut self, on: bool) -> io::Result<()> {
+        let event = input_event {
+            time: libc::timeval {
+                tv_sec: 0,
+                tv_usec: 0,
+            },
+            type_: EV_FF,
+            code: self.effect_id,
+            value: on as c_int,
+        };
+
+        let bytes = unsafe {
+            slice::from_raw_parts(
+                &event as *const input_event as *const u8,
+                mem::size_of::<input_event>(),
+            )
+        };
+
+        self.file.write_all(bytes)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Whether the motor is on `elapsed` into a schedule, the way the playing thread would have left it
+    fn motor_at(schedule: &[(Duration, bool)], elapsed: Duration) -> bool {
+        schedule
+            .iter()
+            .take_while(|(offset, _)| *offset <= elapsed)
+            .last()
+            .is_some_and(|(_, on)| *on)
+    }
+
+    #[test]
+    fn segments_follow_each_other() {
+        let ms = Duration::from_millis;
+        assert_eq!(
+            schedule(LOW_BATTERY),
+            [
+                (ms(0), true),
+                (ms(100), false),
+                (ms(200), true),
+                (ms(300), false)
+            ]
+        );
+        assert_eq!(schedule(&[]), [(Duration::ZERO, false)]);
+    }
+
+    #[test]
+    fn motor_follows_the_pattern() {
+        let ms = Duration::from_millis;
+        let schedule = schedule(&[(true, 150), (false, 50), (true, 20)]);
+
+        let timeline = [
+            (0, true),
+            (149, true),
+            (150, false),
+            (199, false),
+            (200, true),
+            (219, true),
+            (220, false),
+            (10_000, false),
+        ];
+        for (elapsed, on) in timeline {
+            assert_eq!(motor_at(&schedule, ms(elapsed)), on, "at {} ms", elapsed);
+        }
+    }
+
+    #[test]
+    fn named_patterns() {
+        assert_eq!(named("connect"), Some(CONNECT));
+        assert_eq!(named("idle-warning"), Some(IDLE_WARNING));
+        assert_eq!(named("Connect"), None);
+    }
+}
@@ -0,0 +1,61 @@
+//! Player slot assignment, so the first remote to connect is player 1, the second player 2 and so on.
+
+use std::collections::HashMap;
+
+pub struct PlayerSlots {
+    // Bluetooth address -> the slot it had last time, persisted in the config file
+    remembered: HashMap<String, u8>,
+    // Bluetooth addresses currently holding a slot
+    active: HashMap<String, u8>,
+}
+
+impl PlayerSlots {
+    pub fn new(remembered: &HashMap<String, u8>) -> PlayerSlots {
+        PlayerSlots {
+            remembered: remembered
+                .iter()
+                .map(|(address, slot)| (address.to_uppercase(), *slot))
+                .collect(),
+            active: HashMap::new(),
+        }
+    }
+
+    /// Gives `address` the slot it had before if it's free, otherwise the lowest free slot.
+    pub fn assign(&mut self, address: &str) -> u8 {
+        let address = address.to_uppercase();
+        if let Some(slot) = self.active.get(&address) {
+            return *slot;
+        }
+
+        let slot = match self.remembered.get(&address) {
+            Some(slot) if *slot > 0 && !self.is_taken(*slot) => *slot,
+            _ => (1..=u8::MAX).find(|slot| !self.is_taken(*slot)).unwrap(),
+        };
+
+        self.remembered.insert(address.clone(), slot);
+        self.active.insert(address, slot);
+        slot
+    }
+
+    /// The slot `address` had the last time it connected, if any.
+    pub fn remembered(&self, address: &str) -> Option<u8> {
+        self.remembered.get(&address.to_uppercase()).copied()
+    }
+
+    /// Frees the slot held by `address` so the next remote to connect can take it.
+    pub fn release(&mut self, address: &str) {
+        self.active.remove(&address.to_uppercase());
+    }
+
+    /// The LED mask for `slot`: one LED per player for the first four slots, binary after that.
+    pub fn led_mask(slot: u8) -> u8 {
+        match slot {
+            1..=4 => 1 << (slot - 1),
+            _ => slot & 0x0f,
+        }
+    }
+
+    fn is_taken(&self, slot: u8) -> bool {
+        self.active.values().any(|active_slot| *active_slot == slot)
+    }
+}
@@ -0,0 +1,210 @@
+//! Experimental support for the Wii Remote's built-in speaker.
+//!
+//! The speaker is driven with raw HID output reports written to the remote's `hidraw` node, alongside the
+//! hid-wiimote driver. Audio has to be streamed as 4-bit Yamaha ADPCM, 20 bytes (40 samples) per report,
+//! and Bluetooth can only reliably push around 75 of those reports a second. That caps the sample rate at
+//! [`SAMPLE_RATE`] (3 kHz, so nothing above 1.5 kHz survives), and the hardware's tiny speaker adds its own
+//! distortion on top. Good enough for chimes and beeps, not for music.
+
+use std::{
+    f32::consts::PI,
+    fs::{self, File, OpenOptions},
+    io::{self, ErrorKind, Read, Write},
+    os::unix::fs::OpenOptionsExt,
+    path::{Path, PathBuf},
+    thread,
+    time::{Duration, Instant},
+};
+
+/// The sample rate, in Hz, that samples passed to [`play`] are expected to be in.
+pub const SAMPLE_RATE: u32 = 3000;
+
+const SAMPLES_PER_REPORT: usize = 40;
+
+// Output report IDs, see https://wiibrew.org/wiki/Wiimote#Speaker
+const REPORT_SPEAKER_ENABLE: u8 = 0x14;
+const REPORT_SPEAKER_DATA: u8 = 0x18;
+const REPORT_SPEAKER_MUTE: u8 = 0x19;
+const REPORT_WRITE_MEMORY: u8 = 0x16;
+const REPORT_READ_MEMORY: u8 = 0x17;
+const REPORT_READ_DATA: u8 = 0x21;
+
+/// How long [`read_register`] waits for the remote to answer.
+const READ_TIMEOUT: Duration = Duration::from_millis(500);
+
+const YAMAHA_INDEX_SCALE: [i32; 16] = [
+    230, 230, 230, 230, 307, 409, 512, 614, 230, 230, 230, 230, 307, 409, 512, 614,
+];
+const YAMAHA_DIFF_LOOKUP: [i32; 16] =
+    [1, 3, 5, 7, 9, 11, 13, 15, -1, -3, -5, -7, -9, -11, -13, -15];
+
+/// Encodes signed 16-bit PCM samples as 4-bit Yamaha ADPCM, two samples per byte.
+pub fn encode_adpcm(samples: &[i16]) -> Vec<u8> {
+    let mut predictor: i32 = 0;
+    let mut step: i32 = 127;
+    let mut encoded = Vec::with_capacity(samples.len().div_ceil(2));
+
+    for pair in samples.chunks(2) {
+        let mut byte = 0;
+        for (i, sample) in pair.iter().enumerate() {
+            let delta = *sample as i32 - predictor;
+            let nibble = (delta.abs() * 4 / step).min(7) + if delta < 0 { 8 } else { 0 };
+
+            predictor = (predictor + step * YAMAHA_DIFF_LOOKUP[nibble as usize] / 8)
+                .clamp(i16::MIN as i32, i16::MAX as i32);
+            step = ((step * YAMAHA_INDEX_SCALE[nibble as usize]) >> 8).clamp(127, 24576);
+
+            byte |= (nibble as u8) << (i * 4);
+        }
+
+        encoded.push(byte);
+    }
+
+    encoded
+}
+
+/// Finds the `/dev/hidrawN` node belonging to the HID device at `udev_device_path`.
+pub fn find_hidraw(udev_device_path: &str) -> Option<PathBuf> {
+    let hidraw = fs::read_dir(Path::new(udev_device_path).join("hidraw")).ok()?;
+    hidraw
+        .flatten()
+        .next()
+        .map(|entry| Path::new("/dev").join(entry.file_name()))
+}
+
+/// Streams `samples` (mono, [`SAMPLE_RATE`] Hz) to the speaker, blocking until playback has finished.
+pub fn play(hidraw_path: &Path, samples: &[i16]) -> io::Result<()> {
+    let mut hidraw = OpenOptions::new().write(true).open(hidraw_path)?;
+
+    // Power the speaker on and configure it while muted so the setup doesn't pop
+    hidraw.write_all(&[REPORT_SPEAKER_ENABLE, 0x04])?;
+    hidraw.write_all(&[REPORT_SPEAKER_MUTE, 0x04])?;
+    write_register(&mut hidraw, 0xa20009, &[0x01])?;
+    write_register(&mut hidraw, 0xa20001, &[0x08])?;
+
+    // 4-bit ADPCM, the sample rate as a divisor of 6 MHz, and a volume of 0x40
+    let rate = (6_000_000 / SAMPLE_RATE) as u16;
+    let [rate_low, rate_high] = rate.to_le_bytes();
+    write_register(
+        &mut hidraw,
+        0xa20001,
+        &[0x00, 0x00, rate_low, rate_high, 0x40, 0x00, 0x00],
+    )?;
+    write_register(&mut hidraw, 0xa20008, &[0x01])?;
+    hidraw.write_all(&[REPORT_SPEAKER_MUTE, 0x00])?;
+
+    // Pace the reports to the sample rate, the remote drops anything that arrives too early
+    let report_interval = Duration::from_secs_f64(SAMPLES_PER_REPORT as f64 / SAMPLE_RATE as f64);
+    let start = Instant::now();
+    for (i, chunk) in encode_adpcm(samples)
+        .chunks(SAMPLES_PER_REPORT / 2)
+        .enumerate()
+    {
+        let mut report = [0u8; 22];
+        report[0] = REPORT_SPEAKER_DATA;
+        report[1] = (chunk.len() as u8) << 3;
+        report[2..2 + chunk.len()].copy_from_slice(chunk);
+        hidraw.write_all(&report)?;
+
+        let deadline = start + report_interval * (i as u32 + 1);
+        thread::sleep(deadline.saturating_duration_since(Instant::now()));
+    }
+
+    hidraw.write_all(&[REPORT_SPEAKER_MUTE, 0x04])?;
+    hidraw.write_all(&[REPORT_SPEAKER_ENABLE, 0x00])
+}
+
+/// A short rising two-note chime, played on connect by `--startup-chime`.
+pub fn startup_chime() -> Vec<i16> {
+    [523.25, 783.99]
+        .iter()
+        .flat_map(|frequency| tone(*frequency, Duration::from_millis(150)))
+        .collect()
+}
+
+fn tone(frequency: f32, duration: Duration) -> impl Iterator<Item = i16> {
+    let sample_count = (duration.as_secs_f32() * SAMPLE_RATE as f32) as usize;
+    (0..sample_count).map(move |i| {
+        let t = i as f32 / SAMPLE_RATE as f32;
+        ((2.0 * PI * frequency * t).sin() * i16::MAX as f32 * 0.5) as i16
+    })
+}
+
+/// Writes `data`, at most 16 bytes, to the remote's control registers at `address`.
+pub(crate) fn write_register(hidraw: &mut File, address: u32, data: &[u8]) -> io::Result<()> {
+    // 0x04 selects the control registers rather than EEPROM, the payload is always padded to 16 bytes
+    let mut report = [0u8; 22];
+    report[0] = REPORT_WRITE_MEMORY;
+    report[1] = 0x04;
+    report[2..5].copy_from_slice(&address.to_be_bytes()[1..]);
+    report[5] = data.len() as u8;
+    report[6..6 + data.len()].copy_from_slice(data);
+    hidraw.write_all(&report)?;
+
+    // Give the remote time to apply the write before the next one arrives
+    thread::sleep(Duration::from_millis(10));
+    Ok(())
+}
+
+/// Reads `size` bytes, at most 16, from the remote's control registers at `address`.
+///
+/// The answer arrives as an input report that hid-wiimote sees as well, it ignores ones it didn't ask for.
+pub(crate) fn read_register(hidraw_path: &Path, address: u32, size: u8) -> io::Result<Vec<u8>> {
+    read_memory(hidraw_path, 0x04, address, size)
+}
+
+/// Reads `size` bytes, at most 16, from the remote's EEPROM at `address`, like its accelerometer calibration.
+pub(crate) fn read_eeprom(hidraw_path: &Path, address: u32, size: u8) -> io::Result<Vec<u8>> {
+    read_memory(hidraw_path, 0x00, address, size)
+}
+
+// `space` is 0x04 for the control registers and 0x00 for EEPROM
+fn read_memory(hidraw_path: &Path, space: u8, address: u32, size: u8) -> io::Result<Vec<u8>> {
+    let size = size.clamp(1, 16);
+    let mut hidraw = OpenOptions::new()
+        .read(true)
+        .write(true)
+        .custom_flags(libc::O_NONBLOCK)
+        .open(hidraw_path)?;
+
+    let mut request = [0u8; 7];
+    request[0] = REPORT_READ_MEMORY;
+    request[1] = space;
+    request[2..5].copy_from_slice(&address.to_be_bytes()[1..]);
+    request[5..7].copy_from_slice(&(size as u16).to_be_bytes());
+    hidraw.write_all(&request)?;
+
+    let started = Instant::now();
+    let mut report = [0u8; 22];
+    while started.elapsed() < READ_TIMEOUT {
+        let length = match hidraw.read(&mut report) {
+            Ok(length) => length,
+            Err(err) if err.kind() == ErrorKind::WouldBlock => {
+                thread::sleep(Duration::from_millis(5));
+                continue;
+            }
+            Err(err) => return Err(err),
+        };
+
+        // The button bytes come first, then the size and error nibbles and the low half of the address
+        if length < 6 + size as usize
+            || report[0] != REPORT_READ_DATA
+            || report[4..6] != address.to_be_bytes()[2..]
+        {
+            continue;
+        }
+
+        return match report[3] & 0x0f {
+            0 => Ok(report[6..6 + size as usize].to_vec()),
+            error => Err(io::Error::other(format!(
+                "the remote refused to read {:#08x} (error {})",
+                address, error
+            ))),
+        };
+    }
+
+    Err(io::Error::new(
+        ErrorKind::TimedOut,
+        format!("the remote didn't answer reading {:#08x}", address),
+    ))
+}
@@ -0,0 +1,516 @@
+//! A Unix socket for inspecting and steering a running BlueWii, one command per line:
+//!
+//! ```text
+//! $ echo status | socat - UNIX-CONNECT:$XDG_RUNTIME_DIR/bluewii.sock
+//! connected=AA:BB:CC:DD:EE:FF player=1 capabilities=ir,accel,nunchuk profile=default output=gamepad accel=on
+//! $ echo set-profile retroarch | socat - UNIX-CONNECT:$XDG_RUNTIME_DIR/bluewii.sock
+//! ok profile=retroarch
+//! $ echo set-output keyboard | socat - UNIX-CONNECT:$XDG_RUNTIME_DIR/bluewii.sock
+//! ok output=keyboard
+//! $ echo set-accel off | socat - UNIX-CONNECT:$XDG_RUNTIME_DIR/bluewii.sock
+//! ok accel=off
+//! $ echo rumble low-battery | socat - UNIX-CONNECT:$XDG_RUNTIME_DIR/bluewii.sock
+//! ok
+//! ```
+//!
+//! `rumble` plays one of the patterns in [`rumble`](crate::rumble): `connect`, `low-battery`, `error` or `tick`.
+//!
+//! `dump-config` replies with the mapping that's actually in effect, once the built-in defaults, the active profile
+//! and the output mode have all been applied, along with every connected remote:
+//!
+//! ```text
+//! $ echo dump-config | socat - UNIX-CONNECT:$XDG_RUNTIME_DIR/bluewii.sock
+//! {
+//!   "profile": "default",
+//!   "output_mode": "gamepad",
+//!   "mapping": {
+//!     "a": "BTN_SOUTH",
+//!     ...
+//!   },
+//!   "chords": {},
+//!   "commands": {},
+//!   "remotes": [
+//!     {
+//!       "address": "AA:BB:CC:DD:EE:FF",
+//!       "player": 1,
+//!       "battery": 80
+//!     }
+//!   ]
+//! }
+//! ```
+//!
+//! `forget <address>` unpairs a remote, see [`WiiRemote::forget`]. A remote this instance is using is only
+//! forgotten with `forget <address> --force`:
+//!
+//! ```text
+//! $ echo forget AA:BB:CC:DD:EE:FF | socat - UNIX-CONNECT:$XDG_RUNTIME_DIR/bluewii.sock
+//! error: AA:BB:CC:DD:EE:FF is in use, add --force to forget it anyway
+//! $ echo forget AA:BB:CC:DD:EE:FF --force | socat - UNIX-CONNECT:$XDG_RUNTIME_DIR/bluewii.sock
+//! ok forgot=AA:BB:CC:DD:EE:FF
+//! ```
+//!
+//! `history` replies with the last connects and disconnects, see [`history`](crate::history), oldest first and one
+//! per line. `history <count>` only replies with the newest `count`:
+//!
+//! ```text
+//! $ echo history | socat - UNIX-CONNECT:$XDG_RUNTIME_DIR/bluewii.sock
+//! at=1700000000 ago=3600 address=AA:BB:CC:DD:EE:FF event=connected
+//! at=1700000900 ago=2700 address=AA:BB:CC:DD:EE:FF event=disconnected reason=out-of-range
+//! at=1700000905 ago=2695 address=AA:BB:CC:DD:EE:FF event=reconnected
+//! ```
+//!
+//! Every reply is a single line except for those two, which end with an empty line instead. Errors start with
+//! `error:`.
+
+use std::{
+    env, fs,
+    io::{BufRead, BufReader, ErrorKind, Write},
+    os::unix::net::{UnixListener, UnixStream},
+    path::{Path, PathBuf},
+    sync::{mpsc::Sender, Arc, Mutex},
+    time::SystemTime,
+};
+
+use anyhow::Context;
+use log::{debug, info, warn};
+
+use crate::{
+    button::Button,
+    config::Config,
+    evdev,
+    forwarder::{self, OutputMode},
+    history::{self, ConnectionEvent},
+    rumble,
+    utils::FormattedUnwrap,
+    wii_remote::{WiiRemote, WiiRemoteBuilder},
+    xwiimote,
+};
+
+/// Something the poll loop should do, since it owns the remote's virtual devices.
+pub enum Request {
+    SetProfile(String),
+    SetOutputMode(OutputMode),
+    Rumble(&'static [(bool, u64)]),
+    /// Turns accelerometer reporting on or off, the interface is only open while it's on.
+    SetAccel(bool),
+}
+
+pub struct Status {
+    /// The connected remote's Bluetooth address and player slot.
+    pub remote: Option<(String, u8)>,
+    /// The address and player slot of every connected remote, player 1's included, in the order they connected.
+    pub remotes: Vec<(String, u8)>,
+    /// What the connected remote has, see [`xwiimote::Capabilities`].
+    pub capabilities: Option<xwiimote::Capabilities>,
+    pub profile: String,
+    pub output_mode: OutputMode,
+    pub accel: bool,
+}
+
+pub static STATUS: Mutex<Status> = Mutex::new(Status {
+    remote: None,
+    remotes: Vec::new(),
+    capabilities: None,
+    profile: String::new(),
+    output_mode: OutputMode::Gamepad,
+    accel: true,
+});
+
+/// `$XDG_RUNTIME_DIR/bluewii.sock`, or `/tmp/bluewii.sock` outside of a login session.
+pub fn default_path() -> PathBuf {
+    match env::var_os("XDG_RUNTIME_DIR") {
+        Some(dir) if !dir.is_empty() => PathBuf::from(dir).join("bluewii.sock"),
+        _ => PathBuf::from("/tmp/bluewii.sock"),
+    }
+}
+
+/// Answers commands on `path` until the process exits, `remotes` being what `forget` runs `bluetoothctl` with.
+pub fn serve(
+    path: &Path,
+    config: Arc<Config>,
+    requests: Sender<Request>,
+    remotes: WiiRemoteBuilder,
+) {
+    // A socket left behind by a previous run would make binding fail
+    match fs::remove_file(path) {
+        Err(err) if err.kind() != ErrorKind::NotFound => warn!(
+            "Failed to remove the old status socket {}: {}",
+            path.display(),
+            err
+        ),
+        _ => {}
+    }
+
+    let listener = UnixListener::bind(path)
+        .with_context(|| format!("Failed to listen on the status socket `{}'", path.display()))
+        .unwrap_or_fmt();
+
+    info!("Listening for commands on {}", path.display());
+    for stream in listener.incoming() {
+        match stream {
+            Ok(stream) => {
+                if let Err(err) = handle_client(stream, &config, &requests, &remotes) {
+                    debug!("Status socket client went away: {}", err);
+                }
+            }
+            Err(err) => warn!("Failed to accept a status socket client: {}", err),
+        }
+    }
+}
+
+fn handle_client(
+    stream: UnixStream,
+    config: &Config,
+    requests: &Sender<Request>,
+    remotes: &WiiRemoteBuilder,
+) -> std::io::Result<()> {
+    let mut writer = stream.try_clone()?;
+    for line in BufReader::new(stream).lines() {
+        let line = line?;
+        let reply = handle_command(line.trim(), config, requests, remotes);
+        writeln!(writer, "{}", reply)?;
+    }
+
+    Ok(())
+}
+
+fn handle_command(
+    command: &str,
+    config: &Config,
+    requests: &Sender<Request>,
+    remotes: &WiiRemoteBuilder,
+) -> String {
+    let (name, argument) = match command.split_once(' ') {
+        Some((name, argument)) => (name, argument.trim()),
+        None => (command, ""),
+    };
+
+    match name {
+        "status" => {
+            let status = STATUS.lock().unwrap();
+            match &status.remote {
+                Some((address, player_slot)) => format!(
+                    "connected={} player={} capabilities={} profile={} output={} accel={}",
+                    address,
+                    player_slot,
+                    status
+                        .capabilities
+                        .map_or("none".to_owned(), |capabilities| capabilities.summary()),
+                    status.profile,
+                    status.output_mode.name(),
+                    on_off(status.accel)
+                ),
+                None => format!(
+                    "connected=none profile={} output={} accel={}",
+                    status.profile,
+                    status.output_mode.name(),
+                    on_off(status.accel)
+                ),
+            }
+        }
+        "set-profile" if argument.is_empty() => "error: usage: set-profile <name>".to_owned(),
+        "set-profile" => {
+            let profile = config.resolve_profile(argument).to_owned();
+            STATUS.lock().unwrap().profile = profile.clone();
+            match requests.send(Request::SetProfile(profile.clone())) {
+                Ok(()) => format!("ok profile={}", profile),
+                Err(_) => "error: BlueWii is shutting down".to_owned(),
+            }
+        }
+        "set-output" => match OutputMode::from_name(argument) {
+            // The poll loop updates `STATUS` once the switch has actually happened
+            Some(output_mode) => match requests.send(Request::SetOutputMode(output_mode)) {
+                Ok(()) => format!("ok output={}", output_mode.name()),
+                Err(_) => "error: BlueWii is shutting down".to_owned(),
+            },
+            None => "error: usage: set-output gamepad|keyboard|split".to_owned(),
+        },
+        "rumble" => match rumble::named(argument) {
+            Some(_) if STATUS.lock().unwrap().remote.is_none() => {
+                "error: no Wii Remote is connected".to_owned()
+            }
+            Some(pattern) => match requests.send(Request::Rumble(pattern)) {
+                Ok(()) => "ok".to_owned(),
+                Err(_) => "error: BlueWii is shutting down".to_owned(),
+            },
+            None => format!("error: unknown rumble pattern `{}'", argument),
+        },
+        "set-accel" => {
+            let accel = match argument {
+                "on" => true,
+                "off" => false,
+                _ => return "error: usage: set-accel on|off".to_owned(),
+            };
+
+            STATUS.lock().unwrap().accel = accel;
+            match requests.send(Request::SetAccel(accel)) {
+                Ok(()) => format!("ok accel={}", on_off(accel)),
+                Err(_) => "error: BlueWii is shutting down".to_owned(),
+            }
+        }
+        "dump-config" => dump_config(config),
+        "forget" => {
+            let (address, force) = match argument.split_once(' ') {
+                Some((address, "--force")) => (address.to_uppercase(), true),
+                None if !argument.is_empty() => (argument.to_uppercase(), false),
+                _ => return "error: usage: forget <address> [--force]".to_owned(),
+            };
+
+            if !WiiRemote::is_valid_address(&address) {
+                return format!("error: `{}' is not a Bluetooth address", address);
+            }
+
+            let in_use = STATUS
+                .lock()
+                .unwrap()
+                .remotes
+                .iter()
+                .any(|(connected, _)| connected.eq_ignore_ascii_case(&address));
+            if in_use && !force {
+                return format!(
+                    "error: {} is in use, add --force to forget it anyway",
+                    address
+                );
+            }
+
+            match remotes.build().forget(&address) {
+                Ok(()) => {
+                    info!("Forgot {} (status socket)", address);
+                    format!("ok forgot={}", address)
+                }
+                Err(err) => format!("error: {}", err),
+            }
+        }
+        "history" => match argument {
+            "" => history(usize::MAX),
+            count => match count.parse() {
+                Ok(count) => history(count),
+                Err(_) => "error: usage: history [count]".to_owned(),
+            },
+        },
+        _ => format!("error: unknown command `{}'", name),
+    }
+}
+
+fn on_off(enabled: bool) -> &'static str {
+    match enabled {
+        true => "on",
+        false => "off",
+    }
+}
+
+/// The effective mapping and every connected remote as pretty-printed JSON.
+fn dump_config(config: &Config) -> String {
+    let (profile, output_mode, mut remotes) = {
+        let status = STATUS.lock().unwrap();
+        (
+            status.profile.clone(),
+            status.output_mode,
+            status.remotes.clone(),
+        )
+    };
+
+    let mapping = forwarder::mapping(config, &profile, output_mode);
+    let keys = mapping
+        .keys
+        .iter()
+        .map(|(key, output)| (key_name(*key), output_name(*output)))
+        .collect::<Vec<_>>();
+
+    let chords = mapping
+        .chords
+        .iter()
+        .map(|(keys, output)| {
+            let keys = keys.iter().map(|key| key_name(*key)).collect::<Vec<_>>();
+            (keys.join("+"), output_name(*output))
+        })
+        .collect::<Vec<_>>();
+
+    let mut commands = config
+        .commands
+        .iter()
+        .map(|(button, command)| (button.name().to_owned(), command.clone()))
+        .collect::<Vec<_>>();
+    commands.sort();
+
+    remotes.sort_by_key(|(_, player_slot)| *player_slot);
+    let bound_remotes = xwiimote::bound_remotes();
+    let remotes = remotes
+        .iter()
+        .map(|(address, player_slot)| {
+            let battery = bound_remotes
+                .iter()
+                .find(|(_, bound_address)| bound_address == address)
+                .and_then(|(syspath, _)| WiiRemote::battery_level(&syspath.to_string_lossy()));
+
+            format!(
+                "    {{\n      \"address\": {},\n      \"player\": {},\n      \"battery\": {}\n    }}",
+                json_string(address),
+                player_slot,
+                match battery {
+                    Some(battery) => battery.to_string(),
+                    None => "null".to_owned(),
+                }
+            )
+        })
+        .collect::<Vec<_>>();
+    let remotes = match remotes.is_empty() {
+        true => "[]".to_owned(),
+        false => format!("[\n{}\n  ]", remotes.join(",\n")),
+    };
+
+    // Followed by an empty line, since this is the one reply that spans several
+    format!(
+        "{{\n  \"profile\": {},\n  \"output_mode\": {},\n  \"mapping\": {},\n  \"chords\": {},\n  \"commands\": {},\n  \"remotes\": {}\n}}\n",
+        json_string(&profile),
+        json_string(output_mode.name()),
+        json_object(&keys),
+        json_object(&chords),
+        json_object(&commands),
+        remotes
+    )
+}
+
+/// The newest `count` connection events, one per line and followed by an empty line.
+fn history(count: usize) -> String {
+    let now = SystemTime::now();
+    let mut reply = String::new();
+    for entry in history::recent(count) {
+        let at = entry
+            .at
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .map_or(0, |at| at.as_secs());
+        let ago = now.duration_since(entry.at).map_or(0, |ago| ago.as_secs());
+        let event = match entry.event {
+            ConnectionEvent::Connected => "connected".to_owned(),
+            ConnectionEvent::Reconnected => "reconnected".to_owned(),
+            ConnectionEvent::Disconnected(reason) => {
+                format!("disconnected reason={}", reason.name())
+            }
+        };
+
+        reply.push_str(&format!(
+            "at={} ago={} address={} event={}\n",
+            at, ago, entry.address, event
+        ));
+    }
+
+    reply
+}
+
+fn key_name(key: u16) -> String {
+    match Button::from_code(key) {
+        Some(button) => button.name().to_owned(),
+        None => key.to_string(),
+    }
+}
+
+fn output_name(output: u16) -> String {
+    match evdev::name_from_code(output) {
+        Some(name) => name.to_owned(),
+        None => output.to_string(),
+    }
+}
+
+/// A JSON object of string values, indented to sit one level deep.
+fn json_object(entries: &[(String, String)]) -> String {
+    if entries.is_empty() {
+        return "{}".to_owned();
+    }
+
+    let entries = entries
+        .iter()
+        .map(|(key, value)| format!("    {}: {}", json_string(key), json_string(value)))
+        .collect::<Vec<_>>();
+
+    format!("{{\n{}\n  }}", entries.join(",\n"))
+}
+
+fn json_string(value: &str) -> String {
+    let mut json = String::from('"');
+    for c in value.chars() {
+        match c {
+            '"' => json.push_str("\\\""),
+            '\\' => json.push_str("\\\\"),
+            '\n' => json.push_str("\\n"),
+            c if c.is_control() => json.push_str(&format!("\\u{:04x}", c as u32)),
+            c => json.push(c),
+        }
+    }
+
+    json.push('"');
+    json
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn json_strings_are_escaped() {
+        assert_eq!(json_string("plain"), "\"plain\"");
+        assert_eq!(
+            json_string("say \"hi\" C:\\ \n\t\u{1b}"),
+            "\"say \\\"hi\\\" C:\\\\ \\n\\u0009\\u001b\""
+        );
+        assert_eq!(json_string("Wiimote \u{e9}"), "\"Wiimote \u{e9}\"");
+    }
+
+    #[test]
+    fn json_objects() {
+        assert_eq!(json_object(&[]), "{}");
+        assert_eq!(
+            json_object(&[
+                ("a".to_owned(), "BTN_SOUTH".to_owned()),
+                ("b\"".to_owned(), "x".to_owned()),
+            ]),
+            "{\n    \"a\": \"BTN_SOUTH\",\n    \"b\\\"\": \"x\"\n  }"
+        );
+    }
+
+    #[test]
+    fn dump_config_covers_every_remote() {
+        let mut config = Config::default();
+        config
+            .commands
+            .insert(Button::Home, "notify-send \"Home\"".to_owned());
+        {
+            let mut status = STATUS.lock().unwrap();
+            status.profile = "default".to_owned();
+            status.output_mode = OutputMode::Gamepad;
+            status.remotes.clear();
+        }
+
+        let dump = dump_config(&config);
+        assert!(
+            dump.starts_with("{\n  \"profile\": \"default\",\n  \"output_mode\": \"gamepad\",\n")
+        );
+        assert!(dump.contains("\n  \"chords\": {},\n"));
+        assert!(dump
+            .contains("\n  \"commands\": {\n    \"home\": \"notify-send \\\"Home\\\"\"\n  },\n"));
+        assert!(dump.ends_with("\n  \"remotes\": []\n}\n"));
+
+        // Listed by player, whatever order they connected in
+        STATUS.lock().unwrap().remotes = vec![
+            ("00:19:1D:00:00:02".to_owned(), 2),
+            ("00:19:1D:00:00:01".to_owned(), 1),
+        ];
+        let dump = dump_config(&config);
+        STATUS.lock().unwrap().remotes.clear();
+        assert!(dump.ends_with(
+            "\n  \"remotes\": [\n\
+             \x20   {\n\
+             \x20     \"address\": \"00:19:1D:00:00:01\",\n\
+             \x20     \"player\": 1,\n\
+             \x20     \"battery\": null\n\
+             \x20   },\n\
+             \x20   {\n\
+             \x20     \"address\": \"00:19:1D:00:00:02\",\n\
+             \x20     \"player\": 2,\n\
+             \x20     \"battery\": null\n\
+             \x20   }\n\
+             \x20 ]\n\
+             }\n"
+        ));
+    }
+}
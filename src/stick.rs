@@ -0,0 +1,558 @@
+//! The analog sticks of the Nunchuk and Classic Controller, read from their hid-wiimote interfaces and reported
+//! on the virtual gamepad once calibrated.
+//!
+//! Worn sticks rarely rest at zero or reach the ends of the range the kernel advertises, so every axis can be
+//! given its own resting center, travel and deadzone in the config file, as written by `--calibrate`:
+//!
+//! ```toml
+//! [calibration.nunchuk]
+//! x = { center = 3, min = -98, max = 104, deadzone = 4 }
+//! y = { center = -2, min = -101, max = 97, deadzone = 4 }
+//! ```
+//!
+//! Axes missing from the config file fall back on the calibration stored in the extension itself, see
+//! [`ExtensionCalibration`], and on the range the kernel advertises if that can't be read.
+//!
+//! Sticks go on `ABS_X`/`ABS_Y` and, for the Classic Controller's right stick, `ABS_RX`/`ABS_RY`. The right stick
+//! is left out while `accel_orientation` is set, since tilt is reported on the same axes.
+//!
+//! The Classic Controller's L and R triggers go on `ABS_Z`/`ABS_RZ` from 0 to [`TRIGGER_RANGE`], and press
+//! `BTN_TL2`/`BTN_TR2` once pulled past `trigger_threshold`. How they behave depends on the variant, which
+//! hid-wiimote doesn't tell apart:
+//!
+//! - The original Classic Controller reports how far each trigger is pulled, so the axis follows it, and the
+//!   click at the end of its travel arrives well past the threshold.
+//! - The Classic Controller Pro's triggers are plain buttons that never report any pressure. A click with no
+//!   pressure behind it gives the trigger away, and from then on it reads as fully pulled while clicked.
+//!
+//! `ABS_RZ` is also a tilt axis, so like the right stick, the R trigger is left out while `accel_orientation` is set.
+//!
+//! With `stick_mode = "dpad"` in the `[nunchuk]` table, the Nunchuk's stick presses the Wii Remote's D-pad
+//! instead of moving `ABS_X`/`ABS_Y`, for games that only understand a D-pad, see [`directions`].
+
+use std::io;
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    evdev::{
+        ABS_HAT0X, ABS_HAT0Y, ABS_HAT1X, ABS_HAT1Y, ABS_HAT2X, ABS_HAT2Y, ABS_HAT3X, ABS_HAT3Y,
+        ABS_RX, ABS_RY, ABS_RZ, ABS_X, ABS_Y, ABS_Z, BTN_TL, BTN_TL2, BTN_TR, BTN_TR2,
+    },
+    xwiimote::{self, Evdev},
+};
+
+/// Calibrated axes are reported from `-OUTPUT_RANGE` to `OUTPUT_RANGE`.
+pub const OUTPUT_RANGE: i32 = 32767;
+
+/// Triggers are reported from 0, let go, to `TRIGGER_RANGE`, fully pulled.
+pub const TRIGGER_RANGE: i32 = 255;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Extension {
+    Nunchuk,
+    Classic,
+}
+
+/// One axis of an extension's sticks.
+pub struct StickAxis {
+    /// What it's called in the config file.
+    pub name: &'static str,
+    /// The code hid-wiimote reports it as.
+    pub source: u16,
+    /// The code it's reported as on the virtual gamepad.
+    pub output: u16,
+}
+
+const NUNCHUK_AXES: [StickAxis; 2] = [
+    StickAxis {
+        name: "x",
+        source: ABS_HAT0X,
+        output: ABS_X,
+    },
+    StickAxis {
+        name: "y",
+        source: ABS_HAT0Y,
+        output: ABS_Y,
+    },
+];
+
+const CLASSIC_AXES: [StickAxis; 4] = [
+    StickAxis {
+        name: "left-x",
+        source: ABS_HAT1X,
+        output: ABS_X,
+    },
+    StickAxis {
+        name: "left-y",
+        source: ABS_HAT1Y,
+        output: ABS_Y,
+    },
+    StickAxis {
+        name: "right-x",
+        source: ABS_HAT2X,
+        output: ABS_RX,
+    },
+    StickAxis {
+        name: "right-y",
+        source: ABS_HAT2Y,
+        output: ABS_RY,
+    },
+];
+
+/// One of the Classic Controller's analog triggers.
+pub struct Trigger {
+    pub name: &'static str,
+    /// The code hid-wiimote reports its pressure as.
+    pub source: u16,
+    /// The code hid-wiimote reports its click as.
+    pub click: u16,
+    /// The axis it's reported as on the virtual gamepad.
+    pub output: u16,
+    /// The button it presses on the virtual gamepad once pulled far enough.
+    pub button: u16,
+}
+
+pub const CLASSIC_TRIGGERS: [Trigger; 2] = [
+    Trigger {
+        name: "left-trigger",
+        source: ABS_HAT3Y,
+        click: BTN_TL,
+        output: ABS_Z,
+        button: BTN_TL2,
+    },
+    Trigger {
+        name: "right-trigger",
+        source: ABS_HAT3X,
+        click: BTN_TR,
+        output: ABS_RZ,
+        button: BTN_TR2,
+    },
+];
+
+impl Extension {
+    pub const ALL: [Extension; 2] = [Extension::Nunchuk, Extension::Classic];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Extension::Nunchuk => "nunchuk",
+            Extension::Classic => "classic",
+        }
+    }
+
+    /// The name of the hid-wiimote interface it shows up as while plugged in.
+    pub fn interface(self) -> &'static str {
+        match self {
+            Extension::Nunchuk => "Nintendo Wii Remote Nunchuk",
+            Extension::Classic => "Nintendo Wii Remote Classic Controller",
+        }
+    }
+
+    pub fn axes(self) -> &'static [StickAxis] {
+        match self {
+            Extension::Nunchuk => &NUNCHUK_AXES,
+            Extension::Classic => &CLASSIC_AXES,
+        }
+    }
+
+    pub fn triggers(self) -> &'static [Trigger] {
+        match self {
+            Extension::Nunchuk => &[],
+            Extension::Classic => &CLASSIC_TRIGGERS,
+        }
+    }
+}
+
+/// Whichever extension is plugged into the remote at `udev_device_path`.
+pub fn plugged_in(udev_device_path: &str) -> Option<Extension> {
+    Extension::ALL.into_iter().find(|extension| {
+        xwiimote::find_interface(udev_device_path, extension.interface()).is_some()
+    })
+}
+
+/// Opens the interface of whichever extension is plugged into the remote at `udev_device_path`.
+pub fn open(udev_device_path: &str) -> Option<(Extension, Evdev)> {
+    Extension::ALL.into_iter().find_map(|extension| {
+        xwiimote::find_interface(udev_device_path, extension.interface())?;
+        xwiimote::open_interface(udev_device_path, extension.interface())
+            .map(|evdev| (extension, evdev))
+    })
+}
+
+#[derive(Clone, Copy, Debug, Deserialize, Serialize, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct AxisCalibration {
+    /// Where the axis rests.
+    pub center: i32,
+    pub min: i32,
+    pub max: i32,
+    /// How far from `center` the axis can wander while resting without being reported.
+    pub deadzone: i32,
+}
+
+impl AxisCalibration {
+    /// The range the kernel advertises for `source`, for axes without a calibration.
+    pub fn uncalibrated(evdev: &Evdev, source: u16) -> io::Result<AxisCalibration> {
+        let info = evdev.abs_info(source)?;
+        Ok(AxisCalibration {
+            center: (info.minimum + info.maximum) / 2,
+            min: info.minimum,
+            max: info.maximum,
+            deadzone: info.flat,
+        })
+    }
+
+    /// Scales a raw reading to `-OUTPUT_RANGE..=OUTPUT_RANGE`.
+    ///
+    /// Each side of `center` is scaled on its own, so an off-center stick still reaches both ends, and the travel
+    /// starts at the edge of the deadzone so there's no jump when leaving it.
+    pub fn apply(&self, raw: i32) -> i32 {
+        let offset = raw - self.center;
+        if offset.abs() <= self.deadzone {
+            return 0;
+        }
+
+        let travel = match offset > 0 {
+            true => self.max - self.center,
+            false => self.center - self.min,
+        } - self.deadzone;
+
+        if travel <= 0 {
+            return 0;
+        }
+
+        let scaled = (offset.abs() - self.deadzone) as i64 * OUTPUT_RANGE as i64 / travel as i64;
+        scaled.min(OUTPUT_RANGE as i64) as i32 * offset.signum()
+    }
+}
+
+/// Where the calibration data starts in an extension's registers, see https://wiibrew.org/wiki/Wiimote/Extension_Controllers
+pub const CALIBRATION_REGISTER: u32 = 0xa40020;
+
+/// How many bytes of calibration data there are, the last two being checksums.
+pub const CALIBRATION_SIZE: u8 = 16;
+
+/// The stick calibration an extension was given at the factory, read out of its registers when it's plugged in.
+#[derive(Clone, Debug, PartialEq)]
+pub struct ExtensionCalibration {
+    pub extension: Extension,
+    /// The center, min and max of each axis that had a sensible calibration, by the axis's config file name, in
+    /// the units hid-wiimote reports. The deadzone is left at 0 for the kernel's to be used.
+    pub axes: Vec<(&'static str, AxisCalibration)>,
+}
+
+impl ExtensionCalibration {
+    /// Makes sense of the [`CALIBRATION_SIZE`] bytes at [`CALIBRATION_REGISTER`], or `None` if the checksums
+    /// don't add up.
+    ///
+    /// Each axis is stored as its max, min and center, 8 bits each: the Nunchuk's from byte 8 after its
+    /// accelerometer's calibration, the Classic Controller's from byte 0. hid-wiimote reports the Nunchuk's sticks
+    /// at full resolution and the Classic Controller's at 6 bits, both centered on 0.
+    pub fn parse(extension: Extension, data: &[u8]) -> Option<ExtensionCalibration> {
+        if data.len() < CALIBRATION_SIZE as usize {
+            return None;
+        }
+
+        let sum = data[..14]
+            .iter()
+            .fold(0u8, |sum, byte| sum.wrapping_add(*byte));
+        if data[14] != sum.wrapping_add(0x55) || data[15] != sum.wrapping_add(0xaa) {
+            return None;
+        }
+
+        let (start, scale): (usize, fn(u8) -> i32) = match extension {
+            Extension::Nunchuk => (8, |byte| byte as i32 - 0x80),
+            Extension::Classic => (0, |byte| (byte >> 2) as i32 - 0x20),
+        };
+
+        let axes = extension
+            .axes()
+            .iter()
+            .enumerate()
+            .filter_map(|(index, axis)| {
+                let offset = start + index * 3;
+                let calibration = AxisCalibration {
+                    center: scale(data[offset + 2]),
+                    min: scale(data[offset + 1]),
+                    max: scale(data[offset]),
+                    deadzone: 0,
+                };
+
+                // A worn or third party extension can pass the checksums with a calibration that makes no sense
+                (calibration.min < calibration.center && calibration.center < calibration.max)
+                    .then_some((axis.name, calibration))
+            })
+            .collect();
+
+        Some(ExtensionCalibration { extension, axes })
+    }
+
+    /// The calibration of the axis called `axis`, if it had a sensible one.
+    pub fn axis(&self, axis: &str) -> Option<AxisCalibration> {
+        self.axes
+            .iter()
+            .find(|(name, _)| *name == axis)
+            .map(|(_, calibration)| *calibration)
+    }
+}
+
+/// Where a trigger is, worked out from its pressure and its click.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct TriggerState {
+    /// The pressure hid-wiimote reports when fully pulled.
+    max: i32,
+    /// How far it has to be pulled to press its button, in `0..=TRIGGER_RANGE`.
+    threshold: i32,
+    pressure: i32,
+    clicked: bool,
+    /// Whether it has clicked without any pressure behind it, so is a Classic Controller Pro's digital trigger.
+    digital: bool,
+}
+
+impl TriggerState {
+    /// `threshold` is how far, from 0.0 to 1.0, it has to be pulled to press its button.
+    pub fn new(max: i32, threshold: f32) -> TriggerState {
+        TriggerState {
+            max: max.max(1),
+            threshold: ((threshold.clamp(0.0, 1.0) * TRIGGER_RANGE as f32).round() as i32).max(1),
+            pressure: 0,
+            clicked: false,
+            digital: false,
+        }
+    }
+
+    pub fn set_pressure(&mut self, raw: i32) {
+        self.pressure = raw;
+    }
+
+    pub fn set_clicked(&mut self, clicked: bool) {
+        if clicked && self.scaled() < self.threshold {
+            self.digital = true;
+        }
+
+        self.clicked = clicked;
+    }
+
+    /// How far it's pulled, from 0 to [`TRIGGER_RANGE`].
+    pub fn axis(&self) -> i32 {
+        match self.digital && self.clicked {
+            true => TRIGGER_RANGE,
+            false => self.scaled(),
+        }
+    }
+
+    /// Whether it's pulled far enough to press its button.
+    pub fn pressed(&self) -> bool {
+        self.axis() >= self.threshold
+    }
+
+    fn scaled(&self) -> i32 {
+        self.pressure.clamp(0, self.max) * TRIGGER_RANGE / self.max
+    }
+}
+
+/// What the Nunchuk's stick is reported as.
+#[derive(Clone, Copy, Debug, Default, Deserialize, Serialize, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum StickMode {
+    /// `ABS_X`/`ABS_Y` on the virtual gamepad.
+    #[default]
+    Analog,
+    /// The Wii Remote's D-pad, as if it had been pressed.
+    Dpad,
+}
+
+/// How far, as a fraction of full deflection, a direction's stick has to come back past its threshold before it's
+/// released, so a stick resting right at the threshold doesn't make the direction flicker.
+const DPAD_HYSTERESIS: f32 = 0.1;
+
+/// The up, down, left and right presses for a calibrated `(x, y)` stick position, with up positive like
+/// hid-wiimote reports it. A direction is pressed past `threshold`, a fraction of full deflection, and stays
+/// pressed (per `held`) until the stick comes back [`DPAD_HYSTERESIS`] below it.
+pub fn directions((x, y): (i32, i32), threshold: f32, held: [bool; 4]) -> [bool; 4] {
+    let pressed = |position: i32, held: bool| {
+        let position = position as f32 / OUTPUT_RANGE as f32;
+        match held {
+            true => position > (threshold - DPAD_HYSTERESIS).max(0.0),
+            false => position > threshold,
+        }
+    };
+
+    [
+        pressed(y, held[0]),
+        pressed(-y, held[1]),
+        pressed(-x, held[2]),
+        pressed(x, held[3]),
+    ]
+}
+
+/// Works out a calibration from readings taken while the axis was left alone and while it was moved around.
+pub fn calibrate(resting: &[i32], moving: &[i32]) -> Option<AxisCalibration> {
+    let center = resting.iter().sum::<i32>() / resting.len().max(1) as i32;
+    let wander = resting.iter().map(|value| (value - center).abs()).max()?;
+
+    Some(AxisCalibration {
+        center,
+        min: moving.iter().chain(resting).copied().min()?,
+        max: moving.iter().chain(resting).copied().max()?,
+        // Leave some room beyond the wander that was seen, it's usually a little worse in use
+        deadzone: wander * 2 + 1,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // The range hid-wiimote advertises for the Classic Controller's trigger pressure
+    const PRESSURE_MAX: i32 = 31;
+
+    // A stick `fraction` of the way over
+    fn deflected(fraction: f32) -> i32 {
+        (fraction * OUTPUT_RANGE as f32).round() as i32
+    }
+
+    #[test]
+    fn dpad_press_hold_and_release() {
+        let none = [false; 4];
+        let right = [false, false, false, true];
+
+        // Pressed only once past the threshold
+        assert_eq!(directions((deflected(0.49), 0), 0.5, none), none);
+        assert_eq!(directions((deflected(0.55), 0), 0.5, none), right);
+
+        // Held anywhere inside the band below it
+        assert_eq!(directions((deflected(0.45), 0), 0.5, right), right);
+        assert_eq!(directions((deflected(0.41), 0), 0.5, right), right);
+
+        // Released once back past the band
+        assert_eq!(directions((deflected(0.39), 0), 0.5, right), none);
+        assert_eq!(directions((deflected(0.45), 0), 0.5, none), none);
+    }
+
+    #[test]
+    fn dpad_directions() {
+        let none = [false; 4];
+        let full = OUTPUT_RANGE;
+        assert_eq!(
+            directions((0, full), 0.5, none),
+            [true, false, false, false]
+        );
+        assert_eq!(
+            directions((0, -full), 0.5, none),
+            [false, true, false, false]
+        );
+        assert_eq!(
+            directions((-full, 0), 0.5, none),
+            [false, false, true, false]
+        );
+        assert_eq!(
+            directions((full, full), 0.5, none),
+            [true, false, false, true]
+        );
+    }
+
+    // A stick resting off-center, with less travel to the left than to the right
+    const OFF_CENTER: AxisCalibration = AxisCalibration {
+        center: 10,
+        min: -70,
+        max: 110,
+        deadzone: 5,
+    };
+
+    #[test]
+    fn deadzone_boundaries() {
+        assert_eq!(OFF_CENTER.apply(10), 0);
+        assert_eq!(OFF_CENTER.apply(15), 0);
+        assert_eq!(OFF_CENTER.apply(5), 0);
+
+        // Just past the edge is the first step of travel, not a jump
+        assert_eq!(OFF_CENTER.apply(16), OUTPUT_RANGE / 95);
+        assert_eq!(OFF_CENTER.apply(4), -(OUTPUT_RANGE / 75));
+    }
+
+    #[test]
+    fn each_side_reaches_its_end() {
+        assert_eq!(OFF_CENTER.apply(110), OUTPUT_RANGE);
+        assert_eq!(OFF_CENTER.apply(-70), -OUTPUT_RANGE);
+
+        // Readings past the calibrated ends are held to them
+        assert_eq!(OFF_CENTER.apply(200), OUTPUT_RANGE);
+        assert_eq!(OFF_CENTER.apply(-200), -OUTPUT_RANGE);
+    }
+
+    #[test]
+    fn deadzone_covering_a_side_leaves_it_at_rest() {
+        let calibration = AxisCalibration {
+            deadzone: 100,
+            ..OFF_CENTER
+        };
+        assert_eq!(calibration.apply(110), 0);
+        assert_eq!(calibration.apply(-70), 0);
+    }
+
+    #[test]
+    fn calibration_from_samples() {
+        assert_eq!(
+            calibrate(&[10, 12, 8, 10], &[-70, 40, 110, 0]),
+            Some(OFF_CENTER)
+        );
+        assert_eq!(calibrate(&[], &[-70, 110]), None);
+    }
+
+    #[test]
+    fn trigger_pressure_scales_to_the_axis() {
+        let mut trigger = TriggerState::new(PRESSURE_MAX, 0.5);
+        assert_eq!(trigger.axis(), 0);
+
+        trigger.set_pressure(PRESSURE_MAX);
+        assert_eq!(trigger.axis(), TRIGGER_RANGE);
+
+        // Readings past either end are held to the range
+        trigger.set_pressure(PRESSURE_MAX * 2);
+        assert_eq!(trigger.axis(), TRIGGER_RANGE);
+        trigger.set_pressure(-5);
+        assert_eq!(trigger.axis(), 0);
+    }
+
+    #[test]
+    fn trigger_presses_at_the_threshold_and_releases_below_it() {
+        let mut trigger = TriggerState::new(255, 0.5);
+
+        trigger.set_pressure(127);
+        assert!(!trigger.pressed());
+        trigger.set_pressure(128);
+        assert!(trigger.pressed());
+
+        // The click at the end of an analog trigger's travel doesn't make it digital
+        trigger.set_pressure(255);
+        trigger.set_clicked(true);
+        trigger.set_clicked(false);
+        trigger.set_pressure(100);
+        assert!(!trigger.pressed());
+        assert_eq!(trigger.axis(), 100);
+    }
+
+    #[test]
+    fn trigger_clicking_without_pressure_is_digital() {
+        let mut trigger = TriggerState::new(PRESSURE_MAX, 0.5);
+
+        trigger.set_clicked(true);
+        assert_eq!(trigger.axis(), TRIGGER_RANGE);
+        assert!(trigger.pressed());
+
+        trigger.set_clicked(false);
+        assert_eq!(trigger.axis(), 0);
+        assert!(!trigger.pressed());
+    }
+
+    #[test]
+    fn trigger_threshold_of_zero_still_needs_a_pull() {
+        let mut trigger = TriggerState::new(PRESSURE_MAX, 0.0);
+        assert!(!trigger.pressed());
+
+        trigger.set_pressure(1);
+        assert!(trigger.pressed());
+    }
+}
@@ -0,0 +1,104 @@
+//! Coalescing the accelerometer and IR camera reports, which each come in about 100 times a second, so they don't
+//! flood whatever reads the virtual devices.
+//!
+//! A reading that arrives too soon after the last one forwarded is held back, and the latest one held back is
+//! forwarded once the interval has passed, so only the readings in between are dropped. Buttons never go through
+//! this, every press and release is forwarded right away.
+
+use std::time::{Duration, Instant};
+
+pub struct Throttle {
+    interval: Duration,
+    // When a reading was last let through
+    last: Option<Instant>,
+    // Whether a reading was held back since then
+    pending: bool,
+}
+
+impl Throttle {
+    /// Lets `rate` readings a second through, a rate of 0 lets every reading through.
+    pub fn new(rate: u32) -> Throttle {
+        Throttle {
+            interval: match rate {
+                0 => Duration::ZERO,
+                rate => Duration::from_secs(1) / rate,
+            },
+            last: None,
+            pending: false,
+        }
+    }
+
+    /// Whether a reading arriving at `now` is forwarded, otherwise it's held back until [`Throttle::take_due`].
+    pub fn ready(&mut self, now: Instant) -> bool {
+        if self.last.is_some_and(|last| now < last + self.interval) {
+            self.pending = true;
+            return false;
+        }
+
+        self.last = Some(now);
+        self.pending = false;
+        true
+    }
+
+    /// Whether the reading held back last is due at `now`, for when no newer reading has come in to replace it.
+    pub fn take_due(&mut self, now: Instant) -> bool {
+        match self.pending {
+            true => self.ready(now),
+            false => false,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ms(millis: u64) -> Duration {
+        Duration::from_millis(millis)
+    }
+
+    #[test]
+    fn drops_readings_within_the_interval() {
+        let start = Instant::now();
+        let mut throttle = Throttle::new(20);
+
+        assert!(throttle.ready(start));
+        assert!(!throttle.ready(start + ms(10)));
+        assert!(!throttle.ready(start + ms(49)));
+        assert!(throttle.ready(start + ms(50)));
+    }
+
+    #[test]
+    fn lets_the_last_reading_through() {
+        let start = Instant::now();
+        let mut throttle = Throttle::new(20);
+
+        assert!(throttle.ready(start));
+        assert!(!throttle.ready(start + ms(10)));
+        assert!(!throttle.take_due(start + ms(49)));
+        assert!(throttle.take_due(start + ms(50)));
+
+        // Only once, there's nothing left held back
+        assert!(!throttle.take_due(start + ms(200)));
+    }
+
+    #[test]
+    fn nothing_is_due_without_a_reading_held_back() {
+        let start = Instant::now();
+        let mut throttle = Throttle::new(20);
+
+        assert!(!throttle.take_due(start));
+        assert!(throttle.ready(start));
+        assert!(!throttle.take_due(start + ms(100)));
+    }
+
+    #[test]
+    fn rate_of_zero_lets_everything_through() {
+        let start = Instant::now();
+        let mut throttle = Throttle::new(0);
+
+        assert!(throttle.ready(start));
+        assert!(throttle.ready(start));
+        assert!(throttle.ready(start + ms(1)));
+    }
+}
@@ -0,0 +1,283 @@
+//! Virtual input devices created through `/dev/uinput`.
+
+use std::{
+    ffi::c_int,
+    fs::{File, OpenOptions},
+    io::{self, Write},
+    mem,
+    os::{fd::AsRawFd, unix::fs::OpenOptionsExt},
+    slice,
+};
+
+use libc::{
+    input_absinfo, input_event, input_id, uinput_abs_setup, uinput_setup, Ioctl, _IO, _IOW,
+    UINPUT_MAX_NAME_SIZE,
+};
+
+use serde::{Deserialize, Serialize};
+
+use crate::evdev::{BUS_VIRTUAL, EV_ABS, EV_KEY, EV_REL, EV_SYN, SYN_REPORT};
+
+const UI_DEV_CREATE: Ioctl = _IO(b'U' as u32, 1);
+const UI_DEV_DESTROY: Ioctl = _IO(b'U' as u32, 2);
+const UI_DEV_SETUP: Ioctl = _IOW::<uinput_setup>(b'U' as u32, 3);
+const UI_ABS_SETUP: Ioctl = _IOW::<uinput_abs_setup>(b'U' as u32, 4);
+const UI_SET_EVBIT: Ioctl = _IOW::<c_int>(b'U' as u32, 100);
+const UI_SET_KEYBIT: Ioctl = _IOW::<c_int>(b'U' as u32, 101);
+const UI_SET_RELBIT: Ioctl = _IOW::<c_int>(b'U' as u32, 102);
+const UI_SET_ABSBIT: Ioctl = _IOW::<c_int>(b'U' as u32, 103);
+
+// The pid.codes vendor ID for open source projects, so games don't mistake us for a real Wii Remote
+const VENDOR_ID: u16 = 0x1209;
+const PRODUCT_ID: u16 = 0xb1ee;
+
+/// The bus and IDs a virtual device reports, BlueWii's own unless the config file's `[gamepad_ids]` says otherwise.
+///
+/// Some games only accept controllers they have on a list, and copying the IDs of one on it, like
+/// `bustype = 0x03`, `vendor = 0x045e` and `product = 0x028e` for a wired Xbox 360 controller, can get them to take
+/// the virtual gamepad. Its buttons and axes stay the same though, so the game may well map them wrong.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct DeviceIds {
+    /// `BUS_*` from `input.h`, like `0x03` for USB or `0x05` for Bluetooth.
+    pub bustype: u16,
+    pub vendor: u16,
+    pub product: u16,
+    pub version: u16,
+}
+
+impl Default for DeviceIds {
+    fn default() -> DeviceIds {
+        DeviceIds {
+            bustype: BUS_VIRTUAL,
+            vendor: VENDOR_ID,
+            product: PRODUCT_ID,
+            version: 1,
+        }
+    }
+}
+
+/// An `EV_ABS` axis and the range of values it reports.
+pub struct AbsAxis {
+    pub code: u16,
+    pub min: i32,
+    pub max: i32,
+}
+
+/// What a remote's virtual devices are called, with `{slot}` standing in for its player number and `{mac}` for
+/// its Bluetooth address. Devices other than the gamepad get what they are appended, like `" Keyboard"`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct NameTemplate(String);
+
+impl NameTemplate {
+    pub const DEFAULT: &'static str = "BlueWii Player {slot}";
+
+    /// Checks that `template` only uses placeholders [`NameTemplate::expand`] knows, and that every `{` is closed.
+    pub fn parse(template: &str) -> Result<NameTemplate, String> {
+        let mut rest = template;
+        while let Some(start) = rest.find('{') {
+            let end = rest[start..]
+                .find('}')
+                .ok_or_else(|| format!("`{}' has a `{{' that's never closed", template))?;
+
+            match &rest[start + 1..start + end] {
+                "slot" | "mac" => rest = &rest[start + end + 1..],
+                placeholder => {
+                    return Err(format!(
+                        "`{{{}}}' isn't a placeholder, only `{{slot}}' and `{{mac}}' are",
+                        placeholder
+                    ))
+                }
+            }
+        }
+
+        Ok(NameTemplate(template.to_owned()))
+    }
+
+    /// The name of player `player_slot`'s devices, whose remote is at `address`.
+    pub fn expand(&self, player_slot: u8, address: &str) -> String {
+        self.0
+            .replace("{slot}", &player_slot.to_string())
+            .replace("{mac}", address)
+    }
+}
+
+impl Default for NameTemplate {
+    fn default() -> NameTemplate {
+        NameTemplate(NameTemplate::DEFAULT.to_owned())
+    }
+}
+
+/// Everything a virtual device is able to emit.
+#[derive(Default)]
+pub struct Capabilities {
+    pub keys: Vec<u16>,
+    pub relative: Vec<u16>,
+    pub absolute: Vec<AbsAxis>,
+}
+
+/// A uinput device, destroyed again when dropped.
+pub struct VirtualDevice {
+    file: File,
+}
+
+impl VirtualDevice {
+    /// Registers a device called `name` that can emit the given `capabilities`.
+    pub fn create(name: &str, capabilities: &Capabilities) -> io::Result<VirtualDevice> {
+        VirtualDevice::create_with_ids(name, capabilities, DeviceIds::default())
+    }
+
+    /// Like [`VirtualDevice::create`], but reporting `ids` instead of BlueWii's own.
+    pub fn create_with_ids(
+        name: &str,
+        capabilities: &Capabilities,
+        ids: DeviceIds,
+    ) -> io::Result<VirtualDevice> {
+        let file = OpenOptions::new()
+            .write(true)
+            .custom_flags(libc::O_NONBLOCK)
+            .open("/dev/uinput")?;
+        let device = VirtualDevice { file };
+
+        if !capabilities.keys.is_empty() {
+            device.ioctl(UI_SET_EVBIT, EV_KEY as c_int)?;
+        }
+
+        for key in &capabilities.keys {
+            device.ioctl(UI_SET_KEYBIT, *key as c_int)?;
+        }
+
+        if !capabilities.relative.is_empty() {
+            device.ioctl(UI_SET_EVBIT, EV_REL as c_int)?;
+        }
+
+        for code in &capabilities.relative {
+            device.ioctl(UI_SET_RELBIT, *code as c_int)?;
+        }
+
+        if !capabilities.absolute.is_empty() {
+            device.ioctl(UI_SET_EVBIT, EV_ABS as c_int)?;
+        }
+
+        for axis in &capabilities.absolute {
+            device.ioctl(UI_SET_ABSBIT, axis.code as c_int)?;
+
+            let abs_setup = uinput_abs_setup {
+                code: axis.code,
+                absinfo: input_absinfo {
+                    value: 0,
+                    minimum: axis.min,
+                    maximum: axis.max,
+                    fuzz: 0,
+                    flat: 0,
+                    resolution: 0,
+                },
+            };
+
+            if unsafe { libc::ioctl(device.file.as_raw_fd(), UI_ABS_SETUP, &abs_setup) } < 0 {
+                return Err(io::Error::last_os_error());
+            }
+        }
+
+        let mut setup = uinput_setup {
+            id: input_id {
+                bustype: ids.bustype,
+                vendor: ids.vendor,
+                product: ids.product,
+                version: ids.version,
+            },
+            name: [0; UINPUT_MAX_NAME_SIZE],
+            ff_effects_max: 0,
+        };
+
+        // Leave room for the null terminator
+        for (dst, src) in setup
+            .name
+            .iter_mut()
+            .zip(name.bytes().take(UINPUT_MAX_NAME_SIZE - 1))
+        {
+            *dst = src as _;
+        }
+
+        if unsafe { libc::ioctl(device.file.as_raw_fd(), UI_DEV_SETUP, &setup) } < 0 {
+            return Err(io::Error::last_os_error());
+        }
+
+        if unsafe { libc::ioctl(device.file.as_raw_fd(), UI_DEV_CREATE) } < 0 {
+            return Err(io::Error::last_os_error());
+        }
+
+        Ok(device)
+    }
+
+    pub fn emit(&mut self, event_type: u16, code: u16, value: i32) -> io::Result<()> {
+        let event = input_event {
+            time: libc::timeval {
+                tv_sec: 0,
+                tv_usec: 0,
+            },
+            type_: event_type,
+            code,
+            value,
+        };
+
+        // The kernel expects the raw struct, one event per write
+        let bytes = unsafe {
+            slice::from_raw_parts(
+                &event as *const input_event as *const u8,
+                mem::size_of::<input_event>(),
+            )
+        };
+
+        self.file.write_all(bytes)
+    }
+
+    pub fn sync(&mut self) -> io::Result<()> {
+        self.emit(EV_SYN, SYN_REPORT, 0)
+    }
+
+    fn ioctl(&self, request: Ioctl, value: c_int) -> io::Result<()> {
+        match unsafe { libc::ioctl(self.file.as_raw_fd(), request, value) } {
+            ret if ret < 0 => Err(io::Error::last_os_error()),
+            _ => Ok(()),
+        }
+    }
+}
+
+impl Drop for VirtualDevice {
+    // Also runs while a panic unwinds, and the kernel removes the device anyway once `/dev/uinput` is closed,
+    // so a device only outlives BlueWii if the fd does
+    fn drop(&mut self) {
+        unsafe {
+            libc::ioctl(self.file.as_raw_fd(), UI_DEV_DESTROY);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{io::Read, os::fd::OwnedFd, thread};
+
+    use super::*;
+    use crate::evdev::KEY_ENTER;
+
+    #[test]
+    fn device_is_released_when_its_thread_panics() {
+        // A pipe stands in for `/dev/uinput`, its reader sees the end of the file once the device lets go of it
+        let (mut reader, writer) = io::pipe().unwrap();
+        let mut device = VirtualDevice {
+            file: File::from(OwnedFd::from(writer)),
+        };
+
+        let forwarding = thread::spawn(move || {
+            device.emit(EV_KEY, KEY_ENTER, 1).unwrap();
+            panic!("forwarding failed");
+        });
+
+        assert!(forwarding.join().is_err());
+
+        let mut written = Vec::new();
+        reader.read_to_end(&mut written).unwrap();
+        assert_eq!(written.len(), mem::size_of::<input_event>());
+    }
+}
@@ -1,9 +1,32 @@
 use std::{
+    borrow::Cow,
+    ffi::OsString,
     fmt::{Debug, Display},
-    process::exit,
+    fs,
+    io::{self, ErrorKind, Read},
+    path::Path,
+    process::{exit, Command, Output, Stdio},
+    thread,
+    time::{Duration, Instant},
 };
 
-use log::error;
+use log::{debug, error};
+
+use crate::error::{self, BlueWiiError};
+
+// Exit codes, shared by every mode so scripts can tell them apart, `--help` lists them too:
+//
+// 0  success, or `--check` found a remote connected
+// 1  anything else went wrong, or `--check` found no remote connected
+// 2  `--check` couldn't check, because `bluetoothctl` is missing or a device can't be opened
+// 3  a required external binary isn't installed
+// 4  `--fail-fast` found no Wii Remote to connect to
+
+/// Exit code used when a required external binary (`bluetoothctl`, `xwiishow`) isn't installed.
+pub const EXIT_MISSING_DEPENDENCY: i32 = 3;
+
+/// Exit code used when `--fail-fast` gives up after the first attempt to connect.
+pub const EXIT_NO_REMOTE: i32 = 4;
 
 pub trait FormattedUnwrap<T> {
     fn unwrap_or_fmt(self) -> T;
@@ -21,3 +44,145 @@ impl<T, E: Display + Debug> FormattedUnwrap<T> for Result<T, E> {
         }
     }
 }
+
+pub trait MissingBinaryExit {
+    /// Exits with [`EXIT_MISSING_DEPENDENCY`] if `binary` couldn't be found, pointing the user at `package`.
+    fn exit_if_missing(self, binary: &str, package: &str) -> Self;
+}
+
+impl<T> MissingBinaryExit for io::Result<T> {
+    fn exit_if_missing(self, binary: &str, package: &str) -> Self {
+        if let Err(err) = &self {
+            exit_if_not_found(err, binary, package);
+        }
+
+        self
+    }
+}
+
+impl<T> MissingBinaryExit for error::Result<T> {
+    fn exit_if_missing(self, binary: &str, package: &str) -> Self {
+        if let Err(BlueWiiError::SpawnFailed { source, .. }) = &self {
+            exit_if_not_found(source, binary, package);
+        }
+
+        self
+    }
+}
+
+fn exit_if_not_found(err: &io::Error, binary: &str, package: &str) {
+    if err.kind() == ErrorKind::NotFound {
+        error!(
+            "Could not find `{}'. Install the `{}' package or pass the path to the executable on the command line.",
+            binary, package
+        );
+        exit(EXIT_MISSING_DEPENDENCY);
+    }
+}
+
+/// Like [`Command::output`], but kills the child once `timeout` runs out, returning `None` after reaping it.
+pub fn output_with_timeout(command: &mut Command, timeout: Duration) -> io::Result<Option<Output>> {
+    let mut child = command
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()?;
+
+    // Drain the pipes on other threads, so a chatty child can't fill them up and never exit
+    let drain = |pipe: Option<Box<dyn Read + Send>>| {
+        thread::spawn(move || {
+            let mut output = Vec::new();
+            if let Some(mut pipe) = pipe {
+                let _ = pipe.read_to_end(&mut output);
+            }
+
+            output
+        })
+    };
+
+    let stdout = drain(child.stdout.take().map(|pipe| Box::new(pipe) as _));
+    let stderr = drain(child.stderr.take().map(|pipe| Box::new(pipe) as _));
+
+    let deadline = Instant::now() + timeout;
+    let status = loop {
+        if let Some(status) = child.try_wait()? {
+            break status;
+        }
+
+        if Instant::now() >= deadline {
+            let _ = child.kill();
+            let _ = child.wait();
+            return Ok(None);
+        }
+
+        thread::sleep(Duration::from_millis(50));
+    };
+
+    Ok(Some(Output {
+        status,
+        stdout: stdout.join().unwrap_or_default(),
+        stderr: stderr.join().unwrap_or_default(),
+    }))
+}
+
+/// Decodes what `command` printed, replacing anything that isn't UTF-8 instead of failing, since some locales and
+/// escape sequences produce stray bytes that shouldn't take the daemon down.
+pub fn decode_output<'a>(bytes: &'a [u8], command: &str) -> Cow<'a, str> {
+    let decoded = String::from_utf8_lossy(bytes);
+    if let Cow::Owned(_) = decoded {
+        debug!("`{}' printed invalid UTF-8, replacing it", command);
+    }
+
+    decoded
+}
+
+/// Writes `contents` to a file next to `path` and renames it over `path`, so anything reading `path` sees either
+/// the old contents or the new ones and never half of them.
+pub fn write_atomically(path: &Path, contents: &str) -> io::Result<()> {
+    let mut temporary = OsString::from(path.as_os_str());
+    temporary.push(".tmp");
+
+    fs::write(&temporary, contents)?;
+    fs::rename(&temporary, path).inspect_err(|_| {
+        let _ = fs::remove_file(&temporary);
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::wii_remote::{parse_xwiishow_list, WiiRemote};
+
+    #[test]
+    fn invalid_utf8_is_replaced() {
+        assert!(matches!(
+            decode_output(b"Connected: yes", "test"),
+            Cow::Borrowed("Connected: yes")
+        ));
+        assert_eq!(
+            decode_output(b"Name: Wii\xffmote", "test"),
+            "Name: Wii\u{fffd}mote"
+        );
+    }
+
+    #[test]
+    fn parsers_run_on_invalid_utf8() {
+        let devices = decode_output(
+            b"Device 00:19:1D:12:34:56 Nintendo \xc3\x28RVL-CNT-01\nDevice 00:19:1D:AB:CD:EF Caf\xe9\n",
+            "bluetoothctl devices",
+        );
+        let addresses = devices
+            .lines()
+            .filter_map(WiiRemote::parse_address)
+            .collect::<Vec<_>>();
+        assert_eq!(addresses, ["00:19:1D:12:34:56", "00:19:1D:AB:CD:EF"]);
+
+        let list = decode_output(
+            b"Listing connected Wii Remote devices:\n\x1b[1m\xfe\n  Found device #1: /sys/devices/virtual/misc/uhid/0005:057E:0306.0006\n",
+            "xwiishow list",
+        );
+        assert_eq!(
+            parse_xwiishow_list(&list),
+            ["/sys/devices/virtual/misc/uhid/0005:057E:0306.0006"]
+        );
+    }
+}
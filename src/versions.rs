@@ -0,0 +1,97 @@
+//! Detects the versions of the external tools BlueWii parses the output of, since that output changes
+//! between releases.
+
+use std::{io::ErrorKind, ops::RangeInclusive, process::Command};
+
+use log::{info, warn};
+
+/// `bluetoothctl` releases whose output BlueWii is known to parse. Older ones exit with 0 even when a
+/// connection fails, and don't print "Connection successful".
+pub const KNOWN_GOOD_BLUETOOTHCTL: RangeInclusive<(u32, u32)> = (5, 50)..=(5, 99);
+
+pub enum ToolVersion {
+    Missing,
+    /// The tool ran but didn't print anything that looks like a version, xwiishow has no `--version`.
+    Unknown,
+    Version(u32, u32),
+}
+
+/// Runs `<binary> --version` and picks out the first `major.minor` it prints.
+pub fn detect(binary: &str) -> ToolVersion {
+    let output = match Command::new(binary).arg("--version").output() {
+        Ok(output) => output,
+        Err(err) if err.kind() == ErrorKind::NotFound => return ToolVersion::Missing,
+        Err(_) => return ToolVersion::Unknown,
+    };
+
+    // Some tools print their version (or usage) to stderr
+    let text = format!(
+        "{}{}",
+        String::from_utf8_lossy(&output.stdout),
+        String::from_utf8_lossy(&output.stderr)
+    );
+
+    match parse_version(&text) {
+        Some((major, minor)) => ToolVersion::Version(major, minor),
+        None => ToolVersion::Unknown,
+    }
+}
+
+/// Finds the first `major.minor` in `text`, like the `5.66` in `bluetoothctl: 5.66`.
+pub fn parse_version(text: &str) -> Option<(u32, u32)> {
+    text.split(|c: char| !c.is_ascii_digit() && c != '.')
+        .find_map(|token| {
+            let mut parts = token.split('.');
+            let major = parts.next()?.parse().ok()?;
+            let minor = parts.next()?.parse().ok()?;
+            Some((major, minor))
+        })
+}
+
+/// Logs the versions of `bluetoothctl` and `xwiishow`, returning `false` if either is missing or
+/// `bluetoothctl` is outside [`KNOWN_GOOD_BLUETOOTHCTL`].
+pub fn check(bluetoothctl_path: &str, xwiishow_path: &str) -> bool {
+    let bluetoothctl_ok = match detect(bluetoothctl_path) {
+        ToolVersion::Missing => {
+            warn!("`{}' is not installed", bluetoothctl_path);
+            false
+        }
+        ToolVersion::Unknown => {
+            warn!("Could not detect the version of `{}'", bluetoothctl_path);
+            true
+        }
+        ToolVersion::Version(major, minor) if KNOWN_GOOD_BLUETOOTHCTL.contains(&(major, minor)) => {
+            info!("Found bluetoothctl {}.{}", major, minor);
+            true
+        }
+        ToolVersion::Version(major, minor) => {
+            warn!(
+                "Found bluetoothctl {}.{}, which BlueWii hasn't been tested with (known good: {}.{} to {}.{})",
+                major,
+                minor,
+                KNOWN_GOOD_BLUETOOTHCTL.start().0,
+                KNOWN_GOOD_BLUETOOTHCTL.start().1,
+                KNOWN_GOOD_BLUETOOTHCTL.end().0,
+                KNOWN_GOOD_BLUETOOTHCTL.end().1
+            );
+            false
+        }
+    };
+
+    let xwiishow_ok = match detect(xwiishow_path) {
+        ToolVersion::Missing => {
+            warn!("`{}' is not installed", xwiishow_path);
+            false
+        }
+        ToolVersion::Unknown => {
+            info!("Found xwiishow (it doesn't report a version)");
+            true
+        }
+        ToolVersion::Version(major, minor) => {
+            info!("Found xwiishow {}.{}", major, minor);
+            true
+        }
+    };
+
+    bluetoothctl_ok && xwiishow_ok
+}
@@ -1,140 +1,2264 @@
 use std::{
-    io::{BufRead, BufReader},
-    process::{Command, Stdio},
+    cell::RefCell,
+    fs,
+    io::{self, BufRead, BufReader},
+    path::{Path, PathBuf},
+    process::{Command, Output},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        mpsc::{self, RecvTimeoutError},
+        Arc, Mutex,
+    },
+    thread,
+    time::{Duration, Instant},
 };
 
 use anyhow::Context;
+use log::{debug, info, warn};
 
-use crate::utils::FormattedUnwrap;
+use crate::{
+    accel::{self, AccelCalibration},
+    bluetoothctl::{self, Bluetoothctl},
+    device_line::DeviceLineParser,
+    error::{self, BlueWiiError},
+    events::{Interfaces, WiiEvent, WiiState},
+    identity, ir, rumble, speaker,
+    stick::{self, ExtensionCalibration},
+    utils::{decode_output, FormattedUnwrap, MissingBinaryExit},
+    xwiimote,
+};
+
+/// How long `bluetoothctl -t 30 scan on' is asked to scan for.
+const SCAN_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// How much longer the scan may take before it's killed.
+const SCAN_GRACE: Duration = Duration::from_secs(5);
+
+/// The shortest wait between checks in `wait_for_connection`, for when the connection cache is turned off.
+const MIN_CONNECTION_POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+/// How often a running scan checks whether a remote was connected some other way.
+const HOTPLUG_INTERVAL: Duration = Duration::from_secs(1);
+
+/// How often `wait_for_udev_device_path` asks `xwiishow list` again.
+const DEVICE_PATH_POLL_INTERVAL: Duration = Duration::from_millis(200);
+
+/// How long the `xwiimote` connect backend waits for hid-wiimote to pick a remote up after each connect.
+const BIND_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// How many times the `xwiimote` connect backend connects before giving up on a remote that keeps dropping.
+const BIND_ATTEMPTS: u32 = 3;
+
+/// How long `bluetoothctl power on` or `off` may take, the controller answers in well under a second when it's fine.
+const ADAPTER_POWER_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// How long the controller stays off in [`WiiRemote::reset_adapter`], some don't come back if it's turned straight
+/// back on.
+const ADAPTER_RESET_PAUSE: Duration = Duration::from_secs(1);
+
+/// How long [`WiiRemote::pair`] waits on the remote, long enough to find and press its SYNC button.
+const PAIR_TIMEOUT: Duration = Duration::from_secs(30);
+
+// Addresses of the remotes some `WiiRemote` has claimed, which every other one leaves alone when looking for one
+static CLAIMED: Mutex<Vec<String>> = Mutex::new(Vec::new());
+
+// When the last scan `try_connect` started ended, shared since every `WiiRemote` scans on the same adapter
+static LAST_SCAN: Mutex<Option<Instant>> = Mutex::new(None);
+
+// When each remote some `WiiRemote` connected or adopted last did, so `try_connect` can go back to the latest one
+static LAST_CONNECTED: Mutex<Vec<(String, Instant)>> = Mutex::new(Vec::new());
 
 pub struct WiiRemote {
     pub bluetooth_address: String,
+    pub udev_device_path: Option<String>,
+    pub bluetoothctl_path: String,
+    /// The Bluetooth controller to use instead of the default one, by address, see [`bluetoothctl`].
+    pub adapter: Option<String>,
+    pub xwiishow_path: String,
+    /// How long `connected_addresses` trusts its last answer before asking `bluetoothctl` again.
+    pub connection_cache_ttl: Duration,
+    // When `connected_addresses` last asked `bluetoothctl`, and what it found
+    connection_cache: RefCell<Option<(Instant, Vec<String>)>>,
+    /// How long `bluetoothctl connect` may take before it's killed, it can hang while the adapter is busy.
+    pub connect_timeout: Duration,
+    /// What decides whether [`WiiRemote::connect`] worked.
+    pub connect_backend: ConnectBackend,
+    /// How long `try_connect` leaves the adapter idle after a scan before starting another, see
+    /// [`WiiRemote::try_connect`].
+    pub scan_cooldown: Duration,
+    /// The only addresses that are ever connected, any address when empty.
+    pub allow: Vec<String>,
+    /// Addresses that are never connected, even when they're also in `allow`.
+    pub deny: Vec<String>,
+    /// How the lines `bluetoothctl` prints about each device are read, see [`device_line`](crate::device_line).
+    pub device_line: DeviceLineParser,
+    /// Whether `try_connect` connects to the remote with the strongest signal instead of the one that connected
+    /// most recently.
+    ///
+    /// The signal strength comes from the text `bluetoothctl scan on` prints, which only mentions it when it
+    /// changes, and not at all for some adapters and BlueZ versions. Remotes it never mentioned rank below all
+    /// others, and with no signal strength at all this is the same as picking the first one found.
+    pub prefer_strongest: bool,
+    /// Whether `try_connect` tries every paired remote bluez knows about before scanning for one, since a paired
+    /// remote that's turned on reconnects without being discoverable.
+    pub prefer_paired: bool,
+    // What `poll_events` reads from, reopened whenever `udev_device_path` changes
+    events: RefCell<Option<Interfaces>>,
+    // What `accel_calibration` read, and for which syspath
+    accel_calibration: RefCell<Option<(String, AccelCalibration)>>,
+    // Set while `sweep_leds` or `blink_leds` is running, so the LEDs aren't mistaken for having been changed
+    leds_animating: Arc<AtomicBool>,
+    // The address this remote holds in `CLAIMED`, if it claimed one
+    claim: Option<String>,
 }
 
-impl WiiRemote {
-    pub const fn new() -> WiiRemote {
+impl Default for WiiRemote {
+    fn default() -> WiiRemote {
+        WiiRemote::new()
+    }
+}
+
+/// Configures a [`WiiRemote`] before creating it, every setting it leaves alone keeps its default.
+///
+/// One builder can create any number of remotes that share the same settings, like one for each player, and
+/// each of them still claims a remote of its own.
+#[derive(Clone, Debug)]
+pub struct WiiRemoteBuilder {
+    bluetoothctl_path: String,
+    xwiishow_path: String,
+    adapter: Option<String>,
+    connection_cache_ttl: Duration,
+    connect_timeout: Duration,
+    connect_backend: ConnectBackend,
+    scan_cooldown: Duration,
+    allow: Vec<String>,
+    deny: Vec<String>,
+    prefer_strongest: bool,
+    prefer_paired: bool,
+    device_line: DeviceLineParser,
+}
+
+impl Default for WiiRemoteBuilder {
+    fn default() -> WiiRemoteBuilder {
+        WiiRemoteBuilder {
+            bluetoothctl_path: "bluetoothctl".to_owned(),
+            xwiishow_path: "xwiishow".to_owned(),
+            adapter: None,
+            connection_cache_ttl: Duration::from_secs(2),
+            connect_timeout: Duration::from_secs(20),
+            connect_backend: ConnectBackend::default(),
+            scan_cooldown: Duration::ZERO,
+            allow: Vec::new(),
+            deny: Vec::new(),
+            prefer_strongest: false,
+            prefer_paired: false,
+            device_line: DeviceLineParser::default(),
+        }
+    }
+}
+
+impl WiiRemoteBuilder {
+    /// See [`WiiRemote::bluetoothctl_path`].
+    pub fn bluetoothctl_path(mut self, path: &str) -> Self {
+        self.bluetoothctl_path = path.to_owned();
+        self
+    }
+
+    /// See [`WiiRemote::xwiishow_path`].
+    pub fn xwiishow_path(mut self, path: &str) -> Self {
+        self.xwiishow_path = path.to_owned();
+        self
+    }
+
+    /// See [`WiiRemote::adapter`].
+    pub fn adapter(mut self, adapter: Option<String>) -> Self {
+        self.adapter = adapter;
+        self
+    }
+
+    /// See [`WiiRemote::connection_cache_ttl`].
+    pub fn connection_cache_ttl(mut self, ttl: Duration) -> Self {
+        self.connection_cache_ttl = ttl;
+        self
+    }
+
+    /// See [`WiiRemote::connect_timeout`].
+    pub fn connect_timeout(mut self, timeout: Duration) -> Self {
+        self.connect_timeout = timeout;
+        self
+    }
+
+    /// See [`WiiRemote::connect_backend`].
+    pub fn connect_backend(mut self, backend: ConnectBackend) -> Self {
+        self.connect_backend = backend;
+        self
+    }
+
+    /// See [`WiiRemote::scan_cooldown`].
+    pub fn scan_cooldown(mut self, cooldown: Duration) -> Self {
+        self.scan_cooldown = cooldown;
+        self
+    }
+
+    /// See [`WiiRemote::allow`].
+    pub fn allow(mut self, addresses: Vec<String>) -> Self {
+        self.allow = addresses;
+        self
+    }
+
+    /// See [`WiiRemote::deny`].
+    pub fn deny(mut self, addresses: Vec<String>) -> Self {
+        self.deny = addresses;
+        self
+    }
+
+    /// See [`WiiRemote::prefer_strongest`].
+    pub fn prefer_strongest(mut self, prefer_strongest: bool) -> Self {
+        self.prefer_strongest = prefer_strongest;
+        self
+    }
+
+    /// See [`WiiRemote::prefer_paired`].
+    pub fn prefer_paired(mut self, prefer_paired: bool) -> Self {
+        self.prefer_paired = prefer_paired;
+        self
+    }
+
+    /// See [`WiiRemote::device_line`].
+    pub fn device_line(mut self, parser: DeviceLineParser) -> Self {
+        self.device_line = parser;
+        self
+    }
+
+    /// Creates a remote with these settings, not connected to anything yet.
+    pub fn build(&self) -> WiiRemote {
         WiiRemote {
             bluetooth_address: String::new(),
+            udev_device_path: None,
+            bluetoothctl_path: self.bluetoothctl_path.clone(),
+            adapter: self.adapter.clone(),
+            xwiishow_path: self.xwiishow_path.clone(),
+            connection_cache_ttl: self.connection_cache_ttl,
+            connection_cache: RefCell::new(None),
+            connect_timeout: self.connect_timeout,
+            connect_backend: self.connect_backend,
+            scan_cooldown: self.scan_cooldown,
+            allow: self.allow.clone(),
+            deny: self.deny.clone(),
+            prefer_strongest: self.prefer_strongest,
+            prefer_paired: self.prefer_paired,
+            device_line: self.device_line.clone(),
+            events: RefCell::new(None),
+            accel_calibration: RefCell::new(None),
+            leds_animating: Arc::new(AtomicBool::new(false)),
+            claim: None,
+        }
+    }
+}
+
+/// How [`WiiRemote::connect`] tells that a remote connected. Finding remotes works the same either way.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum ConnectBackend {
+    /// Whatever `bluetoothctl connect` says.
+    #[default]
+    Bluetoothctl,
+    /// Once hid-wiimote drives the remote, which is what libxwiimote goes by. xwiimote has no way to connect of
+    /// its own, so the link is still brought up with `bluetoothctl connect`, but a remote that drops right after
+    /// is connected again instead of being taken at its word.
+    Xwiimote,
+}
+
+/// A Wii Remote known to `bluetoothctl`, whether or not it's currently connected.
+pub struct DiscoveredRemote {
+    pub address: String,
+    pub name: String,
+    pub paired: bool,
+    pub trusted: bool,
+    pub connected: bool,
+    /// The signal strength in dBm when the remote was last heard from during a scan, if `bluetoothctl` said.
+    pub rssi: Option<i16>,
+}
+
+/// A device `bluetoothctl devices` lists as a Wii Remote, see [`WiiRemote::enumerate_known`].
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct KnownDevice {
+    pub address: String,
+    pub name: String,
+    /// What `bluetoothctl info` says about it, `None` when it failed, like it does for a device bluez has since
+    /// forgotten or, on some versions, one that's out of range.
+    pub info: Option<BluezInfo>,
+}
+
+/// The fields of `bluetoothctl info <address>`, those it left out being `false` or `None`.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct BluezInfo {
+    pub alias: Option<String>,
+    pub paired: bool,
+    /// Whether the pairing keys were stored, only newer BlueZ versions say.
+    pub bonded: Option<bool>,
+    pub trusted: bool,
+    pub blocked: bool,
+    pub connected: bool,
+    /// Like `usb:v057Ep0306d0600`, for telling a genuine remote from a third party one without connecting it.
+    pub modalias: Option<String>,
+    /// The signal strength in dBm, only there while bluez is hearing from the device.
+    pub rssi: Option<i16>,
+}
+
+impl BluezInfo {
+    /// Reads the output of `bluetoothctl info <address>`, or `None` for a device bluez doesn't know about.
+    ///
+    /// The fields look like `\tPaired: yes` under a `Device <address> (public)` line, anything else is skipped.
+    pub fn parse(output: &str) -> Option<BluezInfo> {
+        if output.contains("not available") {
+            return None;
         }
+
+        let mut info = BluezInfo::default();
+        let mut found = false;
+        for line in output.lines() {
+            let (key, value) = match line.trim().split_once(": ") {
+                Some((key, value)) => (key, value.trim()),
+                None => continue,
+            };
+
+            let yes = value == "yes";
+            match key {
+                "Alias" => info.alias = Some(value.to_owned()),
+                "Paired" => info.paired = yes,
+                "Bonded" => info.bonded = Some(yes),
+                "Trusted" => info.trusted = yes,
+                "Blocked" => info.blocked = yes,
+                "Connected" => info.connected = yes,
+                "Modalias" => info.modalias = Some(value.to_owned()),
+                "RSSI" => info.rssi = parse_rssi(line),
+                _ => continue,
+            }
+
+            found = true;
+        }
+
+        found.then_some(info)
+    }
+}
+
+/// The HID IDs embedded in a remote's syspath, e.g. `0005:057E:0306.0006`, and what else the kernel says about it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DeviceInfo {
+    pub bus: u16,
+    pub vendor: u16,
+    pub product: u16,
+    /// The model number at the end of its HID name, see [`model_from_name`]. `None` from [`parse_device_info`],
+    /// which only has the syspath to go on.
+    pub named_model: Option<&'static str>,
+    /// Where the MotionPlus hid-wiimote found is, `None` when it found none or, from [`parse_device_info`], wasn't
+    /// asked.
+    pub motion_plus: Option<MotionPlusSource>,
+    /// Which interfaces hid-wiimote created for it, `None` from [`parse_device_info`].
+    pub capabilities: Option<xwiimote::Capabilities>,
+}
+
+/// Where a remote's gyroscope is.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MotionPlusSource {
+    /// Inside a Wii Remote Plus (`RVL-CNT-01-TR`).
+    BuiltIn,
+    /// In a MotionPlus adapter plugged into an original remote's extension port.
+    External,
+}
+
+impl MotionPlusSource {
+    pub fn name(&self) -> &'static str {
+        match self {
+            MotionPlusSource::BuiltIn => "built-in",
+            MotionPlusSource::External => "external",
+        }
+    }
+}
+
+/// The model number at the end of a name like `Nintendo RVL-CNT-01-TR`, as bluez and the kernel both have it.
+pub fn model_from_name(name: &str) -> Option<&'static str> {
+    let name = name.trim();
+    match (
+        name.ends_with("RVL-CNT-01-TR"),
+        name.ends_with("RVL-CNT-01"),
+    ) {
+        (true, _) => Some("RVL-CNT-01-TR"),
+        (false, true) => Some("RVL-CNT-01"),
+        (false, false) => None,
+    }
+}
+
+/// Nintendo's USB/Bluetooth vendor ID.
+pub const NINTENDO_VENDOR_ID: u16 = 0x057E;
+
+impl DeviceInfo {
+    /// The model number from its name, or else from the product ID if it's one Nintendo made.
+    pub fn model(&self) -> Option<&'static str> {
+        match (self.named_model, self.vendor, self.product) {
+            (Some(model), _, _) => Some(model),
+            (None, NINTENDO_VENDOR_ID, 0x0306) => Some("RVL-CNT-01"),
+            (None, NINTENDO_VENDOR_ID, 0x0330) => Some("RVL-CNT-01-TR"),
+            (None, _, _) => None,
+        }
+    }
+
+    /// Whether it's a Wii Remote Plus, whose MotionPlus is inside it rather than plugged in.
+    pub fn has_builtin_motion_plus(&self) -> bool {
+        self.model() == Some("RVL-CNT-01-TR")
+    }
+
+    /// Whether the remote reports Nintendo's vendor ID, third-party remotes usually copy it but not always.
+    pub fn is_genuine(&self) -> bool {
+        self.vendor == NINTENDO_VENDOR_ID
+    }
+}
+
+impl WiiRemote {
+    fn bluetoothctl(&self) -> Bluetoothctl {
+        Bluetoothctl {
+            path: self.bluetoothctl_path.clone(),
+            adapter: self.adapter.clone(),
+        }
+    }
+
+    /// A remote with every setting at its default, running `bluetoothctl` and `xwiishow` from `PATH`.
+    pub fn new() -> WiiRemote {
+        WiiRemote::builder().build()
+    }
+
+    pub fn with_paths(bluetoothctl_path: &str, xwiishow_path: &str) -> WiiRemote {
+        WiiRemote::builder()
+            .bluetoothctl_path(bluetoothctl_path)
+            .xwiishow_path(xwiishow_path)
+            .build()
+    }
+
+    /// Starts configuring a remote, see [`WiiRemoteBuilder`].
+    pub fn builder() -> WiiRemoteBuilder {
+        WiiRemoteBuilder::default()
     }
 
+    /// Connects to a Wii Remote, adopting one that's already connected or else scanning and connecting to the
+    /// one found that connected most recently, or the first one found when none has connected yet. Remotes claimed
+    /// by another `WiiRemote`, or ruled out by `allow` and `deny`, are skipped.
+    ///
+    /// Scanning keeps the adapter busy enough to disturb other devices on it, like headphones, so a new scan
+    /// waits until `scan_cooldown` has passed since the last one. A remote that connects on its own in the
+    /// meantime is still picked up right away.
     pub fn try_connect(&mut self) -> bool {
-        if WiiRemote::is_connected(self) {
+        if self.adopt_bound_remote() || self.adopt_connected() {
             return true;
         }
 
+        if self.prefer_paired && self.connect_paired() {
+            return true;
+        }
+
+        while let Some(left) = self.scan_cooldown_left() {
+            thread::sleep(left.min(HOTPLUG_INTERVAL));
+            if self.adopt_bound_remote() {
+                return true;
+            }
+        }
+
         // If we're not connected to a Wii Remote, try to connect to one
-        let bluetoothctl_status = Command::new("bluetoothctl")
-            .arg("-t 30")
-            .arg("scan on")
-            .stdout(Stdio::piped())
-            .spawn()
+        self.bluetooth_address = String::new();
+        let remotes = self.scan();
+        *LAST_SCAN.lock().unwrap() = Some(Instant::now());
+
+        // The scan stops early when a remote is connected some other way, through the desktop's Bluetooth settings
+        if self.adopt_bound_remote() {
+            return true;
+        }
+
+        match self.pick(&remotes) {
+            Some(remote) => {
+                let address = remote.address.clone();
+                self.connect(&address).is_ok()
+            }
+            None => false,
+        }
+    }
+
+    // The remote `try_connect` goes for out of a scan's `remotes`
+    fn pick<'a>(&self, remotes: &'a [DiscoveredRemote]) -> Option<&'a DiscoveredRemote> {
+        let candidates = remotes
+            .iter()
+            .filter(|remote| !self.is_off_limits(&remote.address));
+        // Searched from the back so the first remote seen wins a tie, and when nothing is known to rank them by
+        match self.prefer_strongest {
+            true => candidates.rev().max_by_key(|remote| remote.rssi),
+            false => candidates
+                .rev()
+                .max_by_key(|remote| last_connected(&remote.address)),
+        }
+    }
+
+    /// Tries to connect each paired remote in the order `bluetoothctl devices` lists them, stopping at the first
+    /// that connects. Every one that's turned off takes up to [`WiiRemote::connect_timeout`] to give up on.
+    fn connect_paired(&mut self) -> bool {
+        let paired = self
+            .enumerate_known()
+            .into_iter()
+            .filter(|device| {
+                device
+                    .info
+                    .as_ref()
+                    .is_some_and(|info| info.paired && !info.blocked)
+            })
+            .filter(|device| !self.is_off_limits(&device.address))
+            .collect::<Vec<_>>();
+
+        for device in paired {
+            debug!(
+                "Trying paired Wii Remote {} before scanning",
+                device.address
+            );
+            match self.connect(&device.address) {
+                Ok(()) => {
+                    info!("Reconnected paired Wii Remote {}", device.address);
+                    return true;
+                }
+                Err(err) => debug!("{}", err),
+            }
+
+            // Someone may have connected a remote while we were waiting on this one
+            if self.adopt_bound_remote() {
+                return true;
+            }
+        }
+
+        false
+    }
+
+    fn scan_cooldown_left(&self) -> Option<Duration> {
+        let last_scan = (*LAST_SCAN.lock().unwrap())?;
+        self.scan_cooldown
+            .checked_sub(last_scan.elapsed())
+            .filter(|left| !left.is_zero())
+    }
+
+    /// Scans for Wii Remotes that are discoverable, in the order they're found.
+    ///
+    /// Only `address` and `name` are filled in, see [`WiiRemote::known_remotes`] for the rest. The scan ends
+    /// early, returning what it found so far, if hid-wiimote starts driving a remote in the meantime.
+    pub fn scan(&self) -> Vec<DiscoveredRemote> {
+        let (mut bluetoothctl_status, bluetoothctl_status_output) = self
+            .bluetoothctl()
+            .spawn_scan(SCAN_TIMEOUT)
+            .exit_if_missing(&self.bluetoothctl_path, "bluez")
             .context("Failed to execute `bluetoothctl -t 30 scan on'")
             .unwrap_or_fmt();
 
-        // Read the output of the `bluetoothctl -t 30 scan on` command
-        let bluetoothctl_status_output = bluetoothctl_status
-            .stdout
-            .context("Failed to read out of `bluetoothctl -t 30 scan on'")
-            .unwrap_or_fmt();
+        // Read the lines on another thread, so a bluetoothctl that ignores `-t` can't keep us waiting forever
+        let (lines_sender, lines) = mpsc::channel();
+        thread::spawn(move || {
+            for line in BufReader::new(bluetoothctl_status_output).lines() {
+                if lines_sender.send(line).is_err() {
+                    break;
+                }
+            }
+        });
 
         // Read the output of the `bluetoothctl -t 30 scan on` command as it comes in
-        self.bluetooth_address = String::new();
-        let stdout_reader = BufReader::new(bluetoothctl_status_output);
-        for line in stdout_reader.lines() {
-            let line = line
-                .context("Failed to read line from `bluetoothctl -t 30 scan on' output")
-                .unwrap_or_fmt();
+        let mut remotes: Vec<DiscoveredRemote> = Vec::new();
+        let deadline = Instant::now() + SCAN_TIMEOUT + SCAN_GRACE;
+        let mut killed = false;
+        loop {
+            // Wake up every so often to notice a remote connected through the desktop's Bluetooth settings
+            let wait = deadline
+                .saturating_duration_since(Instant::now())
+                .min(HOTPLUG_INTERVAL);
 
-            if !line.contains("RVL") {
+            let line = match lines.recv_timeout(wait) {
+                Ok(line) => line
+                    .context("Failed to read line from `bluetoothctl -t 30 scan on' output")
+                    .unwrap_or_fmt(),
+                Err(RecvTimeoutError::Timeout) if Instant::now() < deadline => {
+                    match xwiimote::bound_remotes()
+                        .iter()
+                        .all(|(_, address)| self.is_off_limits(address))
+                    {
+                        true => continue,
+                        false => {
+                            let _ = bluetoothctl_status.kill();
+                            let _ = bluetoothctl_status.wait();
+                            return remotes;
+                        }
+                    }
+                }
+                Err(RecvTimeoutError::Timeout) => {
+                    warn!(
+                        "`bluetoothctl scan on' is still running after {} seconds, stopping it",
+                        (SCAN_TIMEOUT + SCAN_GRACE).as_secs()
+                    );
+                    let _ = bluetoothctl_status.kill();
+                    killed = true;
+                    break;
+                }
+                Err(RecvTimeoutError::Disconnected) => break,
+            };
+
+            // Only printed as `[CHG] Device <address> RSSI: ...` lines, which don't carry the name
+            if let (Some(address), Some(rssi)) =
+                (WiiRemote::parse_address(&line), parse_rssi(&line))
+            {
+                if let Some(remote) = remotes.iter_mut().find(|remote| remote.address == address) {
+                    remote.rssi = Some(rssi);
+                }
+                continue;
+            }
+
+            if !self.device_line.matches(&line) {
                 continue;
             }
 
-            self.bluetooth_address = line.split_whitespace().nth(2).unwrap().to_owned();
+            match self.device_line.parse(&line) {
+                Some((address, _)) if remotes.iter().any(|remote| remote.address == address) => {}
+                Some((address, name)) => remotes.push(DiscoveredRemote {
+                    name,
+                    address,
+                    paired: false,
+                    trusted: false,
+                    connected: false,
+                    rssi: None,
+                }),
+                None => debug!("Skipping malformed `bluetoothctl scan on' line: {}", line),
+            }
         }
 
-        // Test to see if we found a Wii Remote
-        if self.bluetooth_address.is_empty() {
-            return false;
+        // Reap the child whether it exited by itself or was killed, a failed scan not finding anything is worth
+        // telling apart from there being nothing to find
+        match bluetoothctl_status.wait() {
+            Ok(status) if status.success() || killed => {}
+            Ok(status) if remotes.is_empty() => warn!(
+                "`bluetoothctl -t 30 scan on' failed ({}), is the Bluetooth adapter powered on?",
+                status
+            ),
+            Ok(status) => debug!("`bluetoothctl -t 30 scan on' exited with {}", status),
+            Err(err) => warn!("Failed to wait for `bluetoothctl -t 30 scan on': {}", err),
         }
 
-        // Try executing the `bluetoothctl connect` command
-        let _bluetoothctl_connect_output = Command::new("bluetoothctl")
-            .arg("connect")
-            .arg(&self.bluetooth_address)
-            .output()
-            .context("Failed to execute `bluetoothctl connect'")
-            .unwrap_or_fmt();
+        remotes
+    }
+
+    /// Runs `bluetoothctl connect <address>`, returning `None` if it had to be killed for taking too long.
+    fn run_connect(&self, address: &str) -> error::Result<Option<Output>> {
+        let output = self
+            .bluetoothctl()
+            .output_until(
+                &["connect", address],
+                is_connect_result,
+                self.connect_timeout,
+            )
+            .map_err(|err| BlueWiiError::spawn_failed("bluetoothctl connect", err))?;
+
+        if output.is_none() {
+            warn!(
+                "`bluetoothctl connect {}' timed out after {} seconds, is the adapter busy?",
+                address,
+                self.connect_timeout.as_secs()
+            );
+        }
 
-        // If we've reached this point, we failed to connect to a Wii Remote
-        return true;
+        Ok(output)
     }
 
-    pub fn is_connected(&mut self) -> bool {
-        // First, check to see if we're connected to any Wii Remotes
-        // Normally we'd execute this in Bash: `bluetoothctl devices | grep RVL | cut -d " " -f 2 | bluetoothctl info | grep "Connected: yes"`
-        let bluetoothctl_devices_output = Command::new("bluetoothctl")
-            .arg("devices")
-            .output()
-            .context("Failed to execute `bluetoothctl devices'")
-            .unwrap_or_fmt();
+    /// Removes `address` from bluez entirely, unpairing it, so it has to be paired again before it connects. A
+    /// remote that's connected is disconnected along the way.
+    pub fn forget(&mut self, address: &str) -> error::Result<()> {
+        if !self.is_known(address)? {
+            return Err(BlueWiiError::DeviceNotFound(format!(
+                "{} isn't known to bluez",
+                address
+            )));
+        }
 
-        let bluetoothctl_devices_str = std::str::from_utf8(&bluetoothctl_devices_output.stdout)
-            .context("Failed to convert `bluetoothctl devices' output to a string.")
-            .unwrap_or_fmt();
+        *self.connection_cache.get_mut() = None;
+        let bluetoothctl_remove_output = self
+            .bluetoothctl()
+            .output(&["remove", address])
+            .map_err(|err| BlueWiiError::spawn_failed("bluetoothctl remove", err))?
+            .ok_or_else(|| no_answer(&format!("bluetoothctl remove {}", address)))?;
 
-        for line in bluetoothctl_devices_str.lines() {
-            if !line.contains("RVL") {
-                continue;
+        // `Device has been removed' isn't printed by every version, so it's only taken as gone once it isn't listed
+        match self.is_known(address)? {
+            false => Ok(()),
+            true => Err(BlueWiiError::CommandFailed {
+                command: format!("bluetoothctl remove {}", address),
+                reason: decode_output(&bluetoothctl_remove_output.stdout, "bluetoothctl remove")
+                    .lines()
+                    .map(str::trim)
+                    .rfind(|line| !line.is_empty())
+                    .unwrap_or("it's still listed")
+                    .to_owned(),
+            }),
+        }
+    }
+
+    /// Pairs and trusts `address`, a remote whose red SYNC button was just pressed, so from then on it connects by
+    /// pressing any button instead of needing 1+2 each time.
+    ///
+    /// Wii Remotes only do legacy PIN pairing, with a PIN made of the raw bytes of the adapter's address, which can't
+    /// be typed into an agent. bluez's `wiimote` plugin (built in unless bluez was configured without it) answers
+    /// the PIN request itself, so all BlueWii does is register a `NoInputNoOutput` agent for the request to go
+    /// through and leave the rest to it. Pairing a remote with 1+2, whose PIN is the remote's own address, isn't
+    /// supported, since the plugin always answers with the adapter's.
+    ///
+    /// bluez can only pair with a device it has seen, so a remote it doesn't know yet is scanned for first, for up to
+    /// [`PAIR_TIMEOUT`].
+    pub fn pair(&mut self, address: &str) -> error::Result<()> {
+        *self.connection_cache.get_mut() = None;
+        let command = format!("bluetoothctl pair {}", address);
+        if !self.is_known(address)? && !self.discover(address)? {
+            return Err(BlueWiiError::CommandFailed {
+                command,
+                reason: format!(
+                    "it wasn't found within {} seconds, was the SYNC button pressed?",
+                    PAIR_TIMEOUT.as_secs()
+                ),
+            });
+        }
+
+        let pair = format!("pair {}", address);
+        let output = self
+            .bluetoothctl()
+            .session_output(
+                &["agent NoInputNoOutput", "default-agent", &pair],
+                is_pair_result,
+                PAIR_TIMEOUT,
+            )
+            .map_err(|err| BlueWiiError::spawn_failed("bluetoothctl pair", err))?;
+
+        let Some(output) = output else {
+            return Err(BlueWiiError::CommandFailed {
+                command,
+                reason: format!(
+                    "no answer within {} seconds, was the SYNC button pressed?",
+                    PAIR_TIMEOUT.as_secs()
+                ),
+            });
+        };
+
+        let stdout = decode_output(&output.stdout, "bluetoothctl pair");
+        let failure = stdout
+            .lines()
+            .map(str::trim)
+            .find(|line| line.contains("Failed to pair") || line.contains("not available"));
+        match failure {
+            None if !stdout.contains("Pairing successful") => {
+                return Err(BlueWiiError::CommandFailed {
+                    command,
+                    reason: "it didn't say whether pairing worked".to_owned(),
+                });
+            }
+            // Pairing it again is pointless, trusting it may not be
+            Some(line) if line.contains("AlreadyExists") => {
+                info!("{} is already paired", address);
+            }
+            // It went undiscoverable again between being found and being paired
+            Some(line) if line.contains("not available") => {
+                return Err(BlueWiiError::CommandFailed {
+                    command,
+                    reason: format!("{} (press SYNC again and retry)", line),
+                });
+            }
+            Some(line) if line.contains("Authentication") => {
+                return Err(BlueWiiError::CommandFailed {
+                    command,
+                    reason: format!(
+                        "{} (is bluez's wiimote plugin disabled, or was 1+2 pressed instead of SYNC?)",
+                        line
+                    ),
+                });
+            }
+            Some(line) => {
+                return Err(BlueWiiError::CommandFailed {
+                    command,
+                    reason: line.to_owned(),
+                });
             }
+            None => {}
+        }
 
-            self.bluetooth_address = line.split_whitespace().nth(1).unwrap().to_owned();
-            return true;
+        let bluetoothctl_trust_output = self
+            .bluetoothctl()
+            .output(&["trust", address])
+            .map_err(|err| BlueWiiError::spawn_failed("bluetoothctl trust", err))?
+            .ok_or_else(|| no_answer(&format!("bluetoothctl trust {}", address)))?;
+
+        match decode_output(&bluetoothctl_trust_output.stdout, "bluetoothctl trust")
+            .contains("trust succeeded")
+        {
+            true => Ok(()),
+            false => Err(BlueWiiError::CommandFailed {
+                command: format!("bluetoothctl trust {}", address),
+                reason: "it was paired, but not trusted".to_owned(),
+            }),
+        }
+    }
+
+    // Scans until bluez reports `address`, for at most `PAIR_TIMEOUT`, whether it was found
+    fn discover(&self, address: &str) -> error::Result<bool> {
+        info!("Looking for {}, press its SYNC button...", address);
+        let (mut bluetoothctl_scan, bluetoothctl_scan_output) = self
+            .bluetoothctl()
+            .spawn_scan(PAIR_TIMEOUT)
+            .map_err(|err| BlueWiiError::spawn_failed("bluetoothctl scan on", err))?;
+
+        // Read on another thread like `scan` does, so a bluetoothctl that ignores `-t` can't keep us waiting
+        let (lines_sender, lines) = mpsc::channel();
+        thread::spawn(move || {
+            for line in BufReader::new(bluetoothctl_scan_output).lines() {
+                let Ok(line) = line else { break };
+                if lines_sender.send(line).is_err() {
+                    break;
+                }
+            }
+        });
+
+        let deadline = Instant::now() + PAIR_TIMEOUT + SCAN_GRACE;
+        let found = loop {
+            match lines.recv_timeout(deadline.saturating_duration_since(Instant::now())) {
+                Ok(line)
+                    if WiiRemote::parse_address(&line)
+                        .is_some_and(|found| found.eq_ignore_ascii_case(address)) =>
+                {
+                    break true
+                }
+                Ok(_) => {}
+                Err(_) => break false,
+            }
+        };
+
+        let _ = bluetoothctl_scan.kill();
+        let _ = bluetoothctl_scan.wait();
+        Ok(found)
+    }
+
+    // Whether `bluetoothctl devices` lists `address`, whatever kind of device it is
+    fn is_known(&self, address: &str) -> error::Result<bool> {
+        let bluetoothctl_devices_output = self
+            .bluetoothctl()
+            .output(&["devices"])
+            .map_err(|err| BlueWiiError::spawn_failed("bluetoothctl devices", err))?
+            .ok_or_else(|| no_answer("bluetoothctl devices"))?;
+
+        let address = address.to_uppercase();
+        Ok(
+            decode_output(&bluetoothctl_devices_output.stdout, "bluetoothctl devices")
+                .lines()
+                .any(|line| line.to_uppercase().contains(&address)),
+        )
+    }
+
+    /// Turns the Bluetooth controller off and back on, for an adapter that stopped connecting anything until it's
+    /// power cycled. Every other device connected through it drops too.
+    pub fn reset_adapter(&mut self) -> error::Result<()> {
+        *self.connection_cache.get_mut() = None;
+        self.set_power("off")?;
+        thread::sleep(ADAPTER_RESET_PAUSE);
+        self.set_power("on")?;
+
+        // `power on' can report success for a controller rfkill still has blocked
+        let bluetoothctl_show_output = self
+            .bluetoothctl()
+            .output(&["show"])
+            .map_err(|err| BlueWiiError::spawn_failed("bluetoothctl show", err))?
+            .ok_or_else(|| no_answer("bluetoothctl show"))?;
+
+        let bluetoothctl_show_str =
+            decode_output(&bluetoothctl_show_output.stdout, "bluetoothctl show");
+        match bluetoothctl_show_str
+            .lines()
+            .any(|line| line.trim() == "Powered: yes")
+        {
+            true => Ok(()),
+            false => Err(BlueWiiError::CommandFailed {
+                command: "bluetoothctl power on".to_owned(),
+                reason: "the controller is still powered off".to_owned(),
+            }),
         }
+    }
 
-        return false;
+    // `state` is `on' or `off'
+    fn set_power(&self, state: &str) -> error::Result<()> {
+        let command = format!("bluetoothctl power {}", state);
+        let bluetoothctl_power_output = self
+            .bluetoothctl()
+            .output_until(&["power", state], is_power_result, ADAPTER_POWER_TIMEOUT)
+            .map_err(|err| BlueWiiError::spawn_failed(&command, err))?
+            .ok_or_else(|| BlueWiiError::Timeout {
+                what: format!("`{}'", command),
+                after: ADAPTER_POWER_TIMEOUT,
+            })?;
+
+        // Like `Changing power on succeeded', or `Failed to set power on: org.bluez.Error.Blocked'
+        let bluetoothctl_power_str =
+            decode_output(&bluetoothctl_power_output.stdout, "bluetoothctl power");
+        match bluetoothctl_power_str.contains("succeeded") {
+            true => Ok(()),
+            false => Err(BlueWiiError::CommandFailed {
+                command,
+                reason: bluetoothctl_power_str
+                    .lines()
+                    .map(str::trim)
+                    .rfind(|line| !line.is_empty())
+                    .unwrap_or("no output")
+                    .to_owned(),
+            }),
+        }
+    }
+
+    /// Like [`WiiRemote::try_connect`], but only adopts a remote that was connected some other way, never scanning.
+    pub fn wait_for_existing(&mut self, timeout: Duration) -> bool {
+        let deadline = Instant::now() + timeout;
+        loop {
+            if self.adopt_bound_remote() || self.adopt_connected() {
+                return true;
+            }
+
+            let remaining = deadline.saturating_duration_since(Instant::now());
+            if remaining.is_zero() {
+                return false;
+            }
+
+            thread::sleep(HOTPLUG_INTERVAL.min(remaining));
+        }
+    }
+
+    /// Takes over a remote hid-wiimote is already driving, which is how remotes connected outside of BlueWii show up.
+    fn adopt_bound_remote(&mut self) -> bool {
+        match xwiimote::bound_remotes()
+            .into_iter()
+            .find(|(_, address)| !self.is_off_limits(address))
+        {
+            Some((_, address)) => {
+                info!(
+                    "Adopting Wii Remote {}, which is already connected",
+                    address
+                );
+                remember_connected(&address);
+                self.bluetooth_address = address;
+                *self.connection_cache.get_mut() = None;
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Connects to the remote at `address` without scanning, which works as long as it's discoverable or bluez
+    /// already knows it. How it's decided that the remote connected is up to `connect_backend`.
+    pub fn connect(&mut self, address: &str) -> error::Result<()> {
+        *self.connection_cache.get_mut() = None;
+        if self.connect_backend == ConnectBackend::Xwiimote {
+            return self.connect_until_bound(address);
+        }
+
+        // Execute `bluetoothctl connect <address>`, skipping the scan entirely
+        let bluetoothctl_connect_output = match self.run_connect(address)? {
+            Some(output) => output,
+            None => {
+                return Err(BlueWiiError::Timeout {
+                    what: format!("`bluetoothctl connect {}'", address),
+                    after: self.connect_timeout,
+                })
+            }
+        };
+
+        // Older versions of bluetoothctl exit with 0 even when the connection fails
+        let bluetoothctl_connect_str =
+            decode_output(&bluetoothctl_connect_output.stdout, "bluetoothctl connect");
+        match bluetoothctl_connect_str.contains("Connection successful") {
+            true => {
+                remember_connected(address);
+                self.bluetooth_address = address.to_owned();
+                Ok(())
+            }
+            false => Err(BlueWiiError::ConnectFailed {
+                address: address.to_owned(),
+                reason: bluetoothctl_connect_str
+                    .lines()
+                    .map(str::trim)
+                    .rfind(|line| !line.is_empty())
+                    .unwrap_or("no output")
+                    .to_owned(),
+            }),
+        }
+    }
+
+    /// Connects `address` until hid-wiimote picks it up, for [`ConnectBackend::Xwiimote`]. What `bluetoothctl
+    /// connect` says is only logged, since it can report a failure for a link that comes back by itself.
+    fn connect_until_bound(&mut self, address: &str) -> error::Result<()> {
+        let is_bound = || {
+            xwiimote::bound_remotes()
+                .iter()
+                .any(|(_, bound_address)| bound_address == address)
+        };
+
+        for attempt in 1..=BIND_ATTEMPTS {
+            if let Some(output) = self.run_connect(address)? {
+                debug!(
+                    "`bluetoothctl connect {}' said: {}",
+                    address,
+                    decode_output(&output.stdout, "bluetoothctl connect").trim()
+                );
+            }
+
+            let deadline = Instant::now() + BIND_TIMEOUT;
+            while Instant::now() < deadline {
+                if is_bound() {
+                    remember_connected(address);
+                    self.bluetooth_address = address.to_owned();
+                    return Ok(());
+                }
+
+                thread::sleep(MIN_CONNECTION_POLL_INTERVAL);
+            }
+
+            debug!(
+                "hid-wiimote didn't pick up Wii Remote {} (attempt {} of {})",
+                address, attempt, BIND_ATTEMPTS
+            );
+        }
+
+        Err(BlueWiiError::ConnectFailed {
+            address: address.to_owned(),
+            reason: format!(
+                "hid-wiimote didn't pick it up after {} attempts",
+                BIND_ATTEMPTS
+            ),
+        })
+    }
+
+    /// Whether a Wii Remote is connected, as last seen by `bluetoothctl`, see
+    /// [`WiiRemote::connected_addresses`].
+    pub fn is_connected(&self) -> bool {
+        !self.connected_addresses().is_empty()
+    }
+
+    /// The addresses of the connected Wii Remotes, the ones `bluetoothctl devices` lists whose `bluetoothctl info`
+    /// says `Connected: yes`, so paired remotes that are turned off aren't among them. Remotes claimed by another
+    /// `WiiRemote`, or ruled out by `allow` and `deny`, are left out.
+    ///
+    /// The answer is cached for `connection_cache_ttl` so frequent callers don't spawn a process every time,
+    /// and the cache is dropped whenever BlueWii connects or disconnects a remote itself.
+    pub fn connected_addresses(&self) -> Vec<String> {
+        if let Some((checked_at, addresses)) = &*self.connection_cache.borrow() {
+            if checked_at.elapsed() < self.connection_cache_ttl {
+                return addresses.clone();
+            }
+        }
+
+        let addresses = self.query_connected();
+        *self.connection_cache.borrow_mut() = Some((Instant::now(), addresses.clone()));
+        addresses
+    }
+
+    // Takes the first of `connected_addresses` as this remote's address
+    fn adopt_connected(&mut self) -> bool {
+        match self.connected_addresses().into_iter().next() {
+            Some(address) => {
+                remember_connected(&address);
+                self.bluetooth_address = address;
+                true
+            }
+            None => false,
+        }
+    }
+
+    fn query_connected(&self) -> Vec<String> {
+        // Normally we'd execute this in Bash: `bluetoothctl devices | grep RVL | cut -d " " -f 2 | bluetoothctl info | grep "Connected: yes"`
+        self.known_devices()
+            .into_iter()
+            .map(|(address, _)| address)
+            .filter(|address| !self.is_off_limits(address))
+            .filter(|address| self.info(address).is_some_and(|info| info.connected))
+            .collect()
+    }
+
+    /// Blocks for up to `timeout` until a Wii Remote is connected, returning whether one showed up.
+    ///
+    /// Checks are spaced out by `connection_cache_ttl`, so waiting doesn't spawn `bluetoothctl` any more often
+    /// than [`WiiRemote::is_connected`] would on its own.
+    pub fn wait_for_connection(&mut self, timeout: Duration) -> bool {
+        let deadline = Instant::now() + timeout;
+        loop {
+            if self.adopt_connected() {
+                return true;
+            }
+
+            let remaining = deadline.saturating_duration_since(Instant::now());
+            if remaining.is_zero() {
+                return false;
+            }
+
+            thread::sleep(
+                self.connection_cache_ttl
+                    .max(MIN_CONNECTION_POLL_INTERVAL)
+                    .min(remaining),
+            );
+        }
     }
 
     pub fn disconnect(&mut self) {
-        // Execute `bluetoothctl disconnect <bluetooth_address>`
-        let _bluetoothctl_disconnect_output = Command::new("bluetoothctl")
-            .arg("disconnect")
-            .arg(&self.bluetooth_address)
-            .output()
+        self.disconnect_address(&self.bluetooth_address.clone());
+    }
+
+    pub fn disconnect_address(&mut self, address: &str) {
+        *self.connection_cache.get_mut() = None;
+
+        // Execute `bluetoothctl disconnect <address>`
+        let _bluetoothctl_disconnect_output = self
+            .bluetoothctl()
+            .output(&["disconnect", address])
+            .exit_if_missing(&self.bluetoothctl_path, "bluez")
             .context("Failed to execute `bluetoothctl disconnect'")
             .unwrap_or_fmt();
     }
 
     pub fn get_udev_device_path(&self) -> Option<String> {
         // Execute `xwiishow list`
-        let xwiishow_output = Command::new("xwiishow")
+        let xwiishow_output = Command::new(&self.xwiishow_path)
             .arg("list")
             .output()
+            .exit_if_missing(&self.xwiishow_path, "xwiimote")
             .context("Failed to execute `xwiishow list'")
             .unwrap_or_fmt();
 
-        let xwiishow_str = std::str::from_utf8(&xwiishow_output.stdout)
-            .context("Failed to convert `xwiishow list' output to a string.")
+        let xwiishow_str = decode_output(&xwiishow_output.stdout, "xwiishow list");
+        let paths = parse_xwiishow_list(&xwiishow_str);
+
+        // With several remotes connected, the one hid-wiimote has under our address is the one that's ours
+        let own_path = xwiimote::bound_remotes()
+            .into_iter()
+            .find(|(_, address)| *address == self.bluetooth_address)
+            .map(|(syspath, _)| syspath.to_string_lossy().into_owned());
+
+        // Otherwise any device but one known to be another remote's, an old one of ours might just have a new syspath
+        match own_path {
+            Some(own_path) if paths.contains(&own_path) => Some(own_path),
+            _ => paths.into_iter().find(|path| {
+                identity::address(path).is_none_or(|address| address == self.bluetooth_address)
+            }),
+        }
+    }
+
+    /// Like [`WiiRemote::get_udev_device_path`], but gives the kernel up to `timeout` to create the remote's HID
+    /// device, which can lag behind `bluetoothctl connect` reporting success.
+    pub fn wait_for_udev_device_path(&self, timeout: Duration) -> Option<String> {
+        let deadline = Instant::now() + timeout;
+        let mut logged = false;
+        loop {
+            if let Some(path) = self.get_udev_device_path() {
+                return Some(path);
+            }
+
+            if Instant::now() >= deadline {
+                return None;
+            }
+
+            if !logged {
+                info!(
+                    "Waiting up to {} seconds for xwiishow to list Wii Remote {}...",
+                    timeout.as_secs(),
+                    self.bluetooth_address
+                );
+                logged = true;
+            }
+
+            thread::sleep(DEVICE_PATH_POLL_INTERVAL);
+        }
+    }
+
+    /// Marks the connected remote as taken, so no other `WiiRemote` adopts or connects it while this one does.
+    pub fn claim(&mut self) {
+        self.release_claim();
+        CLAIMED.lock().unwrap().push(self.bluetooth_address.clone());
+        self.claim = Some(self.bluetooth_address.clone());
+    }
+
+    /// Lets other `WiiRemote`s have the remote this one claimed again.
+    pub fn release_claim(&mut self) {
+        if let Some(address) = self.claim.take() {
+            let mut claimed = CLAIMED.lock().unwrap();
+            if let Some(index) = claimed.iter().position(|claimed| *claimed == address) {
+                claimed.swap_remove(index);
+            }
+        }
+    }
+
+    /// How many remotes are claimed by any `WiiRemote`.
+    pub fn claimed_count() -> usize {
+        CLAIMED.lock().unwrap().len()
+    }
+
+    /// Whether `address` may be connected, going by `allow` and `deny`. Deny wins when an address is in both.
+    pub fn is_allowed(&self, address: &str) -> bool {
+        let listed = |addresses: &[String]| {
+            addresses
+                .iter()
+                .any(|listed| listed.eq_ignore_ascii_case(address))
+        };
+
+        !listed(&self.deny) && (self.allow.is_empty() || listed(&self.allow))
+    }
+
+    // A remote another `WiiRemote` has, or one the allow and deny lists rule out
+    fn is_off_limits(&self, address: &str) -> bool {
+        self.is_claimed_elsewhere(address) || !self.is_allowed(address)
+    }
+
+    fn is_claimed_elsewhere(&self, address: &str) -> bool {
+        self.claim.as_deref() != Some(address)
+            && CLAIMED
+                .lock()
+                .unwrap()
+                .iter()
+                .any(|claimed| claimed == address)
+    }
+
+    /// Every button press, accelerometer reading and IR frame since the last call, without blocking.
+    ///
+    /// Returns right away with an empty list when nothing new has arrived or no remote is connected. The first
+    /// call for a remote opens its buttons, accelerometer and IR camera, which turns the latter two on and costs
+    /// battery from then on, and an interface that stops reading (when the remote disconnects) is left out.
+    pub fn poll_events(&self) -> Vec<WiiEvent> {
+        self.with_interfaces(true, true, Interfaces::read)
+            .unwrap_or_default()
+    }
+
+    /// Like [`WiiRemote::poll_events`], but with the accelerometer and IR camera only open while `accel` and `ir`
+    /// are set, so they're left as they are for whoever else reads them.
+    ///
+    /// An interface that gets closed here is only turned off if nothing else has it open, and its events stop
+    /// showing up until it's asked for again.
+    pub fn poll_events_from(&self, accel: bool, ir: bool) -> Vec<WiiEvent> {
+        self.with_interfaces(accel, ir, Interfaces::read)
+            .unwrap_or_default()
+    }
+
+    /// The remote's buttons, accelerometer, IR camera, battery and extension all as of the same moment, `None`
+    /// with no remote connected.
+    ///
+    /// Every event the remote sent up to the call is taken into account, without being taken away from
+    /// [`WiiRemote::poll_events`], which still returns them next. Like it, the first call for a remote opens its
+    /// accelerometer and IR camera, and until they've reported something their parts of the state are empty.
+    pub fn read_state(&self) -> Option<WiiState> {
+        let mut state = self.with_interfaces(true, true, |interfaces| {
+            interfaces.refresh();
+            interfaces.state.clone()
+        })?;
+
+        state.battery = self
+            .udev_device_path
+            .as_deref()
+            .and_then(WiiRemote::battery_level);
+        Some(state)
+    }
+
+    /// The latest accelerometer reading in g, using the calibration the remote was made with, see
+    /// [`AccelCalibration::to_g`]. Nothing [`WiiRemote::poll_events`] would return is taken, but it opens the
+    /// accelerometer the same way, and `None` comes back until the remote has sent a reading.
+    pub fn read_accel_g(&self) -> Option<(f32, f32, f32)> {
+        let calibration = self.accel_calibration()?;
+        let reading = self
+            .with_interfaces(true, true, |interfaces| interfaces.accel_reading())
+            .flatten()?;
+
+        Some(calibration.to_g(reading))
+    }
+
+    /// The accelerometer calibration stored in the remote, read once per connection and kept, or `None` if it
+    /// couldn't be read.
+    pub fn accel_calibration(&self) -> Option<AccelCalibration> {
+        let udev_device_path = self.udev_device_path.as_deref()?;
+        if let Some((path, calibration)) = &*self.accel_calibration.borrow() {
+            if path == udev_device_path {
+                return Some(*calibration);
+            }
+        }
+
+        let hidraw_path = speaker::find_hidraw(udev_device_path)?;
+        let data = match speaker::read_eeprom(
+            &hidraw_path,
+            accel::CALIBRATION_ADDRESS,
+            accel::CALIBRATION_SIZE,
+        ) {
+            Ok(data) => data,
+            Err(err) => {
+                debug!("Failed to read the accelerometer calibration: {}", err);
+                return None;
+            }
+        };
+
+        let Some(calibration) = AccelCalibration::parse(&data) else {
+            debug!(
+                "The accelerometer calibration failed its checksum: {:02x?}",
+                data
+            );
+            return None;
+        };
+
+        debug!(
+            "Accelerometer calibration: zero {:?}, 1 g {:?}",
+            calibration.zero, calibration.one_g
+        );
+        *self.accel_calibration.borrow_mut() = Some((udev_device_path.to_owned(), calibration));
+        Some(calibration)
+    }
+
+    // Opens the interfaces for the current syspath if they aren't yet, with the accelerometer and IR camera only
+    // open while `accel` and `ir` are set, `None` with no remote connected
+    fn with_interfaces<T>(
+        &self,
+        accel: bool,
+        ir: bool,
+        f: impl FnOnce(&mut Interfaces) -> T,
+    ) -> Option<T> {
+        let mut events = self.events.borrow_mut();
+        let udev_device_path = match &self.udev_device_path {
+            Some(path) => path,
+            None => {
+                *events = None;
+                return None;
+            }
+        };
+
+        match &mut *events {
+            Some(interfaces) if interfaces.udev_device_path == *udev_device_path => {
+                interfaces.set_open(accel, ir);
+                Some(f(interfaces))
+            }
+            _ => Some(f(events.insert(Interfaces::open(
+                udev_device_path,
+                accel,
+                ir,
+            )))),
+        }
+    }
+
+    /// The HID IDs of the connected remote parsed out of its syspath, along with its model and where its
+    /// MotionPlus is, if it has one.
+    pub fn device_info(&self) -> Option<DeviceInfo> {
+        let udev_device_path = self.udev_device_path.as_deref()?;
+        let mut info = parse_device_info(udev_device_path)?;
+
+        // Third-party Wii Remote Pluses often keep the original's product ID but not its name
+        info.named_model = fs::read_to_string(Path::new(udev_device_path).join("uevent"))
+            .ok()
+            .and_then(|uevent| {
+                uevent
+                    .lines()
+                    .find_map(|line| line.strip_prefix("HID_NAME="))
+                    .and_then(model_from_name)
+            });
+
+        let capabilities = xwiimote::capabilities(udev_device_path);
+        info.capabilities = Some(capabilities);
+
+        // hid-wiimote names both kinds the same, only the model tells them apart
+        info.motion_plus = capabilities
+            .motion_plus
+            .then(|| match info.has_builtin_motion_plus() {
+                true => MotionPlusSource::BuiltIn,
+                false => MotionPlusSource::External,
+            });
+
+        Some(info)
+    }
+
+    /// Plays mono 16-bit PCM at [`speaker::SAMPLE_RATE`] through the remote's speaker, blocking until it's done.
+    pub fn play_sound(&self, samples: &[i16]) -> error::Result<()> {
+        let udev_device_path = self
+            .udev_device_path
+            .as_deref()
+            .ok_or_else(BlueWiiError::not_connected)?;
+
+        let hidraw_path = speaker::find_hidraw(udev_device_path).ok_or_else(|| {
+            BlueWiiError::DeviceNotFound(format!(
+                "No hidraw device found under `{}'",
+                udev_device_path
+            ))
+        })?;
+
+        speaker::play(&hidraw_path, samples).map_err(|err| {
+            BlueWiiError::io(
+                format!("Failed to write to `{}'", hidraw_path.display()),
+                &hidraw_path,
+                err,
+            )
+        })
+    }
+
+    /// Sets the IR camera's sensitivity, from 1 (least sensitive) to 5 like the Wii's own setting, with anything
+    /// outside of that clamped. See [`ir::set_sensitivity`] for what the levels do, the camera has to be on
+    /// already.
+    pub fn set_ir_sensitivity(&self, level: u8) -> error::Result<()> {
+        let udev_device_path = self
+            .udev_device_path
+            .as_deref()
+            .ok_or_else(BlueWiiError::not_connected)?;
+
+        let hidraw_path = speaker::find_hidraw(udev_device_path).ok_or_else(|| {
+            BlueWiiError::DeviceNotFound(format!(
+                "No hidraw device found under `{}'",
+                udev_device_path
+            ))
+        })?;
+
+        ir::set_sensitivity(&hidraw_path, level).map_err(|err| {
+            BlueWiiError::io(
+                format!("Failed to write to `{}'", hidraw_path.display()),
+                &hidraw_path,
+                err,
+            )
+        })
+    }
+
+    /// The stick calibration stored in the plugged in Nunchuk or Classic Controller, or `None` if neither is
+    /// plugged in or it couldn't be read. Failures are only logged at debug, the kernel's ranges work well enough
+    /// without it.
+    pub fn extension_calibration(&self) -> Option<ExtensionCalibration> {
+        let udev_device_path = self.udev_device_path.as_deref()?;
+        let extension = stick::plugged_in(udev_device_path)?;
+        let hidraw_path = speaker::find_hidraw(udev_device_path)?;
+
+        let data = match speaker::read_register(
+            &hidraw_path,
+            stick::CALIBRATION_REGISTER,
+            stick::CALIBRATION_SIZE,
+        ) {
+            Ok(data) => data,
+            Err(err) => {
+                debug!(
+                    "Failed to read the {}'s calibration: {}",
+                    extension.name(),
+                    err
+                );
+                return None;
+            }
+        };
+
+        let calibration = ExtensionCalibration::parse(extension, &data);
+        match &calibration {
+            Some(calibration) => {
+                for (axis, axis_calibration) in &calibration.axes {
+                    debug!(
+                        "The {}'s own calibration of {}: center {}, min {}, max {}",
+                        extension.name(),
+                        axis,
+                        axis_calibration.center,
+                        axis_calibration.min,
+                        axis_calibration.max
+                    );
+                }
+            }
+            None => debug!(
+                "The {}'s calibration failed its checksum: {:02x?}",
+                extension.name(),
+                data
+            ),
+        }
+
+        calibration
+    }
+
+    /// Plays a rumble pattern of `(on, duration_ms)` segments in the background, see [`rumble`].
+    pub fn set_rumble_pattern(&self, pattern: &[(bool, u64)]) -> error::Result<()> {
+        let udev_device_path = self
+            .udev_device_path
+            .as_deref()
+            .ok_or_else(BlueWiiError::not_connected)?;
+
+        let event_path =
+            xwiimote::find_interface(udev_device_path, xwiimote::CORE).ok_or_else(|| {
+                BlueWiiError::DeviceNotFound(format!(
+                    "No core interface found under `{}'",
+                    udev_device_path
+                ))
+            })?;
+
+        rumble::play(&event_path, pattern).map_err(|err| {
+            BlueWiiError::io(
+                format!("Failed to rumble through `{}'", event_path.display()),
+                &event_path,
+                err,
+            )
+        })
+    }
+
+    /// The LED mask for `battery_level`, one LED for each started quarter of charge like the Wii itself.
+    pub fn battery_led_mask(battery_level: u8) -> u8 {
+        match battery_level {
+            76.. => 0b1111,
+            51..=75 => 0b0111,
+            26..=50 => 0b0011,
+            _ => 0b0001,
+        }
+    }
+
+    /// Lights the player LEDs set in `mask`, bit 0 being the leftmost LED.
+    pub fn set_leds(&self, mask: u8) -> error::Result<()> {
+        let udev_device_path = self
+            .udev_device_path
+            .as_deref()
+            .ok_or_else(BlueWiiError::not_connected)?;
+
+        write_leds(udev_device_path, mask)
+    }
+
+    /// The mask of the player LEDs lit right now, read back from the kernel so changes made by other programs
+    /// show up, in the same order as [`WiiRemote::set_leds`].
+    pub fn read_leds(&self) -> error::Result<u8> {
+        let udev_device_path = self
+            .udev_device_path
+            .as_deref()
+            .ok_or_else(BlueWiiError::not_connected)?;
+
+        read_leds(udev_device_path)
+    }
+
+    /// Whether [`WiiRemote::sweep_leds`] or [`WiiRemote::blink_leds`] is still changing the LEDs.
+    pub fn is_animating_leds(&self) -> bool {
+        self.leds_animating.load(Ordering::Relaxed)
+    }
+
+    /// Lights each mask of `pattern` in turn from a separate thread, spending `step` on each, then settles on
+    /// `mask`. [`SWEEP`] sweeps a single lit LED from 1 to 4 and back.
+    pub fn sweep_leds(&self, pattern: &[u8], step: Duration, mask: u8) -> error::Result<()> {
+        let udev_device_path = self
+            .udev_device_path
+            .clone()
+            .ok_or_else(BlueWiiError::not_connected)?;
+
+        let pattern = pattern.to_vec();
+        let animating = Arc::clone(&self.leds_animating);
+        animating.store(true, Ordering::Relaxed);
+        thread::spawn(move || {
+            for sweep_mask in pattern.iter().chain(Some(&mask)) {
+                if let Err(err) = write_leds(&udev_device_path, *sweep_mask) {
+                    warn!("Failed to sweep the LEDs: {:#}", err);
+                    break;
+                }
+
+                thread::sleep(step);
+            }
+
+            animating.store(false, Ordering::Relaxed);
+        });
+
+        Ok(())
+    }
+
+    /// Blinks the player LEDs `times` times from a separate thread, spending `step` off and then on each time, and
+    /// leaves them as they were.
+    pub fn blink_leds(&self, times: u32, step: Duration) -> error::Result<()> {
+        let udev_device_path = self
+            .udev_device_path
+            .clone()
+            .ok_or_else(BlueWiiError::not_connected)?;
+        let mask = read_leds(&udev_device_path)?;
+
+        let animating = Arc::clone(&self.leds_animating);
+        animating.store(true, Ordering::Relaxed);
+        thread::spawn(move || {
+            'blink: for _ in 0..times {
+                for blink_mask in [0, mask] {
+                    if let Err(err) = write_leds(&udev_device_path, blink_mask) {
+                        warn!("Failed to blink the LEDs: {:#}", err);
+                        break 'blink;
+                    }
+
+                    thread::sleep(step);
+                }
+            }
+
+            animating.store(false, Ordering::Relaxed);
+        });
+
+        Ok(())
+    }
+
+    /// Checks that `address` looks like `AA:BB:CC:DD:EE:FF`.
+    pub fn is_valid_address(address: &str) -> bool {
+        let octets = address.split(':').collect::<Vec<_>>();
+        octets.len() == 6
+            && octets
+                .iter()
+                .all(|octet| octet.len() == 2 && octet.chars().all(|c| c.is_ascii_hexdigit()))
+    }
+
+    /// Picks the Bluetooth address out of a `bluetoothctl` line like `[NEW] Device AA:BB:CC:DD:EE:FF Nintendo RVL-CNT-01`,
+    /// uppercased so addresses always compare equal.
+    pub fn parse_address(line: &str) -> Option<String> {
+        line.split_whitespace()
+            .find(|token| WiiRemote::is_valid_address(token))
+            .map(str::to_uppercase)
+    }
+
+    pub fn battery_level(udev_device_path: &str) -> Option<u8> {
+        // hid-wiimote registers the battery as `<hid device>/power_supply/wiimote_battery_<address>`
+        let power_supplies = fs::read_dir(Path::new(udev_device_path).join("power_supply")).ok()?;
+        for power_supply in power_supplies.flatten() {
+            if let Ok(capacity) = fs::read_to_string(power_supply.path().join("capacity")) {
+                return capacity.trim().parse().ok();
+            }
+        }
+
+        None
+    }
+
+    pub fn list_remotes(&self) -> Vec<DiscoveredRemote> {
+        // Run a scan so that remotes which are powered on but unknown to bluez show up in `bluetoothctl devices`
+        let (mut bluetoothctl_scan, mut bluetoothctl_scan_output) = self
+            .bluetoothctl()
+            .spawn_scan(SCAN_TIMEOUT)
+            .exit_if_missing(&self.bluetoothctl_path, "bluez")
+            .context("Failed to execute `bluetoothctl -t 30 scan on'")
+            .unwrap_or_fmt();
+
+        let _ = io::copy(&mut bluetoothctl_scan_output, &mut io::sink());
+        let _ = bluetoothctl_scan.wait();
+
+        self.known_remotes()
+    }
+
+    /// Every Wii Remote bluez knows about, without scanning for new ones first.
+    pub fn known_remotes(&self) -> Vec<DiscoveredRemote> {
+        self.enumerate_known()
+            .into_iter()
+            .map(|device| {
+                let info = device.info.unwrap_or_default();
+                DiscoveredRemote {
+                    address: device.address,
+                    name: device.name,
+                    paired: info.paired,
+                    trusted: info.trusted,
+                    connected: info.connected,
+                    rssi: info.rssi,
+                }
+            })
+            .collect()
+    }
+
+    /// Every Wii Remote bluez knows about along with everything `bluetoothctl info` says about each, for building
+    /// something like `--list` on. Nothing is scanned for first.
+    pub fn enumerate_known(&self) -> Vec<KnownDevice> {
+        self.known_devices()
+            .into_iter()
+            .map(|(address, name)| KnownDevice {
+                info: self.info(&address),
+                address,
+                name,
+            })
+            .collect()
+    }
+
+    // The address and name of every device `bluetoothctl devices` lists that looks like a Wii Remote
+    fn known_devices(&self) -> Vec<(String, String)> {
+        let bluetoothctl_devices_output = self
+            .bluetoothctl()
+            .output(&["devices"])
+            .exit_if_missing(&self.bluetoothctl_path, "bluez")
+            .context("Failed to execute `bluetoothctl devices'")
             .unwrap_or_fmt();
+        let Some(bluetoothctl_devices_output) = bluetoothctl_devices_output else {
+            warn!(
+                "`bluetoothctl devices' didn't answer within {} seconds, is the adapter busy?",
+                bluetoothctl::SESSION_TIMEOUT.as_secs()
+            );
+            return Vec::new();
+        };
+
+        let bluetoothctl_devices_str =
+            decode_output(&bluetoothctl_devices_output.stdout, "bluetoothctl devices");
 
-        /*
-        The output will look like this:
-        ```
-        Listing connected Wii Remote devices:
-          Found device #1: /sys/devices/virtual/misc/uhid/0005:057E:0306.0006
-        End of device list
-        ```
-        So we should only parse lines that contain "Found device #1" and splice by the first colon
-        */
-        for line in xwiishow_str.lines() {
-            if !line.contains("Found device #1") {
+        // Each line looks like `Device AA:BB:CC:DD:EE:FF Nintendo RVL-CNT-01`
+        let mut devices = Vec::new();
+        for line in bluetoothctl_devices_str.lines() {
+            if !self.device_line.matches(line) {
                 continue;
             }
 
-            let udev_device_path = line.split(":").skip(1).collect::<String>();
-            return Some(udev_device_path);
+            match self.device_line.parse(line) {
+                Some(parsed) => devices.push(parsed),
+                None => debug!("Skipping malformed `bluetoothctl devices' line: {}", line),
+            }
+        }
+
+        devices
+    }
+
+    fn info(&self, address: &str) -> Option<BluezInfo> {
+        // Execute `bluetoothctl info <bluetooth_address>`
+        let bluetoothctl_info_output = self
+            .bluetoothctl()
+            .output(&["info", address])
+            .exit_if_missing(&self.bluetoothctl_path, "bluez")
+            .context("Failed to execute `bluetoothctl info'")
+            .unwrap_or_fmt();
+        let Some(bluetoothctl_info_output) = bluetoothctl_info_output else {
+            debug!("`bluetoothctl info {}' didn't answer in time", address);
+            return None;
+        };
+
+        let bluetoothctl_info_str =
+            decode_output(&bluetoothctl_info_output.stdout, "bluetoothctl info");
+
+        let info = BluezInfo::parse(&bluetoothctl_info_str);
+        if info.is_none() {
+            debug!("`bluetoothctl info {}' said nothing about it", address);
+        }
+
+        info
+    }
+}
+
+/// Whether a line of `bluetoothctl power on` or `off` output is the last one it prints.
+fn is_power_result(line: &str) -> bool {
+    line.contains("succeeded") || line.contains("Failed to set power")
+}
+
+/// Whether a line of `bluetoothctl connect` output is the last one it prints.
+fn is_connect_result(line: &str) -> bool {
+    line.contains("Connection successful")
+        || line.contains("Failed to connect")
+        || line.contains("not available")
+}
+
+/// Whether a line of `bluetoothctl pair` output is the last one it prints, `not available` coming straight away for
+/// a device bluez has no record of.
+fn is_pair_result(line: &str) -> bool {
+    line.contains("Pairing successful")
+        || line.contains("Failed to pair")
+        || line.contains("not available")
+}
+
+/// The masks of the default startup sweep, one LED at a time from the leftmost to the rightmost and back.
+pub const SWEEP: [u8; 7] = [0b0001, 0b0010, 0b0100, 0b1000, 0b0100, 0b0010, 0b0001];
+
+// hid-wiimote registers the LEDs as `<hid device>/leds/<hid device>:blue:p0` through `:blue:p3`
+fn leds(udev_device_path: &str) -> error::Result<Vec<(u8, PathBuf)>> {
+    let leds = fs::read_dir(Path::new(udev_device_path).join("leds")).map_err(|_| {
+        BlueWiiError::DeviceNotFound(format!("No LEDs found under `{}'", udev_device_path))
+    })?;
+
+    Ok(leds
+        .flatten()
+        .filter_map(|led| {
+            let index = led
+                .file_name()
+                .to_string_lossy()
+                .rsplit_once(":blue:p")
+                .and_then(|(_, index)| index.parse::<u8>().ok())?;
+            (index < 4).then(|| (index, led.path()))
+        })
+        .collect())
+}
+
+fn remember_connected(address: &str) {
+    let mut last_connected = LAST_CONNECTED.lock().unwrap();
+    last_connected.retain(|(connected, _)| !connected.eq_ignore_ascii_case(address));
+    last_connected.push((address.to_owned(), Instant::now()));
+}
+
+// When `address` last connected, `None` ranks below any time so remotes that never did come last
+fn last_connected(address: &str) -> Option<Instant> {
+    LAST_CONNECTED
+        .lock()
+        .unwrap()
+        .iter()
+        .find(|(connected, _)| connected.eq_ignore_ascii_case(address))
+        .map(|(_, at)| *at)
+}
+
+// The error for a `bluetoothctl` command the session on the selected adapter never answered
+fn no_answer(command: &str) -> BlueWiiError {
+    BlueWiiError::Timeout {
+        what: format!("`{}'", command),
+        after: bluetoothctl::SESSION_TIMEOUT,
+    }
+}
+
+// The signal strength of a `[CHG] Device <address> RSSI: -56` line, newer BlueZ versions write
+// `RSSI: 0xffffffc8 (-56)` instead
+fn parse_rssi(line: &str) -> Option<i16> {
+    let (_, rssi) = line.split_once("RSSI:")?;
+    rssi.split_whitespace()
+        .last()?
+        .trim_matches(|c| c == '(' || c == ')')
+        .parse()
+        .ok()
+}
+
+// The mask `write_leds` would take to light the LEDs that are lit right now
+fn read_leds(udev_device_path: &str) -> error::Result<u8> {
+    let mut mask = 0;
+    for (index, led) in leds(udev_device_path)? {
+        let path = led.join("brightness");
+        let brightness = fs::read_to_string(&path).map_err(|err| {
+            BlueWiiError::io(format!("Failed to read LED {}", index + 1), &path, err)
+        })?;
+
+        if brightness.trim() != "0" {
+            mask |= 1 << index;
+        }
+    }
+
+    Ok(mask)
+}
+
+fn write_leds(udev_device_path: &str, mask: u8) -> error::Result<()> {
+    for (index, led) in leds(udev_device_path)? {
+        let brightness = match mask & (1 << index) {
+            0 => "0",
+            _ => "1",
+        };
+
+        let path = led.join("brightness");
+        fs::write(&path, brightness).map_err(|err| {
+            BlueWiiError::io(format!("Failed to set LED {}", index + 1), &path, err)
+        })?;
+    }
+
+    Ok(())
+}
+
+/// Parses the output of `xwiishow list` into the syspath of every device it found, in order.
+///
+/// The output looks like this:
+/// ```text
+/// Listing connected Wii Remote devices:
+///   Found device #1: /sys/devices/virtual/misc/uhid/0005:057E:0306.0006
+/// End of device list
+/// ```
+/// So only lines starting with "Found device #" are parsed, split at the first colon to keep the colons inside
+/// the path itself, and with the space that follows the label dropped.
+pub fn parse_xwiishow_list(output: &str) -> Vec<String> {
+    output
+        .lines()
+        .filter(|line| line.trim_start().starts_with("Found device #"))
+        .filter_map(|line| line.split_once(':'))
+        .map(|(_, path)| path.trim())
+        .filter(|path| !path.is_empty())
+        .map(str::to_owned)
+        .collect()
+}
+
+/// Parses the `BUS:VENDOR:PRODUCT.INSTANCE` name at the end of a syspath, all in hex.
+///
+/// For `/sys/devices/virtual/misc/uhid/0005:057E:0306.0006` that's bus 0x0005 (Bluetooth), vendor 0x057E
+/// (Nintendo) and product 0x0306 (the original Wii Remote), the instance number is dropped.
+pub fn parse_device_info(syspath: &str) -> Option<DeviceInfo> {
+    let name = syspath.trim_end_matches('/').rsplit('/').next()?;
+    let ids = name.split_once('.').map_or(name, |(ids, _)| ids);
+
+    let mut ids = ids.split(':').map(|id| u16::from_str_radix(id, 16));
+    let info = DeviceInfo {
+        bus: ids.next()?.ok()?,
+        vendor: ids.next()?.ok()?,
+        product: ids.next()?.ok()?,
+        named_model: None,
+        motion_plus: None,
+        capabilities: None,
+    };
+
+    match ids.next() {
+        Some(_) => None,
+        None => Some(info),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{env, os::unix::fs::PermissionsExt, process};
+
+    use super::*;
+
+    // A `bluetoothctl` that runs `script` whatever it's asked
+    fn fake_bluetoothctl(name: &str, script: &str) -> PathBuf {
+        let path = env::temp_dir().join(format!("bluewii-{}-{}.sh", process::id(), name));
+        fs::write(&path, format!("#!/bin/sh\n{}\nexit 0\n", script)).unwrap();
+        fs::set_permissions(&path, fs::Permissions::from_mode(0o755)).unwrap();
+        path
+    }
+
+    // One that always lists a paired remote, but only says it's connected once `marker` exists, standing in for one
+    // that's switched on
+    fn connecting_bluetoothctl(name: &str, marker: &Path) -> PathBuf {
+        fake_bluetoothctl(
+            name,
+            &format!(
+                "echo 'Device 00:19:1D:AB:CD:EF Nintendo RVL-CNT-01'\n\
+                 echo 'Paired: yes'\n\
+                 [ -e '{}' ] && echo 'Connected: yes' || echo 'Connected: no'",
+                marker.display()
+            ),
+        )
+    }
+
+    fn discovered(address: &str, rssi: Option<i16>) -> DiscoveredRemote {
+        DiscoveredRemote {
+            address: address.to_owned(),
+            name: "Nintendo RVL-CNT-01".to_owned(),
+            paired: true,
+            trusted: true,
+            connected: false,
+            rssi,
         }
+    }
+
+    #[test]
+    fn most_recently_connected_is_picked() {
+        let remotes = [
+            discovered("00:19:1D:00:00:01", Some(-40)),
+            discovered("00:19:1D:00:00:02", None),
+            discovered("00:19:1D:00:00:03", Some(-80)),
+        ];
+        let pick = |wii_remote: &WiiRemote| wii_remote.pick(&remotes).unwrap().address.clone();
+
+        let wii_remote = WiiRemote::new();
+        assert_eq!(pick(&wii_remote), "00:19:1D:00:00:01");
+
+        remember_connected("00:19:1D:00:00:03");
+        thread::sleep(Duration::from_millis(1));
+        remember_connected("00:19:1d:00:00:02");
+        assert_eq!(pick(&wii_remote), "00:19:1D:00:00:02");
+        thread::sleep(Duration::from_millis(1));
+        remember_connected("00:19:1D:00:00:03");
+        assert_eq!(pick(&wii_remote), "00:19:1D:00:00:03");
 
-        return None;
+        let strongest = WiiRemote::builder().prefer_strongest(true).build();
+        assert_eq!(pick(&strongest), "00:19:1D:00:00:01");
+    }
+
+    #[test]
+    fn model_from_either_name() {
+        assert_eq!(model_from_name("Nintendo RVL-CNT-01"), Some("RVL-CNT-01"));
+        assert_eq!(
+            model_from_name("Nintendo RVL-CNT-01-TR\n"),
+            Some("RVL-CNT-01-TR")
+        );
+        assert_eq!(model_from_name("Nintendo RVL-WBC-01"), None);
+    }
+
+    #[test]
+    fn name_wins_over_product_id() {
+        let info = parse_device_info("/sys/devices/virtual/misc/uhid/0005:057E:0306.0006").unwrap();
+        assert_eq!(info.model(), Some("RVL-CNT-01"));
+        assert!(!info.has_builtin_motion_plus());
+
+        let plus = DeviceInfo {
+            named_model: model_from_name("Nintendo RVL-CNT-01-TR"),
+            ..info
+        };
+        assert_eq!(plus.model(), Some("RVL-CNT-01-TR"));
+        assert!(plus.has_builtin_motion_plus());
+    }
+
+    #[test]
+    fn bluez_info() {
+        let output = "Device 00:19:1D:12:34:56 (public)\n\
+            \tName: Nintendo RVL-CNT-01\n\
+            \tAlias: Nintendo RVL-CNT-01\n\
+            \tClass: 0x00002504\n\
+            \tIcon: input-gaming\n\
+            \tPaired: yes\n\
+            \tBonded: yes\n\
+            \tTrusted: yes\n\
+            \tBlocked: no\n\
+            \tConnected: no\n\
+            \tLegacyPairing: yes\n\
+            \tUUID: Human Interface Device... (00001124-0000-1000-8000-00805f9b34fb)\n\
+            \tModalias: usb:v057Ep0306d0600\n\
+            \tRSSI: 0xffffffc8 (-56)\n";
+        assert_eq!(
+            BluezInfo::parse(output),
+            Some(BluezInfo {
+                alias: Some("Nintendo RVL-CNT-01".to_owned()),
+                paired: true,
+                bonded: Some(true),
+                trusted: true,
+                blocked: false,
+                connected: false,
+                modalias: Some("usb:v057Ep0306d0600".to_owned()),
+                rssi: Some(-56),
+            })
+        );
+    }
+
+    #[test]
+    fn bluez_info_from_older_bluez() {
+        // No `Bonded', `Modalias' or `RSSI' lines, for a remote that isn't in range
+        let output = "Device 00:19:1D:12:34:56 (public)\n\
+            \tName: Nintendo RVL-CNT-01-TR\n\
+            \tAlias: Nintendo RVL-CNT-01-TR\n\
+            \tPaired: no\n\
+            \tTrusted: no\n\
+            \tBlocked: yes\n\
+            \tConnected: no\n";
+        assert_eq!(
+            BluezInfo::parse(output),
+            Some(BluezInfo {
+                alias: Some("Nintendo RVL-CNT-01-TR".to_owned()),
+                blocked: true,
+                ..BluezInfo::default()
+            })
+        );
+    }
+
+    #[test]
+    fn bluez_info_for_an_unknown_device() {
+        assert_eq!(
+            BluezInfo::parse("Device 00:19:1D:12:34:56 not available\n"),
+            None
+        );
+        assert_eq!(BluezInfo::parse(""), None);
+    }
+
+    #[test]
+    fn pair_results() {
+        assert!(is_pair_result("Pairing successful"));
+        assert!(is_pair_result(
+            "Failed to pair: org.bluez.Error.AuthenticationFailed"
+        ));
+        assert!(is_pair_result("Device 00:19:1D:12:34:56 not available"));
+        assert!(!is_pair_result("Attempting to pair with 00:19:1D:12:34:56"));
+    }
+
+    #[test]
+    fn address_from_well_formed_lines() {
+        assert_eq!(
+            WiiRemote::parse_address("[NEW] Device 00:19:1d:12:34:56 Nintendo RVL-CNT-01"),
+            Some("00:19:1D:12:34:56".to_owned())
+        );
+        assert_eq!(
+            WiiRemote::parse_address("[CHG] Device 00:19:1D:12:34:56 RSSI: -56"),
+            Some("00:19:1D:12:34:56".to_owned())
+        );
+    }
+
+    #[test]
+    fn address_from_malformed_lines() {
+        assert_eq!(WiiRemote::parse_address(""), None);
+        assert_eq!(WiiRemote::parse_address("[NEW] Device"), None);
+        assert_eq!(
+            WiiRemote::parse_address("[NEW] Device 00:19:1D:12:34 Nintendo RVL-CNT-01"),
+            None
+        );
+        assert_eq!(
+            WiiRemote::parse_address("[NEW] Device 00:19:1D:12:34:5G Nintendo RVL-CNT-01"),
+            None
+        );
+    }
+
+    #[test]
+    fn valid_addresses() {
+        assert!(WiiRemote::is_valid_address("00:19:1D:12:34:56"));
+        assert!(WiiRemote::is_valid_address("00:19:1d:12:34:56"));
+        assert!(!WiiRemote::is_valid_address("00:19:1D:12:34:56:78"));
+        assert!(!WiiRemote::is_valid_address("0:19:1D:12:34:567"));
+        assert!(!WiiRemote::is_valid_address("00-19-1D-12-34-56"));
+    }
+
+    #[test]
+    fn waits_for_a_remote_to_connect() {
+        let marker = env::temp_dir().join(format!("bluewii-{}-connected", process::id()));
+        let bluetoothctl = connecting_bluetoothctl("connecting", &marker);
+        let mut wii_remote = WiiRemote::builder()
+            .bluetoothctl_path(bluetoothctl.to_str().unwrap())
+            .connection_cache_ttl(Duration::from_millis(50))
+            .build();
+        assert!(!wii_remote.is_connected());
+
+        let connects = {
+            let marker = marker.clone();
+            thread::spawn(move || {
+                thread::sleep(Duration::from_millis(300));
+                fs::write(marker, "").unwrap();
+            })
+        };
+
+        let start = Instant::now();
+        assert!(wii_remote.wait_for_connection(Duration::from_secs(5)));
+        assert!(start.elapsed() >= Duration::from_millis(300));
+        assert_eq!(wii_remote.bluetooth_address, "00:19:1D:AB:CD:EF");
+
+        connects.join().unwrap();
+        let _ = fs::remove_file(marker);
+        let _ = fs::remove_file(bluetoothctl);
+    }
+
+    #[test]
+    fn only_connected_remotes_are_connected() {
+        let bluetoothctl = fake_bluetoothctl(
+            "connected",
+            "case \"$1\" in\n\
+             devices)\n\
+                 echo 'Device 00:19:1D:00:00:0A Nintendo RVL-CNT-01'\n\
+                 echo 'Device 00:19:1D:00:00:0B Nintendo RVL-CNT-01-TR' ;;\n\
+             info)\n\
+                 echo \"Device $2 (public)\"\n\
+                 echo 'Paired: yes'\n\
+                 [ \"$2\" = 00:19:1D:00:00:0A ] && echo 'Connected: yes' || echo 'Connected: no' ;;\n\
+             esac",
+        );
+        let wii_remote = WiiRemote::with_paths(bluetoothctl.to_str().unwrap(), "xwiishow");
+
+        assert_eq!(wii_remote.connected_addresses(), ["00:19:1D:00:00:0A"]);
+        assert!(wii_remote.is_connected());
+
+        let _ = fs::remove_file(bluetoothctl);
+    }
+
+    // What `--check` goes by, a remote that's paired but switched off mustn't pass for a healthy one
+    #[test]
+    fn switched_off_remote_is_not_connected() {
+        let bluetoothctl = fake_bluetoothctl(
+            "switched-off",
+            "echo 'Device 00:19:1D:00:00:0C Nintendo RVL-CNT-01'\n\
+             echo 'Paired: yes'\n\
+             echo 'Connected: no'",
+        );
+        let wii_remote = WiiRemote::with_paths(bluetoothctl.to_str().unwrap(), "xwiishow");
+
+        assert!(!wii_remote.is_connected());
+        assert_eq!(wii_remote.known_remotes().len(), 1);
+
+        let _ = fs::remove_file(bluetoothctl);
+    }
+
+    #[test]
+    fn gives_up_waiting_at_the_timeout() {
+        let marker = env::temp_dir().join(format!("bluewii-{}-never", process::id()));
+        let bluetoothctl = connecting_bluetoothctl("never-connecting", &marker);
+        let mut wii_remote = WiiRemote::builder()
+            .bluetoothctl_path(bluetoothctl.to_str().unwrap())
+            .connection_cache_ttl(Duration::from_millis(50))
+            .build();
+
+        let start = Instant::now();
+        assert!(!wii_remote.wait_for_connection(Duration::from_millis(300)));
+        assert!(start.elapsed() >= Duration::from_millis(300));
+
+        let _ = fs::remove_file(bluetoothctl);
+    }
+
+    #[test]
+    fn scan_child_is_reaped() {
+        let pid_file = env::temp_dir().join(format!("bluewii-{}-scan.pid", process::id()));
+        let bluetoothctl = fake_bluetoothctl(
+            "scan",
+            &format!(
+                "echo $$ > '{}'\necho '[NEW] Device 00:19:1D:AB:CD:01 Nintendo RVL-CNT-01'",
+                pid_file.display()
+            ),
+        );
+        let wii_remote = WiiRemote::with_paths(bluetoothctl.to_str().unwrap(), "true");
+
+        let remotes = wii_remote.scan();
+        assert_eq!(remotes.len(), 1);
+        assert_eq!(remotes[0].address, "00:19:1D:AB:CD:01");
+
+        // An exited child that was never waited on stays behind as a zombie
+        let pid = fs::read_to_string(&pid_file).unwrap();
+        assert!(!Path::new("/proc").join(pid.trim()).exists());
+
+        let _ = fs::remove_file(pid_file);
+        let _ = fs::remove_file(bluetoothctl);
+    }
+
+    #[test]
+    fn xwiishow_list() {
+        let output = "Listing connected Wii Remote devices:
+  Found device #1: /sys/devices/pci0000:00/0000:00:14.0/usb1/1-7/1-7:1.0/bluetooth/hci0/hci0:256/0005:057E:0306.0006
+  Found device #2: /sys/devices/virtual/misc/uhid/0005:057E:0330.000A
+End of device list
+";
+        assert_eq!(
+            parse_xwiishow_list(output),
+            [
+                "/sys/devices/pci0000:00/0000:00:14.0/usb1/1-7/1-7:1.0/bluetooth/hci0/hci0:256/0005:057E:0306.0006",
+                "/sys/devices/virtual/misc/uhid/0005:057E:0330.000A",
+            ]
+        );
+    }
+
+    #[test]
+    fn xwiishow_list_path_is_trimmed() {
+        // The sample from `parse_xwiishow_list`'s documentation, the path has to match the syspath udev reports
+        let output = "Listing connected Wii Remote devices:
+  Found device #1: /sys/devices/virtual/misc/uhid/0005:057E:0306.0006\r
+End of device list
+";
+        assert_eq!(
+            parse_xwiishow_list(output),
+            ["/sys/devices/virtual/misc/uhid/0005:057E:0306.0006"]
+        );
+    }
+
+    #[test]
+    fn xwiishow_list_without_remotes() {
+        let output = "Listing connected Wii Remote devices:\nEnd of device list\n";
+        assert!(parse_xwiishow_list(output).is_empty());
+        assert!(parse_xwiishow_list("  Found device #1: \n").is_empty());
+    }
+
+    #[test]
+    fn device_info_from_syspath() {
+        let info =
+            parse_device_info("/sys/devices/virtual/misc/uhid/0005:057E:0330.000A/").unwrap();
+        assert_eq!(
+            (info.bus, info.vendor, info.product),
+            (0x0005, 0x057E, 0x0330)
+        );
+        assert_eq!(info.model(), Some("RVL-CNT-01-TR"));
+        assert!(parse_device_info("/sys/devices/virtual/misc/uhid").is_none());
+    }
+
+    #[test]
+    fn device_info_from_documented_syspath() {
+        let info = parse_device_info("/sys/devices/virtual/misc/uhid/0005:057E:0306.0006").unwrap();
+        assert_eq!(
+            (info.bus, info.vendor, info.product),
+            (0x0005, 0x057E, 0x0306)
+        );
+        assert!(info.is_genuine());
+
+        let third_party =
+            parse_device_info("/sys/devices/virtual/misc/uhid/0005:1234:0306.0007").unwrap();
+        assert!(!third_party.is_genuine());
+    }
+
+    #[test]
+    fn device_info_from_malformed_syspaths() {
+        assert!(parse_device_info("").is_none());
+        assert!(parse_device_info("/sys/devices/virtual/misc/uhid/0005:057E.0006").is_none());
+        assert!(
+            parse_device_info("/sys/devices/virtual/misc/uhid/0005:057E:0306:0001.0006").is_none()
+        );
+        assert!(parse_device_info("/sys/devices/virtual/misc/uhid/0005:NTDO:0306.0006").is_none());
+    }
+
+    #[test]
+    fn missing_bluetoothctl_is_an_error() {
+        let missing = env::temp_dir().join(format!("bluewii-{}-missing", process::id()));
+        let mut wii_remote = WiiRemote::with_paths(missing.to_str().unwrap(), "xwiishow");
+
+        for result in [
+            wii_remote.forget("00:19:1D:AB:CD:EF"),
+            wii_remote.pair("00:19:1D:AB:CD:EF"),
+            wii_remote.connect("00:19:1D:AB:CD:EF"),
+            wii_remote.reset_adapter(),
+        ] {
+            match result {
+                Err(BlueWiiError::SpawnFailed { source, .. }) => {
+                    assert_eq!(source.kind(), io::ErrorKind::NotFound)
+                }
+                other => panic!("expected SpawnFailed, got {:?}", other),
+            }
+        }
     }
 }
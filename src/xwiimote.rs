@@ -0,0 +1,303 @@
+//! Direct access to the input devices hid-wiimote creates for each part of a Wii Remote.
+//!
+//! This is the same kernel interface libxwiimote wraps: every interface (the buttons, the accelerometer, the IR
+//! camera, extensions, ...) is its own evdev device below the remote's HID device, told apart by its name.
+//! Opening an interface is what tells the driver to start reporting it, so only open what's actually used.
+
+use std::{
+    ffi::CStr,
+    fs::{self, File, OpenOptions},
+    io::{self, ErrorKind, Read},
+    mem,
+    os::{fd::AsRawFd, unix::fs::OpenOptionsExt},
+    path::{Path, PathBuf},
+    slice,
+};
+
+use libc::{input_absinfo, input_event, _IOR};
+use libudev_sys::{udev, udev_monitor};
+use log::warn;
+
+use crate::{permissions, stick::Extension};
+
+pub const CORE: &str = "Nintendo Wii Remote";
+pub const ACCELEROMETER: &str = "Nintendo Wii Remote Accelerometer";
+pub const IR: &str = "Nintendo Wii Remote IR";
+pub const MOTION_PLUS: &str = "Nintendo Wii Remote Motion Plus";
+
+/// Where the kernel lists every HID device bound to hid-wiimote.
+const DRIVER_PATH: &str = "/sys/bus/hid/drivers/wiimote";
+
+/// Every remote hid-wiimote is currently driving, however it was connected, as `(HID syspath, Bluetooth address)`.
+pub fn bound_remotes() -> Vec<(PathBuf, String)> {
+    let entries = match fs::read_dir(DRIVER_PATH) {
+        Ok(entries) => entries,
+        Err(_) => return Vec::new(),
+    };
+
+    let mut remotes = Vec::new();
+    for entry in entries.flatten() {
+        // Devices are the `0005:057E:0306.0006` links, everything else is a driver attribute like `bind`
+        if !entry.file_name().to_string_lossy().contains(':') {
+            continue;
+        }
+
+        let syspath = match fs::canonicalize(entry.path()) {
+            Ok(syspath) => syspath,
+            Err(_) => continue,
+        };
+
+        // hid-wiimote devices carry the remote's Bluetooth address as `HID_UNIQ`
+        let address = fs::read_to_string(syspath.join("uevent"))
+            .ok()
+            .and_then(|uevent| {
+                uevent
+                    .lines()
+                    .find_map(|line| line.strip_prefix("HID_UNIQ="))
+                    .map(str::to_uppercase)
+            });
+
+        if let Some(address) = address {
+            remotes.push((syspath, address));
+        }
+    }
+
+    remotes
+}
+
+/// Finds the `/dev/input/eventN` node of the interface called `name` below the HID device at `udev_device_path`.
+pub fn find_interface(udev_device_path: &str, name: &str) -> Option<PathBuf> {
+    let inputs = fs::read_dir(Path::new(udev_device_path).join("input")).ok()?;
+    for input in inputs.flatten() {
+        match fs::read_to_string(input.path().join("name")) {
+            Ok(input_name) if input_name.trim() == name => {}
+            _ => continue,
+        }
+
+        for entry in fs::read_dir(input.path()).ok()?.flatten() {
+            let file_name = entry.file_name();
+            if file_name.to_string_lossy().starts_with("event") {
+                return Some(Path::new("/dev/input").join(file_name));
+            }
+        }
+    }
+
+    None
+}
+
+/// Which parts of a remote hid-wiimote has interfaces for, see [`capabilities`].
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Capabilities {
+    pub ir: bool,
+    pub accel: bool,
+    pub motion_plus: bool,
+    /// The extension plugged in when it was probed.
+    pub extension: Option<Extension>,
+}
+
+impl Capabilities {
+    /// The parts present separated by commas, like `ir,accel,nunchuk`, or `none`.
+    pub fn summary(&self) -> String {
+        let parts = [
+            self.ir.then_some("ir"),
+            self.accel.then_some("accel"),
+            self.motion_plus.then_some("motion-plus"),
+            self.extension.map(Extension::name),
+        ]
+        .into_iter()
+        .flatten()
+        .collect::<Vec<_>>();
+
+        match parts.is_empty() {
+            true => "none".to_owned(),
+            false => parts.join(","),
+        }
+    }
+}
+
+/// Looks for the interfaces below the HID device at `udev_device_path` without opening any of them, so nothing is
+/// turned on by probing.
+pub fn capabilities(udev_device_path: &str) -> Capabilities {
+    Capabilities {
+        ir: find_interface(udev_device_path, IR).is_some(),
+        accel: find_interface(udev_device_path, ACCELEROMETER).is_some(),
+        motion_plus: find_interface(udev_device_path, MOTION_PLUS).is_some(),
+        extension: Extension::ALL
+            .into_iter()
+            .find(|extension| find_interface(udev_device_path, extension.interface()).is_some()),
+    }
+}
+
+/// Opens the interface called `name`, logging why when it can't be.
+pub fn open_interface(udev_device_path: &str, name: &str) -> Option<Evdev> {
+    let path = match find_interface(udev_device_path, name) {
+        Some(path) => path,
+        None => {
+            warn!("Wii Remote has no `{}' interface", name);
+            return None;
+        }
+    };
+
+    match Evdev::open(&path) {
+        Ok(evdev) => Some(evdev),
+        Err(err) if permissions::is_permission_error(&err) => {
+            permissions::warn_denied(&path);
+            None
+        }
+        Err(err) => {
+            warn!("Failed to open {}: {}", path.display(), err);
+            None
+        }
+    }
+}
+
+/// A non-blocking reader for one interface's events.
+pub struct Evdev {
+    file: File,
+}
+
+impl Evdev {
+    pub fn open(path: &Path) -> io::Result<Evdev> {
+        let file = OpenOptions::new()
+            .read(true)
+            .custom_flags(libc::O_NONBLOCK)
+            .open(path)?;
+
+        Ok(Evdev { file })
+    }
+
+    /// Returns every event that's queued up, or an empty list when there's nothing new.
+    pub fn read_events(&mut self) -> io::Result<Vec<input_event>> {
+        let mut events = Vec::new();
+        loop {
+            let mut event: input_event = unsafe { mem::zeroed() };
+            let buffer = unsafe {
+                slice::from_raw_parts_mut(
+                    &mut event as *mut input_event as *mut u8,
+                    mem::size_of::<input_event>(),
+                )
+            };
+
+            match self.file.read(buffer) {
+                Ok(read) if read == buffer.len() => events.push(event),
+                Ok(_) => break,
+                Err(err) if err.kind() == ErrorKind::WouldBlock => break,
+                Err(err) => return Err(err),
+            }
+        }
+
+        Ok(events)
+    }
+
+    /// The current value and range of the absolute axis `code`.
+    pub fn abs_info(&self, code: u16) -> io::Result<input_absinfo> {
+        let mut info: input_absinfo = unsafe { mem::zeroed() };
+        let request = _IOR::<input_absinfo>(b'E' as u32, 0x40 + code as u32);
+        if unsafe { libc::ioctl(self.file.as_raw_fd(), request, &mut info) } < 0 {
+            return Err(io::Error::last_os_error());
+        }
+
+        Ok(info)
+    }
+}
+
+/// Watches for interfaces appearing and disappearing below one remote, like an extension being plugged in, the
+/// way libxwiimote's `XWII_EVENT_WATCH` does: through udev's uevents rather than by checking sysfs over and over.
+pub struct InterfaceMonitor {
+    udev: *mut udev,
+    monitor: *mut udev_monitor,
+    udev_device_path: String,
+}
+
+// Both udev objects are only ever touched through `&mut self`, so moving them to another thread is fine
+unsafe impl Send for InterfaceMonitor {}
+
+impl InterfaceMonitor {
+    pub fn open(udev_device_path: &str) -> io::Result<InterfaceMonitor> {
+        unsafe {
+            let udev = libudev_sys::udev_new();
+            if udev.is_null() {
+                return Err(io::Error::last_os_error());
+            }
+
+            // Owns `udev` from here on, so the early returns below free it
+            let mut watch = InterfaceMonitor {
+                udev,
+                monitor: std::ptr::null_mut(),
+                udev_device_path: udev_device_path.to_owned(),
+            };
+
+            // The socket is non-blocking, so `changes` never waits for a uevent
+            watch.monitor = libudev_sys::udev_monitor_new_from_netlink(udev, c"udev".as_ptr());
+            if watch.monitor.is_null()
+                || libudev_sys::udev_monitor_filter_add_match_subsystem_devtype(
+                    watch.monitor,
+                    c"input".as_ptr(),
+                    std::ptr::null(),
+                ) < 0
+                || libudev_sys::udev_monitor_enable_receiving(watch.monitor) < 0
+            {
+                return Err(io::Error::last_os_error());
+            }
+
+            Ok(watch)
+        }
+    }
+
+    /// The names of the interfaces added (`true`) or removed (`false`) below the remote since the last call.
+    pub fn changes(&mut self) -> Vec<(String, bool)> {
+        let mut changes = Vec::new();
+        loop {
+            let device = unsafe { libudev_sys::udev_monitor_receive_device(self.monitor) };
+            if device.is_null() {
+                break;
+            }
+
+            let value = |pointer: *const libc::c_char| match pointer.is_null() {
+                true => None,
+                false => Some(
+                    unsafe { CStr::from_ptr(pointer) }
+                        .to_string_lossy()
+                        .into_owned(),
+                ),
+            };
+
+            let syspath = value(unsafe { libudev_sys::udev_device_get_syspath(device) });
+            let action = value(unsafe { libudev_sys::udev_device_get_action(device) });
+            // Only the `inputN` device has a name, its `eventN` node gets a uevent of its own. The property is
+            // used instead of the `name` attribute since a removed device's attributes are already gone.
+            let name = value(unsafe {
+                libudev_sys::udev_device_get_property_value(device, c"NAME".as_ptr())
+            });
+            unsafe { libudev_sys::udev_device_unref(device) };
+
+            let (Some(syspath), Some(action), Some(name)) = (syspath, action, name) else {
+                continue;
+            };
+
+            if !Path::new(&syspath).starts_with(&self.udev_device_path) {
+                continue;
+            }
+
+            match action.as_str() {
+                "add" => changes.push((name.trim_matches('"').to_owned(), true)),
+                "remove" => changes.push((name.trim_matches('"').to_owned(), false)),
+                _ => {}
+            }
+        }
+
+        changes
+    }
+}
+
+impl Drop for InterfaceMonitor {
+    fn drop(&mut self) {
+        unsafe {
+            if !self.monitor.is_null() {
+                libudev_sys::udev_monitor_unref(self.monitor);
+            }
+
+            libudev_sys::udev_unref(self.udev);
+        }
+    }
+}